@@ -0,0 +1,987 @@
+mod directives;
+mod error;
+mod format;
+pub(crate) mod lexer;
+mod listing;
+mod macros;
+mod parser;
+mod pseudo;
+mod reloc;
+
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::fs;
+use std::mem;
+use std::path::Path;
+
+use ternary::{T12, T24, Tryte};
+
+use directives::{Addr, Cursor, align_up};
+pub use error::{Error, ErrorKind, Result};
+pub use format::{Literals, format, same_output};
+use lexer::{Lexeme, Span};
+use listing::Record;
+pub use listing::{Contents, Line, Listing};
+use macros::{Preprocessor, Reader};
+use parser::{Arg, BinaryOp, Expr, Instruction, Statement};
+pub use pseudo::PSEUDO_INSTRUCTIONS;
+use reloc::Place;
+
+use crate::image::{Image, Segment, Symbol};
+use crate::inst::Inst;
+use crate::map::{Map, SectionEntry};
+use crate::object::{Object, RelocKind, Relocation, Section};
+use crate::opcodes::{self, Opcode};
+use crate::operands::{self, ADDR_WIDTH, IMMEDIATE_WIDTH, OFFSET_WIDTH};
+use crate::registers::Register;
+
+const WORD_SIZE: i64 = 4;
+const WORD_WIDTH: usize = 24;
+
+pub const ENTRY_SYMBOL: &str = "_start";
+
+// An image or object along with its listing and map.
+#[derive(Clone, Debug)]
+pub struct Assembly<T> {
+    pub output: T,
+    pub listing: Listing,
+    pub map: Map,
+}
+
+// Includes are resolved relative to the current directory.
+pub fn assemble(source: &str, origin: i32) -> Result<Image> {
+    assemble_with(source, None, origin, &mut |path| fs::read_to_string(path))
+        .map(|assembly| assembly.output)
+}
+
+// `path` names `source` in diagnostics, and includes are resolved relative to
+// its directory.
+pub fn assemble_file(path: &Path, source: &str, origin: i32) -> Result<Image> {
+    assemble_file_listed(path, source, origin).map(|assembly| assembly.output)
+}
+
+pub fn assemble_file_listed(path: &Path, source: &str, origin: i32) -> Result<Assembly<Image>> {
+    assemble_with(source, Some(path), origin, &mut |path| {
+        fs::read_to_string(path)
+    })
+}
+
+// Assembles `source` into a relocatable object, with every section starting
+// at offset 0. Includes are resolved relative to the current directory.
+pub fn assemble_object(source: &str) -> Result<Object> {
+    preprocess(
+        source,
+        None,
+        &mut |path| fs::read_to_string(path),
+        object_lexemes,
+    )
+    .map(|assembly| assembly.output)
+}
+
+pub fn assemble_object_file(path: &Path, source: &str) -> Result<Object> {
+    assemble_object_file_listed(path, source).map(|assembly| assembly.output)
+}
+
+pub fn assemble_object_file_listed(path: &Path, source: &str) -> Result<Assembly<Object>> {
+    preprocess(
+        source,
+        Some(path),
+        &mut |path| fs::read_to_string(path),
+        object_lexemes,
+    )
+}
+
+fn assemble_with(
+    source: &str,
+    path: Option<&Path>,
+    origin: i32,
+    read: &mut Reader<'_>,
+) -> Result<Assembly<Image>> {
+    preprocess(source, path, read, |lexemes, preprocessor| {
+        assemble_lexemes(lexemes, origin, preprocessor)
+    })
+}
+
+fn preprocess<T>(
+    source: &str,
+    path: Option<&Path>,
+    read: &mut Reader<'_>,
+    finish: impl FnOnce(Vec<Lexeme>, &Preprocessor) -> Result<T>,
+) -> Result<T> {
+    let mut preprocessor = Preprocessor::new(read);
+    preprocessor
+        .run(source, path)
+        .and_then(|lexemes| finish(lexemes, &preprocessor))
+        .map_err(|error| preprocessor.locate(error))
+}
+
+fn assemble_lexemes(
+    lexemes: Vec<Lexeme>,
+    origin: i32,
+    preprocessor: &Preprocessor,
+) -> Result<Assembly<Image>> {
+    let statements = parser::parse(lexemes)?;
+    let mut assembler = Assembler::new(i64::from(origin), false);
+    assembler.layout(&statements)?;
+    let mut output = assembler.emit(&statements)?;
+    let listing = Listing::new(mem::take(&mut output.records), preprocessor);
+
+    let sections = Section::ALL
+        .into_iter()
+        .map(|section| SectionEntry {
+            section,
+            addr: assembler.bases[section.index()],
+            size: assembler.ends[section.index()] - assembler.bases[section.index()],
+        })
+        .collect();
+    let map = Map::new(
+        sections,
+        &output.sections.concat(),
+        assembler
+            .symbols
+            .iter()
+            .map(|(name, &addr)| (name.clone(), assembler.symbol_sections[name], addr)),
+    );
+
+    let mut image = Image::new(origin);
+    image.segments = output.segments();
+    image.lines = listing.line_table(&Section::ALL);
+    image.symbols = assembler
+        .symbols
+        .into_iter()
+        .filter_map(|(name, addr)| {
+            let addr = i32::try_from(addr).ok()?;
+            Some(Symbol { name, addr })
+        })
+        .collect();
+    image
+        .symbols
+        .sort_by(|a, b| (a.addr, &a.name).cmp(&(b.addr, &b.name)));
+
+    if let Some(symbol) = image.symbol(ENTRY_SYMBOL) {
+        image.entry = symbol.addr;
+    }
+
+    Ok(Assembly {
+        output: image,
+        listing,
+        map,
+    })
+}
+
+fn object_lexemes(lexemes: Vec<Lexeme>, preprocessor: &Preprocessor) -> Result<Assembly<Object>> {
+    let statements = parser::parse(lexemes)?;
+    let mut assembler = Assembler::new(0, true);
+    assembler.layout(&statements)?;
+    let mut output = assembler.emit(&statements)?;
+    let listing = Listing::new(mem::take(&mut output.records), preprocessor);
+
+    let sections = Section::ALL
+        .into_iter()
+        .map(|section| SectionEntry {
+            section,
+            addr: 0,
+            size: assembler.ends[section.index()],
+        })
+        .collect();
+    let map = Map::new(
+        sections,
+        &[],
+        assembler
+            .relative_symbols
+            .iter()
+            .map(|(name, &(section, offset))| (name.clone(), section, offset)),
+    );
+
+    let mut object = assembler.object(&output)?;
+    object.lines = listing.line_table(&[Section::Text]);
+
+    Ok(Assembly {
+        output: object,
+        listing,
+        map,
+    })
+}
+
+#[derive(Clone, Copy, Debug)]
+struct Placement {
+    section: Section,
+    addr: Addr,
+    size: i64,
+}
+
+// In a relocatable object every section is relative, so `symbols` only ever
+// holds absolute values and labels stay in `relative_symbols` as offsets.
+struct Assembler {
+    origin: i64,
+    relocatable: bool,
+    symbols: HashMap<String, i64>,
+    relative_symbols: HashMap<String, (Section, i64)>,
+    symbol_sections: HashMap<String, Section>,
+    globals: Vec<(String, Span)>,
+    placements: Vec<Placement>,
+    bases: [i64; 3],
+    ends: [i64; 3],
+    relocations: RefCell<Vec<Relocation>>,
+}
+
+impl Assembler {
+    fn new(origin: i64, relocatable: bool) -> Self {
+        Assembler {
+            origin,
+            relocatable,
+            symbols: HashMap::new(),
+            relative_symbols: HashMap::new(),
+            symbol_sections: HashMap::new(),
+            globals: Vec::new(),
+            placements: Vec::new(),
+            bases: [0; 3],
+            ends: [0; 3],
+            relocations: RefCell::new(Vec::new()),
+        }
+    }
+
+    fn layout(&mut self, statements: &[Statement]) -> Result<()> {
+        let mut cursor = Cursor::new((!self.relocatable).then_some(self.origin));
+        for statement in statements {
+            let size = match statement {
+                Statement::Label(name, span) => {
+                    self.define_symbol(name, cursor.section, cursor.addr(), *span)?;
+                    0
+                }
+                Statement::Instruction(instruction) => {
+                    let words = if let Some(words) = self.pseudo_size(instruction)? {
+                        words
+                    } else {
+                        opcode(instruction)?;
+                        1
+                    };
+
+                    if cursor.section == Section::Bss {
+                        return Err(Error::new(
+                            instruction.span,
+                            ErrorKind::NotAllowedInBss(instruction.mnemonic.clone()),
+                        ));
+                    }
+
+                    cursor.check_alignment(WORD_SIZE, instruction.span)?;
+                    WORD_SIZE * i64::try_from(words).unwrap()
+                }
+                Statement::Directive(directive) => self.layout_directive(directive, &mut cursor)?,
+            };
+
+            self.placements.push(Placement {
+                section: cursor.section,
+                addr: cursor.addr(),
+                size,
+            });
+            cursor.advance(size);
+        }
+
+        if self.relocatable {
+            for section in Section::ALL {
+                self.ends[section.index()] = cursor.end(section).value();
+            }
+
+            return Ok(());
+        }
+
+        let mut end = self.origin;
+        for section in [Section::Text, Section::Data, Section::Bss] {
+            let base = align_up(end, WORD_SIZE);
+            self.bases[section.index()] = base;
+            end = match cursor.end(section) {
+                Addr::Absolute(addr) => addr,
+                Addr::Relative(offset) => base + offset,
+            };
+            self.ends[section.index()] = end;
+        }
+
+        for (name, (section, offset)) in self.relative_symbols.drain() {
+            self.symbols
+                .insert(name, self.bases[section.index()] + offset);
+        }
+
+        Ok(())
+    }
+
+    fn define_symbol(
+        &mut self,
+        name: &str,
+        section: Section,
+        addr: Addr,
+        span: Span,
+    ) -> Result<()> {
+        if self.symbols.contains_key(name) || self.relative_symbols.contains_key(name) {
+            return Err(Error::new(
+                span,
+                ErrorKind::DuplicateSymbol(name.to_string()),
+            ));
+        }
+
+        self.symbol_sections.insert(name.to_string(), section);
+        match addr {
+            Addr::Absolute(addr) => {
+                self.symbols.insert(name.to_string(), addr);
+            }
+            Addr::Relative(offset) => {
+                self.relative_symbols
+                    .insert(name.to_string(), (section, offset));
+            }
+        }
+
+        Ok(())
+    }
+
+    fn emit(&self, statements: &[Statement]) -> Result<Output> {
+        let mut output = Output::default();
+        for (statement, placement) in statements.iter().zip(&self.placements) {
+            let addr = match placement.addr {
+                Addr::Absolute(addr) => addr,
+                Addr::Relative(offset) => self.bases[placement.section.index()] + offset,
+            };
+            let place = Place {
+                section: placement.section,
+                addr,
+            };
+
+            let (trytes, span, contents) = match statement {
+                Statement::Label(name, span) => {
+                    (Vec::new(), *span, Some(Contents::Label(name.clone())))
+                }
+                Statement::Instruction(instruction) => {
+                    let words = usize::try_from(placement.size / WORD_SIZE).unwrap();
+                    let insts = match self.expand(instruction, place, words)? {
+                        Some(insts) => insts,
+                        None => vec![self.build_inst(instruction, place)?],
+                    };
+
+                    let mut trytes = Vec::new();
+                    for inst in &insts {
+                        let word = inst.to_word().map_err(|error| {
+                            Error::new(instruction.span, ErrorKind::Encoding(format!("{error:?}")))
+                        })?;
+
+                        trytes.extend(word.into_trytes());
+                    }
+
+                    (trytes, instruction.span, Some(Contents::Insts(insts)))
+                }
+                Statement::Directive(directive) => {
+                    if directive.name == ".org" && addr < output.end(placement.section) {
+                        return Err(Error::new(directive.span, ErrorKind::OrgBackwards(addr)));
+                    }
+
+                    let trytes = self.directive_trytes(directive, place, placement.size)?;
+                    let contents = if matches!(directive.name.as_str(), ".zero" | ".align") {
+                        Contents::Zeros(trytes.len())
+                    } else {
+                        Contents::Data(trytes.clone())
+                    };
+                    let contents = (!trytes.is_empty()).then_some(contents);
+                    (trytes, directive.span, contents)
+                }
+            };
+
+            output.push(placement.section, addr, &trytes, span)?;
+            if let Some(contents) = contents {
+                output.records.push(Record {
+                    span,
+                    place,
+                    contents,
+                });
+            }
+        }
+
+        Ok(output)
+    }
+
+    fn build_inst(&self, instruction: &Instruction, place: Place) -> Result<Inst> {
+        let ops = Operands {
+            assembler: self,
+            instruction,
+            place,
+        };
+
+        match opcode(instruction)? {
+            opcodes::AND => ops.rrr().map(Inst::And),
+            opcodes::OR => ops.rrr().map(Inst::Or),
+            opcodes::TMUL => ops.rrr().map(Inst::Tmul),
+            opcodes::TCMP => ops.rrr().map(Inst::Tcmp),
+            opcodes::CMP => ops.rrr().map(Inst::Cmp),
+            opcodes::SHF => ops.rrr().map(Inst::Shf),
+            opcodes::ADD => ops.rrr().map(Inst::Add),
+            opcodes::MUL => ops.rr().map(Inst::Mul),
+            opcodes::DIV => ops.rr().map(Inst::Div),
+            opcodes::ANDI => ops.rri().map(Inst::Andi),
+            opcodes::ORI => ops.rri().map(Inst::Ori),
+            opcodes::TMULI => ops.rri().map(Inst::Tmuli),
+            opcodes::TCMPI => ops.rri().map(Inst::Tcmpi),
+            opcodes::SHFI => ops.rri().map(Inst::Shfi),
+            opcodes::ADDI => ops.rri().map(Inst::Addi),
+            opcodes::LUI => ops.ri().map(Inst::Lui),
+            opcodes::LT => ops.rro().map(Inst::Lt),
+            opcodes::LH => ops.rro().map(Inst::Lh),
+            opcodes::LW => ops.rro().map(Inst::Lw),
+            opcodes::ST => ops.rro().map(Inst::St),
+            opcodes::SH => ops.rro().map(Inst::Sh),
+            opcodes::SW => ops.rro().map(Inst::Sw),
+            opcodes::BT => ops.ro().map(Inst::BT),
+            opcodes::B0 => ops.ro().map(Inst::B0),
+            opcodes::B1 => ops.ro().map(Inst::B1),
+            opcodes::BT0 => ops.ro().map(Inst::BT0),
+            opcodes::BT1 => ops.ro().map(Inst::BT1),
+            opcodes::B01 => ops.ro().map(Inst::B01),
+            opcodes::BAL => ops.o().map(Inst::Bal),
+            opcodes::J => ops.a().map(Inst::J),
+            opcodes::JAL => ops.a().map(Inst::Jal),
+            opcodes::JR => ops.r().map(Inst::Jr),
+            opcodes::JALR => ops.r().map(Inst::Jalr),
+            opcodes::SYSCALL => ops.empty().map(Inst::Syscall),
+            opcodes::BREAK => ops.empty().map(Inst::Break),
+            _ => unreachable!(),
+        }
+    }
+
+    fn eval_arg(&self, arg: &Arg, width: usize) -> Result<i64> {
+        let Arg::Expr(expr) = arg else {
+            return Err(Error::new(arg.span(), ErrorKind::ExpectedExpression));
+        };
+
+        let value = self.eval(expr)?;
+        check_width(value, width, arg.span())
+    }
+
+    fn eval_early(&self, arg: &Arg) -> Result<i64> {
+        let Arg::Expr(expr) = arg else {
+            return Err(Error::new(arg.span(), ErrorKind::ExpectedExpression));
+        };
+
+        eval_known(expr, &self.symbols)
+    }
+
+    fn eval(&self, expr: &Expr) -> Result<i64> {
+        if self.relocatable {
+            return self.eval_constant(expr);
+        }
+
+        eval(expr, &self.symbols)
+    }
+}
+
+fn eval(expr: &Expr, symbols: &HashMap<String, i64>) -> Result<i64> {
+    match expr {
+        Expr::Integer(value, _) => Ok(*value),
+        Expr::Symbol(name, span) => symbols
+            .get(name)
+            .copied()
+            .ok_or_else(|| Error::new(*span, ErrorKind::UndefinedSymbol(name.clone()))),
+        Expr::Neg(expr, span) => eval(expr, symbols)?
+            .checked_neg()
+            .ok_or_else(|| Error::new(*span, ErrorKind::Overflow)),
+        Expr::Binary(op, lhs, rhs, span) => {
+            binary(*op, eval(lhs, symbols)?, eval(rhs, symbols)?, *span)
+        }
+        Expr::Part(part, expr, span) => {
+            let value = check_width(eval(expr, symbols)?, WORD_WIDTH, *span)?;
+            Ok(part.apply(value))
+        }
+    }
+}
+
+fn binary(op: BinaryOp, lhs: i64, rhs: i64, span: Span) -> Result<i64> {
+    if rhs == 0 && matches!(op, BinaryOp::Div | BinaryOp::Rem) {
+        return Err(Error::new(span, ErrorKind::DivideByZero));
+    }
+
+    let value = match op {
+        BinaryOp::Add => lhs.checked_add(rhs),
+        BinaryOp::Sub => lhs.checked_sub(rhs),
+        BinaryOp::Mul => lhs.checked_mul(rhs),
+        BinaryOp::Div => lhs.checked_div(rhs),
+        BinaryOp::Rem => lhs.checked_rem(rhs),
+    };
+
+    value.ok_or_else(|| Error::new(span, ErrorKind::Overflow))
+}
+
+// Values that affect layout or conditional assembly must be known when they
+// are reached, so they can only refer to symbols that are already defined.
+fn eval_known(expr: &Expr, symbols: &HashMap<String, i64>) -> Result<i64> {
+    eval(expr, symbols).map_err(|error| match error.kind {
+        ErrorKind::UndefinedSymbol(name) => Error {
+            kind: ErrorKind::UnknownValue(name),
+            ..error
+        },
+        _ => error,
+    })
+}
+
+#[derive(Default)]
+struct Output {
+    sections: [Vec<Segment>; 3],
+    records: Vec<Record>,
+}
+
+impl Output {
+    fn end(&self, section: Section) -> i64 {
+        self.sections[section.index()]
+            .last()
+            .map_or(i64::MIN, |segment| {
+                i64::from(segment.addr) + i64::try_from(segment.trytes.len()).unwrap()
+            })
+    }
+
+    fn push(&mut self, section: Section, addr: i64, trytes: &[Tryte], span: Span) -> Result<()> {
+        if trytes.is_empty() {
+            return Ok(());
+        }
+
+        let end = addr + i64::try_from(trytes.len()).unwrap();
+        if i32::try_from(end).is_err() {
+            return Err(Error::new(span, ErrorKind::AddressOutOfRange(end)));
+        }
+
+        let segments = &mut self.sections[section.index()];
+        match segments.last_mut() {
+            Some(segment)
+                if i64::from(segment.addr) + i64::try_from(segment.trytes.len()).unwrap()
+                    == addr =>
+            {
+                segment.trytes.extend_from_slice(trytes);
+            }
+            _ => segments.push(Segment {
+                addr: i32::try_from(addr)
+                    .map_err(|_| Error::new(span, ErrorKind::AddressOutOfRange(addr)))?,
+                trytes: trytes.to_vec(),
+            }),
+        }
+
+        Ok(())
+    }
+
+    fn segments(self) -> Vec<Segment> {
+        self.sections.into_iter().flatten().collect()
+    }
+
+    // Only meaningful for a relocatable section, which has no gaps.
+    fn trytes(&self, section: Section) -> Vec<Tryte> {
+        self.sections[section.index()]
+            .iter()
+            .flat_map(|segment| segment.trytes.iter().copied())
+            .collect()
+    }
+}
+
+fn check_width(value: i64, width: usize, span: Span) -> Result<i64> {
+    if !operands::fits_width(value, width) {
+        return Err(Error::new(
+            span,
+            ErrorKind::ImmediateOutOfRange(value, width),
+        ));
+    }
+
+    Ok(value)
+}
+
+fn opcode(instruction: &Instruction) -> Result<Opcode> {
+    Opcode::from_mnemonic(&instruction.mnemonic).ok_or_else(|| {
+        Error::new(
+            instruction.span,
+            ErrorKind::UnknownMnemonic(instruction.mnemonic.clone()),
+        )
+    })
+}
+
+// Branch and `bal` operands are written as absolute target addresses and
+// encoded relative to the pc of the following instruction.
+struct Operands<'a> {
+    assembler: &'a Assembler,
+    instruction: &'a Instruction,
+    place: Place,
+}
+
+impl Operands<'_> {
+    fn empty(&self) -> Result<operands::Empty> {
+        self.args::<0>()?;
+        Ok(operands::Empty)
+    }
+
+    fn r(&self) -> Result<operands::R> {
+        let [src] = self.args()?;
+        let src = register(src)?;
+        Ok(operands::R { src })
+    }
+
+    fn rr(&self) -> Result<operands::RR> {
+        let [lhs, rhs] = self.args()?;
+        let lhs = register(lhs)?;
+        let rhs = register(rhs)?;
+        Ok(operands::RR { lhs, rhs })
+    }
+
+    fn rrr(&self) -> Result<operands::RRR> {
+        let [dest, lhs, rhs] = self.args()?;
+        let dest = register(dest)?;
+        let lhs = register(lhs)?;
+        let rhs = register(rhs)?;
+        Ok(operands::RRR { dest, lhs, rhs })
+    }
+
+    fn ri(&self) -> Result<operands::RI> {
+        let [dest, immediate] = self.args()?;
+        let dest = register(dest)?;
+        let immediate = self.t12(immediate)?;
+        Ok(operands::RI { dest, immediate })
+    }
+
+    fn rri(&self) -> Result<operands::RRI> {
+        let [dest, src, immediate] = self.args()?;
+        let dest = register(dest)?;
+        let src = register(src)?;
+        let immediate = self.t12(immediate)?;
+        Ok(operands::RRI {
+            dest,
+            src,
+            immediate,
+        })
+    }
+
+    fn rro(&self) -> Result<operands::RRO> {
+        let [dest, src, offset] = self.args()?;
+        let dest = register(dest)?;
+        let src = register(src)?;
+        let offset = self.t12(offset)?;
+        Ok(operands::RRO { dest, src, offset })
+    }
+
+    fn ro(&self) -> Result<operands::RO> {
+        let [src, target] = self.args()?;
+        let src = register(src)?;
+        let offset = self.branch(target)?;
+        Ok(operands::RO { src, offset })
+    }
+
+    fn o(&self) -> Result<operands::O> {
+        let [target] = self.args()?;
+        let offset = self.branch(target)?;
+        Ok(operands::O { offset })
+    }
+
+    fn a(&self) -> Result<operands::A> {
+        let [addr] = self.args()?;
+        let addr = self.field(addr, RelocKind::Address, ADDR_WIDTH, 0)?;
+        Ok(operands::A {
+            addr: T24::try_from_int(addr).unwrap(),
+        })
+    }
+
+    fn branch(&self, target: &Arg) -> Result<T24> {
+        let next_pc = self.place.addr + WORD_SIZE;
+        let offset = self.field(target, RelocKind::Branch, OFFSET_WIDTH, -next_pc)?;
+        Ok(T24::try_from_int(offset).unwrap())
+    }
+
+    // Evaluates an operand that a relocation can fill in, recording one if
+    // the value isn't known until link time.
+    fn field(&self, arg: &Arg, kind: RelocKind, width: usize, bias: i64) -> Result<i64> {
+        if !self.assembler.relocatable {
+            return self.value(arg, width, bias);
+        }
+
+        self.assembler.relocate(arg, kind, self.place, width, bias)
+    }
+
+    fn args<const N: usize>(&self) -> Result<&[Arg; N]> {
+        let args = &self.instruction.args;
+        args.as_slice().try_into().map_err(|_| {
+            Error::new(
+                self.instruction.span,
+                ErrorKind::WrongOperandCount(N, args.len()),
+            )
+        })
+    }
+
+    fn value(&self, arg: &Arg, width: usize, bias: i64) -> Result<i64> {
+        let Arg::Expr(expr) = arg else {
+            return Err(Error::new(arg.span(), ErrorKind::ExpectedExpression));
+        };
+
+        let value = binary(BinaryOp::Add, self.assembler.eval(expr)?, bias, arg.span())?;
+        check_width(value, width, arg.span())
+    }
+
+    fn t12(&self, arg: &Arg) -> Result<T12> {
+        let value = if self.assembler.relocatable {
+            self.assembler.relocate_immediate(arg, self.place)?
+        } else {
+            self.value(arg, IMMEDIATE_WIDTH, 0)?
+        };
+
+        Ok(T12::try_from_int(value).unwrap())
+    }
+}
+
+fn register(arg: &Arg) -> Result<Register> {
+    match arg {
+        Arg::Register(register, _) => Ok(*register),
+        arg => Err(Error::new(arg.span(), ErrorKind::ExpectedRegister)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::registers;
+
+    pub(super) fn words(source: &str, origin: i32) -> Vec<T24> {
+        let image = assemble(source, origin).unwrap();
+        image.segments[0]
+            .trytes
+            .chunks(4)
+            .map(|trytes| T24::try_from(trytes).unwrap())
+            .collect()
+    }
+
+    fn assemble_one(source: &str) -> Inst {
+        let words = words(source, 0);
+        assert_eq!(1, words.len());
+        Inst::from_word(words[0]).unwrap()
+    }
+
+    fn error(source: &str) -> (usize, usize, ErrorKind) {
+        let error = assemble(source, 0).unwrap_err();
+        (error.line, error.column, error.kind)
+    }
+
+    #[test]
+    fn assemble_operand_shapes() {
+        assert_eq!(
+            Inst::Add(operands::RRR {
+                dest: registers::T0,
+                lhs: registers::T1,
+                rhs: registers::T2,
+            }),
+            assemble_one("add $t0, $t1, $t2")
+        );
+        assert_eq!(
+            Inst::Div(operands::RR {
+                lhs: registers::A0,
+                rhs: registers::S14,
+            }),
+            assemble_one("div $a0, $s14")
+        );
+        assert_eq!(
+            Inst::Addi(operands::RRI {
+                dest: registers::SP,
+                src: registers::SP,
+                immediate: T12::try_from_int(-12).unwrap(),
+            }),
+            assemble_one("addi $sp, $sp, -12")
+        );
+        assert_eq!(
+            Inst::Lui(operands::RI {
+                dest: registers::T0,
+                immediate: T12::try_from_int(265_720).unwrap(),
+            }),
+            assemble_one("lui $t0, 265720")
+        );
+        assert_eq!(
+            Inst::Lw(operands::RRO {
+                dest: registers::T0,
+                src: registers::FP,
+                offset: T12::try_from_int(8).unwrap(),
+            }),
+            assemble_one("lw $t0, $fp, 8")
+        );
+        assert_eq!(
+            Inst::Jr(operands::R { src: registers::RA }),
+            assemble_one("jr $ra")
+        );
+        assert_eq!(
+            Inst::J(operands::A {
+                addr: T24::try_from_int(-400).unwrap(),
+            }),
+            assemble_one("j -400")
+        );
+        assert_eq!(Inst::Syscall(operands::Empty), assemble_one("SYSCALL"));
+    }
+
+    #[test]
+    fn assemble_labels() {
+        let source = "
+            start:
+                addi $t0, $zero, 3
+            loop:
+                addi $t0, $t0, -1
+                b1 $t0, loop
+                bal end
+                jal start
+            end:
+                break
+        ";
+        let words = words(source, -8);
+        let insts: Vec<_> = words
+            .into_iter()
+            .map(|w| Inst::from_word(w).unwrap())
+            .collect();
+
+        assert_eq!(
+            Inst::B1(operands::RO {
+                src: registers::T0,
+                offset: T24::try_from_int(-8).unwrap(),
+            }),
+            insts[2]
+        );
+        assert_eq!(
+            Inst::Bal(operands::O {
+                offset: T24::try_from_int(4).unwrap(),
+            }),
+            insts[3]
+        );
+        assert_eq!(
+            Inst::Jal(operands::A {
+                addr: T24::try_from_int(-8).unwrap(),
+            }),
+            insts[4]
+        );
+    }
+
+    #[test]
+    fn assemble_word_directive() {
+        let words = words("start: .word 1, -1, start, end\nend:", 400);
+        let values: Vec<i64> = words.iter().map(|w| w.try_into_int().unwrap()).collect();
+        assert_eq!(vec![1, -1, 400, 416], values);
+    }
+
+    #[test]
+    fn assemble_expressions() {
+        let source = "
+            start:
+                .word end - start, (end - start) / 4, -7 % 3, 0t1T01 * 2, 0h10 - 1αT
+                .word start + 2 * 3 - 1
+            end:
+        ";
+        let values: Vec<i64> = words(source, 100)
+            .iter()
+            .map(|w| w.try_into_int().unwrap())
+            .collect();
+        assert_eq!(vec![24, 6, -1, 38, -702, 105], values);
+
+        assert_eq!(
+            Inst::Addi(operands::RRI {
+                dest: registers::T0,
+                src: registers::ZERO,
+                immediate: T12::try_from_int(-265_719).unwrap(),
+            }),
+            assemble_one("addi $t0, $zero, 1 - 0t111111111111")
+        );
+        assert_eq!(
+            Inst::J(operands::A {
+                addr: T24::try_from_int(1_162_261_467).unwrap(),
+            }),
+            assemble_one("j 3γT")
+        );
+
+        let values: Vec<i64> = words(".word %hi(-1), %lo(-1), %hi(265721), %lo(265721)", 0)
+            .iter()
+            .map(|w| w.try_into_int().unwrap())
+            .collect();
+        assert_eq!(vec![0, -1, 1, -265_720], values);
+    }
+
+    #[test]
+    fn assemble_image_symbols() {
+        let source = "
+            helper:
+                jr $ra
+            _start:
+                jal helper
+                syscall
+        ";
+
+        let image = assemble(source, -8).unwrap();
+        assert_eq!(-4, image.entry);
+        assert_eq!(1, image.segments.len());
+        assert_eq!(
+            (-8, 12),
+            (image.segments[0].addr, image.segments[0].trytes.len())
+        );
+
+        let symbols: Vec<_> = image
+            .symbols
+            .iter()
+            .map(|symbol| (symbol.name.as_str(), symbol.addr))
+            .collect();
+        assert_eq!(vec![("helper", -8), ("_start", -4)], symbols);
+
+        assert_eq!(-8, assemble("main: jr $ra", -8).unwrap().entry);
+    }
+
+    #[test]
+    fn assemble_errors() {
+        assert_eq!(
+            (1, 16, ErrorKind::ImmediateOutOfRange(265_721, 12)),
+            error("addi $t0, $t0, βT / 2 + 1")
+        );
+        assert_eq!(
+            (1, 9, ErrorKind::ImmediateOutOfRange(-21_523_361, 16)),
+            error("bT $t0, -(0t1111111111111111 + 1) + 4")
+        );
+        assert_eq!(
+            (1, 3, ErrorKind::ImmediateOutOfRange(1_937_102_445, 20)),
+            error("j 5γT")
+        );
+        assert_eq!(
+            (1, 9, ErrorKind::Overflow),
+            error("bT $t0, -9223372036854775807")
+        );
+        assert_eq!((1, 9, ErrorKind::DivideByZero), error(".word 1 / (2 - 2)"));
+        assert_eq!((1, 9, ErrorKind::DivideByZero), error(".word 1 % 0"));
+        assert_eq!((1, 11, ErrorKind::Overflow), error(".word 1δT * 1δT"));
+        assert_eq!(
+            (1, 7, ErrorKind::ImmediateOutOfRange(i64::MAX, 24)),
+            error(".word %hi(9223372036854775807)")
+        );
+        assert_eq!(
+            (1, 7, ErrorKind::ImmediateOutOfRange(i64::MIN, 24)),
+            error(".word -9223372036854775807 - 1")
+        );
+        assert_eq!(
+            (2, 1, ErrorKind::UnknownMnemonic("frob".into())),
+            error("nop:\nfrob $t0")
+        );
+        assert_eq!(
+            (1, 10, ErrorKind::UnknownRegister("x1".into())),
+            error("add $t0, $x1, $t2")
+        );
+        assert_eq!(
+            (1, 16, ErrorKind::ImmediateOutOfRange(265_721, 12)),
+            error("addi $t0, $t0, 265721")
+        );
+        assert_eq!(
+            (1, 9, ErrorKind::ImmediateOutOfRange(-21_523_365, 16)),
+            error("bT $t0, -21523361")
+        );
+        assert_eq!(
+            (1, 1, ErrorKind::WrongOperandCount(3, 2)),
+            error("add $t0, $t1")
+        );
+        assert_eq!(
+            (1, 10, ErrorKind::ExpectedRegister),
+            error("add $t0, 1, $t2")
+        );
+        assert_eq!(
+            (1, 3, ErrorKind::UndefinedSymbol("nowhere".into())),
+            error("j nowhere")
+        );
+        assert_eq!(
+            (1, 1, ErrorKind::UnknownDirective(".frob".into())),
+            error(".frob 1")
+        );
+        assert_eq!(
+            (2, 1, ErrorKind::DuplicateSymbol("a".into())),
+            error("a: syscall\na: syscall")
+        );
+    }
+}
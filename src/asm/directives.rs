@@ -0,0 +1,463 @@
+use ternary::{TInt, Tryte};
+
+use crate::asm::error::{Error, ErrorKind, Result};
+use crate::asm::lexer::Span;
+use crate::asm::parser::{Arg, Directive, Expr};
+use crate::asm::reloc::Place;
+use crate::asm::{Assembler, WORD_SIZE};
+use crate::object::{RelocKind, Section};
+
+// Only `.text` has a known address in the first pass, and not even that in a
+// relocatable object. `.data` and `.bss` follow it, so until an `.org` their
+// locations are offsets from a base that is only known once the whole program
+// has been laid out. Bases are aligned to a word, so an offset has the same
+// alignment as its final address.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Addr {
+    Absolute(i64),
+    Relative(i64),
+}
+
+impl Addr {
+    pub fn value(self) -> i64 {
+        match self {
+            Addr::Absolute(value) | Addr::Relative(value) => value,
+        }
+    }
+
+    fn offset(self, n: i64) -> Self {
+        match self {
+            Addr::Absolute(value) => Addr::Absolute(value + n),
+            Addr::Relative(value) => Addr::Relative(value + n),
+        }
+    }
+}
+
+pub struct Cursor {
+    pub section: Section,
+    addrs: [Addr; 3],
+}
+
+impl Cursor {
+    pub fn new(origin: Option<i64>) -> Self {
+        let text = origin.map_or(Addr::Relative(0), Addr::Absolute);
+        Cursor {
+            section: Section::Text,
+            addrs: [text, Addr::Relative(0), Addr::Relative(0)],
+        }
+    }
+
+    pub fn addr(&self) -> Addr {
+        self.addrs[self.section.index()]
+    }
+
+    pub fn end(&self, section: Section) -> Addr {
+        self.addrs[section.index()]
+    }
+
+    pub fn advance(&mut self, n: i64) {
+        let addr = &mut self.addrs[self.section.index()];
+        *addr = addr.offset(n);
+    }
+
+    pub fn check_alignment(&self, align: i64, span: Span) -> Result<()> {
+        if self.addr().value().rem_euclid(align) != 0 {
+            return Err(Error::new(span, ErrorKind::Misaligned(align)));
+        }
+
+        Ok(())
+    }
+
+    fn set(&mut self, addr: Addr) {
+        self.addrs[self.section.index()] = addr;
+    }
+}
+
+pub fn align_up(value: i64, align: i64) -> i64 {
+    value + (align - value.rem_euclid(align)) % align
+}
+
+impl Assembler {
+    // Returns the number of trytes the directive occupies, after applying
+    // any change it makes to the current section or location.
+    pub(super) fn layout_directive(
+        &mut self,
+        directive: &Directive,
+        cursor: &mut Cursor,
+    ) -> Result<i64> {
+        let name = directive.name.as_str();
+        if let Some(section) = section(name) {
+            args::<0>(directive)?;
+            cursor.section = section;
+            return Ok(0);
+        }
+
+        match name {
+            ".org" => {
+                if self.relocatable {
+                    return Err(Error::new(
+                        directive.span,
+                        ErrorKind::NotAllowedInObject(name.to_string()),
+                    ));
+                }
+
+                let [addr] = args(directive)?;
+                let addr = self.eval_early(addr)?;
+                if i32::try_from(addr).is_err() {
+                    return Err(Error::new(
+                        directive.args[0].span(),
+                        ErrorKind::AddressOutOfRange(addr),
+                    ));
+                }
+
+                if let Addr::Absolute(current) = cursor.addr()
+                    && addr < current
+                {
+                    return Err(Error::new(directive.span, ErrorKind::OrgBackwards(addr)));
+                }
+
+                cursor.set(Addr::Absolute(addr));
+                Ok(0)
+            }
+            ".global" | ".globl" => {
+                if directive.args.is_empty() {
+                    return Err(Error::new(directive.span, ErrorKind::ExpectedIdentifier));
+                }
+
+                for arg in &directive.args {
+                    let Arg::Expr(Expr::Symbol(name, span)) = arg else {
+                        return Err(Error::new(arg.span(), ErrorKind::ExpectedIdentifier));
+                    };
+
+                    if !self.globals.iter().any(|(global, _)| global == name) {
+                        self.globals.push((name.clone(), *span));
+                    }
+                }
+
+                Ok(0)
+            }
+            ".zero" => {
+                let [count] = args(directive)?;
+                let count = self.eval_early(count)?;
+                if count < 0 {
+                    return Err(Error::new(
+                        directive.args[0].span(),
+                        ErrorKind::InvalidCount(count),
+                    ));
+                }
+
+                // checked before the zeros are allocated in the second pass
+                let end = cursor.addr().value().saturating_add(count);
+                if i32::try_from(end).is_err() {
+                    return Err(Error::new(
+                        directive.args[0].span(),
+                        ErrorKind::AddressOutOfRange(end),
+                    ));
+                }
+
+                Ok(count)
+            }
+            ".align" => {
+                let [align] = args(directive)?;
+                let align = self.eval_early(align)?;
+                if ![1, 2, WORD_SIZE].contains(&align) {
+                    return Err(Error::new(
+                        directive.args[0].span(),
+                        ErrorKind::InvalidAlignment(align),
+                    ));
+                }
+
+                let value = cursor.addr().value();
+                Ok(align_up(value, align) - value)
+            }
+            ".tryte" | ".half" | ".word" | ".string" | ".asciz" => {
+                if cursor.section == Section::Bss {
+                    return Err(Error::new(
+                        directive.span,
+                        ErrorKind::NotAllowedInBss(name.to_string()),
+                    ));
+                }
+
+                if let Some((size, _)) = value_size(name) {
+                    cursor.check_alignment(size, directive.span)?;
+                    return Ok(size * i64::try_from(directive.args.len()).unwrap());
+                }
+
+                let mut size = 0;
+                for arg in &directive.args {
+                    let Arg::String(s, _) = arg else {
+                        return Err(Error::new(arg.span(), ErrorKind::ExpectedString));
+                    };
+
+                    size += i64::try_from(s.chars().count()).unwrap() + 1;
+                }
+
+                Ok(size)
+            }
+            _ => Err(Error::new(
+                directive.span,
+                ErrorKind::UnknownDirective(name.to_string()),
+            )),
+        }
+    }
+
+    pub(super) fn directive_trytes(
+        &self,
+        directive: &Directive,
+        place: Place,
+        size: i64,
+    ) -> Result<Vec<Tryte>> {
+        let name = directive.name.as_str();
+        match name {
+            ".zero" | ".align" => Ok(vec![Tryte::ZERO; usize::try_from(size).unwrap()]),
+            ".tryte" | ".half" | ".word" => {
+                let (size, width) = value_size(name).unwrap();
+                let mut trytes = Vec::new();
+                for (i, arg) in (0..).zip(&directive.args) {
+                    let value = if self.relocatable && name == ".word" {
+                        let place = Place {
+                            addr: place.addr + i * size,
+                            ..place
+                        };
+                        self.relocate(arg, RelocKind::Word, place, width, 0)?
+                    } else {
+                        self.eval_arg(arg, width)?
+                    };
+
+                    trytes.extend(value_trytes(value, size));
+                }
+
+                Ok(trytes)
+            }
+            ".string" | ".asciz" => {
+                let mut trytes = Vec::new();
+                for arg in &directive.args {
+                    if let Arg::String(s, span) = arg {
+                        trytes.extend(string_trytes(s, *span)?);
+                    }
+                }
+
+                Ok(trytes)
+            }
+            _ => Ok(Vec::new()),
+        }
+    }
+}
+
+fn section(name: &str) -> Option<Section> {
+    match name {
+        ".text" => Some(Section::Text),
+        ".data" => Some(Section::Data),
+        ".bss" => Some(Section::Bss),
+        _ => None,
+    }
+}
+
+fn value_size(name: &str) -> Option<(i64, usize)> {
+    match name {
+        ".tryte" => Some((1, 6)),
+        ".half" => Some((2, 12)),
+        ".word" => Some((4, 24)),
+        _ => None,
+    }
+}
+
+fn value_trytes(value: i64, size: i64) -> Vec<Tryte> {
+    match size {
+        1 => TInt::<1>::try_from_int(value)
+            .unwrap()
+            .into_trytes()
+            .to_vec(),
+        2 => TInt::<2>::try_from_int(value)
+            .unwrap()
+            .into_trytes()
+            .to_vec(),
+        _ => TInt::<4>::try_from_int(value)
+            .unwrap()
+            .into_trytes()
+            .to_vec(),
+    }
+}
+
+fn string_trytes(s: &str, span: Span) -> Result<Vec<Tryte>> {
+    s.chars()
+        .chain(['\0'])
+        .map(|c| {
+            TInt::<1>::try_from_int(u32::from(c))
+                .map(|tryte| tryte.into_trytes()[0])
+                .map_err(|_| Error::new(span, ErrorKind::InvalidCharacter(c)))
+        })
+        .collect()
+}
+
+fn args<const N: usize>(directive: &Directive) -> Result<&[Arg; N]> {
+    directive.args.as_slice().try_into().map_err(|_| {
+        Error::new(
+            directive.span,
+            ErrorKind::WrongOperandCount(N, directive.args.len()),
+        )
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::asm::assemble;
+    use crate::image::Image;
+
+    fn layout(image: &Image) -> Vec<(i32, usize)> {
+        image
+            .segments
+            .iter()
+            .map(|segment| (segment.addr, segment.trytes.len()))
+            .collect()
+    }
+
+    fn values(trytes: &[Tryte]) -> Vec<i64> {
+        trytes
+            .iter()
+            .map(|&tryte| {
+                TInt::<1>::try_from(&[tryte][..])
+                    .unwrap()
+                    .try_into_int()
+                    .unwrap()
+            })
+            .collect()
+    }
+
+    fn error(source: &str) -> (usize, usize, ErrorKind) {
+        let error = assemble(source, 0).unwrap_err();
+        (error.line, error.column, error.kind)
+    }
+
+    #[test]
+    fn assemble_sections() {
+        let source = "
+            .data
+            msg: .asciz \"hi\"
+            .text
+            _start:
+                la $a1, msg
+                syscall
+            .bss
+            buf: .zero 8
+            .data
+                .align 4
+            n:  .word 5
+        ";
+
+        let image = assemble(source, 0).unwrap();
+        assert_eq!(vec![(0, 12), (12, 8), (20, 8)], layout(&image));
+        assert_eq!(vec![104, 105, 0, 0], values(&image.segments[1].trytes[..4]));
+        assert_eq!(vec![0; 8], values(&image.segments[2].trytes));
+
+        let symbols: Vec<_> = image
+            .symbols
+            .iter()
+            .map(|symbol| (symbol.name.as_str(), symbol.addr))
+            .collect();
+        assert_eq!(
+            vec![("_start", 0), ("msg", 12), ("n", 16), ("buf", 20)],
+            symbols
+        );
+    }
+
+    #[test]
+    fn assemble_data_directives() {
+        let source = "
+            .tryte -364, 65
+            .half 1000
+            .word -1
+            .string \"a\\n\"
+            .zero 2
+            .align 2
+            .half -1
+        ";
+
+        let image = assemble(source, 0).unwrap();
+        assert_eq!(vec![(0, 16)], layout(&image));
+
+        let trytes = &image.segments[0].trytes;
+        assert_eq!(vec![-364, 65], values(&trytes[..2]));
+        assert_eq!(
+            1000,
+            TInt::<2>::try_from(&trytes[2..4])
+                .unwrap()
+                .try_into_int::<i64>()
+                .unwrap()
+        );
+        assert_eq!(
+            -1,
+            TInt::<4>::try_from(&trytes[4..8])
+                .unwrap()
+                .try_into_int::<i64>()
+                .unwrap()
+        );
+        assert_eq!(vec![97, 10, 0, 0, 0, 0], values(&trytes[8..14]));
+    }
+
+    #[test]
+    fn assemble_org() {
+        let source = "
+            .org 8
+                nop
+            .org 20
+            x:  nop
+            .data
+            y:  .tryte 1
+        ";
+
+        let image = assemble(source, 0).unwrap();
+        assert_eq!(vec![(8, 4), (20, 4), (24, 1)], layout(&image));
+        assert_eq!(Some(20), image.symbol("x").map(|symbol| symbol.addr));
+        assert_eq!(Some(24), image.symbol("y").map(|symbol| symbol.addr));
+    }
+
+    #[test]
+    fn directive_errors() {
+        assert_eq!((2, 1, ErrorKind::Misaligned(4)), error(".tryte 1\n.word 2"));
+        assert_eq!((2, 1, ErrorKind::Misaligned(2)), error(".tryte 1\n.half 2"));
+        assert_eq!((2, 1, ErrorKind::Misaligned(4)), error(".zero 2\nnop"));
+        assert_eq!(
+            (2, 1, ErrorKind::NotAllowedInBss(".word".into())),
+            error(".bss\n.word 1")
+        );
+        assert_eq!(
+            (2, 1, ErrorKind::NotAllowedInBss("nop".into())),
+            error(".bss\nnop")
+        );
+        assert_eq!((1, 8, ErrorKind::InvalidAlignment(3)), error(".align 3"));
+        assert_eq!((1, 7, ErrorKind::InvalidCount(-1)), error(".zero -1"));
+        assert_eq!(
+            (3, 7, ErrorKind::AddressOutOfRange(141_214_768_248)),
+            error("nop\nnop\n.zero 141214768240")
+        );
+        assert_eq!(
+            (1, 7, ErrorKind::AddressOutOfRange(i64::MAX)),
+            error(".zero 9223372036854775807")
+        );
+        assert_eq!(
+            (1, 6, ErrorKind::AddressOutOfRange(2_147_483_648)),
+            error(".org 2147483648")
+        );
+        assert_eq!((2, 1, ErrorKind::OrgBackwards(4)), error(".org 8\n.org 4"));
+        assert_eq!(
+            (4, 1, ErrorKind::OrgBackwards(0)),
+            error("nop\n.data\n.word 1\n.org 0")
+        );
+        assert_eq!(
+            (1, 6, ErrorKind::UnknownValue("later".into())),
+            error(".org later\nlater:")
+        );
+        assert_eq!((1, 8, ErrorKind::ExpectedString), error(".asciz 5"));
+        assert_eq!(
+            (1, 8, ErrorKind::ImmediateOutOfRange(365, 6)),
+            error(".tryte 365")
+        );
+        assert_eq!(
+            (1, 8, ErrorKind::InvalidCharacter('☃')),
+            error(".asciz \"☃\"")
+        );
+        assert_eq!((1, 1, ErrorKind::WrongOperandCount(0, 1)), error(".text 1"));
+    }
+}
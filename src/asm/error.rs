@@ -0,0 +1,176 @@
+use std::fmt;
+
+use crate::asm::lexer::Span;
+
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum ErrorKind {
+    UnexpectedCharacter(char),
+    UnexpectedToken(String),
+    InvalidInteger(String),
+    UnterminatedString,
+    InvalidEscape(char),
+    UnknownMnemonic(String),
+    UnknownDirective(String),
+    UnknownRegister(String),
+    ExpectedRegister,
+    ExpectedExpression,
+    UnknownOperator(String),
+    ExpressionTooDeep,
+    WrongOperandCount(usize, usize),
+    UndefinedSymbol(String),
+    DuplicateSymbol(String),
+    ImmediateOutOfRange(i64, usize),
+    Overflow,
+    DivideByZero,
+    UnknownValue(String),
+    ExpectedString,
+    InvalidCharacter(char),
+    InvalidCount(i64),
+    InvalidAlignment(i64),
+    Misaligned(i64),
+    OrgBackwards(i64),
+    NotAllowedInBss(String),
+    AddressOutOfRange(i64),
+    NotRelocatable,
+    NotAllowedInObject(String),
+    ExpectedIdentifier,
+    UnknownParameter(String),
+    DuplicateMacro(String),
+    UnterminatedMacro(String),
+    MacroRecursion(String),
+    UnterminatedConditional,
+    UnexpectedDirective(String),
+    IncludeCycle(String),
+    Include(String, String),
+    Encoding(String),
+}
+
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Error {
+    pub line: usize,
+    pub column: usize,
+    pub kind: ErrorKind,
+    pub file: Option<String>,
+    pub expansions: Vec<Expansion>,
+    pub(super) source: usize,
+}
+
+// The call site of a macro that the error occurred in, innermost first.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Expansion {
+    pub name: String,
+    pub file: Option<String>,
+    pub line: usize,
+    pub column: usize,
+}
+
+pub type Result<T> = std::result::Result<T, Error>;
+
+impl Error {
+    pub fn new(span: Span, kind: ErrorKind) -> Self {
+        Error {
+            line: span.line,
+            column: span.column,
+            kind,
+            file: None,
+            expansions: Vec::new(),
+            source: span.source,
+        }
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write_location(f, self.file.as_deref(), self.line, self.column)?;
+        write!(f, "{}", self.kind)?;
+        for expansion in &self.expansions {
+            writeln!(f)?;
+            write_location(
+                f,
+                expansion.file.as_deref(),
+                expansion.line,
+                expansion.column,
+            )?;
+            write!(f, "note: in expansion of macro `{}`", expansion.name)?;
+        }
+
+        Ok(())
+    }
+}
+
+fn write_location(
+    f: &mut fmt::Formatter<'_>,
+    file: Option<&str>,
+    line: usize,
+    column: usize,
+) -> fmt::Result {
+    if let Some(file) = file {
+        write!(f, "{file}:")?;
+    }
+
+    write!(f, "{line}:{column}: ")
+}
+
+impl fmt::Display for ErrorKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ErrorKind::UnexpectedCharacter(c) => write!(f, "unexpected character {c:?}"),
+            ErrorKind::UnexpectedToken(token) => write!(f, "unexpected {token}"),
+            ErrorKind::InvalidInteger(s) => write!(f, "invalid integer literal `{s}`"),
+            ErrorKind::UnterminatedString => write!(f, "unterminated string literal"),
+            ErrorKind::InvalidEscape(c) => write!(f, "invalid escape sequence `\\{c}`"),
+            ErrorKind::UnknownMnemonic(name) => write!(f, "unknown mnemonic `{name}`"),
+            ErrorKind::UnknownDirective(name) => write!(f, "unknown directive `{name}`"),
+            ErrorKind::UnknownRegister(name) => write!(f, "unknown register `${name}`"),
+            ErrorKind::ExpectedRegister => write!(f, "expected register"),
+            ErrorKind::ExpectedExpression => write!(f, "expected expression"),
+            ErrorKind::UnknownOperator(name) => write!(f, "unknown operator `%{name}`"),
+            ErrorKind::ExpressionTooDeep => write!(f, "expression is nested too deeply"),
+            ErrorKind::WrongOperandCount(expected, found) => {
+                write!(f, "expected {expected} operand(s), found {found}")
+            }
+            ErrorKind::UndefinedSymbol(name) => write!(f, "undefined symbol `{name}`"),
+            ErrorKind::DuplicateSymbol(name) => write!(f, "symbol `{name}` is already defined"),
+            ErrorKind::ImmediateOutOfRange(value, width) => {
+                write!(f, "value {value} does not fit in {width} trits")
+            }
+            ErrorKind::Overflow => write!(f, "arithmetic overflow"),
+            ErrorKind::DivideByZero => write!(f, "division by zero"),
+            ErrorKind::UnknownValue(name) => {
+                write!(f, "value of `{name}` is not known at this point")
+            }
+            ErrorKind::ExpectedString => write!(f, "expected string"),
+            ErrorKind::InvalidCharacter(c) => write!(f, "character {c:?} does not fit in a tryte"),
+            ErrorKind::InvalidCount(count) => write!(f, "invalid count {count}"),
+            ErrorKind::InvalidAlignment(align) => {
+                write!(f, "invalid alignment {align}; expected 1, 2 or 4")
+            }
+            ErrorKind::Misaligned(align) => write!(f, "address is not aligned to {align} trytes"),
+            ErrorKind::OrgBackwards(addr) => {
+                write!(f, "`.org` cannot move back to address {addr}")
+            }
+            ErrorKind::NotAllowedInBss(name) => write!(f, "`{name}` is not allowed in `.bss`"),
+            ErrorKind::AddressOutOfRange(addr) => write!(f, "address {addr} is out of range"),
+            ErrorKind::NotRelocatable => write!(f, "value cannot be relocated"),
+            ErrorKind::NotAllowedInObject(name) => {
+                write!(f, "`{name}` is not allowed in a relocatable object")
+            }
+            ErrorKind::ExpectedIdentifier => write!(f, "expected identifier"),
+            ErrorKind::UnknownParameter(name) => write!(f, "unknown macro parameter `\\{name}`"),
+            ErrorKind::DuplicateMacro(name) => write!(f, "macro `{name}` is already defined"),
+            ErrorKind::UnterminatedMacro(name) => {
+                write!(f, "macro `{name}` has no matching `.endm`")
+            }
+            ErrorKind::MacroRecursion(name) => {
+                write!(f, "macro `{name}` is expanded too deeply")
+            }
+            ErrorKind::UnterminatedConditional => write!(f, "conditional has no matching `.endif`"),
+            ErrorKind::UnexpectedDirective(name) => write!(f, "unexpected `{name}`"),
+            ErrorKind::IncludeCycle(path) => write!(f, "`{path}` includes itself"),
+            ErrorKind::Include(path, message) => write!(f, "cannot include `{path}`: {message}"),
+            ErrorKind::Encoding(message) => write!(f, "{message}"),
+        }
+    }
+}
+
+impl std::error::Error for Error {}
@@ -0,0 +1,465 @@
+use std::fmt::Write as _;
+use std::path::Path;
+
+use crate::asm::error::{Error, Result};
+use crate::asm::lexer::{self, Lexeme, Token};
+use crate::asm::{PSEUDO_INSTRUCTIONS, assemble_file, assemble_object_file};
+use crate::debug::LineTable;
+use crate::numerals::{balanced_ternary, heptavintimal};
+use crate::opcodes::Opcode;
+use crate::registers::Register;
+
+const INDENT: usize = 4;
+
+// How integer literals are written.
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub enum Literals {
+    #[default]
+    Decimal,
+    Ternary,
+    Heptavintimal,
+}
+
+impl Literals {
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "decimal" => Some(Literals::Decimal),
+            "ternary" => Some(Literals::Ternary),
+            "heptavintimal" => Some(Literals::Heptavintimal),
+            _ => None,
+        }
+    }
+
+    fn write(self, out: &mut String, value: i64) {
+        match self {
+            Literals::Decimal => write!(out, "{value}"),
+            Literals::Ternary => write!(out, "0t{}", balanced_ternary(value)),
+            Literals::Heptavintimal => {
+                let digits = heptavintimal(value);
+                match digits.strip_prefix('-') {
+                    Some(digits) => write!(out, "-0h{digits}"),
+                    None => write!(out, "0h{digits}"),
+                }
+            }
+        }
+        .unwrap();
+    }
+}
+
+// One statement, as in `label: mnemonic operands`.
+#[derive(Default)]
+struct Statement {
+    labels: String,
+    mnemonic: String,
+    operands: String,
+}
+
+// The code on a line, and the comment after it.
+struct Line {
+    statements: Vec<Statement>,
+    comment: Option<String>,
+    indented: bool,
+}
+
+// Reprints `source` in canonical form. Every line stays where it is, so line
+// numbers in diagnostics and line tables don't change. Statements in a run of
+// lines with no blank or comment-only lines between them start in the same
+// column, as do their operands and trailing comments.
+pub fn format(source: &str, literals: Literals) -> Result<String> {
+    let mut lines = Vec::new();
+    for (index, text) in source.split('\n').enumerate() {
+        let text = text.strip_suffix('\r').unwrap_or(text);
+        let (code, comment) = split_comment(text);
+        let lexemes = lexer::tokenize(code, 0).map_err(|error| Error {
+            line: index + 1,
+            ..error
+        })?;
+
+        lines.push(Line {
+            statements: statements(&lexemes, literals),
+            comment: comment.map(|comment| comment.trim_end().to_string()),
+            indented: text.starts_with(char::is_whitespace),
+        });
+    }
+
+    while lines
+        .last()
+        .is_some_and(|line| line.statements.is_empty() && line.comment.is_none())
+    {
+        lines.pop();
+    }
+
+    let mut out = String::new();
+    for block in lines.chunk_by(|a, b| !a.statements.is_empty() && !b.statements.is_empty()) {
+        write_block(&mut out, block);
+    }
+
+    Ok(out)
+}
+
+// Whether `formatted` assembles to the same image or object as `source`, apart
+// from the columns in its line table. Sources that don't assemble on their
+// own have nothing to compare.
+pub fn same_output(path: &Path, source: &str, formatted: &str) -> bool {
+    if let Ok(mut image) = assemble_file(path, source, 0) {
+        let Ok(mut formatted) = assemble_file(path, formatted, 0) else {
+            return false;
+        };
+
+        clear_columns(&mut image.lines);
+        clear_columns(&mut formatted.lines);
+        return image == formatted;
+    }
+
+    if let Ok(mut object) = assemble_object_file(path, source) {
+        let Ok(mut formatted) = assemble_object_file(path, formatted) else {
+            return false;
+        };
+
+        clear_columns(&mut object.lines);
+        clear_columns(&mut formatted.lines);
+        return object == formatted;
+    }
+
+    true
+}
+
+fn clear_columns(table: &mut LineTable) {
+    for line in &mut table.lines {
+        line.column = 0;
+    }
+}
+
+fn write_block(out: &mut String, block: &[Line]) {
+    let first = || block.iter().filter_map(|line| line.statements.first());
+    let column = first()
+        .filter(|statement| !statement.labels.is_empty() && !statement.mnemonic.is_empty())
+        .map(|statement| statement.labels.len() + 1)
+        .fold(INDENT, usize::max);
+    // directives only set the width in blocks without instructions
+    let width = |directives: bool| {
+        first()
+            .filter(|statement| !statement.operands.is_empty())
+            .filter(|statement| directives || !statement.mnemonic.starts_with('.'))
+            .map(|statement| statement.mnemonic.len())
+            .max()
+    };
+    let width = width(false).or_else(|| width(true)).unwrap_or(0);
+
+    let codes: Vec<String> = block.iter().map(|line| code(line, column, width)).collect();
+    let comment_column = block
+        .iter()
+        .zip(&codes)
+        .filter(|(line, code)| line.comment.is_some() && !code.is_empty())
+        .map(|(_, code)| code.len() + 1)
+        .max()
+        .unwrap_or(0);
+
+    for (line, code) in block.iter().zip(codes) {
+        match &line.comment {
+            Some(comment) if code.is_empty() => {
+                let indent = if line.indented { INDENT } else { 0 };
+                writeln!(out, "{:indent$}{comment}", "").unwrap();
+            }
+            Some(comment) => writeln!(out, "{code:comment_column$}{comment}").unwrap(),
+            None => writeln!(out, "{code}").unwrap(),
+        }
+    }
+}
+
+// The first statement on a line starts at `column`, with its operands after a
+// mnemonic padded to `width`. Any others follow it as they are.
+fn code(line: &Line, column: usize, width: usize) -> String {
+    let mut out = String::new();
+    for (index, statement) in line.statements.iter().enumerate() {
+        if index > 0 {
+            out.push_str("; ");
+            out.push_str(&statement.labels);
+            if !statement.labels.is_empty() && !statement.mnemonic.is_empty() {
+                out.push(' ');
+            }
+            out.push_str(&statement.mnemonic);
+            if !statement.operands.is_empty() {
+                write!(out, " {}", statement.operands).unwrap();
+            }
+            continue;
+        }
+
+        if statement.mnemonic.is_empty() {
+            out.push_str(&statement.labels);
+            continue;
+        }
+
+        write!(out, "{:column$}", statement.labels).unwrap();
+        if statement.operands.is_empty() {
+            out.push_str(&statement.mnemonic);
+        } else {
+            write!(out, "{:width$} {}", statement.mnemonic, statement.operands).unwrap();
+        }
+    }
+
+    out
+}
+
+fn statements(lexemes: &[Lexeme], literals: Literals) -> Vec<Statement> {
+    lexemes
+        .split(|lexeme| matches!(lexeme.token, Token::Newline | Token::Eof))
+        .filter(|tokens| !tokens.is_empty())
+        .map(|mut tokens| {
+            let mut statement = Statement::default();
+            while let [
+                Lexeme {
+                    token: Token::Ident(name),
+                    ..
+                },
+                Lexeme {
+                    token: Token::Colon,
+                    ..
+                },
+                rest @ ..,
+            ] = tokens
+            {
+                if !statement.labels.is_empty() {
+                    statement.labels.push(' ');
+                }
+                write!(statement.labels, "{name}:").unwrap();
+                tokens = rest;
+            }
+
+            match tokens {
+                [
+                    Lexeme {
+                        token: Token::Ident(name),
+                        ..
+                    },
+                    rest @ ..,
+                ] => {
+                    statement.mnemonic = mnemonic(name);
+                    statement.operands = operands(rest, literals);
+                }
+                // not a statement the assembler accepts, but kept as it is
+                _ => statement.mnemonic = operands(tokens, literals),
+            }
+
+            statement
+        })
+        .collect()
+}
+
+// Instructions are written in the case of `instructions.md`. Directives and
+// macros are left alone, since their names are case-sensitive.
+fn mnemonic(name: &str) -> String {
+    if let Some(opcode) = Opcode::from_mnemonic(name) {
+        return opcode.mnemonic().to_string();
+    }
+
+    let lower = name.to_ascii_lowercase();
+    if PSEUDO_INSTRUCTIONS
+        .iter()
+        .any(|(pseudo, _)| *pseudo == lower)
+    {
+        return lower;
+    }
+
+    name.to_string()
+}
+
+// Operands are separated by `, ` and binary operators by spaces, with unary
+// operators, `%hi`/`%lo` and parentheses written against what they apply to.
+fn operands(tokens: &[Lexeme], literals: Literals) -> String {
+    let mut out = String::new();
+    let mut previous: Option<&Token> = None;
+    let mut after_binary = false;
+    for lexeme in tokens {
+        let token = &lexeme.token;
+        let binary = is_operator(token) && previous.is_some_and(ends_operand);
+        let space = match (previous, token) {
+            (None, _) | (_, Token::Comma | Token::Colon) => false,
+            (Some(Token::Comma | Token::Colon), _) => true,
+            _ if binary || after_binary => true,
+            (Some(Token::Ident(_)), Token::LParen) => false,
+            (Some(previous), _) => ends_operand(previous) && starts_operand(token),
+        };
+        if space {
+            out.push(' ');
+        }
+
+        match token {
+            Token::Ident(name) => out.push_str(name),
+            Token::Register(name) => match Register::from_name(name) {
+                Some(register) => write!(out, "{register}").unwrap(),
+                None => write!(out, "${name}").unwrap(),
+            },
+            Token::Param(name) => write!(out, "\\{name}").unwrap(),
+            Token::Integer(value) => literals.write(&mut out, *value),
+            Token::String(value) => write_string(&mut out, value),
+            Token::Comma => out.push(','),
+            Token::Colon => out.push(':'),
+            Token::Plus => out.push('+'),
+            Token::Minus => out.push('-'),
+            Token::Star => out.push('*'),
+            Token::Slash => out.push('/'),
+            Token::Percent => out.push('%'),
+            Token::LParen => out.push('('),
+            Token::RParen => out.push(')'),
+            Token::Newline | Token::Eof => {}
+        }
+
+        previous = Some(token);
+        after_binary = binary;
+    }
+
+    out
+}
+
+fn is_operator(token: &Token) -> bool {
+    matches!(
+        token,
+        Token::Plus | Token::Minus | Token::Star | Token::Slash | Token::Percent
+    )
+}
+
+fn ends_operand(token: &Token) -> bool {
+    matches!(
+        token,
+        Token::Ident(_)
+            | Token::Register(_)
+            | Token::Param(_)
+            | Token::Integer(_)
+            | Token::String(_)
+            | Token::RParen
+    )
+}
+
+fn starts_operand(token: &Token) -> bool {
+    matches!(
+        token,
+        Token::Ident(_)
+            | Token::Register(_)
+            | Token::Param(_)
+            | Token::Integer(_)
+            | Token::String(_)
+            | Token::LParen
+    )
+}
+
+// Strings are written with the escapes that `.string` supports, and any other
+// character as it is.
+fn write_string(out: &mut String, value: &str) {
+    out.push('"');
+    for c in value.chars() {
+        match c {
+            '\n' => out.push_str("\\n"),
+            '\t' => out.push_str("\\t"),
+            '\r' => out.push_str("\\r"),
+            '\0' => out.push_str("\\0"),
+            '\\' | '"' => write!(out, "\\{c}").unwrap(),
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+}
+
+// Splits a line at the `#` that starts its comment, if it has one outside a
+// string.
+fn split_comment(text: &str) -> (&str, Option<&str>) {
+    let mut in_string = false;
+    let mut escaped = false;
+    for (index, c) in text.char_indices() {
+        match c {
+            _ if escaped => escaped = false,
+            '\\' if in_string => escaped = true,
+            '"' => in_string = !in_string,
+            '#' if !in_string => return (&text[..index], Some(&text[index..])),
+            _ => {}
+        }
+    }
+
+    (text, None)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SOURCE: &str = "\
+# Counts down from 10.
+.equ COUNT,0h3+7   # start
+.macro  DEC reg
+  ADDI \\reg,\\reg,- 1
+.endm
+main:   li $t0,COUNT
+loop: DEC $t0 # again
+  bT0 $t0 , loop
+        LA $t1 , msg;nop
+  lui $t2,%hi ( msg+4*( 2-1 ) )   # upper
+        addi $t2, $t2, %lo(msg + 4)
+
+.data
+msg: .string \"a#\\\"b\\n\", \"c\"
+    # the end
+.word 0tT1, -αT, 12_000\r
+\n\n";
+
+    #[test]
+    fn format_source() {
+        let expected = "\
+# Counts down from 10.
+      .equ COUNT, 3 + 7                # start
+      .macro DEC reg
+      addi \\reg, \\reg, -1
+      .endm
+main: li   $t0, COUNT
+loop: DEC  $t0                         # again
+      bT0  $t0, loop
+      la   $t1, msg; nop
+      lui  $t2, %hi(msg + 4 * (2 - 1)) # upper
+      addi $t2, $t2, %lo(msg + 4)
+
+     .data
+msg: .string \"a#\\\"b\\n\", \"c\"
+    # the end
+    .word -2, -729, 12000
+";
+        assert!(assemble_file(Path::new("a.s"), SOURCE, 0).is_ok());
+        let formatted = format(SOURCE, Literals::Decimal).unwrap();
+        assert_eq!(expected, formatted);
+        assert_eq!(formatted, format(&formatted, Literals::Decimal).unwrap());
+        assert!(same_output(Path::new("a.s"), SOURCE, &formatted));
+    }
+
+    #[test]
+    fn format_literals() {
+        for literals in [Literals::Ternary, Literals::Heptavintimal] {
+            let formatted = format(SOURCE, literals).unwrap();
+            assert_eq!(formatted, format(&formatted, literals).unwrap());
+            assert!(same_output(Path::new("a.s"), SOURCE, &formatted));
+        }
+
+        let format = |source, literals| format(source, literals).unwrap();
+        assert_eq!(
+            "    .word 0t10, -0t1T, -0t1TT, 0t0\n",
+            format(".word 3, -2, -5, 0", Literals::Ternary)
+        );
+        assert_eq!(
+            "    .word 0h10, -0h1, 0h5, 0h0\n",
+            format(".word 27, -1, 0t1TT, 0", Literals::Heptavintimal)
+        );
+    }
+
+    #[test]
+    fn compare_output() {
+        let path = Path::new("a.s");
+        assert!(same_output(path, "nop\n", "    nop\n"));
+        assert!(!same_output(path, "nop\n", "break\n"));
+        assert!(!same_output(path, "nop\n", "nop\nnop\n"));
+        assert!(same_output(path, "j elsewhere\n", "    j elsewhere\n"));
+        assert!(!same_output(path, "j elsewhere\n", "jal elsewhere\n"));
+    }
+
+    #[test]
+    fn reject_invalid_tokens() {
+        let error = format("nop\n  li $t0, 0h\n", Literals::Decimal).unwrap_err();
+        assert_eq!((2, 11), (error.line, error.column));
+    }
+}
@@ -0,0 +1,407 @@
+use std::fmt;
+use std::iter::Peekable;
+use std::str::Chars;
+
+use crate::asm::error::{Error, ErrorKind, Result};
+use crate::numerals::HEPTAVINTIMAL_DIGITS;
+
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub struct Span {
+    pub line: usize,
+    pub column: usize,
+    pub source: usize,
+}
+
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum Token {
+    Ident(String),
+    Register(String),
+    Param(String),
+    Integer(i64),
+    String(String),
+    Comma,
+    Colon,
+    Plus,
+    Minus,
+    Star,
+    Slash,
+    Percent,
+    LParen,
+    RParen,
+    Newline,
+    Eof,
+}
+
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Lexeme {
+    pub token: Token,
+    pub span: Span,
+}
+
+impl fmt::Display for Token {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Token::Ident(name) => write!(f, "identifier `{name}`"),
+            Token::Register(name) => write!(f, "register `${name}`"),
+            Token::Param(name) => write!(f, "parameter `\\{name}`"),
+            Token::Integer(value) => write!(f, "integer `{value}`"),
+            Token::String(value) => write!(f, "string {value:?}"),
+            Token::Comma => write!(f, "`,`"),
+            Token::Colon => write!(f, "`:`"),
+            Token::Plus => write!(f, "`+`"),
+            Token::Minus => write!(f, "`-`"),
+            Token::Star => write!(f, "`*`"),
+            Token::Slash => write!(f, "`/`"),
+            Token::Percent => write!(f, "`%`"),
+            Token::LParen => write!(f, "`(`"),
+            Token::RParen => write!(f, "`)`"),
+            Token::Newline => write!(f, "end of line"),
+            Token::Eof => write!(f, "end of input"),
+        }
+    }
+}
+
+// `id` identifies the source in spans, so that diagnostics can name the
+// file or macro expansion a token came from.
+pub fn tokenize(source: &str, id: usize) -> Result<Vec<Lexeme>> {
+    let mut lexer = Lexer {
+        chars: source.chars().peekable(),
+        line: 1,
+        column: 1,
+        source: id,
+    };
+
+    let mut lexemes = Vec::new();
+    loop {
+        let lexeme = lexer.next_lexeme()?;
+        let done = lexeme.token == Token::Eof;
+        lexemes.push(lexeme);
+        if done {
+            return Ok(lexemes);
+        }
+    }
+}
+
+struct Lexer<'a> {
+    chars: Peekable<Chars<'a>>,
+    line: usize,
+    column: usize,
+    source: usize,
+}
+
+impl Lexer<'_> {
+    fn next_lexeme(&mut self) -> Result<Lexeme> {
+        self.skip_whitespace_and_comments();
+
+        let span = self.span();
+        let Some(c) = self.bump() else {
+            return Ok(Lexeme {
+                token: Token::Eof,
+                span,
+            });
+        };
+
+        let token = match c {
+            '\n' | ';' => Token::Newline,
+            ',' => Token::Comma,
+            ':' => Token::Colon,
+            '+' => Token::Plus,
+            '-' => Token::Minus,
+            '*' => Token::Star,
+            '/' => Token::Slash,
+            '%' => Token::Percent,
+            '(' => Token::LParen,
+            ')' => Token::RParen,
+            '"' => Token::String(self.string(span)?),
+            '$' => {
+                let name = self.take_while(is_ident_continue);
+                if name.is_empty() {
+                    return Err(Error::new(span, ErrorKind::UnexpectedCharacter('$')));
+                }
+
+                Token::Register(name)
+            }
+            '\\' => {
+                let name = self.take_while(is_ident_continue);
+                if name.is_empty() {
+                    return Err(Error::new(span, ErrorKind::UnexpectedCharacter('\\')));
+                }
+
+                Token::Param(name)
+            }
+            c if c.is_ascii_digit() => {
+                let mut literal = c.to_string();
+                literal.push_str(&self.take_while(is_ident_continue));
+                let mut value = parse_integer(&literal);
+                if let Some(scale) = self.chars.peek().copied().and_then(prefix_scale) {
+                    literal.push(self.bump().unwrap());
+                    let unit = self.take_while(is_ident_continue);
+                    literal.push_str(&unit);
+                    value = value
+                        .filter(|_| unit == "T")
+                        .and_then(|value| value.checked_mul(scale));
+                }
+
+                let value =
+                    value.ok_or_else(|| Error::new(span, ErrorKind::InvalidInteger(literal)))?;
+                Token::Integer(value)
+            }
+            c if prefix_scale(c).is_some() => {
+                let unit = self.take_while(is_ident_continue);
+                if unit != "T" {
+                    let literal = format!("{c}{unit}");
+                    return Err(Error::new(span, ErrorKind::InvalidInteger(literal)));
+                }
+
+                Token::Integer(prefix_scale(c).unwrap())
+            }
+            c if is_ident_start(c) => {
+                let mut name = c.to_string();
+                name.push_str(&self.take_while(is_ident_continue));
+                Token::Ident(name)
+            }
+            c => return Err(Error::new(span, ErrorKind::UnexpectedCharacter(c))),
+        };
+
+        Ok(Lexeme { token, span })
+    }
+
+    fn string(&mut self, start: Span) -> Result<String> {
+        let mut s = String::new();
+        loop {
+            let span = self.span();
+            match self.bump() {
+                None | Some('\n') => {
+                    return Err(Error::new(start, ErrorKind::UnterminatedString));
+                }
+                Some('"') => return Ok(s),
+                Some('\\') => {
+                    let c = match self.bump() {
+                        Some('n') => '\n',
+                        Some('t') => '\t',
+                        Some('r') => '\r',
+                        Some('0') => '\0',
+                        Some(c @ ('\\' | '"')) => c,
+                        Some(c) => return Err(Error::new(span, ErrorKind::InvalidEscape(c))),
+                        None => return Err(Error::new(start, ErrorKind::UnterminatedString)),
+                    };
+                    s.push(c);
+                }
+                Some(c) => s.push(c),
+            }
+        }
+    }
+
+    fn span(&self) -> Span {
+        Span {
+            line: self.line,
+            column: self.column,
+            source: self.source,
+        }
+    }
+
+    fn bump(&mut self) -> Option<char> {
+        let c = self.chars.next()?;
+        if c == '\n' {
+            self.line += 1;
+            self.column = 1;
+        } else {
+            self.column += 1;
+        }
+
+        Some(c)
+    }
+
+    fn take_while<F>(&mut self, f: F) -> String
+    where
+        F: Fn(char) -> bool,
+    {
+        let mut s = String::new();
+        while let Some(&c) = self.chars.peek() {
+            if !f(c) {
+                break;
+            }
+
+            s.push(c);
+            self.bump();
+        }
+
+        s
+    }
+
+    fn skip_whitespace_and_comments(&mut self) {
+        while let Some(&c) = self.chars.peek() {
+            if c == '#' {
+                self.take_while(|c| c != '\n');
+            } else if c.is_whitespace() && c != '\n' {
+                self.bump();
+            } else {
+                break;
+            }
+        }
+    }
+}
+
+fn is_ident_start(c: char) -> bool {
+    c.is_ascii_alphabetic() || c == '_' || c == '.'
+}
+
+fn is_ident_continue(c: char) -> bool {
+    c.is_ascii_alphanumeric() || c == '_' || c == '.'
+}
+
+// The scale of a prefix from `prefixes.md`, without its trailing `T`.
+fn prefix_scale(c: char) -> Option<i64> {
+    match c {
+        'α' => Some(3_i64.pow(6)),
+        'β' => Some(3_i64.pow(12)),
+        'γ' => Some(3_i64.pow(18)),
+        'δ' => Some(3_i64.pow(24)),
+        _ => None,
+    }
+}
+
+fn parse_integer(literal: &str) -> Option<i64> {
+    let digits: String = literal.chars().filter(|&c| c != '_').collect();
+    if let Some(trits) = digits.strip_prefix("0t") {
+        parse_digits(trits, 3, |c| match c {
+            'T' => Some(-1),
+            '0' => Some(0),
+            '1' => Some(1),
+            _ => None,
+        })
+    } else if let Some(digits) = digits.strip_prefix("0h") {
+        parse_digits(digits, 27, |c| {
+            HEPTAVINTIMAL_DIGITS
+                .find(c.to_ascii_uppercase())
+                .map(|digit| i64::try_from(digit).unwrap())
+        })
+    } else {
+        digits.parse().ok()
+    }
+}
+
+fn parse_digits(digits: &str, radix: i64, digit: impl Fn(char) -> Option<i64>) -> Option<i64> {
+    if digits.is_empty() {
+        return None;
+    }
+
+    digits.chars().try_fold(0, |value: i64, c| {
+        value.checked_mul(radix)?.checked_add(digit(c)?)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn tokens(source: &str) -> Vec<Token> {
+        tokenize(source, 0)
+            .unwrap()
+            .into_iter()
+            .map(|lexeme| lexeme.token)
+            .collect()
+    }
+
+    #[test]
+    fn tokenize_instruction() {
+        assert_eq!(
+            vec![
+                Token::Ident("loop".into()),
+                Token::Colon,
+                Token::Ident("addi".into()),
+                Token::Register("t0".into()),
+                Token::Comma,
+                Token::Param("src".into()),
+                Token::Comma,
+                Token::Minus,
+                Token::Integer(1_000),
+                Token::Newline,
+                Token::Eof,
+            ],
+            tokens("loop: addi $t0, \\src, -1_000 # decrement\n")
+        );
+    }
+
+    #[test]
+    fn tokenize_literals() {
+        assert_eq!(
+            vec![
+                Token::Integer(-8),
+                Token::Integer(-8),
+                Token::Integer(1_000),
+                Token::Integer(728),
+                Token::Integer(-364),
+                Token::Integer(2_916),
+                Token::Integer(531_441),
+                Token::Integer(3 * 387_420_489),
+                Token::Integer(-282_429_536_481),
+                Token::Eof,
+            ],
+            tokens("0tT01 0tT_01 0h1A1 0hQQ 0tTTTTTT 4αT βT 3γT 0tTδT")
+        );
+        assert_eq!(
+            vec![
+                Token::LParen,
+                Token::Ident("end".into()),
+                Token::Minus,
+                Token::Ident("start".into()),
+                Token::RParen,
+                Token::Star,
+                Token::Integer(2),
+                Token::Slash,
+                Token::Integer(3),
+                Token::Percent,
+                Token::Integer(4),
+                Token::Eof,
+            ],
+            tokens("(end - start) * 2 / 3 % 4")
+        );
+    }
+
+    #[test]
+    fn tokenize_string() {
+        assert_eq!(
+            vec![
+                Token::Ident(".string".into()),
+                Token::String("a \"b\"\n# c\0".into()),
+                Token::Eof,
+            ],
+            tokens(r#".string "a \"b\"\n# c\0""#)
+        );
+    }
+
+    #[test]
+    fn tokenize_spans() {
+        let lexemes = tokenize("nop\n  j end", 0).unwrap();
+        let spans: Vec<_> = lexemes
+            .iter()
+            .map(|lexeme| (lexeme.span.line, lexeme.span.column))
+            .collect();
+        assert_eq!(vec![(1, 1), (1, 4), (2, 3), (2, 5), (2, 8)], spans);
+    }
+
+    #[test]
+    fn tokenize_errors() {
+        let error = tokenize("add $t0, @", 0).unwrap_err();
+        assert_eq!((1, 10), (error.line, error.column));
+        assert_eq!(ErrorKind::UnexpectedCharacter('@'), error.kind);
+
+        let error = tokenize(".string \"abc", 0).unwrap_err();
+        assert_eq!((1, 9), (error.line, error.column));
+        assert_eq!(ErrorKind::UnterminatedString, error.kind);
+
+        let error = tokenize(".string \"a\\qb\"", 0).unwrap_err();
+        assert_eq!((1, 11), (error.line, error.column));
+        assert_eq!(ErrorKind::InvalidEscape('q'), error.kind);
+
+        let error = tokenize("\n  addi $t0, $t0, 12x", 0).unwrap_err();
+        assert_eq!((2, 18), (error.line, error.column));
+        assert_eq!(ErrorKind::InvalidInteger("12x".into()), error.kind);
+
+        for literal in ["0t12", "0t", "0hR", "4αX", "0h1α", "99999999δT", "γ"] {
+            let error = tokenize(literal, 0).unwrap_err();
+            assert_eq!(ErrorKind::InvalidInteger(literal.into()), error.kind);
+        }
+    }
+}
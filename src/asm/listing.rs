@@ -0,0 +1,197 @@
+use std::fmt;
+
+use ternary::Tryte;
+
+use crate::asm::lexer::Span;
+use crate::asm::macros::Preprocessor;
+use crate::asm::reloc::Place;
+use crate::debug::{LineTable, Location};
+use crate::image::tryte_to_i16;
+use crate::inst::Inst;
+use crate::numerals::{balanced_ternary, heptavintimal};
+use crate::object::Section;
+
+const WORD_SIZE: usize = 4;
+
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum Contents {
+    Label(String),
+    Insts(Vec<Inst>),
+    Data(Vec<Tryte>),
+    Zeros(usize),
+}
+
+// A statement that defines a label or emits something, at its final address
+// (or its offset, in a relocatable object).
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Line {
+    pub section: Section,
+    pub addr: i64,
+    pub location: Location,
+    pub source: String,
+    pub contents: Contents,
+}
+
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct Listing {
+    pub lines: Vec<Line>,
+}
+
+pub(super) struct Record {
+    pub span: Span,
+    pub place: Place,
+    pub contents: Contents,
+}
+
+impl Listing {
+    pub(super) fn new(records: Vec<Record>, preprocessor: &Preprocessor) -> Self {
+        let lines = records
+            .into_iter()
+            .map(|record| Line {
+                section: record.place.section,
+                addr: record.place.addr,
+                location: preprocessor.location(record.span),
+                source: preprocessor.source_line(record.span).trim().to_string(),
+                contents: record.contents,
+            })
+            .collect();
+
+        Listing { lines }
+    }
+
+    // The location of every instruction word in `sections`.
+    pub(super) fn line_table(&self, sections: &[Section]) -> LineTable {
+        let mut table = LineTable::default();
+        for line in &self.lines {
+            if let Contents::Insts(insts) = &line.contents
+                && sections.contains(&line.section)
+            {
+                for addr in (line.addr..).step_by(WORD_SIZE).take(insts.len()) {
+                    table.push(i32::try_from(addr).unwrap(), &line.location);
+                }
+            }
+        }
+
+        table
+    }
+}
+
+// One row per word, with its address, its trits and its value in
+// heptavintimal, followed by the source line it came from. Each instruction
+// of an expanded pseudo-instruction gets its own row, and the source is only
+// shown on the first row for each line.
+impl fmt::Display for Listing {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut section = None;
+        let mut file = None;
+        let mut previous = None;
+        for line in &self.lines {
+            if section != Some(line.section) {
+                writeln!(f, "{:>11}  {}", "", line.section.name())?;
+                section = Some(line.section);
+            }
+
+            if file != Some(&line.location.file) {
+                if let Some(name) = &line.location.file {
+                    writeln!(f, "{:>11}  # {name}", "")?;
+                }
+                file = Some(&line.location.file);
+            }
+
+            let key = (&line.location.file, line.location.line);
+            let mut source = if previous == Some(key) {
+                None
+            } else {
+                Some(line.location.line)
+            };
+            previous = Some(key);
+
+            let mut row = |addr: i64, trytes: &[Tryte], text: &str| {
+                let (trits, value) = if trytes.is_empty() {
+                    (String::new(), String::new())
+                } else {
+                    let value = trytes_value(trytes);
+                    let width = 6 * trytes.len();
+                    let trits = format!("{:0>width$}", balanced_ternary(value));
+                    (trits, format!("0h{}", heptavintimal(value)))
+                };
+
+                write!(f, "{addr:>11}  {trits:<24}  {value:<11}  {text:<28}")?;
+                match source.take() {
+                    Some(number) => writeln!(f, "  {number:>5}  {}", line.source)?,
+                    None => writeln!(f)?,
+                }
+
+                fmt::Result::Ok(())
+            };
+
+            match &line.contents {
+                Contents::Label(name) => row(line.addr, &[], &format!("{name}:"))?,
+                Contents::Insts(insts) => {
+                    for (addr, inst) in (line.addr..).step_by(WORD_SIZE).zip(insts) {
+                        let text = match i32::try_from(addr) {
+                            Ok(addr) => inst.display_at(addr).to_string(),
+                            Err(_) => inst.to_string(),
+                        };
+                        let word = inst.to_word().map_err(|_| fmt::Error)?;
+                        row(addr, &word.into_trytes(), &text)?;
+                    }
+                }
+                Contents::Data(trytes) => {
+                    for (addr, chunk) in (line.addr..).step_by(WORD_SIZE).zip(trytes.chunks(4)) {
+                        row(addr, chunk, "")?;
+                    }
+                }
+                Contents::Zeros(count) => {
+                    row(line.addr, &[], &format!("{count} zero trytes"))?;
+                }
+            }
+        }
+
+        Ok(())
+    }
+}
+
+// The value of little-endian trytes, as for a word.
+fn trytes_value(trytes: &[Tryte]) -> i64 {
+    trytes.iter().rev().fold(0, |value, &tryte| {
+        value * 729 + i64::from(tryte_to_i16(tryte))
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+
+    use crate::asm::assemble_file_listed;
+
+    #[test]
+    fn format_listing() {
+        let source = "\
+start: li $t0, 1000000 # load
+    b0 $t0, start
+.data
+.tryte 1, -1
+";
+        let assembly = assemble_file_listed(Path::new("a.s"), source, 8).unwrap();
+        let rows: Vec<_> = assembly
+            .listing
+            .to_string()
+            .lines()
+            .map(str::trim_end)
+            .map(str::to_string)
+            .collect();
+
+        let expected = [
+            "             .text",
+            "             # a.s",
+            "          8                                         start:                            1  start: li $t0, 1000000 # load",
+            "          8  00000000001T00001T0T1TT0  0h201OF      lui $t0, 2",
+            "         12  0T0T11T1T0011T0T1T0T1TTT  0h-356PL72D  addi $t0, $t0, -62882",
+            "         16  0000000000000TT01T0T10TT  0h-3P24      b0 $t0, 8                         2  b0 $t0, start",
+            "             .data",
+            "         20  00000T000001              0h-QQ                                          4  .tryte 1, -1",
+        ];
+        assert_eq!(expected.as_slice(), rows.as_slice());
+    }
+}
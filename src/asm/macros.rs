@@ -0,0 +1,936 @@
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::io;
+use std::mem;
+use std::path::{Path, PathBuf};
+
+use crate::asm::error::{Error, ErrorKind, Expansion, Result};
+use crate::asm::eval_known;
+use crate::asm::lexer::{self, Lexeme, Span, Token};
+use crate::asm::parser;
+use crate::debug::Location;
+
+const MAX_DEPTH: usize = 64;
+
+pub type Reader<'a> = dyn FnMut(&Path) -> io::Result<String> + 'a;
+
+enum Source {
+    File(File),
+    Expansion {
+        name: String,
+        definition: usize,
+        call: Span,
+    },
+}
+
+struct File {
+    name: Option<String>,
+    path: Option<PathBuf>,
+    lines: Vec<String>,
+}
+
+#[derive(Clone)]
+struct Macro {
+    params: Vec<String>,
+    body: Vec<Lexeme>,
+    source: usize,
+}
+
+struct Definition {
+    name: String,
+    span: Span,
+    mac: Macro,
+}
+
+struct Constant {
+    tokens: Vec<Lexeme>,
+    reassignable: bool,
+}
+
+#[derive(Clone, Copy)]
+struct Condition {
+    enclosing: bool,
+    value: bool,
+    in_else: bool,
+    span: Span,
+}
+
+impl Condition {
+    fn active(self) -> bool {
+        self.enclosing && self.value != self.in_else
+    }
+}
+
+// Expands macros, includes, constants and conditionals at the token level,
+// leaving a stream of plain statements for the parser. Every source of
+// tokens (a file or a macro expansion) gets an id, which is recorded in the
+// spans of its tokens so that errors can be traced back to their origin.
+pub struct Preprocessor<'a> {
+    read: &'a mut Reader<'a>,
+    sources: Vec<Source>,
+    macros: HashMap<String, Macro>,
+    constants: HashMap<String, Constant>,
+    labels: HashSet<String>,
+    conditions: Vec<Condition>,
+    includes: Vec<PathBuf>,
+    depth: usize,
+    expansions: usize,
+    output: Vec<Lexeme>,
+}
+
+impl<'a> Preprocessor<'a> {
+    pub fn new(read: &'a mut Reader<'a>) -> Self {
+        Preprocessor {
+            read,
+            sources: Vec::new(),
+            macros: HashMap::new(),
+            constants: HashMap::new(),
+            labels: HashSet::new(),
+            conditions: Vec::new(),
+            includes: Vec::new(),
+            depth: 0,
+            expansions: 0,
+            output: Vec::new(),
+        }
+    }
+
+    pub fn run(&mut self, source: &str, path: Option<&Path>) -> Result<Vec<Lexeme>> {
+        let id = self.add_file(path, source);
+        let lexemes = lexer::tokenize(source, id)?;
+        let eof = lexemes.last().unwrap().clone();
+
+        if let Some(path) = path {
+            self.includes.push(canonical(path));
+        }
+
+        self.process(lexemes)?;
+        self.output.push(eof);
+        Ok(mem::take(&mut self.output))
+    }
+
+    pub fn locate(&self, mut error: Error) -> Error {
+        error.file = self.file_name(error.source);
+
+        let mut source = error.source;
+        while let Source::Expansion { name, call, .. } = &self.sources[source] {
+            error.expansions.push(Expansion {
+                name: name.clone(),
+                file: self.file_name(call.source),
+                line: call.line,
+                column: call.column,
+            });
+            source = call.source;
+        }
+
+        error
+    }
+
+    pub fn location(&self, span: Span) -> Location {
+        Location {
+            file: self.file_name(span.source),
+            line: span.line,
+            column: span.column,
+        }
+    }
+
+    // The text of the line that `span` is on.
+    pub fn source_line(&self, span: Span) -> &str {
+        self.file(span.source)
+            .lines
+            .get(span.line.wrapping_sub(1))
+            .map_or("", String::as_str)
+    }
+
+    fn add_file(&mut self, path: Option<&Path>, source: &str) -> usize {
+        self.sources.push(Source::File(File {
+            name: path.map(|path| path.display().to_string()),
+            path: path.map(Path::to_path_buf),
+            lines: source.lines().map(str::to_string).collect(),
+        }));
+        self.sources.len() - 1
+    }
+
+    // tokens from an expansion are located in the file that defines the macro
+    fn file(&self, mut source: usize) -> &File {
+        loop {
+            match &self.sources[source] {
+                Source::File(file) => return file,
+                Source::Expansion { definition, .. } => source = *definition,
+            }
+        }
+    }
+
+    fn file_name(&self, source: usize) -> Option<String> {
+        self.file(source).name.clone()
+    }
+
+    fn active(&self) -> bool {
+        self.conditions
+            .last()
+            .is_none_or(|condition| condition.active())
+    }
+
+    fn process(&mut self, lexemes: Vec<Lexeme>) -> Result<()> {
+        let depth = self.conditions.len();
+        let mut definition: Option<Definition> = None;
+
+        for line in lines(lexemes) {
+            if let Some(def) = &mut definition {
+                match directive(&line[head(&line)..]) {
+                    Some(".endm") => {
+                        let def = definition.take().unwrap();
+                        self.macros.insert(def.name, def.mac);
+                    }
+                    Some(".macro") => {
+                        let lexeme = &line[head(&line)];
+                        return Err(unexpected_directive(lexeme));
+                    }
+                    _ => def.mac.body.extend(line),
+                }
+
+                continue;
+            }
+
+            definition = self.line(line)?;
+        }
+
+        if let Some(def) = definition {
+            return Err(Error::new(def.span, ErrorKind::UnterminatedMacro(def.name)));
+        }
+
+        if self.conditions.len() > depth {
+            let span = self.conditions.last().unwrap().span;
+            return Err(Error::new(span, ErrorKind::UnterminatedConditional));
+        }
+
+        Ok(())
+    }
+
+    fn line(&mut self, mut line: Vec<Lexeme>) -> Result<Option<Definition>> {
+        let start = head(&line);
+        let rest = line.split_off(start);
+        let labels = line;
+
+        if self.active() && !labels.is_empty() {
+            for lexeme in &labels {
+                if let Token::Ident(name) = &lexeme.token {
+                    self.define_label(name, lexeme.span)?;
+                }
+            }
+
+            self.output.extend(labels);
+            self.output.push(newline(rest.last().unwrap()));
+        }
+
+        let (head, args, end) = split_line(&rest);
+        let name = directive(&rest).map(str::to_string);
+        match name.as_deref() {
+            Some(".if" | ".ifdef" | ".ifndef") => {
+                let enclosing = self.active();
+                let value = enclosing && self.condition(head, args)?;
+                self.conditions.push(Condition {
+                    enclosing,
+                    value,
+                    in_else: false,
+                    span: head.span,
+                });
+                return Ok(None);
+            }
+            Some(".else") => {
+                match self.conditions.last_mut() {
+                    Some(condition) if !condition.in_else => condition.in_else = true,
+                    _ => return Err(unexpected_directive(head)),
+                }
+
+                return no_args(args);
+            }
+            Some(".endif") => {
+                if self.conditions.pop().is_none() {
+                    return Err(unexpected_directive(head));
+                }
+
+                return no_args(args);
+            }
+            _ => {}
+        }
+
+        if !self.active() || head.token == Token::Newline || head.token == Token::Eof {
+            return Ok(None);
+        }
+
+        match name.as_deref() {
+            Some(".macro") => return self.define_macro(head, args).map(Some),
+            Some(".endm") => return Err(unexpected_directive(head)),
+            Some(".equ") => self.define_constant(args, false, head.span)?,
+            Some(".set") => self.define_constant(args, true, head.span)?,
+            Some(".include") => self.include(args, head.span)?,
+            _ => {
+                if let Token::Ident(name) = &head.token
+                    && let Some(mac) = self.macros.get(name).cloned()
+                {
+                    self.expand(name, head.span, &mac, args)?;
+                } else {
+                    self.output.push(head.clone());
+                    let args = self.substitute(args);
+                    self.output.extend(args);
+                    self.output.push(newline(end));
+                }
+            }
+        }
+
+        Ok(None)
+    }
+
+    fn condition(&self, head: &Lexeme, args: &[Lexeme]) -> Result<bool> {
+        let Token::Ident(name) = &head.token else {
+            unreachable!()
+        };
+
+        if name == ".if" {
+            let mut lexemes = self.substitute(args);
+            let end = args.last().unwrap_or(head);
+            lexemes.push(Lexeme {
+                token: Token::Eof,
+                span: end.span,
+            });
+
+            let expr = parser::parse_expression(lexemes)?;
+            return Ok(eval_known(&expr, &HashMap::new())? != 0);
+        }
+
+        let [symbol] = args else {
+            let span = args.first().unwrap_or(head).span;
+            return Err(Error::new(span, ErrorKind::ExpectedIdentifier));
+        };
+
+        let Token::Ident(symbol) = &symbol.token else {
+            return Err(Error::new(symbol.span, ErrorKind::ExpectedIdentifier));
+        };
+
+        let defined = self.constants.contains_key(symbol)
+            || self.labels.contains(symbol)
+            || self.macros.contains_key(symbol);
+        Ok(defined == (name == ".ifdef"))
+    }
+
+    fn define_label(&mut self, name: &str, span: Span) -> Result<()> {
+        if self.constants.contains_key(name) {
+            return Err(Error::new(
+                span,
+                ErrorKind::DuplicateSymbol(name.to_string()),
+            ));
+        }
+
+        self.labels.insert(name.to_string());
+        Ok(())
+    }
+
+    fn define_macro(&mut self, head: &Lexeme, args: &[Lexeme]) -> Result<Definition> {
+        let [
+            Lexeme {
+                token: Token::Ident(name),
+                span,
+            },
+            params @ ..,
+        ] = args
+        else {
+            let span = args.first().unwrap_or(head).span;
+            return Err(Error::new(span, ErrorKind::ExpectedIdentifier));
+        };
+
+        if self.macros.contains_key(name) {
+            return Err(Error::new(*span, ErrorKind::DuplicateMacro(name.clone())));
+        }
+
+        let mut names = Vec::new();
+        for (i, lexeme) in params.iter().enumerate() {
+            match &lexeme.token {
+                Token::Ident(param) if i % 2 == 0 => names.push(param.clone()),
+                Token::Comma if i % 2 == 1 => {}
+                _ => return Err(Error::new(lexeme.span, ErrorKind::ExpectedIdentifier)),
+            }
+        }
+
+        if params.len() % 2 == 0
+            && let Some(comma) = params.last()
+        {
+            return Err(Error::new(comma.span, ErrorKind::ExpectedIdentifier));
+        }
+
+        Ok(Definition {
+            name: name.clone(),
+            span: head.span,
+            mac: Macro {
+                params: names,
+                body: Vec::new(),
+                source: head.span.source,
+            },
+        })
+    }
+
+    fn define_constant(&mut self, args: &[Lexeme], reassignable: bool, span: Span) -> Result<()> {
+        let (name, value) = match args {
+            [
+                Lexeme {
+                    token: Token::Ident(name),
+                    span,
+                },
+                Lexeme {
+                    token: Token::Comma,
+                    ..
+                },
+                value @ ..,
+            ] => ((name, *span), value),
+            [lexeme, ..] => return Err(Error::new(lexeme.span, ErrorKind::ExpectedIdentifier)),
+            [] => return Err(Error::new(span, ErrorKind::ExpectedIdentifier)),
+        };
+
+        if value.is_empty() {
+            return Err(Error::new(args[1].span, ErrorKind::ExpectedExpression));
+        }
+
+        let (name, span) = name;
+        let redefinable = self
+            .constants
+            .get(name)
+            .is_none_or(|constant| constant.reassignable && reassignable);
+        if !redefinable || self.labels.contains(name) {
+            return Err(Error::new(span, ErrorKind::DuplicateSymbol(name.clone())));
+        }
+
+        let tokens = evaluate(self.substitute(value))?;
+        self.constants.insert(
+            name.clone(),
+            Constant {
+                tokens,
+                reassignable,
+            },
+        );
+        Ok(())
+    }
+
+    fn include(&mut self, args: &[Lexeme], span: Span) -> Result<()> {
+        let [
+            Lexeme {
+                token: Token::String(name),
+                span,
+            },
+        ] = args
+        else {
+            let span = args.first().map_or(span, |lexeme| lexeme.span);
+            return Err(Error::new(span, ErrorKind::ExpectedString));
+        };
+
+        let path = match self
+            .file(span.source)
+            .path
+            .as_ref()
+            .and_then(|path| path.parent())
+        {
+            Some(dir) => dir.join(name),
+            None => PathBuf::from(name),
+        };
+
+        let key = canonical(&path);
+        if self.includes.contains(&key) {
+            return Err(Error::new(*span, ErrorKind::IncludeCycle(name.clone())));
+        }
+
+        let source = (self.read)(&path).map_err(|error| {
+            Error::new(*span, ErrorKind::Include(name.clone(), error.to_string()))
+        })?;
+
+        let id = self.add_file(Some(&path), &source);
+        let mut lexemes = lexer::tokenize(&source, id)?;
+        if let Some(eof) = lexemes.last_mut() {
+            eof.token = Token::Newline;
+        }
+
+        self.includes.push(key);
+        self.process(lexemes)?;
+        self.includes.pop();
+        Ok(())
+    }
+
+    // Labels defined in a macro body are local to each expansion, so they are
+    // renamed with a suffix that can't appear in source code.
+    fn expand(&mut self, name: &str, call: Span, mac: &Macro, args: &[Lexeme]) -> Result<()> {
+        let args: Vec<_> = if args.is_empty() {
+            Vec::new()
+        } else {
+            args.split(|lexeme| lexeme.token == Token::Comma).collect()
+        };
+
+        if args.len() != mac.params.len() {
+            return Err(Error::new(
+                call,
+                ErrorKind::WrongOperandCount(mac.params.len(), args.len()),
+            ));
+        }
+
+        if self.depth >= MAX_DEPTH {
+            return Err(Error::new(
+                call,
+                ErrorKind::MacroRecursion(name.to_string()),
+            ));
+        }
+
+        let id = self.sources.len();
+        self.sources.push(Source::Expansion {
+            name: name.to_string(),
+            definition: mac.source,
+            call,
+        });
+
+        self.expansions += 1;
+        let suffix = format!("@{}", self.expansions);
+        let locals: HashSet<_> = lines(mac.body.clone())
+            .iter()
+            .flat_map(|line| line[..head(line)].to_vec())
+            .filter_map(|lexeme| match lexeme.token {
+                Token::Ident(name) => Some(name),
+                _ => None,
+            })
+            .collect();
+
+        let mut lexemes = Vec::new();
+        for lexeme in &mac.body {
+            let span = Span {
+                source: id,
+                ..lexeme.span
+            };
+
+            match &lexeme.token {
+                Token::Param(param) => {
+                    let Some(i) = mac.params.iter().position(|name| name == param) else {
+                        return Err(Error::new(span, ErrorKind::UnknownParameter(param.clone())));
+                    };
+
+                    // arguments are reported at the parameter they replace
+                    lexemes.extend(args[i].iter().map(|arg| Lexeme {
+                        token: arg.token.clone(),
+                        span,
+                    }));
+                }
+                Token::Ident(name) if locals.contains(name) => lexemes.push(Lexeme {
+                    token: Token::Ident(format!("{name}{suffix}")),
+                    span,
+                }),
+                token => lexemes.push(Lexeme {
+                    token: token.clone(),
+                    span,
+                }),
+            }
+        }
+
+        self.depth += 1;
+        self.process(lexemes)?;
+        self.depth -= 1;
+        Ok(())
+    }
+
+    fn substitute(&self, lexemes: &[Lexeme]) -> Vec<Lexeme> {
+        let mut output = Vec::new();
+        for lexeme in lexemes {
+            let constant = match &lexeme.token {
+                Token::Ident(name) => self.constants.get(name),
+                _ => None,
+            };
+
+            let Some(constant) = constant else {
+                output.push(lexeme.clone());
+                continue;
+            };
+
+            let paren = |token| Lexeme {
+                token,
+                span: lexeme.span,
+            };
+
+            output.push(paren(Token::LParen));
+            output.extend_from_slice(&constant.tokens);
+            output.push(paren(Token::RParen));
+        }
+
+        output
+    }
+}
+
+// Splits a stream into lines, each ending with its `Newline` or `Eof`.
+fn lines(lexemes: Vec<Lexeme>) -> Vec<Vec<Lexeme>> {
+    let mut lines = Vec::new();
+    let mut line = Vec::new();
+    for lexeme in lexemes {
+        let end = matches!(lexeme.token, Token::Newline | Token::Eof);
+        line.push(lexeme);
+        if end {
+            lines.push(mem::take(&mut line));
+        }
+    }
+
+    lines
+}
+
+// The index of the first token after any labels at the start of the line.
+fn head(line: &[Lexeme]) -> usize {
+    let mut start = 0;
+    while let [
+        Lexeme {
+            token: Token::Ident(_),
+            ..
+        },
+        Lexeme {
+            token: Token::Colon,
+            ..
+        },
+        ..,
+    ] = &line[start..]
+    {
+        start += 2;
+    }
+
+    start
+}
+
+// Splits a line without labels into its first token, its arguments and its
+// terminator.
+fn split_line(line: &[Lexeme]) -> (&Lexeme, &[Lexeme], &Lexeme) {
+    let (end, rest) = line.split_last().unwrap();
+    match rest.split_first() {
+        Some((head, args)) => (head, args, end),
+        None => (end, &[], end),
+    }
+}
+
+fn directive(line: &[Lexeme]) -> Option<&str> {
+    match &line.first()?.token {
+        Token::Ident(name) if name.starts_with('.') => Some(name),
+        _ => None,
+    }
+}
+
+fn newline(end: &Lexeme) -> Lexeme {
+    Lexeme {
+        token: Token::Newline,
+        span: end.span,
+    }
+}
+
+// A value that is already known is stored as one integer, so that using it
+// doesn't paste its whole history. Values that depend on labels keep their
+// tokens, to be evaluated once the labels are assigned.
+fn evaluate(tokens: Vec<Lexeme>) -> Result<Vec<Lexeme>> {
+    let span = tokens[0].span;
+    let mut lexemes = tokens.clone();
+    lexemes.push(Lexeme {
+        token: Token::Eof,
+        span: tokens.last().unwrap().span,
+    });
+
+    let value =
+        parser::parse_expression(lexemes).and_then(|expr| eval_known(&expr, &HashMap::new()));
+    match value {
+        Ok(value) => Ok(vec![Lexeme {
+            token: Token::Integer(value),
+            span,
+        }]),
+        Err(Error {
+            kind: ErrorKind::UnknownValue(_),
+            ..
+        }) => Ok(tokens),
+        Err(error) => Err(error),
+    }
+}
+
+fn unexpected_directive(lexeme: &Lexeme) -> Error {
+    let name = match &lexeme.token {
+        Token::Ident(name) => name.clone(),
+        token => token.to_string(),
+    };
+
+    Error::new(lexeme.span, ErrorKind::UnexpectedDirective(name))
+}
+
+fn no_args<T>(args: &[Lexeme]) -> Result<Option<T>> {
+    match args.first() {
+        Some(lexeme) => Err(Error::new(
+            lexeme.span,
+            ErrorKind::UnexpectedToken(lexeme.token.to_string()),
+        )),
+        None => Ok(None),
+    }
+}
+
+fn canonical(path: &Path) -> PathBuf {
+    fs::canonicalize(path).unwrap_or_else(|_| path.to_path_buf())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Image;
+    use crate::asm::assemble_with;
+
+    use ternary::T24;
+
+    fn assemble_files(files: &[(&str, &str)]) -> Result<Image> {
+        let mut read = |path: &Path| {
+            files
+                .iter()
+                .find(|(name, _)| Path::new(name) == path)
+                .map(|(_, source)| (*source).to_string())
+                .ok_or_else(|| io::Error::from(io::ErrorKind::NotFound))
+        };
+
+        let (path, source) = files[0];
+        assemble_with(source, Some(Path::new(path)), 0, &mut read).map(|assembly| assembly.output)
+    }
+
+    fn assemble(source: &str) -> Result<Image> {
+        assemble_files(&[("main.s", source)])
+    }
+
+    fn error(source: &str) -> (usize, usize, ErrorKind) {
+        let error = assemble(source).unwrap_err();
+        (error.line, error.column, error.kind)
+    }
+
+    fn values(image: &Image) -> Vec<i64> {
+        image.segments[0]
+            .trytes
+            .chunks(4)
+            .map(|trytes| T24::try_from(trytes).unwrap().try_into_int().unwrap())
+            .collect()
+    }
+
+    fn symbols(image: &Image) -> Vec<(&str, i32)> {
+        image
+            .symbols
+            .iter()
+            .map(|symbol| (symbol.name.as_str(), symbol.addr))
+            .collect()
+    }
+
+    #[test]
+    fn expand_macros() {
+        let source = "
+            .macro push reg
+                addi $sp, $sp, -4
+                sw $sp, \\reg, 0
+            .endm
+            .macro countdown reg, n
+                li \\reg, \\n
+            loop:
+                addi \\reg, \\reg, -1
+                b1 \\reg, loop
+            .endm
+
+            start: push $ra
+                countdown $t0, 3
+                countdown $t1, 2 * 2
+        ";
+
+        let expected = "
+                addi $sp, $sp, -4
+                sw $sp, $ra, 0
+                li $t0, 3
+            a:  addi $t0, $t0, -1
+                b1 $t0, a
+                li $t1, 4
+            b:  addi $t1, $t1, -1
+                b1 $t1, b
+        ";
+
+        let image = assemble(source).unwrap();
+        let expected_image = crate::asm::assemble(expected, 0).unwrap();
+        assert_eq!(expected_image.segments, image.segments);
+        assert_eq!(
+            vec![("start", 0), ("loop@2", 12), ("loop@3", 24)],
+            symbols(&image)
+        );
+    }
+
+    #[test]
+    fn expand_constants() {
+        let source = "
+            .equ SIZE, 3 * 4
+            .set i, 0
+            .word SIZE, i
+            .set i, i + 1
+            .set i, i * SIZE
+            .word i, -SIZE
+            .equ LEN, end - start
+            start: .word LEN
+            end:
+        ";
+
+        assert_eq!(vec![12, 0, 12, -12, 4], values(&assemble(source).unwrap()));
+        assert_eq!(
+            (2, 6, ErrorKind::DuplicateSymbol("n".into())),
+            error(".equ n, 1\n.set n, 2")
+        );
+        assert_eq!(
+            (2, 6, ErrorKind::DuplicateSymbol("n".into())),
+            error("n: nop\n.equ n, 2")
+        );
+        assert_eq!(
+            (2, 1, ErrorKind::DuplicateSymbol("n".into())),
+            error(".set n, 1\nn: nop")
+        );
+        assert_eq!((1, 6, ErrorKind::ExpectedIdentifier), error(".equ 1, 2"));
+        assert_eq!((1, 7, ErrorKind::ExpectedExpression), error(".equ n, "));
+
+        let counter = ".set i, 0\n".to_string() + &".set i, i + 1\n".repeat(1000) + ".word i";
+        assert_eq!(vec![1000], values(&assemble(&counter).unwrap()));
+
+        let doubling = ".set x, 1\n".to_string() + &".set x, x + x\n".repeat(30) + ".word x";
+        assert_eq!(vec![1 << 30], values(&assemble(&doubling).unwrap()));
+        let doubling = ".set x, 1\n".to_string() + &".set x, x + x\n".repeat(100);
+        assert_eq!((64, 11, ErrorKind::Overflow), error(&doubling));
+    }
+
+    #[test]
+    fn expand_conditionals() {
+        let source = "
+            .equ MODE, 2
+            first:
+            .if MODE - 2
+                .word 1
+            .else
+                .word 2
+                .if MODE
+                    .word 3
+                .else
+                    .word 4
+                .endif
+            .endif
+            .ifdef first
+                .word 5
+            .endif
+            .ifdef second
+                .word 6
+                .if 1 / 0
+                .endif
+            .else
+                .word 7
+            .endif
+            .ifndef MODE
+                .word 8
+            .endif
+        ";
+        assert_eq!(vec![2, 3, 5, 7], values(&assemble(source).unwrap()));
+
+        assert_eq!(
+            (1, 1, ErrorKind::UnterminatedConditional),
+            error(".if 1\n.if 0\n.endif")
+        );
+        assert_eq!(
+            (3, 1, ErrorKind::UnexpectedDirective(".else".into())),
+            error(".if 1\n.else\n.else\n.endif")
+        );
+        assert_eq!(
+            (1, 1, ErrorKind::UnexpectedDirective(".endif".into())),
+            error(".endif")
+        );
+        assert_eq!(
+            (1, 5, ErrorKind::UnknownValue("later".into())),
+            error(".if later\n.endif\nlater:")
+        );
+        assert_eq!(
+            (2, 8, ErrorKind::UnexpectedToken("integer `1`".into())),
+            error(".if 1\n.endif 1")
+        );
+    }
+
+    #[test]
+    fn expand_includes() {
+        let files = [
+            ("main.s", ".include \"lib/util.s\"\n_start: ret_zero"),
+            (
+                "lib/util.s",
+                ".include \"defs.s\"\n.macro ret_zero\n li $a0, EXIT\n.endm",
+            ),
+            ("lib/defs.s", ".equ EXIT, 0"),
+        ];
+        let image = assemble_files(&files).unwrap();
+        assert_eq!(vec![("_start", 0)], symbols(&image));
+
+        let files = [
+            ("a.s", "nop\n.include \"b.s\""),
+            ("b.s", ".include \"a.s\""),
+        ];
+        let error = assemble_files(&files).unwrap_err();
+        assert_eq!(Some("b.s".into()), error.file);
+        assert_eq!(
+            (1, 10, ErrorKind::IncludeCycle("a.s".into())),
+            (error.line, error.column, error.kind)
+        );
+
+        let error = assemble_files(&[("a.s", ".include \"c.s\"")]).unwrap_err();
+        assert!(matches!(error.kind, ErrorKind::Include(path, _) if path == "c.s"));
+    }
+
+    #[test]
+    fn expansion_errors() {
+        let files = [
+            ("main.s", ".include \"macros.s\"\n\nouter 1"),
+            (
+                "macros.s",
+                ".macro inner value\n  addi $t0, $t0, \\value\n.endm\n.macro outer n\n  inner \\n * βT\n.endm",
+            ),
+        ];
+        assert_eq!(
+            "macros.s:2:18: value 531441 does not fit in 12 trits\n\
+             macros.s:5:3: note: in expansion of macro `inner`\n\
+             main.s:3:1: note: in expansion of macro `outer`",
+            assemble_files(&files).unwrap_err().to_string()
+        );
+
+        assert_eq!(
+            "main.s:2:3: unknown mnemonic `frob`\nmain.s:4:1: note: in expansion of macro `m`",
+            assemble(".macro m\n  frob\n.endm\nm")
+                .unwrap_err()
+                .to_string()
+        );
+
+        assert_eq!(
+            (2, 7, ErrorKind::UnknownParameter("y".into())),
+            error(".macro m x\n  nop \\y\n.endm\nm 1")
+        );
+        assert_eq!(
+            (4, 1, ErrorKind::WrongOperandCount(1, 2)),
+            error(".macro m x\n  nop\n.endm\nm 1, 2")
+        );
+        assert_eq!(
+            (1, 1, ErrorKind::UnterminatedMacro("m".into())),
+            error(".macro m\nnop")
+        );
+        assert_eq!(
+            (3, 8, ErrorKind::DuplicateMacro("m".into())),
+            error(".macro m\n.endm\n.macro m\n.endm")
+        );
+        assert_eq!(
+            (2, 1, ErrorKind::UnexpectedDirective(".macro".into())),
+            error(".macro m\n.macro n\n.endm")
+        );
+        assert_eq!(
+            (1, 1, ErrorKind::UnexpectedDirective(".endm".into())),
+            error(".endm")
+        );
+        assert_eq!(
+            (2, 1, ErrorKind::MacroRecursion("m".into())),
+            error(".macro m\nm\n.endm\nm")
+        );
+
+        let source = "
+            .macro repeat n
+                .if \\n
+                    .word \\n
+                    repeat \\n - 1
+                .endif
+            .endm
+            repeat 3
+        ";
+        assert_eq!(vec![3, 2, 1], values(&assemble(source).unwrap()));
+    }
+}
@@ -0,0 +1,505 @@
+use crate::asm::error::{Error, ErrorKind, Result};
+use crate::asm::lexer::{Lexeme, Span, Token};
+use crate::operands;
+use crate::registers::Register;
+
+// Expressions nested deeper than this are an error, rather than overflowing
+// the stack while they are parsed or evaluated.
+const MAX_DEPTH: usize = 256;
+
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum Statement {
+    Label(String, Span),
+    Instruction(Instruction),
+    Directive(Directive),
+}
+
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Instruction {
+    pub mnemonic: String,
+    pub args: Vec<Arg>,
+    pub span: Span,
+}
+
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Directive {
+    pub name: String,
+    pub args: Vec<Arg>,
+    pub span: Span,
+}
+
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum Arg {
+    Register(Register, Span),
+    String(String, Span),
+    Expr(Expr),
+}
+
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum Expr {
+    Integer(i64, Span),
+    Symbol(String, Span),
+    Neg(Box<Expr>, Span),
+    Binary(BinaryOp, Box<Expr>, Box<Expr>, Span),
+    Part(Part, Box<Expr>, Span),
+}
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum BinaryOp {
+    Add,
+    Sub,
+    Mul,
+    Div,
+    Rem,
+}
+
+// `%hi(expr)` and `%lo(expr)` split a word into the immediates of a `lui` and
+// an `addi` (or a load or store offset) that add up to it.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Part {
+    Hi,
+    Lo,
+}
+
+impl Arg {
+    pub fn span(&self) -> Span {
+        match self {
+            Arg::Register(_, span) | Arg::String(_, span) => *span,
+            Arg::Expr(expr) => expr.span(),
+        }
+    }
+}
+
+impl Expr {
+    pub fn span(&self) -> Span {
+        match self {
+            Expr::Integer(_, span)
+            | Expr::Symbol(_, span)
+            | Expr::Neg(_, span)
+            | Expr::Part(_, _, span) => *span,
+            Expr::Binary(_, lhs, ..) => lhs.span(),
+        }
+    }
+}
+
+impl BinaryOp {
+    fn from_token(token: &Token) -> Option<Self> {
+        match token {
+            Token::Plus => Some(BinaryOp::Add),
+            Token::Minus => Some(BinaryOp::Sub),
+            Token::Star => Some(BinaryOp::Mul),
+            Token::Slash => Some(BinaryOp::Div),
+            Token::Percent => Some(BinaryOp::Rem),
+            _ => None,
+        }
+    }
+
+    fn precedence(self) -> u8 {
+        match self {
+            BinaryOp::Add | BinaryOp::Sub => 1,
+            BinaryOp::Mul | BinaryOp::Div | BinaryOp::Rem => 2,
+        }
+    }
+}
+
+impl Part {
+    fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "hi" => Some(Part::Hi),
+            "lo" => Some(Part::Lo),
+            _ => None,
+        }
+    }
+
+    pub fn apply(self, value: i64) -> i64 {
+        let (hi, lo) = operands::split_address(value);
+        match self {
+            Part::Hi => hi,
+            Part::Lo => lo,
+        }
+    }
+}
+
+// `lexemes` must end with `Eof`, as produced by the lexer.
+pub fn parse(lexemes: Vec<Lexeme>) -> Result<Vec<Statement>> {
+    let mut parser = Parser {
+        lexemes,
+        position: 0,
+        depth: 0,
+    };
+
+    let mut statements = Vec::new();
+    while !parser.at(&Token::Eof) {
+        parser.parse_line(&mut statements)?;
+    }
+
+    Ok(statements)
+}
+
+pub fn parse_expression(lexemes: Vec<Lexeme>) -> Result<Expr> {
+    let mut parser = Parser {
+        lexemes,
+        position: 0,
+        depth: 0,
+    };
+
+    let expr = parser.parse_expr()?;
+    parser.expect_end_of_line()?;
+    Ok(expr)
+}
+
+struct Parser {
+    lexemes: Vec<Lexeme>,
+    position: usize,
+    depth: usize,
+}
+
+impl Parser {
+    fn parse_line(&mut self, statements: &mut Vec<Statement>) -> Result<()> {
+        loop {
+            let lexeme = self.next();
+            match lexeme.token {
+                Token::Newline | Token::Eof => return Ok(()),
+                Token::Ident(name) if self.at(&Token::Colon) => {
+                    self.next();
+                    statements.push(Statement::Label(name, lexeme.span));
+                }
+                Token::Ident(name) if name.starts_with('.') => {
+                    let args = self.parse_args()?;
+                    statements.push(Statement::Directive(Directive {
+                        name,
+                        args,
+                        span: lexeme.span,
+                    }));
+                    return self.expect_end_of_line();
+                }
+                Token::Ident(mnemonic) => {
+                    let args = self.parse_args()?;
+                    statements.push(Statement::Instruction(Instruction {
+                        mnemonic,
+                        args,
+                        span: lexeme.span,
+                    }));
+                    return self.expect_end_of_line();
+                }
+                token => return Err(unexpected(&token, lexeme.span)),
+            }
+        }
+    }
+
+    fn parse_args(&mut self) -> Result<Vec<Arg>> {
+        let mut args = Vec::new();
+        if self.at(&Token::Newline) || self.at(&Token::Eof) {
+            return Ok(args);
+        }
+
+        loop {
+            args.push(self.parse_arg()?);
+            if !self.at(&Token::Comma) {
+                return Ok(args);
+            }
+
+            self.next();
+        }
+    }
+
+    fn parse_arg(&mut self) -> Result<Arg> {
+        if let Token::Register(_) = self.peek().token {
+            let lexeme = self.next();
+            let Token::Register(name) = lexeme.token else {
+                unreachable!()
+            };
+
+            return Register::from_name(&name)
+                .map(|register| Arg::Register(register, lexeme.span))
+                .ok_or_else(|| Error::new(lexeme.span, ErrorKind::UnknownRegister(name)));
+        }
+
+        if let Token::String(_) = self.peek().token {
+            let lexeme = self.next();
+            let Token::String(s) = lexeme.token else {
+                unreachable!()
+            };
+
+            return Ok(Arg::String(s, lexeme.span));
+        }
+
+        self.parse_expr().map(Arg::Expr)
+    }
+
+    fn parse_expr(&mut self) -> Result<Expr> {
+        self.parse_binary(1)
+    }
+
+    fn parse_binary(&mut self, min_precedence: u8) -> Result<Expr> {
+        let depth = self.depth;
+        let expr = self.parse_operators(min_precedence);
+        self.depth = depth;
+        expr
+    }
+
+    // operators of the same precedence are left-associative, so each one
+    // nests the expression on its left a level deeper
+    fn parse_operators(&mut self, min_precedence: u8) -> Result<Expr> {
+        let mut lhs = self.parse_unary()?;
+        while let Some(op) =
+            BinaryOp::from_token(&self.peek().token).filter(|op| op.precedence() >= min_precedence)
+        {
+            let span = self.next().span;
+            self.descend(span)?;
+            let rhs = self.parse_binary(op.precedence() + 1)?;
+            lhs = Expr::Binary(op, Box::new(lhs), Box::new(rhs), span);
+        }
+
+        Ok(lhs)
+    }
+
+    fn parse_unary(&mut self) -> Result<Expr> {
+        let depth = self.depth;
+        let expr = self.parse_operand();
+        self.depth = depth;
+        expr
+    }
+
+    fn parse_operand(&mut self) -> Result<Expr> {
+        let lexeme = self.next();
+        if matches!(
+            lexeme.token,
+            Token::Plus | Token::Minus | Token::LParen | Token::Percent
+        ) {
+            self.descend(lexeme.span)?;
+        }
+
+        match lexeme.token {
+            Token::Integer(value) => Ok(Expr::Integer(value, lexeme.span)),
+            Token::Ident(name) => Ok(Expr::Symbol(name, lexeme.span)),
+            Token::Plus => self.parse_unary(),
+            Token::Minus => {
+                let expr = self.parse_unary()?;
+                Ok(Expr::Neg(Box::new(expr), lexeme.span))
+            }
+            Token::LParen => self.parse_group(),
+            Token::Percent => {
+                let name = self.next();
+                let part = match name.token {
+                    Token::Ident(name) if self.at(&Token::LParen) => Part::from_name(&name)
+                        .ok_or_else(|| Error::new(lexeme.span, ErrorKind::UnknownOperator(name)))?,
+                    token => return Err(unexpected(&token, name.span)),
+                };
+
+                self.next();
+                let expr = self.parse_group()?;
+                Ok(Expr::Part(part, Box::new(expr), lexeme.span))
+            }
+            _ => Err(Error::new(lexeme.span, ErrorKind::ExpectedExpression)),
+        }
+    }
+
+    // the rest of a parenthesized expression, after the `(`
+    fn parse_group(&mut self) -> Result<Expr> {
+        let expr = self.parse_expr()?;
+        let lexeme = self.next();
+        match lexeme.token {
+            Token::RParen => Ok(expr),
+            token => Err(unexpected(&token, lexeme.span)),
+        }
+    }
+
+    fn descend(&mut self, span: Span) -> Result<()> {
+        if self.depth >= MAX_DEPTH {
+            return Err(Error::new(span, ErrorKind::ExpressionTooDeep));
+        }
+
+        self.depth += 1;
+        Ok(())
+    }
+
+    fn expect_end_of_line(&mut self) -> Result<()> {
+        let lexeme = self.next();
+        match lexeme.token {
+            Token::Newline | Token::Eof => Ok(()),
+            token => Err(unexpected(&token, lexeme.span)),
+        }
+    }
+
+    fn at(&self, token: &Token) -> bool {
+        &self.peek().token == token
+    }
+
+    fn peek(&self) -> &Lexeme {
+        &self.lexemes[self.position]
+    }
+
+    // the lexer always ends the stream with `Eof`, which is never consumed
+    fn next(&mut self) -> Lexeme {
+        let lexeme = self.peek().clone();
+        if lexeme.token != Token::Eof {
+            self.position += 1;
+        }
+
+        lexeme
+    }
+}
+
+fn unexpected(token: &Token, span: Span) -> Error {
+    Error::new(span, ErrorKind::UnexpectedToken(token.to_string()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::asm::lexer;
+    use crate::registers;
+
+    fn span(line: usize, column: usize) -> Span {
+        Span {
+            line,
+            column,
+            source: 0,
+        }
+    }
+
+    fn parse(source: &str) -> Result<Vec<Statement>> {
+        super::parse(lexer::tokenize(source, 0)?)
+    }
+
+    #[test]
+    fn parse_statements() {
+        let statements =
+            parse("start: loop:\n  addi $t0, $t0, -1\n  bT $t0, loop; syscall").unwrap();
+        assert_eq!(
+            vec![
+                Statement::Label("start".into(), span(1, 1)),
+                Statement::Label("loop".into(), span(1, 8)),
+                Statement::Instruction(Instruction {
+                    mnemonic: "addi".into(),
+                    args: vec![
+                        Arg::Register(registers::T0, span(2, 8)),
+                        Arg::Register(registers::T0, span(2, 13)),
+                        Arg::Expr(Expr::Neg(
+                            Box::new(Expr::Integer(1, span(2, 19))),
+                            span(2, 18)
+                        )),
+                    ],
+                    span: span(2, 3),
+                }),
+                Statement::Instruction(Instruction {
+                    mnemonic: "bT".into(),
+                    args: vec![
+                        Arg::Register(registers::T0, span(3, 6)),
+                        Arg::Expr(Expr::Symbol("loop".into(), span(3, 11))),
+                    ],
+                    span: span(3, 3),
+                }),
+                Statement::Instruction(Instruction {
+                    mnemonic: "syscall".into(),
+                    args: vec![],
+                    span: span(3, 17),
+                }),
+            ],
+            statements
+        );
+    }
+
+    #[test]
+    fn parse_directive() {
+        assert_eq!(
+            vec![Statement::Directive(Directive {
+                name: ".word".into(),
+                args: vec![
+                    Arg::Expr(Expr::Integer(1, span(1, 7))),
+                    Arg::Expr(Expr::Symbol("end".into(), span(1, 10))),
+                ],
+                span: span(1, 1),
+            })],
+            parse(".word 1, end").unwrap()
+        );
+        assert_eq!(
+            vec![Statement::Directive(Directive {
+                name: ".asciz".into(),
+                args: vec![
+                    Arg::String("hi".into(), span(1, 8)),
+                    Arg::String(String::new(), span(1, 14)),
+                ],
+                span: span(1, 1),
+            })],
+            parse(".asciz \"hi\", \"\"").unwrap()
+        );
+    }
+
+    #[test]
+    fn parse_expressions() {
+        let Statement::Directive(directive) = &parse(".word -(a - 1) * 2 + b % 3").unwrap()[0]
+        else {
+            panic!("expected directive");
+        };
+
+        let a = Expr::Symbol("a".into(), span(1, 9));
+        let one = Expr::Integer(1, span(1, 13));
+        let sub = Expr::Binary(BinaryOp::Sub, Box::new(a), Box::new(one), span(1, 11));
+        let neg = Expr::Neg(Box::new(sub), span(1, 7));
+        let two = Expr::Integer(2, span(1, 18));
+        let mul = Expr::Binary(BinaryOp::Mul, Box::new(neg), Box::new(two), span(1, 16));
+        let b = Expr::Symbol("b".into(), span(1, 22));
+        let three = Expr::Integer(3, span(1, 26));
+        let rem = Expr::Binary(BinaryOp::Rem, Box::new(b), Box::new(three), span(1, 24));
+        let add = Expr::Binary(BinaryOp::Add, Box::new(mul), Box::new(rem), span(1, 20));
+        assert_eq!(vec![Arg::Expr(add)], directive.args);
+        assert_eq!(span(1, 7), directive.args[0].span());
+
+        let Statement::Instruction(instruction) = &parse("lui $t0, %hi(a + 1)").unwrap()[0] else {
+            panic!("expected instruction");
+        };
+
+        let a = Expr::Symbol("a".into(), span(1, 14));
+        let one = Expr::Integer(1, span(1, 18));
+        let add = Expr::Binary(BinaryOp::Add, Box::new(a), Box::new(one), span(1, 16));
+        let hi = Expr::Part(Part::Hi, Box::new(add), span(1, 10));
+        assert_eq!(Arg::Expr(hi), instruction.args[1]);
+    }
+
+    #[test]
+    fn parse_errors() {
+        let error = parse("add $t0, $t1, $t9").unwrap_err();
+        assert_eq!((1, 15), (error.line, error.column));
+        assert_eq!(ErrorKind::UnknownRegister("t9".into()), error.kind);
+
+        let error = parse("add $t0 $t1").unwrap_err();
+        assert_eq!((1, 9), (error.line, error.column));
+
+        let error = parse("addi $t0, $t0,\n").unwrap_err();
+        assert_eq!((1, 15), (error.line, error.column));
+        assert_eq!(ErrorKind::ExpectedExpression, error.kind);
+
+        let error = parse(".word %mid(x)").unwrap_err();
+        assert_eq!((1, 7), (error.line, error.column));
+        assert_eq!(ErrorKind::UnknownOperator("mid".into()), error.kind);
+
+        let error = parse(".word %hi x").unwrap_err();
+        assert_eq!((1, 8), (error.line, error.column));
+
+        let error = parse(".word (1 + 2").unwrap_err();
+        assert_eq!((1, 13), (error.line, error.column));
+        assert_eq!(
+            ErrorKind::UnexpectedToken("end of input".into()),
+            error.kind
+        );
+
+        let nested = |prefix: &str, suffix: &str, n| {
+            format!(".word {}1{}", prefix.repeat(n), suffix.repeat(n))
+        };
+        assert!(parse(&nested("(", ")", MAX_DEPTH)).is_ok());
+        assert!(parse(&nested("-", "", MAX_DEPTH)).is_ok());
+        assert!(parse(&format!(".word 1{}", " + 1".repeat(MAX_DEPTH))).is_ok());
+        for source in [
+            nested("(", ")", 300_000),
+            nested("-", "", 300_000),
+            nested("%lo(", ")", MAX_DEPTH + 1),
+            format!(".word 1{}", " + 1".repeat(300_000)),
+            format!(".word 1{}", " * (1".repeat(300_000)),
+        ] {
+            let error = parse(&source).unwrap_err();
+            assert_eq!(ErrorKind::ExpressionTooDeep, error.kind);
+        }
+    }
+}
@@ -0,0 +1,327 @@
+use ternary::T12;
+
+use crate::asm::error::Result;
+use crate::asm::parser::{Arg, Instruction};
+use crate::asm::reloc::Place;
+use crate::asm::{Assembler, Operands, WORD_WIDTH, register};
+use crate::inst::Inst;
+use crate::object::RelocKind;
+use crate::operands::{self, IMMEDIATE_WIDTH};
+use crate::registers::{self, Register};
+
+// Each pseudo-instruction and its operands, as in `instructions.md`.
+pub const PSEUDO_INSTRUCTIONS: [(&str, &str); 7] = [
+    ("nop", ""),
+    ("mov", "$dest, $src"),
+    ("not", "$dest, $src"),
+    ("sub", "$dest, $a, $b"),
+    ("li", "$dest, immediate (24)"),
+    ("la", "$dest, address (24)"),
+    ("b", "offset (16)"),
+];
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+enum Pseudo {
+    Nop,
+    Mov,
+    Not,
+    Sub,
+    Li,
+    La,
+    B,
+}
+
+impl Pseudo {
+    fn from_mnemonic(mnemonic: &str) -> Option<Self> {
+        match mnemonic.to_ascii_lowercase().as_str() {
+            "nop" => Some(Pseudo::Nop),
+            "mov" => Some(Pseudo::Mov),
+            "not" => Some(Pseudo::Not),
+            "sub" => Some(Pseudo::Sub),
+            "li" => Some(Pseudo::Li),
+            "la" => Some(Pseudo::La),
+            "b" => Some(Pseudo::B),
+            _ => None,
+        }
+    }
+}
+
+impl Assembler {
+    // `li` only gets the short form when its value is already known in the
+    // first pass, so that forward references always take two words.
+    pub(super) fn pseudo_size(&self, instruction: &Instruction) -> Result<Option<usize>> {
+        let Some(pseudo) = Pseudo::from_mnemonic(&instruction.mnemonic) else {
+            return Ok(None);
+        };
+
+        let ops = Operands {
+            assembler: self,
+            instruction,
+            place: Place::default(),
+        };
+
+        let size = match pseudo {
+            Pseudo::Nop | Pseudo::Mov | Pseudo::Not | Pseudo::B => 1,
+            Pseudo::Sub => {
+                let [dest, lhs, rhs] = ops.args()?;
+                sub_sequence(register(dest)?, register(lhs)?, register(rhs)?).len()
+            }
+            Pseudo::Li => {
+                let [dest, value] = ops.args()?;
+                register(dest)?;
+                let short = match value {
+                    Arg::Expr(expr) => self
+                        .eval(expr)
+                        .is_ok_and(|value| operands::fits_width(value, IMMEDIATE_WIDTH)),
+                    Arg::Register(..) | Arg::String(..) => false,
+                };
+
+                if short { 1 } else { 2 }
+            }
+            Pseudo::La => 2,
+        };
+
+        Ok(Some(size))
+    }
+
+    pub(super) fn expand(
+        &self,
+        instruction: &Instruction,
+        place: Place,
+        size: usize,
+    ) -> Result<Option<Vec<Inst>>> {
+        let Some(pseudo) = Pseudo::from_mnemonic(&instruction.mnemonic) else {
+            return Ok(None);
+        };
+
+        let ops = Operands {
+            assembler: self,
+            instruction,
+            place,
+        };
+
+        let insts = match pseudo {
+            Pseudo::Nop => {
+                ops.empty()?;
+                vec![rrr(
+                    Inst::And,
+                    registers::ZERO,
+                    registers::ZERO,
+                    registers::ZERO,
+                )]
+            }
+            Pseudo::Mov => {
+                let [dest, src] = ops.args()?;
+                vec![rrr(
+                    Inst::Tcmp,
+                    register(dest)?,
+                    register(src)?,
+                    registers::ZERO,
+                )]
+            }
+            Pseudo::Not => {
+                let [dest, src] = ops.args()?;
+                vec![rrr(
+                    Inst::Tcmp,
+                    register(dest)?,
+                    registers::ZERO,
+                    register(src)?,
+                )]
+            }
+            Pseudo::Sub => {
+                let [dest, lhs, rhs] = ops.args()?;
+                sub_sequence(register(dest)?, register(lhs)?, register(rhs)?)
+            }
+            Pseudo::Li | Pseudo::La => {
+                let [dest, value] = ops.args()?;
+                let dest = register(dest)?;
+                if size == 1 {
+                    let value = ops.value(value, WORD_WIDTH, 0)?;
+                    vec![addi(dest, registers::ZERO, value)]
+                } else {
+                    let value = ops.field(value, RelocKind::LoadAddress, WORD_WIDTH, 0)?;
+                    load_upper(dest, value)
+                }
+            }
+            Pseudo::B => {
+                let [target] = ops.args()?;
+                vec![Inst::B0(operands::RO {
+                    src: registers::ZERO,
+                    offset: ops.branch(target)?,
+                })]
+            }
+        };
+
+        debug_assert_eq!(size, insts.len());
+        Ok(Some(insts))
+    }
+}
+
+fn sub_sequence(dest: Register, lhs: Register, rhs: Register) -> Vec<Inst> {
+    if dest != lhs {
+        vec![
+            rrr(Inst::Tcmp, dest, registers::ZERO, rhs),
+            rrr(Inst::Add, dest, lhs, dest),
+        ]
+    } else if dest != rhs {
+        vec![
+            rrr(Inst::Tcmp, rhs, registers::ZERO, rhs),
+            rrr(Inst::Add, dest, lhs, rhs),
+            rrr(Inst::Tcmp, rhs, registers::ZERO, rhs),
+        ]
+    } else {
+        vec![rrr(Inst::Tcmp, dest, registers::ZERO, registers::ZERO)]
+    }
+}
+
+// `ori` can't be used for the low part, since it takes the tritwise maximum
+// and would drop negative trits; adding to the zeroed low trits is exact.
+fn load_upper(dest: Register, value: i64) -> Vec<Inst> {
+    let (hi, lo) = operands::split_address(value);
+    vec![
+        Inst::Lui(operands::RI {
+            dest,
+            immediate: T12::try_from_int(hi).unwrap(),
+        }),
+        addi(dest, dest, lo),
+    ]
+}
+
+fn rrr(f: fn(operands::RRR) -> Inst, dest: Register, lhs: Register, rhs: Register) -> Inst {
+    f(operands::RRR { dest, lhs, rhs })
+}
+
+fn addi(dest: Register, src: Register, value: i64) -> Inst {
+    Inst::Addi(operands::RRI {
+        dest,
+        src,
+        immediate: T12::try_from_int(value).unwrap(),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::asm::assemble;
+    use crate::asm::error::ErrorKind;
+    use crate::asm::tests::words;
+    use crate::syscall::Console;
+    use crate::{StopReason, VM};
+
+    fn insts(source: &str) -> Vec<String> {
+        words(source, 0)
+            .into_iter()
+            .map(|word| Inst::from_word(word).unwrap().to_string())
+            .collect()
+    }
+
+    fn run(source: &str) -> String {
+        let image = assemble(source, 0).unwrap();
+        let console = Console::new(&b""[..], Vec::new());
+        let mut vm = VM::with_syscall_handler(729, console);
+        vm.load_image(&image).unwrap();
+        assert_eq!(StopReason::Exit { code: 0 }, vm.run(image.entry).unwrap());
+        String::from_utf8(vm.syscall_handler().output().clone()).unwrap()
+    }
+
+    #[test]
+    fn expand_simple() {
+        assert_eq!(vec!["and $zero, $zero, $zero"], insts("nop"));
+        assert_eq!(vec!["tcmp $t0, $t1, $zero"], insts("mov $t0, $t1"));
+        assert_eq!(vec!["tcmp $t0, $zero, $t1"], insts("NOT $t0, $t1"));
+        assert_eq!(vec!["b0 $zero, -8"], insts("b -4"));
+    }
+
+    #[test]
+    fn expand_sub() {
+        assert_eq!(
+            vec!["tcmp $t0, $zero, $t2", "add $t0, $t1, $t0"],
+            insts("sub $t0, $t1, $t2")
+        );
+        assert_eq!(
+            vec![
+                "tcmp $t1, $zero, $t1",
+                "add $t0, $t0, $t1",
+                "tcmp $t1, $zero, $t1",
+            ],
+            insts("sub $t0, $t0, $t1")
+        );
+        assert_eq!(vec!["tcmp $t0, $zero, $zero"], insts("sub $t0, $t0, $t0"));
+    }
+
+    #[test]
+    fn expand_li_la() {
+        assert_eq!(vec!["addi $t0, $zero, -265720"], insts("li $t0, -265720"));
+        assert_eq!(
+            vec!["lui $t0, 1", "addi $t0, $t0, -265720"],
+            insts("li $t0, 265721")
+        );
+        assert_eq!(
+            vec!["lui $t0, -2", "addi $t0, $t0, 1"],
+            insts("li $t0, -1062881")
+        );
+        assert_eq!(
+            vec!["lui $t0, 0", "addi $t0, $t0, 8", "syscall"],
+            insts("la $t0, end\nend: syscall")
+        );
+        assert_eq!(
+            vec!["addi $t0, $zero, 0", "lui $t0, 0", "addi $t0, $t0, 12"],
+            insts("start: li $t0, start\nli $t0, end\nend:")
+        );
+    }
+
+    #[test]
+    fn expand_errors() {
+        let error = assemble("li $t0, 141214768241", 0).unwrap_err();
+        assert_eq!(
+            ErrorKind::ImmediateOutOfRange(141_214_768_241, 24),
+            error.kind
+        );
+
+        let error = assemble("li $t0, -9223372036854775807 - 1", 0).unwrap_err();
+        assert_eq!(ErrorKind::ImmediateOutOfRange(i64::MIN, 24), error.kind);
+
+        let error = assemble("mov $t0, 1", 0).unwrap_err();
+        assert_eq!(ErrorKind::ExpectedRegister, error.kind);
+
+        let error = assemble("nop $t0", 0).unwrap_err();
+        assert_eq!(ErrorKind::WrongOperandCount(0, 1), error.kind);
+    }
+
+    #[test]
+    fn run_expansions() {
+        let source = "
+            _start:
+                li $s0, -141213768240
+                li $s1, 141214768240
+                li $s2, 1000000
+                sub $t0, $s2, $s0
+                mov $t1, $s1
+                sub $t1, $t1, $s2
+                not $t2, $t1
+                sub $t3, $t3, $t3
+                li $a0, 1
+                mov $a1, $t0
+                syscall
+                li $a0, 3
+                la $a1, sep
+                syscall
+                li $a0, 1
+                mov $a1, $t2
+                b print
+                li $a1, 0
+            print:
+                syscall
+                sub $a1, $s1, $s0
+                add $a1, $s0, $s1
+                add $a1, $a1, $t3
+                sub $a1, $a1, $s2
+                li $a0, 0
+                syscall
+            sep:
+                .word 32
+        ";
+
+        assert_eq!("141214768240 -141213768240", run(source));
+    }
+}
@@ -0,0 +1,365 @@
+use crate::asm::error::{Error, ErrorKind, Result};
+use crate::asm::lexer::Span;
+use crate::asm::parser::{Arg, BinaryOp, Expr, Part};
+use crate::asm::{Assembler, Output, WORD_WIDTH, binary, check_width};
+use crate::object::{Object, RelocKind, Relocation, Section, Symbol, Target};
+use crate::operands::IMMEDIATE_WIDTH;
+
+// Where a value is being emitted. In a relocatable object `addr` is an offset
+// into `section`.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Place {
+    pub section: Section,
+    pub addr: i64,
+}
+
+#[derive(Clone, Debug, Eq, PartialEq)]
+enum Base {
+    Section(Section),
+    External(String),
+}
+
+// A value that is `offset` past the final address of `base`, or just `offset`
+// if it has no base. `part` selects half of a value with a base, since that
+// can't be applied until the address is known.
+#[derive(Clone, Debug, Eq, PartialEq)]
+struct Value {
+    base: Option<Base>,
+    offset: i64,
+    part: Option<Part>,
+}
+
+impl Value {
+    fn constant(offset: i64) -> Self {
+        Value {
+            base: None,
+            offset,
+            part: None,
+        }
+    }
+}
+
+impl Assembler {
+    pub(super) fn eval_constant(&self, expr: &Expr) -> Result<i64> {
+        match self.eval_value(expr)? {
+            Value {
+                base: None, offset, ..
+            } => Ok(offset),
+            Value { .. } => Err(not_relocatable(expr.span())),
+        }
+    }
+
+    // Returns the value to encode for `arg` at `place`, recording a relocation
+    // and returning 0 if it depends on an address that is only known at link
+    // time. Branches within the same section don't need one, since the
+    // distance between two places in a section is fixed.
+    pub(super) fn relocate(
+        &self,
+        arg: &Arg,
+        kind: RelocKind,
+        place: Place,
+        width: usize,
+        bias: i64,
+    ) -> Result<i64> {
+        let Arg::Expr(expr) = arg else {
+            return Err(Error::new(arg.span(), ErrorKind::ExpectedExpression));
+        };
+
+        let value = self.eval_value(expr)?;
+        match (value.base, value.part) {
+            (Some(Base::Section(section)), None)
+                if kind == RelocKind::Branch && section == place.section =>
+            {
+                let offset = binary(BinaryOp::Add, value.offset, bias, arg.span())?;
+                check_width(offset, width, arg.span())
+            }
+            (Some(base), None) => Ok(self.record(place, kind, base, value.offset)),
+            (None, _) if kind != RelocKind::Branch => check_width(value.offset, width, arg.span()),
+            _ => Err(not_relocatable(arg.span())),
+        }
+    }
+
+    // Like `relocate`, for a 12-trit immediate or offset, which can only be
+    // relocated as half of a word.
+    pub(super) fn relocate_immediate(&self, arg: &Arg, place: Place) -> Result<i64> {
+        let Arg::Expr(expr) = arg else {
+            return Err(Error::new(arg.span(), ErrorKind::ExpectedExpression));
+        };
+
+        let value = self.eval_value(expr)?;
+        match (value.base, value.part) {
+            (None, _) => check_width(value.offset, IMMEDIATE_WIDTH, arg.span()),
+            (Some(base), Some(Part::Hi)) => {
+                Ok(self.record(place, RelocKind::Hi, base, value.offset))
+            }
+            (Some(base), Some(Part::Lo)) => {
+                Ok(self.record(place, RelocKind::Lo, base, value.offset))
+            }
+            (Some(_), None) => Err(not_relocatable(arg.span())),
+        }
+    }
+
+    // Records a relocation and returns the placeholder to encode in its place.
+    fn record(&self, place: Place, kind: RelocKind, base: Base, addend: i64) -> i64 {
+        let target = match base {
+            Base::Section(section) => Target::Section(section),
+            Base::External(name) => Target::Symbol(name),
+        };
+
+        self.relocations.borrow_mut().push(Relocation {
+            section: place.section,
+            offset: u32::try_from(place.addr).unwrap(),
+            kind,
+            target,
+            addend,
+        });
+
+        0
+    }
+
+    fn eval_value(&self, expr: &Expr) -> Result<Value> {
+        match expr {
+            Expr::Integer(value, _) => Ok(Value::constant(*value)),
+            Expr::Symbol(name, _) => {
+                if let Some(&value) = self.symbols.get(name) {
+                    Ok(Value::constant(value))
+                } else if let Some(&(section, offset)) = self.relative_symbols.get(name) {
+                    Ok(Value {
+                        base: Some(Base::Section(section)),
+                        offset,
+                        part: None,
+                    })
+                } else {
+                    Ok(Value {
+                        base: Some(Base::External(name.clone())),
+                        offset: 0,
+                        part: None,
+                    })
+                }
+            }
+            Expr::Neg(inner, span) => {
+                let offset = self.eval_constant(inner)?;
+                offset
+                    .checked_neg()
+                    .map(Value::constant)
+                    .ok_or_else(|| Error::new(*span, ErrorKind::Overflow))
+            }
+            Expr::Binary(op, lhs, rhs, span) => {
+                let (lhs, rhs) = (self.eval_value(lhs)?, self.eval_value(rhs)?);
+                if lhs.part.is_some() || rhs.part.is_some() {
+                    return Err(not_relocatable(expr.span()));
+                }
+
+                let base = match (op, lhs.base, rhs.base) {
+                    (_, None, None) => None,
+                    (BinaryOp::Add | BinaryOp::Sub, Some(base), None)
+                    | (BinaryOp::Add, None, Some(base)) => Some(base),
+                    (BinaryOp::Sub, Some(lhs), Some(rhs))
+                        if lhs == rhs && matches!(lhs, Base::Section(_)) =>
+                    {
+                        None
+                    }
+                    _ => return Err(not_relocatable(expr.span())),
+                };
+
+                Ok(Value {
+                    base,
+                    offset: binary(*op, lhs.offset, rhs.offset, *span)?,
+                    part: None,
+                })
+            }
+            Expr::Part(part, inner, _) => match self.eval_value(inner)? {
+                Value { part: Some(_), .. } => Err(not_relocatable(expr.span())),
+                Value {
+                    base: None, offset, ..
+                } => {
+                    let offset = check_width(offset, WORD_WIDTH, expr.span())?;
+                    Ok(Value::constant(part.apply(offset)))
+                }
+                value => Ok(Value {
+                    part: Some(*part),
+                    ..value
+                }),
+            },
+        }
+    }
+
+    pub(super) fn object(self, output: &Output) -> Result<Object> {
+        let mut symbols: Vec<_> = self
+            .relative_symbols
+            .iter()
+            .map(|(name, &(section, offset))| Symbol {
+                name: name.clone(),
+                section,
+                offset: u32::try_from(offset).unwrap(),
+                global: self.globals.iter().any(|(global, _)| global == name),
+            })
+            .collect();
+        symbols.sort_by(|a, b| {
+            (a.section.index(), a.offset, &a.name).cmp(&(b.section.index(), b.offset, &b.name))
+        });
+
+        if let Some((name, span)) = self
+            .globals
+            .iter()
+            .find(|(name, _)| !self.relative_symbols.contains_key(name))
+        {
+            return Err(Error::new(*span, ErrorKind::UndefinedSymbol(name.clone())));
+        }
+
+        Ok(Object {
+            text: output.trytes(Section::Text),
+            data: output.trytes(Section::Data),
+            bss_size: u32::try_from(self.ends[Section::Bss.index()]).unwrap(),
+            symbols,
+            relocations: self.relocations.into_inner(),
+            ..Object::default()
+        })
+    }
+}
+
+fn not_relocatable(span: Span) -> Error {
+    Error::new(span, ErrorKind::NotRelocatable)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::asm::assemble_object;
+
+    fn relocations(source: &str) -> Vec<(Section, u32, RelocKind, Target, i64)> {
+        assemble_object(source)
+            .unwrap()
+            .relocations
+            .into_iter()
+            .map(|r| (r.section, r.offset, r.kind, r.target, r.addend))
+            .collect()
+    }
+
+    fn error(source: &str) -> (usize, usize, ErrorKind) {
+        let error = assemble_object(source).unwrap_err();
+        (error.line, error.column, error.kind)
+    }
+
+    #[test]
+    fn assemble_relocations() {
+        let source = "
+            .global main
+            main:
+                jal helper
+                bal helper
+                b1 $t0, main
+                la $a0, table + 4
+                li $a1, end - main
+            end:
+            .data
+            table: .word 1, end, helper - 4
+            .bss
+            buf: .zero 3
+        ";
+
+        let object = assemble_object(source).unwrap();
+        assert_eq!(28, object.text.len());
+        assert_eq!(12, object.data.len());
+        assert_eq!(3, object.bss_size);
+
+        let symbols: Vec<_> = object
+            .symbols
+            .iter()
+            .map(|symbol| {
+                (
+                    symbol.name.as_str(),
+                    symbol.section,
+                    symbol.offset,
+                    symbol.global,
+                )
+            })
+            .collect();
+        assert_eq!(
+            vec![
+                ("main", Section::Text, 0, true),
+                ("end", Section::Text, 28, false),
+                ("table", Section::Data, 0, false),
+                ("buf", Section::Bss, 0, false),
+            ],
+            symbols
+        );
+        assert_eq!(vec!["helper"], object.undefined());
+
+        let helper = || Target::Symbol("helper".into());
+        assert_eq!(
+            vec![
+                (Section::Text, 0, RelocKind::Address, helper(), 0),
+                (Section::Text, 4, RelocKind::Branch, helper(), 0),
+                (
+                    Section::Text,
+                    12,
+                    RelocKind::LoadAddress,
+                    Target::Section(Section::Data),
+                    4
+                ),
+                (
+                    Section::Data,
+                    4,
+                    RelocKind::Word,
+                    Target::Section(Section::Text),
+                    28
+                ),
+                (Section::Data, 8, RelocKind::Word, helper(), -4),
+            ],
+            relocations(source)
+        );
+
+        let x = || Target::Symbol("x".into());
+        assert_eq!(
+            vec![
+                (Section::Text, 0, RelocKind::Hi, x(), 2),
+                (Section::Text, 4, RelocKind::Lo, x(), 2),
+                (Section::Text, 8, RelocKind::Lo, x(), 2),
+            ],
+            relocations("lui $t0, %hi(x + 2)\naddi $t0, $t0, %lo(x + 2)\nsw $t1, $t0, %lo(x + 2)")
+        );
+    }
+
+    #[test]
+    fn relocation_errors() {
+        assert_eq!(
+            (1, 16, ErrorKind::NotRelocatable),
+            error("addi $t0, $t0, x")
+        );
+        assert_eq!((1, 7, ErrorKind::NotRelocatable), error(".word x * 2"));
+        assert_eq!(
+            (1, 7, ErrorKind::NotRelocatable),
+            error(".word a - b\na:\n.data\nb:")
+        );
+        assert_eq!((1, 7, ErrorKind::NotRelocatable), error(".half x"));
+        assert_eq!((1, 9, ErrorKind::NotRelocatable), error("bT $t0, 8"));
+        assert_eq!(
+            (1, 12, ErrorKind::Overflow),
+            error("x: bT $t0, x - 9223372036854775807 - 1")
+        );
+        assert_eq!(
+            (1, 16, ErrorKind::NotRelocatable),
+            error("addi $t0, $t0, %lo(x) + 1")
+        );
+        assert_eq!(
+            (1, 10, ErrorKind::NotRelocatable),
+            error("lui $t0, %hi(%lo(x))")
+        );
+        assert_eq!((1, 7, ErrorKind::NotRelocatable), error(".word %hi(x)"));
+        assert_eq!((1, 3, ErrorKind::NotRelocatable), error("j %lo(x)"));
+        assert_eq!(
+            (1, 7, ErrorKind::ImmediateOutOfRange(-141_214_768_241, 24)),
+            error(".word %lo(-141214768241)")
+        );
+        assert_eq!(
+            (1, 1, ErrorKind::NotAllowedInObject(".org".into())),
+            error(".org 8")
+        );
+        assert_eq!(
+            (1, 9, ErrorKind::UndefinedSymbol("main".into())),
+            error(".global main")
+        );
+        assert_eq!((1, 9, ErrorKind::ExpectedIdentifier), error(".global 1"));
+    }
+}
@@ -0,0 +1,476 @@
+#![deny(clippy::all, clippy::pedantic)]
+
+use std::collections::{HashMap, HashSet};
+use std::error::Error;
+use std::fmt::Display;
+use std::fs;
+use std::io::{self, BufReader, BufWriter, Write};
+use std::net::TcpListener;
+use std::path::Path;
+use std::process::ExitCode;
+
+use btm::asm::{Listing, Literals};
+use btm::debug::DebugInfo;
+use btm::debugger::{Debugger, PROMPT, Repl};
+use btm::gdb::Stub;
+use btm::link::{self, Layout};
+use btm::map::Map;
+use btm::object::Object;
+use btm::syscall::Console;
+use btm::trace::{BinaryTracer, TextTracer};
+use btm::{Image, StopReason, Trap, VM, asm, dap, disasm, lsp};
+
+const USAGE: &str = "\
+usage: btm <command> [options]
+
+commands:
+  asm <source> -o <image> [--origin ADDR] [--listing FILE] [--map FILE]
+  asm <source> -c -o <object> [--listing FILE] [--map FILE]
+  link <object>... -o <image> [--text ADDR] [--data ADDR] [--bss ADDR] [--map FILE]
+  fmt <source>... [--literals decimal|ternary|heptavintimal] [--check]
+  run <image> [--entry ADDR] [--memory SIZE] [--trace] [--trace-file FILE] [--trace-format text|binary]
+  debug <image> [--entry ADDR] [--memory SIZE]
+  gdb <image> [--entry ADDR] [--memory SIZE] [--port PORT | --stdio]
+  dap
+  lsp
+  disasm <image>";
+
+const DEFAULT_MEMORY_SIZE: u32 = 531_441;
+const DEFAULT_GDB_PORT: u16 = 1234;
+
+type CliResult<T> = Result<T, Box<dyn Error>>;
+
+fn main() -> ExitCode {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    let Some((command, args)) = args.split_first() else {
+        eprintln!("{USAGE}");
+        return ExitCode::from(2);
+    };
+
+    let result = match command.as_str() {
+        "asm" => cmd_asm(args),
+        "link" => cmd_link(args),
+        "fmt" => cmd_fmt(args),
+        "run" => cmd_run(args),
+        "debug" => cmd_debug(args),
+        "gdb" => cmd_gdb(args),
+        "dap" => cmd_dap(args),
+        "lsp" => cmd_lsp(args),
+        "disasm" => cmd_disasm(args),
+        "help" | "-h" | "--help" => {
+            println!("{USAGE}");
+            Ok(ExitCode::SUCCESS)
+        }
+        _ => {
+            eprintln!("btm: unknown command `{command}`\n\n{USAGE}");
+            return ExitCode::from(2);
+        }
+    };
+
+    result.unwrap_or_else(|error| {
+        eprintln!("btm: {error}");
+        ExitCode::FAILURE
+    })
+}
+
+fn cmd_asm(args: &[String]) -> CliResult<ExitCode> {
+    let options = Options::parse(args, &["-o", "--origin", "--listing", "--map"], &["-c"])?;
+    let source_path = options.positional(0, "source")?;
+    let output_path = options.required("-o")?;
+    let source = fs::read_to_string(source_path)?;
+
+    if options.flag("-c") {
+        if options.values.contains_key("--origin") {
+            return Err("`--origin` cannot be used with `-c`".into());
+        }
+
+        let assembly = asm::assemble_object_file_listed(Path::new(source_path), &source)?;
+        fs::write(output_path, assembly.output.to_bytes()?)?;
+        write_reports(&options, &assembly.listing, &assembly.map)?;
+        return Ok(ExitCode::SUCCESS);
+    }
+
+    let origin = options.number("--origin", 0)?;
+    let assembly = asm::assemble_file_listed(Path::new(source_path), &source, origin)?;
+
+    fs::write(output_path, assembly.output.to_bytes()?)?;
+    write_reports(&options, &assembly.listing, &assembly.map)?;
+    Ok(ExitCode::SUCCESS)
+}
+
+fn write_reports(options: &Options, listing: &Listing, map: &Map) -> CliResult<()> {
+    if let Some(path) = options.values.get("--listing") {
+        write_report(path, listing)?;
+    }
+
+    if let Some(path) = options.values.get("--map") {
+        write_report(path, map)?;
+    }
+
+    Ok(())
+}
+
+// `-` writes to stdout.
+fn write_report(path: &str, report: &impl Display) -> CliResult<()> {
+    if path == "-" {
+        print!("{report}");
+    } else {
+        fs::write(path, report.to_string())?;
+    }
+
+    Ok(())
+}
+
+fn cmd_link(args: &[String]) -> CliResult<ExitCode> {
+    let options = Options::parse(args, &["-o", "--text", "--data", "--bss", "--map"], &[])?;
+    let image_path = options.required("-o")?;
+    if options.positional.is_empty() {
+        return Err("missing <object> argument".into());
+    }
+
+    let layout = Layout {
+        text: options.number("--text", 0)?,
+        data: options.optional_number("--data")?,
+        bss: options.optional_number("--bss")?,
+    };
+
+    let objects = options
+        .positional
+        .iter()
+        .map(|path| read_object(path))
+        .collect::<CliResult<Vec<_>>>()?;
+    let (image, map) = link::link_with_map(&objects, &layout)?;
+
+    fs::write(image_path, image.to_bytes()?)?;
+    if let Some(path) = options.values.get("--map") {
+        write_report(path, &map)?;
+    }
+
+    Ok(ExitCode::SUCCESS)
+}
+
+// Rewrites each source in place, or with `--check`, lists the ones that
+// aren't formatted and fails if there are any.
+fn cmd_fmt(args: &[String]) -> CliResult<ExitCode> {
+    let options = Options::parse(args, &["--literals"], &["--check"])?;
+    if options.positional.is_empty() {
+        return Err("missing <source> argument".into());
+    }
+
+    let literals = match options.values.get("--literals") {
+        Some(name) => Literals::from_name(name).ok_or_else(|| {
+            format!("invalid value `{name}` for `--literals`; expected decimal, ternary or heptavintimal")
+        })?,
+        None => Literals::default(),
+    };
+
+    let mut unformatted = false;
+    for path in &options.positional {
+        let source = fs::read_to_string(path)?;
+        let formatted = asm::format(&source, literals).map_err(|mut error| {
+            error.file = Some(path.clone());
+            error
+        })?;
+        if formatted == source {
+            continue;
+        }
+
+        if !asm::same_output(Path::new(path), &source, &formatted) {
+            return Err(format!("formatting `{path}` would change what it assembles to").into());
+        }
+
+        if options.flag("--check") {
+            println!("{path}");
+            unformatted = true;
+        } else {
+            fs::write(path, formatted)?;
+        }
+    }
+
+    Ok(if unformatted {
+        ExitCode::FAILURE
+    } else {
+        ExitCode::SUCCESS
+    })
+}
+
+fn cmd_run(args: &[String]) -> CliResult<ExitCode> {
+    let options = Options::parse(
+        args,
+        &["--entry", "--memory", "--trace-file", "--trace-format"],
+        &["--trace"],
+    )?;
+    let image_path = options.positional(0, "image")?;
+    let memory_size = options.number("--memory", DEFAULT_MEMORY_SIZE)?;
+
+    let image = read_image(image_path)?;
+    let entry = options.number("--entry", image.entry)?;
+    let mut vm = VM::new(memory_size);
+    vm.load_image(&image)?;
+
+    let trace_file = options.values.get("--trace-file");
+    let trace_format = options.values.get("--trace-format").map(String::as_str);
+    if !options.flag("--trace") && trace_file.is_none() && trace_format.is_none() {
+        let result = vm.run(entry);
+        return Ok(report_stop(vm.debug_info(), result));
+    }
+
+    let binary = match trace_format.unwrap_or("text") {
+        "text" => false,
+        "binary" if trace_file.is_some() => true,
+        "binary" => return Err("a binary trace needs `--trace-file`".into()),
+        name => {
+            return Err(format!(
+                "invalid value `{name}` for `--trace-format`; expected text or binary"
+            )
+            .into());
+        }
+    };
+
+    let output: Box<dyn Write> = match trace_file {
+        Some(path) => Box::new(BufWriter::new(fs::File::create(path)?)),
+        None => Box::new(BufWriter::new(io::stderr())),
+    };
+
+    // the trace is flushed before the reason the VM stopped is reported
+    if binary {
+        let mut vm = vm.with_tracer(BinaryTracer::new(output)?);
+        let result = vm.run(entry);
+        vm.tracer_mut().finish()?;
+        Ok(report_stop(vm.debug_info(), result))
+    } else {
+        let debug_info = vm.debug_info().clone();
+        let mut vm = vm.with_tracer(TextTracer::new(output).with_debug_info(debug_info));
+        let result = vm.run(entry);
+        vm.tracer_mut().finish()?;
+        Ok(report_stop(vm.debug_info(), result))
+    }
+}
+
+fn report_stop(debug_info: &DebugInfo, result: Result<StopReason, Trap>) -> ExitCode {
+    match result {
+        Ok(StopReason::Exit { code }) => return exit_code(code),
+        Ok(StopReason::Break { pc }) => {
+            eprintln!("btm: break at pc {pc}{}", annotation(debug_info, pc));
+        }
+        Ok(StopReason::Watch(hit)) => {
+            eprintln!("btm: watchpoint: {hit}{}", annotation(debug_info, hit.pc));
+        }
+        Err(trap) => eprintln!("btm: {trap}{}", annotation(debug_info, trap.pc)),
+    }
+
+    ExitCode::FAILURE
+}
+
+fn cmd_debug(args: &[String]) -> CliResult<ExitCode> {
+    let options = Options::parse(args, &["--entry", "--memory"], &[])?;
+    let image_path = options.positional(0, "image")?;
+    let memory_size = options.number("--memory", DEFAULT_MEMORY_SIZE)?;
+
+    let image = read_image(image_path)?;
+    let entry = options.number("--entry", image.entry)?;
+    let mut vm = VM::new(memory_size);
+    vm.load_image(&image)?;
+    vm.set_pc(entry);
+
+    // stdin is only locked while reading a command, since the program's
+    // syscalls read from it too
+    let mut debugger = Debugger::new(vm);
+    let mut repl = Repl::new(&mut debugger, io::stdout());
+    repl.execute("where")?;
+    loop {
+        print!("{PROMPT}");
+        io::stdout().flush()?;
+
+        let mut line = String::new();
+        if io::stdin().read_line(&mut line)? == 0 || !repl.execute(&line)? {
+            break;
+        }
+    }
+
+    Ok(debugger.exited().map_or(ExitCode::SUCCESS, exit_code))
+}
+
+fn cmd_gdb(args: &[String]) -> CliResult<ExitCode> {
+    let options = Options::parse(args, &["--entry", "--memory", "--port"], &["--stdio"])?;
+    let image_path = options.positional(0, "image")?;
+    let memory_size = options.number("--memory", DEFAULT_MEMORY_SIZE)?;
+    let port = options.number("--port", DEFAULT_GDB_PORT)?;
+
+    let image = read_image(image_path)?;
+    let entry = options.number("--entry", image.entry)?;
+
+    // over stdio, the protocol owns stdin and stdout, so the program's output
+    // goes to stderr and it reads no input
+    if options.flag("--stdio") {
+        let handler = Console::new(io::empty(), io::stderr());
+        let mut vm = VM::with_syscall_handler(memory_size, handler);
+        vm.load_image(&image)?;
+        vm.set_pc(entry);
+
+        let mut debugger = Debugger::new(vm);
+        Stub::new(&mut debugger).serve(BufReader::new(io::stdin()), io::stdout())?;
+        return Ok(debugger.exited().map_or(ExitCode::SUCCESS, exit_code));
+    }
+
+    let mut vm = VM::new(memory_size);
+    vm.load_image(&image)?;
+    vm.set_pc(entry);
+
+    let listener = TcpListener::bind(("127.0.0.1", port))?;
+    eprintln!("btm: listening on {}", listener.local_addr()?);
+    let (stream, _) = listener.accept()?;
+    stream.set_nodelay(true)?;
+
+    let mut debugger = Debugger::new(vm);
+    let reader = BufReader::new(stream.try_clone()?);
+    Stub::new(&mut debugger).serve(reader, stream)?;
+    Ok(debugger.exited().map_or(ExitCode::SUCCESS, exit_code))
+}
+
+fn cmd_dap(args: &[String]) -> CliResult<ExitCode> {
+    Options::parse(args, &[], &[])?;
+    dap::serve(BufReader::new(io::stdin()), io::stdout())?;
+    Ok(ExitCode::SUCCESS)
+}
+
+fn cmd_lsp(args: &[String]) -> CliResult<ExitCode> {
+    Options::parse(args, &[], &[])?;
+    lsp::serve(io::stdin().lock(), io::stdout())?;
+    Ok(ExitCode::SUCCESS)
+}
+
+fn cmd_disasm(args: &[String]) -> CliResult<ExitCode> {
+    let options = Options::parse(args, &[], &[])?;
+    let image_path = options.positional(0, "image")?;
+
+    let image = read_image(image_path)?;
+    let debug_info = DebugInfo::new(&image);
+    for segment in &image.segments {
+        for line in disasm::disassemble_segment(segment)? {
+            for symbol in image
+                .symbols
+                .iter()
+                .filter(|symbol| symbol.addr == line.addr)
+            {
+                println!("{}:", symbol.name);
+            }
+
+            let annotation = debug_info.annotate(line.addr);
+            if annotation.is_empty() {
+                println!("    {:<32} # {}", line.to_string(), line.addr);
+            } else {
+                println!(
+                    "    {:<32} # {:<11} {annotation}",
+                    line.to_string(),
+                    line.addr
+                );
+            }
+        }
+    }
+
+    Ok(ExitCode::SUCCESS)
+}
+
+// ` in function+offset (file:line:column)`, or nothing if `addr` is unknown.
+fn annotation(debug_info: &DebugInfo, addr: i32) -> String {
+    let annotation = debug_info.annotate(addr);
+    if annotation.is_empty() {
+        String::new()
+    } else {
+        format!(" in {annotation}")
+    }
+}
+
+fn read_image(path: &str) -> CliResult<Image> {
+    let bytes = fs::read(path)?;
+    Image::from_bytes(&bytes).map_err(|error| format!("{path}: {error}").into())
+}
+
+fn read_object(path: &str) -> CliResult<Object> {
+    let bytes = fs::read(path)?;
+    Object::from_bytes(&bytes).map_err(|error| format!("{path}: {error}").into())
+}
+
+fn exit_code(code: i32) -> ExitCode {
+    let status = u8::try_from(code.rem_euclid(256)).unwrap();
+    ExitCode::from(status)
+}
+
+struct Options {
+    positional: Vec<String>,
+    values: HashMap<String, String>,
+    flags: HashSet<String>,
+}
+
+impl Options {
+    fn parse(args: &[String], takes_value: &[&str], flags: &[&str]) -> CliResult<Self> {
+        let mut positional = Vec::new();
+        let mut values = HashMap::new();
+        let mut set = HashSet::new();
+
+        let mut args = args.iter();
+        while let Some(arg) = args.next() {
+            if takes_value.contains(&arg.as_str()) {
+                let value = args
+                    .next()
+                    .ok_or_else(|| format!("missing value for `{arg}`"))?;
+                values.insert(arg.clone(), value.clone());
+            } else if flags.contains(&arg.as_str()) {
+                set.insert(arg.clone());
+            } else if arg.starts_with('-') && arg.len() > 1 && !is_number(arg) {
+                return Err(format!("unknown option `{arg}`").into());
+            } else {
+                positional.push(arg.clone());
+            }
+        }
+
+        Ok(Options {
+            positional,
+            values,
+            flags: set,
+        })
+    }
+
+    fn positional(&self, index: usize, name: &str) -> CliResult<&str> {
+        self.positional
+            .get(index)
+            .map(String::as_str)
+            .ok_or_else(|| format!("missing <{name}> argument").into())
+    }
+
+    fn required(&self, name: &str) -> CliResult<&str> {
+        self.values
+            .get(name)
+            .map(String::as_str)
+            .ok_or_else(|| format!("missing `{name}` option").into())
+    }
+
+    fn flag(&self, name: &str) -> bool {
+        self.flags.contains(name)
+    }
+
+    fn number<T>(&self, name: &str, default: T) -> CliResult<T>
+    where
+        T: std::str::FromStr,
+    {
+        Ok(self.optional_number(name)?.unwrap_or(default))
+    }
+
+    fn optional_number<T>(&self, name: &str) -> CliResult<Option<T>>
+    where
+        T: std::str::FromStr,
+    {
+        match self.values.get(name) {
+            Some(value) => value
+                .parse()
+                .map(Some)
+                .map_err(|_| format!("invalid value `{value}` for `{name}`").into()),
+            None => Ok(None),
+        }
+    }
+}
+
+fn is_number(arg: &str) -> bool {
+    arg.parse::<i64>().is_ok()
+}
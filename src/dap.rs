@@ -0,0 +1,758 @@
+mod variables;
+
+use std::collections::HashMap;
+use std::fs;
+use std::io::{self, BufRead, Cursor, Write};
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::{self, TryRecvError};
+use std::thread;
+
+use crate::asm;
+use crate::debug::Location;
+use crate::debugger::{Debugger, Event};
+use crate::image::Image;
+use crate::json::{self, Value, object};
+use crate::syscall::Console;
+use crate::vm::VM;
+
+use variables::Segment;
+
+const THREAD_ID: i64 = 1;
+const FRAME_ID: i64 = 1;
+
+const DEFAULT_MEMORY_SIZE: u32 = 531_441;
+
+// How many instructions run between checks for new requests, such as `pause`.
+const SLICE: usize = 10_000;
+
+// The program reads its input from the `input` launch argument, and its
+// output is sent to the client as `output` events.
+type Handler = Console<Cursor<Vec<u8>>, Vec<u8>>;
+
+type RequestResult = Result<Value, String>;
+
+// Serves the Debug Adapter Protocol until the client disconnects. Requests
+// are read on another thread, so that they can be handled while the program
+// runs.
+pub fn serve<R: BufRead + Send, W: Write>(mut input: R, output: W) -> io::Result<()> {
+    let (sender, receiver) = mpsc::channel();
+    thread::scope(|scope| {
+        scope.spawn(move || {
+            while let Ok(Some(message)) = json::read_message(&mut input) {
+                // a message that isn't JSON has no request to answer
+                let Ok(message) = message else {
+                    continue;
+                };
+
+                if sender.send(message).is_err() {
+                    break;
+                }
+            }
+        });
+
+        let mut adapter = Adapter::new(output);
+        while !adapter.done {
+            let message = if adapter.run.is_some() {
+                match receiver.try_recv() {
+                    Ok(message) => message,
+                    Err(TryRecvError::Empty) => {
+                        adapter.advance()?;
+                        continue;
+                    }
+                    Err(TryRecvError::Disconnected) => break,
+                }
+            } else {
+                match receiver.recv() {
+                    Ok(message) => message,
+                    Err(_) => break,
+                }
+            };
+
+            adapter.handle(&message)?;
+        }
+
+        Ok(())
+    })
+}
+
+// How the program is running between requests.
+enum Run {
+    Continue,
+    // Runs until the source line changes, or for one instruction if `from`
+    // is None. With `over`, calls run until `ret`, where they return to.
+    Step {
+        over: bool,
+        from: Option<Location>,
+        ret: Option<i32>,
+    },
+}
+
+struct Session {
+    debugger: Debugger<Handler>,
+    segments: Vec<Segment>,
+    stop_on_entry: bool,
+    // the breakpoints set in each source, by canonical path
+    breakpoints: HashMap<PathBuf, Vec<i32>>,
+}
+
+struct Adapter<W> {
+    output: W,
+    seq: i64,
+    session: Option<Session>,
+    run: Option<Run>,
+    events: Vec<Value>,
+    done: bool,
+}
+
+impl<W: Write> Adapter<W> {
+    fn new(output: W) -> Self {
+        Adapter {
+            output,
+            seq: 0,
+            session: None,
+            run: None,
+            events: Vec::new(),
+            done: false,
+        }
+    }
+
+    // Responds to a request, then sends the events it caused.
+    fn handle(&mut self, message: &Value) -> io::Result<()> {
+        if message["type"].as_str() != Some("request") {
+            return Ok(());
+        }
+
+        let command = message["command"].as_str().unwrap_or("");
+        let args = &message["arguments"];
+        let result = match command {
+            "initialize" => Ok(capabilities()),
+            "launch" => self.launch(args),
+            "setBreakpoints" => self.set_breakpoints(args),
+            "setExceptionBreakpoints" => Ok(object([("breakpoints", Value::Array(Vec::new()))])),
+            "configurationDone" => self.configuration_done(),
+            "threads" => Ok(object([(
+                "threads",
+                vec![object([("id", THREAD_ID.into()), ("name", "main".into())])].into(),
+            )])),
+            "stackTrace" => self.stack_trace(),
+            "scopes" => Ok(variables::scopes()),
+            "variables" => self.variables(args),
+            "continue" => self.resume(Run::Continue),
+            "next" => self.step(args, true),
+            "stepIn" => self.step(args, false),
+            "stepOut" => self.step_out(),
+            "pause" => Ok(self.pause()),
+            "disconnect" | "terminate" => {
+                self.done = true;
+                Ok(Value::Null)
+            }
+            _ => Err(format!("unsupported request `{command}`")),
+        };
+
+        let mut response = object([
+            ("type", "response".into()),
+            ("request_seq", message["seq"].clone()),
+            ("command", command.into()),
+            ("success", result.is_ok().into()),
+        ]);
+        match result {
+            Ok(body) if !body.is_null() => response.insert("body", body),
+            Ok(_) => {}
+            Err(error) => response.insert("message", error),
+        }
+
+        self.send(response)?;
+        for event in std::mem::take(&mut self.events) {
+            self.send(event)?;
+        }
+
+        Ok(())
+    }
+
+    fn launch(&mut self, args: &Value) -> RequestResult {
+        let program = args["program"]
+            .as_str()
+            .ok_or("missing `program` argument")?;
+        let memory_size = match args["memory"].as_i64() {
+            Some(size) => u32::try_from(size).map_err(|_| format!("invalid memory size {size}"))?,
+            None => DEFAULT_MEMORY_SIZE,
+        };
+        let input = args["input"].as_str().unwrap_or("").as_bytes().to_vec();
+
+        let image = load_program(Path::new(program))?;
+        let handler = Console::new(Cursor::new(input), Vec::new());
+        let mut vm = VM::with_syscall_handler(memory_size, handler);
+        vm.load_image(&image).map_err(|error| error.to_string())?;
+
+        let debugger = Debugger::new(vm);
+        let segments = variables::segments(&image, debugger.debug_info());
+        self.session = Some(Session {
+            debugger,
+            segments,
+            stop_on_entry: args["stopOnEntry"].as_bool().unwrap_or(false),
+            breakpoints: HashMap::new(),
+        });
+
+        self.events.push(event("initialized", Value::Null));
+        Ok(Value::Null)
+    }
+
+    // Breakpoints go on the first instruction of each line, or of the next
+    // line with any code.
+    fn set_breakpoints(&mut self, args: &Value) -> RequestResult {
+        let session = self.session.as_mut().ok_or(NOT_LAUNCHED)?;
+        let path = args["source"]["path"]
+            .as_str()
+            .ok_or("missing source path")?;
+        let path = canonicalize(path);
+
+        let debugger = &mut session.debugger;
+        for addr in session.breakpoints.remove(&path).unwrap_or_default() {
+            debugger.remove_breakpoint(addr);
+        }
+
+        let table = debugger.debug_info().lines();
+        let files: Vec<bool> = table
+            .files
+            .iter()
+            .map(|file| !file.is_empty() && canonicalize(file) == path)
+            .collect();
+
+        let mut addrs = Vec::new();
+        let mut breakpoints = Vec::new();
+        for requested in args["breakpoints"].as_array() {
+            let line = requested["line"].as_i64().unwrap_or(0);
+            let found = table
+                .lines
+                .iter()
+                .filter(|entry| files[usize::try_from(entry.file).unwrap()])
+                .filter(|entry| i64::from(entry.line) >= line)
+                .min_by_key(|entry| (entry.line, entry.addr));
+
+            let breakpoint = match found {
+                Some(entry) => {
+                    addrs.push(entry.addr);
+                    object([
+                        ("verified", true.into()),
+                        ("line", i64::from(entry.line).into()),
+                        ("instructionReference", entry.addr.to_string().into()),
+                    ])
+                }
+                None => object([
+                    ("verified", false.into()),
+                    ("line", line.into()),
+                    ("message", format!("no code at line {line}").into()),
+                ]),
+            };
+            breakpoints.push(breakpoint);
+        }
+
+        for &addr in &addrs {
+            debugger.add_breakpoint(addr);
+        }
+        session.breakpoints.insert(path, addrs);
+
+        Ok(object([("breakpoints", breakpoints.into())]))
+    }
+
+    fn configuration_done(&mut self) -> RequestResult {
+        let session = self.session.as_ref().ok_or(NOT_LAUNCHED)?;
+        let pc = session.debugger.vm().pc();
+        if session.stop_on_entry {
+            self.events.push(stopped("entry", None));
+        } else if session.debugger.breakpoints().contains(&pc) {
+            self.events.push(stopped("breakpoint", None));
+        } else {
+            self.run = Some(Run::Continue);
+        }
+
+        Ok(Value::Null)
+    }
+
+    fn stack_trace(&self) -> RequestResult {
+        let session = self.session.as_ref().ok_or(NOT_LAUNCHED)?;
+        let pc = session.debugger.vm().pc();
+        let annotation = session.debugger.debug_info().annotate(pc);
+
+        let name = match &annotation.function {
+            Some((name, 0)) => name.clone(),
+            Some((name, offset)) => format!("{name}+{offset}"),
+            None => format!("pc {pc}"),
+        };
+        let mut frame = object([
+            ("id", FRAME_ID.into()),
+            ("name", name.into()),
+            ("line", 0.into()),
+            ("column", 0.into()),
+            ("instructionPointerReference", pc.to_string().into()),
+        ]);
+
+        if let Some(location) = &annotation.location {
+            frame.insert("line", location.line);
+            frame.insert("column", location.column);
+            if let Some(file) = &location.file {
+                frame.insert("source", source(file));
+            }
+        }
+
+        Ok(object([
+            ("stackFrames", vec![frame].into()),
+            ("totalFrames", 1.into()),
+        ]))
+    }
+
+    fn variables(&self, args: &Value) -> RequestResult {
+        let session = self.session.as_ref().ok_or(NOT_LAUNCHED)?;
+        let reference = args["variablesReference"].as_i64().unwrap_or(0);
+        let start = args["start"]
+            .as_i64()
+            .and_then(|start| usize::try_from(start).ok())
+            .unwrap_or(0);
+        let count = args["count"]
+            .as_i64()
+            .and_then(|count| usize::try_from(count).ok())
+            .filter(|&count| count > 0);
+
+        let variables = variables::variables(
+            &session.debugger,
+            &session.segments,
+            reference,
+            start,
+            count,
+        )
+        .ok_or_else(|| format!("unknown variables reference {reference}"))?;
+        Ok(object([("variables", variables.into())]))
+    }
+
+    fn step(&mut self, args: &Value, over: bool) -> RequestResult {
+        let session = self.session.as_ref().ok_or(NOT_LAUNCHED)?;
+        let from = if args["granularity"].as_str() == Some("instruction") {
+            None
+        } else {
+            let debugger = &session.debugger;
+            debugger.debug_info().location(debugger.vm().pc())
+        };
+
+        self.resume(Run::Step {
+            over,
+            from,
+            ret: None,
+        })
+    }
+
+    // Runs until the current function returns to $ra.
+    fn step_out(&mut self) -> RequestResult {
+        let session = self.session.as_ref().ok_or(NOT_LAUNCHED)?;
+        let ra = session.debugger.vm().registers()[crate::registers::RA];
+        let ret = ra
+            .try_into_int()
+            .ok()
+            .and_then(|ra: i64| i32::try_from(ra).ok());
+
+        self.resume(Run::Step {
+            over: false,
+            from: None,
+            ret,
+        })
+    }
+
+    fn resume(&mut self, run: Run) -> RequestResult {
+        let session = self.session.as_ref().ok_or(NOT_LAUNCHED)?;
+        if let Some(code) = session.debugger.exited() {
+            return Err(format!("the program has exited with code {code}"));
+        }
+
+        self.run = Some(run);
+        Ok(object([("allThreadsContinued", true.into())]))
+    }
+
+    fn pause(&mut self) -> Value {
+        if self.run.take().is_some() {
+            self.flush_output();
+            self.events.push(stopped("pause", None));
+        }
+
+        Value::Null
+    }
+
+    // Runs the program for a while, and reports it if it stops.
+    fn advance(&mut self) -> io::Result<()> {
+        let (Some(session), Some(run)) = (&mut self.session, &mut self.run) else {
+            self.run = None;
+            return Ok(());
+        };
+
+        let event = advance(&mut session.debugger, run);
+        self.flush_output();
+        if let Some(event) = event {
+            self.run = None;
+            self.events.extend(stop_events(&event));
+        }
+
+        for event in std::mem::take(&mut self.events) {
+            self.send(event)?;
+        }
+
+        Ok(())
+    }
+
+    fn flush_output(&mut self) {
+        let Some(session) = &mut self.session else {
+            return;
+        };
+
+        let output = std::mem::take(session.debugger.vm_mut().syscall_handler_mut().output_mut());
+        if !output.is_empty() {
+            let text = String::from_utf8_lossy(&output).into_owned();
+            self.events.push(event(
+                "output",
+                object([("category", "stdout".into()), ("output", text.into())]),
+            ));
+        }
+    }
+
+    fn send(&mut self, mut message: Value) -> io::Result<()> {
+        self.seq += 1;
+        message.insert("seq", self.seq);
+        json::write_message(&mut self.output, &message)
+    }
+}
+
+const NOT_LAUNCHED: &str = "no program has been launched";
+
+fn capabilities() -> Value {
+    object([
+        ("supportsConfigurationDoneRequest", true.into()),
+        ("supportsSteppingGranularity", true.into()),
+        ("supportsTerminateRequest", true.into()),
+    ])
+}
+
+// Returns the event that stopped the program, or None if it's still running.
+fn advance(debugger: &mut Debugger<Handler>, run: &mut Run) -> Option<Event> {
+    let Run::Step { over, from, ret } = run else {
+        return debugger.run(None, SLICE);
+    };
+
+    for _ in 0..SLICE {
+        if let Some(target) = *ret {
+            match debugger.run(Some(target), SLICE)? {
+                Event::Step => *ret = None,
+                event => return Some(event),
+            }
+        } else if let Some(target) = debugger.call_return().filter(|_| *over) {
+            *ret = Some(target);
+            continue;
+        } else {
+            let event = debugger.step();
+            let pc = debugger.vm().pc();
+            if event != Event::Step {
+                return Some(event);
+            } else if debugger.breakpoints().contains(&pc) {
+                return Some(Event::Breakpoint { pc });
+            }
+        }
+
+        let Some(from) = from else {
+            return Some(Event::Step);
+        };
+
+        let pc = debugger.vm().pc();
+        if let Some(location) = debugger.debug_info().location(pc)
+            && (&location.file, location.line) != (&from.file, from.line)
+        {
+            return Some(Event::Step);
+        }
+    }
+
+    None
+}
+
+fn stop_events(stop: &Event) -> Vec<Value> {
+    match stop {
+        Event::Step => vec![stopped("step", None)],
+        Event::Breakpoint { .. } => vec![stopped("breakpoint", None)],
+        Event::Break { .. } => vec![stopped("breakpoint", Some(stop.to_string()))],
+        Event::Watch(_) => vec![stopped("data breakpoint", Some(stop.to_string()))],
+        Event::Trap(_) => vec![stopped("exception", Some(stop.to_string()))],
+        Event::Exit { code } => vec![
+            event("exited", object([("exitCode", (*code).into())])),
+            event("terminated", Value::Null),
+        ],
+    }
+}
+
+fn stopped(reason: &str, text: Option<String>) -> Value {
+    let mut body = object([
+        ("reason", reason.into()),
+        ("threadId", THREAD_ID.into()),
+        ("allThreadsStopped", true.into()),
+    ]);
+    if let Some(text) = text {
+        body.insert("description", text.clone());
+        body.insert("text", text);
+    }
+
+    event("stopped", body)
+}
+
+fn event(name: &str, body: Value) -> Value {
+    let mut event = object([("type", "event".into()), ("event", name.into())]);
+    if !body.is_null() {
+        event.insert("body", body);
+    }
+
+    event
+}
+
+fn source(file: &str) -> Value {
+    let path = canonicalize(file);
+    let name = path.file_name().map_or_else(
+        || file.to_string(),
+        |name| name.to_string_lossy().into_owned(),
+    );
+    object([
+        ("name", name.into()),
+        ("path", path.to_string_lossy().into_owned().into()),
+    ])
+}
+
+fn canonicalize(path: &str) -> PathBuf {
+    fs::canonicalize(path).unwrap_or_else(|_| PathBuf::from(path))
+}
+
+// Assembles `.s` files, and reads anything else as an image.
+fn load_program(path: &Path) -> Result<Image, String> {
+    let describe = |error: &dyn std::fmt::Display| format!("{}: {error}", path.display());
+    if path.extension().is_some_and(|extension| extension == "s") {
+        let source = fs::read_to_string(path).map_err(|error| describe(&error))?;
+        asm::assemble_file(path, &source, 0).map_err(|error| error.to_string())
+    } else {
+        let bytes = fs::read(path).map_err(|error| describe(&error))?;
+        Image::from_bytes(&bytes).map_err(|error| describe(&error))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::{BufReader, PipeReader, PipeWriter};
+
+    use super::*;
+
+    const PROGRAM: &str = "\
+main:
+    li $t0, 3
+loop:
+    jal square
+    addi $t0, $t0, -1
+    cmp $t1, $t0, $zero
+    b1 $t1, loop
+    li $a0, 1
+    li $a1, 42
+    syscall
+    li $a0, 0
+    li $a1, 7
+    syscall
+square:
+    mul $t0, $t0
+    jr $ra
+";
+
+    // Sends requests and reads what comes back, the way an editor would.
+    struct Client {
+        requests: PipeWriter,
+        messages: BufReader<PipeReader>,
+        seq: i64,
+    }
+
+    impl Client {
+        fn request(&mut self, command: &str, arguments: Value) -> Value {
+            self.seq += 1;
+            let request = object([
+                ("seq", self.seq.into()),
+                ("type", "request".into()),
+                ("command", command.into()),
+                ("arguments", arguments),
+            ]);
+            json::write_message(&mut self.requests, &request).unwrap();
+
+            let response = self.receive();
+            assert_eq!(Some("response"), response["type"].as_str());
+            assert_eq!(Some(self.seq), response["request_seq"].as_i64());
+            assert_eq!(Some(true), response["success"].as_bool(), "{response}");
+            response["body"].clone()
+        }
+
+        fn event(&mut self, name: &str) -> Value {
+            let event = self.receive();
+            assert_eq!(Some(name), event["event"].as_str(), "{event}");
+            event["body"].clone()
+        }
+
+        fn stopped(&mut self, reason: &str) -> i64 {
+            assert_eq!(Some(reason), self.event("stopped")["reason"].as_str());
+            let trace = self.request("stackTrace", object([("threadId", 1.into())]));
+            trace["stackFrames"][0]["line"].as_i64().unwrap()
+        }
+
+        fn receive(&mut self) -> Value {
+            json::read_message(&mut self.messages)
+                .unwrap()
+                .unwrap()
+                .unwrap()
+        }
+    }
+
+    fn session(name: &str, source: &str, test: impl FnOnce(&mut Client, &str)) {
+        let file = format!("btm-dap-{name}-{}.s", std::process::id());
+        let path = std::env::temp_dir().join(file);
+        fs::write(&path, source).unwrap();
+
+        let (request_reader, request_writer) = io::pipe().unwrap();
+        let (message_reader, message_writer) = io::pipe().unwrap();
+        thread::scope(|scope| {
+            scope.spawn(|| serve(BufReader::new(request_reader), message_writer).unwrap());
+
+            let mut client = Client {
+                requests: request_writer,
+                messages: BufReader::new(message_reader),
+                seq: 0,
+            };
+            let capabilities = client.request("initialize", object([("adapterID", "btm".into())]));
+            assert_eq!(
+                Some(true),
+                capabilities["supportsConfigurationDoneRequest"].as_bool()
+            );
+
+            test(&mut client, path.to_str().unwrap());
+            client.request("disconnect", Value::Null);
+        });
+
+        fs::remove_file(path).unwrap();
+    }
+
+    fn breakpoints(path: &str, lines: &[i64]) -> Value {
+        let lines: Vec<_> = lines
+            .iter()
+            .map(|&line| object([("line", line.into())]))
+            .collect();
+        object([
+            ("source", object([("path", path.into())])),
+            ("breakpoints", lines.into()),
+        ])
+    }
+
+    fn variable<'a>(variables: &'a Value, name: &str) -> &'a str {
+        variables["variables"]
+            .as_array()
+            .iter()
+            .find(|variable| variable["name"].as_str() == Some(name))
+            .and_then(|variable| variable["value"].as_str())
+            .unwrap()
+    }
+
+    #[test]
+    fn launch_and_step() {
+        session("step", PROGRAM, |client, path| {
+            let launch = object([("program", path.into()), ("stopOnEntry", true.into())]);
+            client.request("launch", launch);
+            client.event("initialized");
+
+            let body = client.request("setBreakpoints", breakpoints(path, &[3, 14, 40]));
+            let lines: Vec<_> = body["breakpoints"]
+                .as_array()
+                .iter()
+                .map(|breakpoint| {
+                    (
+                        breakpoint["verified"].as_bool(),
+                        breakpoint["line"].as_i64(),
+                    )
+                })
+                .collect();
+            assert_eq!(
+                vec![
+                    (Some(true), Some(4)),
+                    (Some(true), Some(15)),
+                    (Some(false), Some(40))
+                ],
+                lines
+            );
+            client.request("setBreakpoints", breakpoints(path, &[15]));
+
+            client.request("configurationDone", Value::Null);
+            assert_eq!(2, client.stopped("entry"));
+
+            let threads = client.request("threads", Value::Null);
+            assert_eq!(Some("main"), threads["threads"][0]["name"].as_str());
+
+            client.request("continue", object([("threadId", 1.into())]));
+            assert_eq!(15, client.stopped("breakpoint"));
+            let frame = client.request("stackTrace", Value::Null)["stackFrames"][0].clone();
+            assert_eq!(Some("square"), frame["name"].as_str());
+            let source = canonicalize(frame["source"]["path"].as_str().unwrap());
+            assert_eq!(canonicalize(path), source);
+
+            let scopes = client.request("scopes", object([("frameId", 1.into())]));
+            let registers = scopes["scopes"][0]["variablesReference"].clone();
+            let variables =
+                client.request("variables", object([("variablesReference", registers)]));
+            assert_eq!("3", variable(&variables, "$t0"));
+            assert_eq!("44", variable(&variables, "pc"));
+            assert_eq!("8", variable(&variables, "$ra"));
+
+            let memory = scopes["scopes"][1]["variablesReference"].clone();
+            let views = client.request("variables", object([("variablesReference", memory)]));
+            let main = views["variables"][1].clone();
+            assert_eq!(Some("main"), main["name"].as_str());
+            assert_eq!(Some(13), main["indexedVariables"].as_i64());
+            let words = client.request(
+                "variables",
+                object([
+                    ("variablesReference", main["variablesReference"].clone()),
+                    ("start", 11.into()),
+                    ("count", 1.into()),
+                ]),
+            );
+            assert_eq!(1, words["variables"].as_array().len());
+            assert_eq!(Some("[44]"), words["variables"][0]["name"].as_str());
+
+            client.request("next", object([("threadId", 1.into())]));
+            assert_eq!(16, client.stopped("step"));
+            client.request("stepOut", object([("threadId", 1.into())]));
+            assert_eq!(5, client.stopped("step"));
+
+            client.request("setBreakpoints", breakpoints(path, &[]));
+            client.request("next", Value::Null);
+            assert_eq!(6, client.stopped("step"));
+            let instruction = object([("granularity", "instruction".into())]);
+            client.request("stepIn", instruction);
+            assert_eq!(7, client.stopped("step"));
+            client.request("next", Value::Null);
+            assert_eq!(4, client.stopped("step"));
+            client.request("next", Value::Null);
+            assert_eq!(5, client.stopped("step"));
+
+            client.request("continue", Value::Null);
+            let output = client.event("output");
+            assert_eq!(Some("42"), output["output"].as_str());
+            assert_eq!(Some(7), client.event("exited")["exitCode"].as_i64());
+            client.event("terminated");
+        });
+    }
+
+    #[test]
+    fn pause_running_program() {
+        session("pause", "spin: j spin\n", |client, path| {
+            // skipped, since it isn't JSON
+            client
+                .requests
+                .write_all(b"Content-Length: 1\r\n\r\n{")
+                .unwrap();
+            client.request("launch", object([("program", path.into())]));
+            client.event("initialized");
+            client.request("configurationDone", Value::Null);
+            client.request("pause", object([("threadId", 1.into())]));
+            assert_eq!(1, client.stopped("pause"));
+        });
+    }
+}
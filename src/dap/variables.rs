@@ -0,0 +1,140 @@
+use crate::debug::DebugInfo;
+use crate::debugger::Debugger;
+use crate::image::{Image, tryte_to_i16};
+use crate::json::{Value, object};
+use crate::registers::{self, Register};
+use crate::syscall::SyscallHandler;
+
+const REGISTERS: i64 = 1;
+const MEMORY: i64 = 2;
+const STACK: i64 = 3;
+// the first segment's reference, followed by the others in order
+const SEGMENTS: i64 = 100;
+
+const WORD_SIZE: usize = 4;
+const STACK_WORDS: usize = 16;
+
+// A segment of the program's image, shown as an array of words.
+pub(super) struct Segment {
+    name: String,
+    addr: i32,
+    len: usize,
+}
+
+pub(super) fn segments(image: &Image, debug_info: &DebugInfo) -> Vec<Segment> {
+    image
+        .segments
+        .iter()
+        .enumerate()
+        .map(|(i, segment)| {
+            let name = match debug_info.function(segment.addr) {
+                Some((name, 0)) => name.to_string(),
+                _ => format!("segment {i}"),
+            };
+            Segment {
+                name,
+                addr: segment.addr,
+                len: segment.trytes.len(),
+            }
+        })
+        .collect()
+}
+
+pub(super) fn scopes() -> Value {
+    let scope = |name: &str, reference: i64| {
+        object([
+            ("name", name.into()),
+            ("variablesReference", reference.into()),
+            ("expensive", false.into()),
+        ])
+    };
+
+    let mut registers = scope("Registers", REGISTERS);
+    registers.insert("presentationHint", "registers");
+    object([("scopes", vec![registers, scope("Memory", MEMORY)].into())])
+}
+
+// The children of `reference`, from `start` on. Returns None for unknown
+// references.
+pub(super) fn variables<H: SyscallHandler>(
+    debugger: &Debugger<H>,
+    segments: &[Segment],
+    reference: i64,
+    start: usize,
+    count: Option<usize>,
+) -> Option<Vec<Value>> {
+    let vm = debugger.vm();
+    let variables: Vec<Value> = match reference {
+        REGISTERS => {
+            let pc = variable("pc", i64::from(vm.pc()).to_string());
+            let registers = Register::all().map(|register| {
+                let value: i64 = vm.registers()[register].try_into_int().unwrap();
+                variable(&register.to_string(), value.to_string())
+            });
+            page(std::iter::once(pc).chain(registers), start, count).collect()
+        }
+        MEMORY => {
+            let sp: i64 = vm.registers()[registers::SP].try_into_int().unwrap();
+            let mut stack = variable("stack", format!("{STACK_WORDS} words at $sp ({sp})"));
+            stack.insert("variablesReference", STACK);
+            stack.insert("indexedVariables", STACK_WORDS);
+
+            let views = segments.iter().zip(SEGMENTS..).map(|(segment, reference)| {
+                let mut view = variable(
+                    &segment.name,
+                    format!("{} trytes at {}", segment.len, segment.addr),
+                );
+                view.insert("variablesReference", reference);
+                view.insert("indexedVariables", segment.len.div_ceil(WORD_SIZE));
+                view
+            });
+            page(std::iter::once(stack).chain(views), start, count).collect()
+        }
+        STACK => {
+            let sp = i32::try_from(vm.registers()[registers::SP].try_into_int::<i64>().unwrap());
+            let words = (0..STACK_WORDS).filter_map(|i| {
+                let addr = sp.ok()?.checked_add(i32::try_from(i * WORD_SIZE).ok()?)?;
+                word(debugger, addr, WORD_SIZE)
+            });
+            page(words, start, count).collect()
+        }
+        _ => {
+            let index = usize::try_from(reference.checked_sub(SEGMENTS)?).ok()?;
+            let segment = segments.get(index)?;
+            let offsets = (0..segment.len).step_by(WORD_SIZE);
+            page(offsets, start, count)
+                .filter_map(|offset| {
+                    let addr = segment.addr.checked_add(i32::try_from(offset).ok()?)?;
+                    word(debugger, addr, WORD_SIZE.min(segment.len - offset))
+                })
+                .collect()
+        }
+    };
+
+    Some(variables)
+}
+
+fn page<T>(
+    items: impl Iterator<Item = T>,
+    start: usize,
+    count: Option<usize>,
+) -> impl Iterator<Item = T> {
+    items.skip(start).take(count.unwrap_or(usize::MAX))
+}
+
+// The value of the little-endian trytes at `addr`, if they're in memory.
+fn word<H: SyscallHandler>(debugger: &Debugger<H>, addr: i32, len: usize) -> Option<Value> {
+    let trytes = debugger.vm().memory().read(addr, len).ok()?;
+    let value = trytes.iter().rev().fold(0, |value, &tryte| {
+        value * 729 + i64::from(tryte_to_i16(tryte))
+    });
+    Some(variable(&format!("[{addr}]"), value.to_string()))
+}
+
+fn variable(name: &str, value: String) -> Value {
+    object([
+        ("name", name.into()),
+        ("value", value.into()),
+        ("variablesReference", 0.into()),
+    ])
+}
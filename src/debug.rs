@@ -0,0 +1,278 @@
+use std::fmt;
+use std::ops::Range;
+
+use crate::image::{self, Error, Image, Reader, Result, Symbol};
+
+// Where an instruction came from. Instructions expanded from a macro are
+// located in the macro's definition.
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct Location {
+    pub file: Option<String>,
+    pub line: usize,
+    pub column: usize,
+}
+
+impl fmt::Display for Location {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if let Some(file) = &self.file {
+            write!(f, "{file}:")?;
+        }
+
+        write!(f, "{}:{}", self.line, self.column)
+    }
+}
+
+// The location of the instruction at `addr`. `file` is an index into the
+// file names of the table, where an empty name is a source without a path.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct SourceLine {
+    pub addr: i32,
+    pub file: u32,
+    pub line: u32,
+    pub column: u32,
+}
+
+// Maps addresses to locations, sorted by address.
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct LineTable {
+    pub files: Vec<String>,
+    pub lines: Vec<SourceLine>,
+}
+
+impl LineTable {
+    pub fn push(&mut self, addr: i32, location: &Location) {
+        let name = location.file.as_deref().unwrap_or("");
+        let file = self
+            .files
+            .iter()
+            .position(|file| file == name)
+            .unwrap_or_else(|| {
+                self.files.push(name.to_string());
+                self.files.len() - 1
+            });
+
+        let line = SourceLine {
+            addr,
+            file: u32::try_from(file).unwrap(),
+            line: u32::try_from(location.line).unwrap_or(u32::MAX),
+            column: u32::try_from(location.column).unwrap_or(u32::MAX),
+        };
+        let index = self.lines.partition_point(|line| line.addr <= addr);
+        self.lines.insert(index, line);
+    }
+
+    pub fn location(&self, addr: i32) -> Option<Location> {
+        let index = self
+            .lines
+            .binary_search_by_key(&addr, |line| line.addr)
+            .ok()?;
+        Some(self.resolve(&self.lines[index]))
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (i32, Location)> + '_ {
+        self.lines
+            .iter()
+            .map(|line| (line.addr, self.resolve(line)))
+    }
+
+    fn resolve(&self, line: &SourceLine) -> Location {
+        let file = &self.files[usize::try_from(line.file).unwrap()];
+        Location {
+            file: (!file.is_empty()).then(|| file.clone()),
+            line: usize::try_from(line.line).unwrap(),
+            column: usize::try_from(line.column).unwrap(),
+        }
+    }
+
+    pub(crate) fn write(&self, bytes: &mut Vec<u8>) -> Result<()> {
+        bytes.extend_from_slice(&image::count(self.files.len())?.to_le_bytes());
+        for file in &self.files {
+            let len = u16::try_from(file.len()).map_err(|_| Error::InvalidSymbol)?;
+            bytes.extend_from_slice(&len.to_le_bytes());
+            bytes.extend_from_slice(file.as_bytes());
+        }
+
+        bytes.extend_from_slice(&image::count(self.lines.len())?.to_le_bytes());
+        for line in &self.lines {
+            bytes.extend_from_slice(&line.addr.to_le_bytes());
+            bytes.extend_from_slice(&line.file.to_le_bytes());
+            bytes.extend_from_slice(&line.line.to_le_bytes());
+            bytes.extend_from_slice(&line.column.to_le_bytes());
+        }
+
+        Ok(())
+    }
+
+    pub(crate) fn read(reader: &mut Reader<'_>) -> Result<Self> {
+        let mut table = LineTable::default();
+        for _ in 0..reader.u32()? {
+            let len = reader.u16()?;
+            let file = String::from_utf8(reader.take(len.into())?.to_vec())
+                .map_err(|_| Error::InvalidSymbol)?;
+            table.files.push(file);
+        }
+
+        for _ in 0..reader.u32()? {
+            let line = SourceLine {
+                addr: reader.i32()?,
+                file: reader.u32()?,
+                line: reader.u32()?,
+                column: reader.u32()?,
+            };
+            if usize::try_from(line.file).map_or(true, |file| file >= table.files.len()) {
+                return Err(Error::InvalidFile(line.file));
+            }
+
+            table.lines.push(line);
+        }
+
+        if !table.lines.is_sorted_by_key(|line| line.addr) {
+            return Err(Error::UnsortedLines);
+        }
+
+        Ok(table)
+    }
+}
+
+// What an image knows about its addresses: the symbol each one follows, and
+// the source line of each instruction.
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct DebugInfo {
+    symbols: Vec<Symbol>,
+    segments: Vec<Range<i64>>,
+    lines: LineTable,
+}
+
+impl DebugInfo {
+    pub fn new(image: &Image) -> Self {
+        let mut symbols = image.symbols.clone();
+        symbols.sort_by(|a, b| (a.addr, &a.name).cmp(&(b.addr, &b.name)));
+
+        let segments = image
+            .segments
+            .iter()
+            .map(|segment| {
+                let start = i64::from(segment.addr);
+                start..start + i64::try_from(segment.trytes.len()).unwrap()
+            })
+            .collect();
+
+        DebugInfo {
+            symbols,
+            segments,
+            lines: image.lines.clone(),
+        }
+    }
+
+    pub fn symbols(&self) -> &[Symbol] {
+        &self.symbols
+    }
+
+    pub fn lines(&self) -> &LineTable {
+        &self.lines
+    }
+
+    pub fn symbol(&self, name: &str) -> Option<i32> {
+        self.symbols
+            .iter()
+            .find(|symbol| symbol.name == name)
+            .map(|symbol| symbol.addr)
+    }
+
+    // The last symbol at or before `addr` in the same segment, and the
+    // distance from it to `addr`.
+    pub fn function(&self, addr: i32) -> Option<(&str, i32)> {
+        let segment = self
+            .segments
+            .iter()
+            .find(|segment| segment.contains(&i64::from(addr)))?;
+        let index = self.symbols.partition_point(|symbol| symbol.addr <= addr);
+        let symbol = self.symbols[..index].last()?;
+        segment
+            .contains(&i64::from(symbol.addr))
+            .then(|| (symbol.name.as_str(), addr - symbol.addr))
+    }
+
+    pub fn location(&self, addr: i32) -> Option<Location> {
+        self.lines.location(addr)
+    }
+
+    pub fn annotate(&self, addr: i32) -> Annotation {
+        Annotation {
+            function: self
+                .function(addr)
+                .map(|(name, offset)| (name.to_string(), offset)),
+            location: self.location(addr),
+        }
+    }
+}
+
+// Displayed as `function+offset (file:line:column)`, leaving out whatever
+// isn't known.
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct Annotation {
+    pub function: Option<(String, i32)>,
+    pub location: Option<Location>,
+}
+
+impl Annotation {
+    pub fn is_empty(&self) -> bool {
+        self.function.is_none() && self.location.is_none()
+    }
+}
+
+impl fmt::Display for Annotation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.function {
+            Some((name, 0)) => write!(f, "{name}")?,
+            Some((name, offset)) => write!(f, "{name}+{offset}")?,
+            None => {}
+        }
+
+        match (&self.function, &self.location) {
+            (Some(_), Some(location)) => write!(f, " ({location})"),
+            (None, Some(location)) => write!(f, "{location}"),
+            _ => Ok(()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::asm::assemble_file;
+
+    use std::path::Path;
+
+    #[test]
+    fn annotate_addresses() {
+        let source = "
+            main: li $t0, 1000000
+                  nop
+            helper: jr $ra
+            .data
+            .word 1
+        ";
+        let image = assemble_file(Path::new("main.s"), source, 100).unwrap();
+        let debug = DebugInfo::new(&image);
+
+        let location = |line, column| Location {
+            file: Some("main.s".into()),
+            line,
+            column,
+        };
+        assert_eq!(Some(location(2, 19)), debug.location(100));
+        assert_eq!(Some(location(2, 19)), debug.location(104));
+        assert_eq!(Some(location(3, 19)), debug.location(108));
+        assert_eq!(None, debug.location(116));
+
+        assert_eq!(Some(("main", 8)), debug.function(108));
+        assert_eq!(Some(("helper", 0)), debug.function(112));
+        assert_eq!(None, debug.function(116));
+        assert_eq!(None, debug.function(99));
+
+        assert_eq!("main+4 (main.s:2:19)", debug.annotate(104).to_string());
+        assert_eq!("helper (main.s:4:21)", debug.annotate(112).to_string());
+        assert_eq!("", debug.annotate(116).to_string());
+    }
+}
@@ -0,0 +1,249 @@
+mod repl;
+
+use std::collections::BTreeSet;
+use std::fmt;
+
+use crate::debug::DebugInfo;
+use crate::inst::Inst;
+use crate::syscall::SyscallHandler;
+use crate::trap::Trap;
+use crate::vm::{StopReason, VM};
+use crate::watch::{Hit, Watchpoint};
+
+pub use repl::{PROMPT, Repl};
+
+// Why execution stopped.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum Event {
+    Step,
+    Breakpoint { pc: i32 },
+    Break { pc: i32 },
+    Watch(Hit),
+    Exit { code: i32 },
+    Trap(Trap),
+}
+
+impl Event {
+    // whether the program can't run any further
+    pub fn is_final(&self) -> bool {
+        matches!(self, Event::Exit { .. })
+    }
+}
+
+impl fmt::Display for Event {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Event::Step => Ok(()),
+            Event::Breakpoint { pc } => write!(f, "breakpoint at pc {pc}"),
+            Event::Break { pc } => write!(f, "break at pc {pc}"),
+            Event::Watch(hit) => write!(f, "watchpoint: {hit}"),
+            Event::Exit { code } => write!(f, "exited with code {code}"),
+            Event::Trap(trap) => write!(f, "{trap}"),
+        }
+    }
+}
+
+// Runs a VM an instruction at a time, stopping at breakpoints. A breakpoint
+// stops execution before the instruction at its address runs.
+pub struct Debugger<H> {
+    vm: VM<H>,
+    breakpoints: BTreeSet<i32>,
+    exit: Option<i32>,
+}
+
+impl<H: SyscallHandler> Debugger<H> {
+    pub fn new(vm: VM<H>) -> Self {
+        Debugger {
+            vm,
+            breakpoints: BTreeSet::new(),
+            exit: None,
+        }
+    }
+
+    pub fn vm(&self) -> &VM<H> {
+        &self.vm
+    }
+
+    pub fn vm_mut(&mut self) -> &mut VM<H> {
+        &mut self.vm
+    }
+
+    pub fn debug_info(&self) -> &DebugInfo {
+        self.vm.debug_info()
+    }
+
+    pub fn breakpoints(&self) -> &BTreeSet<i32> {
+        &self.breakpoints
+    }
+
+    // Returns false if there was already a breakpoint at `addr`.
+    pub fn add_breakpoint(&mut self, addr: i32) -> bool {
+        self.breakpoints.insert(addr)
+    }
+
+    pub fn remove_breakpoint(&mut self, addr: i32) -> bool {
+        self.breakpoints.remove(&addr)
+    }
+
+    pub fn watchpoints(&self) -> &[Watchpoint] {
+        self.vm.watchpoints()
+    }
+
+    pub fn add_watchpoint(&mut self, watchpoint: Watchpoint) -> bool {
+        self.vm.add_watchpoint(watchpoint)
+    }
+
+    pub fn remove_watchpoint(&mut self, watchpoint: &Watchpoint) -> bool {
+        self.vm.remove_watchpoint(watchpoint)
+    }
+
+    pub fn exited(&self) -> Option<i32> {
+        self.exit
+    }
+
+    pub fn step(&mut self) -> Event {
+        if let Some(code) = self.exit {
+            return Event::Exit { code };
+        }
+
+        let event = match self.vm.step() {
+            Ok(None) => Event::Step,
+            Ok(Some(StopReason::Break { pc })) => Event::Break { pc },
+            Ok(Some(StopReason::Watch(hit))) => Event::Watch(hit),
+            Ok(Some(StopReason::Exit { code })) => Event::Exit { code },
+            Err(trap) => Event::Trap(trap),
+        };
+
+        if let Event::Exit { code } = event {
+            self.exit = Some(code);
+        }
+
+        event
+    }
+
+    // Steps over calls made with `jal`, `jalr` or `bal`, running until they
+    // return to the next instruction.
+    pub fn step_over(&mut self) -> Event {
+        match self.call_return() {
+            Some(addr) => self.cont_until(Some(addr)),
+            None => self.step(),
+        }
+    }
+
+    // Where a call at pc returns to, if the next instruction is one.
+    pub fn call_return(&self) -> Option<i32> {
+        let pc = self.vm.pc();
+        let is_call = self
+            .vm
+            .memory()
+            .load(pc)
+            .ok()
+            .and_then(|word| Inst::from_word(word).ok())
+            .is_some_and(|inst| matches!(inst, Inst::Jal(_) | Inst::Jalr(_) | Inst::Bal(_)));
+        is_call.then_some(pc + 4)
+    }
+
+    // Runs until a breakpoint or until the program stops. A breakpoint at the
+    // current pc doesn't stop it, so that execution can continue from one.
+    pub fn cont(&mut self) -> Event {
+        self.cont_until(None)
+    }
+
+    fn cont_until(&mut self, target: Option<i32>) -> Event {
+        loop {
+            if let Some(event) = self.run(target, usize::MAX) {
+                return event;
+            }
+        }
+    }
+
+    // Like `cont`, but also stops with `Event::Step` once pc reaches
+    // `target`. Returns None if the program is still running after `limit`
+    // instructions, so that callers can do other work in between.
+    pub fn run(&mut self, target: Option<i32>, limit: usize) -> Option<Event> {
+        for _ in 0..limit {
+            let event = self.step();
+            if event != Event::Step {
+                return Some(event);
+            }
+
+            let pc = self.vm.pc();
+            if target == Some(pc) {
+                return Some(Event::Step);
+            }
+
+            if self.breakpoints.contains(&pc) {
+                return Some(Event::Breakpoint { pc });
+            }
+        }
+
+        None
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use std::io;
+
+    use super::*;
+    use crate::asm::assemble;
+    use crate::syscall::Console;
+
+    pub(crate) const PROGRAM: &str = "
+        main:
+            li $t0, 3
+        loop:
+            jal square
+            addi $t0, $t0, -1
+            cmp $t1, $t0, $zero
+            b1 $t1, loop
+            break
+            li $a0, 0
+            li $a1, 7
+            syscall
+        square:
+            mul $t0, $t0
+            jr $ra
+    ";
+
+    pub(crate) fn debugger(source: &str) -> Debugger<Console<io::Empty, Vec<u8>>> {
+        let image = assemble(source, 0).unwrap();
+        let mut vm = VM::with_syscall_handler(729, Console::new(io::empty(), Vec::new()));
+        vm.load_image(&image).unwrap();
+        Debugger::new(vm)
+    }
+
+    #[test]
+    fn step_next_continue() {
+        let mut debugger = debugger(PROGRAM);
+        let square = debugger.debug_info().symbol("square").unwrap();
+        let loop_ = debugger.debug_info().symbol("loop").unwrap();
+
+        assert_eq!(Event::Step, debugger.step());
+        assert_eq!(loop_, debugger.vm().pc());
+        assert_eq!(Event::Step, debugger.step());
+        assert_eq!(square, debugger.vm().pc());
+
+        debugger.vm_mut().set_pc(loop_);
+        assert_eq!(Event::Step, debugger.step_over());
+        assert_eq!(loop_ + 4, debugger.vm().pc());
+
+        assert!(debugger.add_breakpoint(square));
+        assert!(!debugger.add_breakpoint(square));
+        assert_eq!(Event::Breakpoint { pc: square }, debugger.cont());
+        assert_eq!(Event::Breakpoint { pc: square }, debugger.cont());
+        assert!(debugger.remove_breakpoint(square));
+
+        debugger.vm_mut().set_pc(loop_);
+        assert_eq!(Some(loop_ + 4), debugger.call_return());
+        assert_eq!(None, debugger.run(None, 1));
+        assert_eq!(None, debugger.call_return());
+        assert_eq!(Some(Event::Step), debugger.run(Some(loop_ + 4), 3));
+
+        let brk = debugger.debug_info().symbol("square").unwrap() - 16;
+        assert_eq!(Event::Break { pc: brk }, debugger.cont());
+        assert_eq!(Event::Exit { code: 7 }, debugger.cont());
+        assert_eq!(Some(7), debugger.exited());
+        assert_eq!(Event::Exit { code: 7 }, debugger.step());
+    }
+}
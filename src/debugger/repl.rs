@@ -0,0 +1,603 @@
+use std::io::{self, Write};
+
+use ternary::{T24, TInt};
+
+use crate::debugger::{Debugger, Event};
+use crate::inst::Inst;
+use crate::registers::Register;
+use crate::syscall::SyscallHandler;
+use crate::watch::{Target, WatchKind, Watchpoint};
+
+pub const PROMPT: &str = "(btm) ";
+
+const HELP: &str = "\
+step [N]              execute N instructions (s)
+next [N]              like step, but run calls to completion (n)
+continue              run until a breakpoint or the program stops (c)
+break [LOC]           set a breakpoint at LOC, or list breakpoints (b)
+delete LOC            remove the breakpoint at LOC (d)
+watch [REG|LOC [N]]   stop after a write to REG or to N trytes at LOC, or list
+                      watchpoints
+rwatch LOC [N]        stop after a read of N trytes at LOC
+awatch LOC [N]        stop after a read or write of N trytes at LOC
+unwatch REG|LOC       remove the watchpoints on REG or at LOC
+registers [REG...]    print all registers, or the ones given (r)
+x/NU LOC              examine N trytes (t), halves (h) or words (w) at LOC
+disasm [LOC] [N]      disassemble N instructions around pc, or from LOC
+set REG VALUE         set a register, or pc
+set t|h|w LOC VALUE   store a tryte, half or word in memory
+where                 show the current instruction
+quit                  stop debugging (q)
+
+LOC is a number, a symbol, a register or pc, optionally followed by +N or -N.
+An empty line repeats the last command.";
+
+type CommandResult<T = ()> = Result<T, String>;
+
+// Reads commands a line at a time and writes their results to `output`.
+pub struct Repl<'a, H, W> {
+    debugger: &'a mut Debugger<H>,
+    output: W,
+    last: String,
+}
+
+impl<'a, H: SyscallHandler, W: Write> Repl<'a, H, W> {
+    pub fn new(debugger: &'a mut Debugger<H>, output: W) -> Self {
+        Repl {
+            debugger,
+            output,
+            last: String::new(),
+        }
+    }
+
+    pub fn output(&self) -> &W {
+        &self.output
+    }
+
+    // Returns false once the user quits.
+    pub fn execute(&mut self, line: &str) -> io::Result<bool> {
+        let line = if line.trim().is_empty() {
+            self.last.clone()
+        } else {
+            line.trim().to_string()
+        };
+
+        self.last.clone_from(&line);
+        let words: Vec<_> = line.split_whitespace().collect();
+        let Some((&command, args)) = words.split_first() else {
+            return Ok(true);
+        };
+
+        let result = match command {
+            "q" | "quit" | "exit" => return Ok(false),
+            "h" | "help" => writeln!(self.output, "{HELP}").map_err(|error| error.to_string()),
+            "s" | "step" => self.run(args, Debugger::step),
+            "n" | "next" => self.run(args, Debugger::step_over),
+            "c" | "continue" => self.run(&[], Debugger::cont),
+            "b" | "break" => self.set_breakpoint(args),
+            "d" | "delete" => self.delete_breakpoint(args),
+            "watch" => self.watch(WatchKind::Write, args),
+            "rwatch" => self.watch(WatchKind::Read, args),
+            "awatch" => self.watch(WatchKind::Access, args),
+            "unwatch" => self.unwatch(args),
+            "r" | "registers" => self.registers(args),
+            "disasm" => self.disassemble(args),
+            "set" => self.set(args),
+            "where" => self.show_pc(),
+            _ if command.starts_with("x/") || command == "x" => {
+                self.examine(command.strip_prefix("x/").unwrap_or(""), args)
+            }
+            _ => Err(format!("unknown command `{command}` (try `help`)")),
+        };
+
+        if let Err(message) = result {
+            writeln!(self.output, "error: {message}")?;
+        }
+
+        Ok(true)
+    }
+
+    fn run(&mut self, args: &[&str], f: fn(&mut Debugger<H>) -> Event) -> CommandResult {
+        let count = match args {
+            [] => 1,
+            [count] => count
+                .parse::<usize>()
+                .map_err(|_| format!("invalid count `{count}`"))?,
+            _ => return Err("too many arguments".into()),
+        };
+
+        if let Some(code) = self.debugger.exited() {
+            return Err(format!("the program has exited with code {code}"));
+        }
+
+        let mut event = Event::Step;
+        for _ in 0..count {
+            event = f(self.debugger);
+            if event != Event::Step {
+                break;
+            }
+        }
+
+        if event != Event::Step {
+            self.print(format_args!("{event}"))?;
+        }
+
+        if !event.is_final() {
+            self.show_pc()?;
+        }
+
+        Ok(())
+    }
+
+    fn set_breakpoint(&mut self, args: &[&str]) -> CommandResult {
+        if args.is_empty() {
+            let breakpoints: Vec<_> = self.debugger.breakpoints().iter().copied().collect();
+            if breakpoints.is_empty() {
+                return self.print(format_args!("no breakpoints"));
+            }
+
+            for addr in breakpoints {
+                let annotation = self.debugger.debug_info().annotate(addr);
+                self.print(format_args!("{addr:>11}  {annotation}"))?;
+            }
+
+            return Ok(());
+        }
+
+        for arg in args {
+            let addr = self.location(arg)?;
+            if addr % 4 != 0 {
+                return Err(format!("address {addr} is not aligned to 4 trytes"));
+            }
+
+            self.debugger.add_breakpoint(addr);
+            let annotation = self.debugger.debug_info().annotate(addr);
+            self.print(format_args!("breakpoint at {addr} {annotation}"))?;
+        }
+
+        Ok(())
+    }
+
+    fn delete_breakpoint(&mut self, args: &[&str]) -> CommandResult {
+        if args.is_empty() {
+            return Err("expected a location".into());
+        }
+
+        for arg in args {
+            let addr = self.location(arg)?;
+            if !self.debugger.remove_breakpoint(addr) {
+                return Err(format!("no breakpoint at {addr}"));
+            }
+        }
+
+        Ok(())
+    }
+
+    fn watch(&mut self, kind: WatchKind, args: &[&str]) -> CommandResult {
+        if args.is_empty() && kind == WatchKind::Write {
+            let watchpoints = self.debugger.watchpoints().to_vec();
+            if watchpoints.is_empty() {
+                return self.print(format_args!("no watchpoints"));
+            }
+
+            for watchpoint in watchpoints {
+                self.print(format_args!("{watchpoint}"))?;
+            }
+
+            return Ok(());
+        }
+
+        let target = match args {
+            [] => return Err("expected a location".into()),
+            [arg] => self.watch_target(arg, 4)?,
+            [arg, size] => {
+                let size = size
+                    .parse::<usize>()
+                    .ok()
+                    .filter(|&size| size > 0)
+                    .ok_or_else(|| format!("invalid size `{size}`"))?;
+                self.watch_target(arg, size)?
+            }
+            _ => return Err("too many arguments".into()),
+        };
+
+        let watchpoint = match target {
+            Target::Register(register) if kind == WatchKind::Write => {
+                Watchpoint::register(register)
+            }
+            Target::Register(_) => return Err("registers can only be watched for writes".into()),
+            Target::Memory { addr, size } => Watchpoint::memory(kind, addr, size),
+        };
+
+        self.debugger.add_watchpoint(watchpoint);
+        self.print(format_args!("watchpoint: {watchpoint}"))
+    }
+
+    fn unwatch(&mut self, args: &[&str]) -> CommandResult {
+        let [arg] = args else {
+            return Err("expected a register or location".into());
+        };
+
+        let target = self.watch_target(arg, 1)?;
+        let watchpoints: Vec<_> = self
+            .debugger
+            .watchpoints()
+            .iter()
+            .filter(|watchpoint| match (watchpoint.target, target) {
+                (Target::Memory { addr, .. }, Target::Memory { addr: other, .. }) => addr == other,
+                (watched, target) => watched == target,
+            })
+            .copied()
+            .collect();
+
+        if watchpoints.is_empty() {
+            return Err(format!("no watchpoint on {arg}"));
+        }
+
+        for watchpoint in &watchpoints {
+            self.debugger.remove_watchpoint(watchpoint);
+        }
+
+        Ok(())
+    }
+
+    // A bare register is watched itself. Any other location, including one
+    // based on a register like `$sp+0`, is an address.
+    fn watch_target(&self, arg: &str, size: usize) -> CommandResult<Target> {
+        if arg.starts_with('$') && !arg.contains(['+', '-']) {
+            return Ok(Target::Register(register(arg)?));
+        }
+
+        let addr = self.location(arg)?;
+        if i32::try_from(size)
+            .ok()
+            .and_then(|size| addr.checked_add(size))
+            .is_none()
+        {
+            return Err(format!("`{arg}` is out of range"));
+        }
+
+        Ok(Target::Memory { addr, size })
+    }
+
+    fn registers(&mut self, args: &[&str]) -> CommandResult {
+        if !args.is_empty() {
+            for arg in args {
+                let register = register(arg)?;
+                let value = self.register_value(register);
+                self.print(format_args!("{register} = {value}"))?;
+            }
+
+            return Ok(());
+        }
+
+        let pc = self.debugger.vm().pc();
+        let mut row = format!("{:>5} {pc:>12}", "pc");
+        for (i, register) in Register::all().enumerate() {
+            let name = register.to_string();
+            let column = format!("{name:>5} {:>12}", self.register_value(register));
+            if (i + 1) % 4 == 0 {
+                self.print(format_args!("{row}"))?;
+                row = column;
+            } else {
+                row = format!("{row}  {column}");
+            }
+        }
+
+        self.print(format_args!("{row}"))
+    }
+
+    fn examine(&mut self, format: &str, args: &[&str]) -> CommandResult {
+        let [arg] = args else {
+            return Err("expected a location".into());
+        };
+
+        let digits = format.trim_end_matches(|c: char| c.is_ascii_alphabetic());
+        let count = if digits.is_empty() {
+            1
+        } else {
+            digits
+                .parse::<usize>()
+                .map_err(|_| format!("invalid count `{digits}`"))?
+        };
+
+        let (size, per_row) = match &format[digits.len()..] {
+            "" | "w" => (4, 4),
+            "h" => (2, 8),
+            "t" => (1, 8),
+            unit => return Err(format!("invalid unit `{unit}`")),
+        };
+
+        let start = self.location(arg)?;
+        let memory = self.debugger.vm().memory();
+        let mut values = Vec::new();
+        for i in 0..count {
+            let addr = i32::try_from(i * size)
+                .ok()
+                .and_then(|offset| start.checked_add(offset))
+                .ok_or("address out of range")?;
+            let value: i64 = match size {
+                1 => load::<1>(memory.load(addr)),
+                2 => load::<2>(memory.load(addr)),
+                _ => load::<4>(memory.load(addr)),
+            }
+            .map_err(|error| error.to_string())?;
+            values.push((addr, value));
+        }
+
+        for row in values.chunks(per_row) {
+            let addr = row[0].0;
+            let values: Vec<_> = row.iter().map(|(_, value)| format!("{value:>9}")).collect();
+            self.print(format_args!("{addr:>11}: {}", values.join(" ")))?;
+        }
+
+        Ok(())
+    }
+
+    fn disassemble(&mut self, args: &[&str]) -> CommandResult {
+        let pc = self.debugger.vm().pc();
+        let (start, count) = match args {
+            [] => (pc.saturating_sub(8), 5),
+            [arg] => (self.location(arg)?, 5),
+            [arg, count] => (
+                self.location(arg)?,
+                count
+                    .parse::<i32>()
+                    .map_err(|_| format!("invalid count `{count}`"))?,
+            ),
+            _ => return Err("too many arguments".into()),
+        };
+
+        let end = count
+            .checked_mul(4)
+            .and_then(|len| start.checked_add(len))
+            .ok_or_else(|| format!("count {count} is out of range"))?;
+        for addr in (start..end).step_by(4) {
+            let Ok(word) = self.debugger.vm().memory().load::<4>(addr) else {
+                continue;
+            };
+
+            let marker = match (addr == pc, self.debugger.breakpoints().contains(&addr)) {
+                (true, true) => "*>",
+                (true, false) => "=>",
+                (false, true) => "* ",
+                (false, false) => "  ",
+            };
+            let text = instruction(word, addr);
+            let annotation = self.debugger.debug_info().annotate(addr);
+            self.print(format_args!("{marker} {addr:>11}  {text:<28} {annotation}"))?;
+        }
+
+        Ok(())
+    }
+
+    fn set(&mut self, args: &[&str]) -> CommandResult {
+        match args {
+            ["pc", value] => {
+                let value = self.location(value)?;
+                self.debugger.vm_mut().set_pc(value);
+                Ok(())
+            }
+            [name, value] => {
+                let register = register(name)?;
+                let value = self.value(value)?;
+                let value =
+                    T24::try_from_int(value).map_err(|_| format!("{value} is out of range"))?;
+                self.debugger.vm_mut().registers_mut()[register] = value;
+                Ok(())
+            }
+            [unit, addr, value] => {
+                let addr = self.location(addr)?;
+                let value = self.value(value)?;
+                let memory = self.debugger.vm_mut().memory_mut();
+                match *unit {
+                    "t" | "tryte" => store::<1>(memory, addr, value),
+                    "h" | "half" => store::<2>(memory, addr, value),
+                    "w" | "word" => store::<4>(memory, addr, value),
+                    _ => Err(format!("invalid unit `{unit}`")),
+                }
+            }
+            _ => Err("expected `set REG VALUE` or `set t|h|w LOC VALUE`".into()),
+        }
+    }
+
+    fn show_pc(&mut self) -> CommandResult {
+        let pc = self.debugger.vm().pc();
+        let text = match self.debugger.vm().memory().load::<4>(pc) {
+            Ok(word) => instruction(word, pc),
+            Err(error) => error.to_string(),
+        };
+        let annotation = self.debugger.debug_info().annotate(pc);
+        self.print(format_args!("=> {pc:>11}  {text:<28} {annotation}"))
+    }
+
+    fn register_value(&self, register: Register) -> i64 {
+        self.debugger.vm().registers()[register]
+            .try_into_int()
+            .unwrap()
+    }
+
+    // A number, symbol, register or pc, plus an optional offset.
+    fn location(&self, arg: &str) -> CommandResult<i32> {
+        let value = self.value(arg)?;
+        i32::try_from(value).map_err(|_| format!("address {value} is out of range"))
+    }
+
+    fn value(&self, arg: &str) -> CommandResult<i64> {
+        // the first character is skipped, since it can be the sign of a number
+        let sign = arg
+            .char_indices()
+            .skip(1)
+            .find(|&(_, c)| c == '+' || c == '-');
+        let (base, offset) = match sign {
+            Some((index, _)) => arg.split_at(index),
+            None => (arg, ""),
+        };
+
+        let offset = if offset.is_empty() {
+            0
+        } else {
+            parse_number(offset.trim_start_matches('+'))
+                .ok_or_else(|| format!("invalid offset `{offset}`"))?
+        };
+
+        let base = if base == "pc" {
+            i64::from(self.debugger.vm().pc())
+        } else if base.starts_with('$') {
+            self.register_value(register(base)?)
+        } else if let Some(value) = parse_number(base) {
+            value
+        } else if let Some(addr) = self.debugger.debug_info().symbol(base) {
+            i64::from(addr)
+        } else {
+            return Err(format!("unknown symbol `{base}`"));
+        };
+
+        base.checked_add(offset)
+            .ok_or_else(|| format!("`{arg}` is out of range"))
+    }
+
+    fn print(&mut self, args: std::fmt::Arguments<'_>) -> CommandResult {
+        writeln!(self.output, "{args}").map_err(|error| error.to_string())
+    }
+}
+
+fn register(name: &str) -> CommandResult<Register> {
+    name.strip_prefix('$')
+        .and_then(Register::from_name)
+        .ok_or_else(|| format!("unknown register `{name}`"))
+}
+
+fn parse_number(text: &str) -> Option<i64> {
+    text.parse().ok()
+}
+
+fn instruction(word: T24, addr: i32) -> String {
+    match Inst::from_word(word) {
+        Ok(inst) => inst.display_at(addr).to_string(),
+        Err(_) => format!(".word {}", word.try_into_int::<i64>().unwrap()),
+    }
+}
+
+fn load<const N: usize>(value: crate::Result<TInt<N>>) -> crate::Result<i64> {
+    Ok(value?.try_into_int()?)
+}
+
+fn store<const N: usize>(memory: &mut crate::Memory, addr: i32, value: i64) -> CommandResult {
+    let value = TInt::<N>::try_from_int(value).map_err(|_| format!("{value} is out of range"))?;
+    memory.store(addr, value).map_err(|error| error.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::debugger::tests::{PROGRAM, debugger};
+
+    fn session(commands: &[&str]) -> String {
+        let mut debugger = debugger(PROGRAM);
+        let mut repl = Repl::new(&mut debugger, Vec::new());
+        for command in commands {
+            if !repl.execute(command).unwrap() {
+                break;
+            }
+        }
+
+        String::from_utf8(repl.output().clone()).unwrap()
+    }
+
+    #[test]
+    fn repl_commands() {
+        let output = session(&[
+            "break square",
+            "c",
+            "r $t0 $ra",
+            "d square",
+            "set $t0 1",
+            "set w 100 -5",
+            "x/2w 100",
+            "x/3t 100",
+            "disasm square-4 3",
+            "c",
+            "",
+            "step",
+            "quit",
+            "where",
+        ]);
+
+        let expected = "\
+breakpoint at 36 square (14:13)
+breakpoint at pc 36
+=>          36  mul $t0, $t0                 square (14:13)
+$t0 = 3
+$ra = 8
+        100:        -5         0
+        100:        -5         0         0
+            32  syscall                      loop+28 (12:13)
+=>          36  mul $t0, $t0                 square (14:13)
+            40  jr $ra                       square+4 (15:13)
+break at pc 20
+=>          24  addi $a0, $zero, 0           loop+20 (10:13)
+exited with code 7
+error: the program has exited with code 7
+";
+        assert_eq!(expected, output);
+    }
+
+    #[test]
+    fn repl_watchpoints() {
+        let output = session(&[
+            "watch $t0",
+            "awatch square 2",
+            "watch",
+            "c",
+            "c",
+            "unwatch $t0",
+            "rwatch $t0",
+            "watch 100 0",
+            "unwatch $t1",
+            "unwatch square",
+            "watch",
+        ]);
+
+        let expected = "\
+watchpoint: write $t0
+watchpoint: access 36 (2 trytes)
+write $t0
+access 36 (2 trytes)
+watchpoint: write to $t0 at pc 0: 0 -> 3
+=>           4  jal 36                       loop (5:13)
+watchpoint: write to $t0 at pc 8: 3 -> 2
+=>          12  cmp $t1, $t0, $zero          loop+8 (7:13)
+error: registers can only be watched for writes
+error: invalid size `0`
+error: no watchpoint on $t1
+no watchpoints
+";
+        assert_eq!(expected, output);
+    }
+
+    #[test]
+    fn repl_errors() {
+        let output = session(&[
+            "bogus",
+            "break nowhere",
+            "break 2",
+            "set $q 1",
+            "x/2z 0",
+            "x é+4",
+            "disasm 0 1000000000",
+        ]);
+        assert_eq!(
+            "\
+error: unknown command `bogus` (try `help`)
+error: unknown symbol `nowhere`
+error: address 2 is not aligned to 4 trytes
+error: unknown register `$q`
+error: invalid unit `z`
+error: unknown symbol `é`
+error: count 1000000000 is out of range
+",
+            output
+        );
+    }
+}
@@ -0,0 +1,143 @@
+use std::fmt;
+use std::ops::Range;
+
+use ternary::{T24, Tryte};
+
+use crate::error::{Error, Result};
+use crate::image::Segment;
+use crate::inst::Inst;
+use crate::memory::Memory;
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct Line {
+    pub addr: i32,
+    pub word: T24,
+    pub inst: Option<Inst>,
+}
+
+impl fmt::Display for Line {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if let Some(inst) = self.inst {
+            write!(f, "{}", inst.display_at(self.addr))
+        } else {
+            let value: i64 = self.word.try_into_int().map_err(|_| fmt::Error)?;
+            write!(f, ".word {value}")
+        }
+    }
+}
+
+pub fn disassemble(memory: &Memory, range: Range<i32>) -> Result<Vec<Line>> {
+    range
+        .step_by(4)
+        .map(|addr| {
+            let word = memory.load(addr)?;
+            let inst = Inst::from_word(word).ok();
+            Ok(Line { addr, word, inst })
+        })
+        .collect()
+}
+
+// Decodes the words a segment covers straight from its trytes, reading the
+// trytes of those words that are outside the segment as zero.
+pub fn disassemble_segment(segment: &Segment) -> Result<Vec<Line>> {
+    let start = segment.addr - segment.addr.rem_euclid(4);
+    let mut trytes = vec![Tryte::ZERO; usize::try_from(segment.addr - start).unwrap()];
+    trytes.extend_from_slice(&segment.trytes);
+    trytes.resize(trytes.len().next_multiple_of(4), Tryte::ZERO);
+
+    trytes
+        .chunks(4)
+        .zip((i64::from(start)..).step_by(4))
+        .map(|(word, addr)| {
+            let addr = i32::try_from(addr).map_err(|_| Error::InvalidAddress(segment.addr))?;
+            let word = T24::try_from(word).unwrap();
+            let inst = Inst::from_word(word).ok();
+            Ok(Line { addr, word, inst })
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::asm;
+    use crate::image::{Image, Segment};
+
+    fn load(memory: &mut Memory, image: &Image) {
+        for segment in &image.segments {
+            memory.write(segment.addr, &segment.trytes).unwrap();
+        }
+    }
+
+    #[test]
+    fn disassemble_program() {
+        let source = "
+            start:
+                addi $t0, $zero, 3
+            loop:
+                addi $t0, $t0, -1
+                b1 $t0, loop
+                bal end
+                .word 40, -1
+                jal start
+            end:
+                syscall
+        ";
+
+        let mut memory = Memory::new(64);
+        let image = asm::assemble(source, -16).unwrap();
+        load(&mut memory, &image);
+
+        let lines = disassemble(&memory, -16..16).unwrap();
+        let text: Vec<_> = lines.iter().map(ToString::to_string).collect();
+        assert_eq!(
+            vec![
+                "addi $t0, $zero, 3",
+                "addi $t0, $t0, -1",
+                "b1 $t0, -12",
+                "bal 12",
+                ".word 40",
+                ".word -1",
+                "jal -16",
+                "syscall",
+            ],
+            text
+        );
+
+        let reassembled = asm::assemble(&text.join("\n"), -16).unwrap();
+        assert_eq!(image.segments, reassembled.segments);
+    }
+
+    #[test]
+    fn disassemble_segments() {
+        let image = asm::assemble(".org 2000000000\nsyscall\n.tryte 40", 0).unwrap();
+        let lines = disassemble_segment(&image.segments[0]).unwrap();
+        let text: Vec<_> = lines.iter().map(ToString::to_string).collect();
+        assert_eq!(vec!["syscall", ".word 40"], text);
+        assert_eq!(2_000_000_004, lines[1].addr);
+
+        let segment = Segment {
+            addr: -2,
+            trytes: image.segments[0].trytes.clone(),
+        };
+        let lines = disassemble_segment(&segment).unwrap();
+        assert_eq!(
+            vec![-4, 0],
+            lines.iter().map(|line| line.addr).collect::<Vec<_>>()
+        );
+
+        let segment = Segment {
+            addr: i32::MAX - 1,
+            trytes: image.segments[0].trytes.clone(),
+        };
+        assert!(disassemble_segment(&segment).is_err());
+    }
+
+    #[test]
+    fn disassemble_out_of_bounds() {
+        let memory = Memory::new(16);
+        assert!(disassemble(&memory, -8..8).is_ok());
+        assert!(disassemble(&memory, -12..0).is_err());
+        assert!(disassemble(&memory, -6..2).is_err());
+    }
+}
@@ -1,9 +1,20 @@
+use std::fmt;
+use std::io;
+
+use crate::image;
+use crate::trap::Trap;
+
 #[derive(Debug)]
 pub enum Error {
     InvalidOpcode(i8),
     InvalidRegister(i8),
     InvalidAddress(i32),
     InvalidAlignment(i32, usize),
+    ImmediateOutOfRange(i64, usize),
+    InvalidSyscall(i64),
+    Io(io::Error),
+    Image(image::Error),
+    Trap(Trap),
     Ternary(ternary::Error),
 }
 
@@ -14,3 +25,44 @@ impl From<ternary::Error> for Error {
         Error::Ternary(error)
     }
 }
+
+impl From<io::Error> for Error {
+    fn from(error: io::Error) -> Self {
+        Error::Io(error)
+    }
+}
+
+impl From<image::Error> for Error {
+    fn from(error: image::Error) -> Self {
+        Error::Image(error)
+    }
+}
+
+impl From<Trap> for Error {
+    fn from(trap: Trap) -> Self {
+        Error::Trap(trap)
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::InvalidOpcode(opcode) => write!(f, "invalid opcode {opcode}"),
+            Error::InvalidRegister(register) => write!(f, "invalid register {register}"),
+            Error::InvalidAddress(addr) => write!(f, "invalid address {addr}"),
+            Error::InvalidAlignment(addr, align) => {
+                write!(f, "address {addr} is not aligned to {align} trytes")
+            }
+            Error::ImmediateOutOfRange(value, width) => {
+                write!(f, "value {value} does not fit in {width} trits")
+            }
+            Error::InvalidSyscall(number) => write!(f, "invalid syscall {number}"),
+            Error::Io(error) => write!(f, "{error}"),
+            Error::Image(error) => write!(f, "{error}"),
+            Error::Trap(trap) => write!(f, "{trap}"),
+            Error::Ternary(error) => write!(f, "{error:?}"),
+        }
+    }
+}
+
+impl std::error::Error for Error {}
@@ -0,0 +1,645 @@
+mod packet;
+
+use std::io::{self, BufRead, Write};
+use std::sync::mpsc::{self, TryRecvError};
+use std::thread;
+
+use ternary::T24;
+
+use crate::debugger::{Debugger, Event};
+use crate::image::{i16_to_tryte, tryte_to_i16};
+use crate::registers::Register;
+use crate::syscall::SyscallHandler;
+use crate::trap::TrapCause;
+use crate::watch::{Target, WatchKind, Watchpoint};
+
+use packet::{Connection, Input, decode_hex, encode_hex};
+
+// pc comes after the 41 registers
+const PC_REGNUM: usize = 41;
+const REGISTER_COUNT: usize = PC_REGNUM + 1;
+const REGISTER_SIZE: usize = 8;
+
+const TRYTE_SIZE: usize = 2;
+
+// How many instructions run between checks for an interrupt
+const SLICE: usize = 10_000;
+
+// The largest packet we accept, and the most trytes an `m` reply can hold
+const PACKET_SIZE: usize = 0x4000;
+const MAX_MEMORY_LEN: usize = PACKET_SIZE / (2 * TRYTE_SIZE);
+
+const EINVAL: &str = "E16";
+const EFAULT: &str = "E0e";
+
+// GDB's own signal numbers, which don't depend on the host
+const SIGINT: u8 = 2;
+const SIGILL: u8 = 4;
+const SIGTRAP: u8 = 5;
+const SIGFPE: u8 = 8;
+const SIGSEGV: u8 = 11;
+const SIGSYS: u8 = 12;
+
+// Serves the GDB remote serial protocol for a debugger. Registers are sent as
+// 64-bit little-endian integers, described by `target_description`. Memory
+// addresses and lengths count trytes, and each tryte is sent as a 16-bit
+// little-endian integer.
+pub struct Stub<'a, H> {
+    debugger: &'a mut Debugger<H>,
+    stop: String,
+    running: bool,
+    done: bool,
+}
+
+impl<'a, H: SyscallHandler> Stub<'a, H> {
+    pub fn new(debugger: &'a mut Debugger<H>) -> Self {
+        Stub {
+            debugger,
+            stop: stop_signal(SIGTRAP),
+            running: false,
+            done: false,
+        }
+    }
+
+    // Handles packets until the client detaches, kills the program or
+    // disconnects. Packets are read on another thread, so that an interrupt
+    // can stop the program while it runs.
+    pub fn serve<R: BufRead + Send, W: Write>(
+        &mut self,
+        mut input: R,
+        output: W,
+    ) -> io::Result<()> {
+        let (sender, receiver) = mpsc::channel();
+        thread::scope(|scope| {
+            scope.spawn(move || {
+                while let Ok(Some(input)) = packet::receive(&mut input) {
+                    if sender.send(input).is_err() {
+                        break;
+                    }
+                }
+            });
+
+            let mut connection = Connection::new(output);
+            while !self.done {
+                let input = if self.running {
+                    match receiver.try_recv() {
+                        Ok(input) => input,
+                        Err(TryRecvError::Empty) => {
+                            if let Some(event) = self.debugger.run(None, SLICE) {
+                                self.running = false;
+                                self.stop = stop_reply(&event);
+                                connection.send(&self.stop)?;
+                            }
+                            continue;
+                        }
+                        Err(TryRecvError::Disconnected) => break,
+                    }
+                } else {
+                    match receiver.recv() {
+                        Ok(input) => input,
+                        Err(_) => break,
+                    }
+                };
+
+                let reply = match input {
+                    Input::Resend => {
+                        connection.resend()?;
+                        continue;
+                    }
+                    Input::Corrupt => {
+                        connection.acknowledge(false)?;
+                        continue;
+                    }
+                    Input::Interrupt => {
+                        if self.running {
+                            self.running = false;
+                            self.stop = stop_signal(SIGINT);
+                        }
+                        stop_signal(SIGINT)
+                    }
+                    Input::Packet(packet) => {
+                        connection.acknowledge(true)?;
+                        if packet == "QStartNoAckMode" {
+                            connection.send("OK")?;
+                            connection.disable_ack();
+                            continue;
+                        }
+
+                        match self.handle(&packet) {
+                            Some(reply) => reply,
+                            None => continue,
+                        }
+                    }
+                };
+
+                connection.send(&reply)?;
+            }
+
+            Ok(())
+        })
+    }
+
+    // Returns None for packets that don't get a reply.
+    fn handle(&mut self, packet: &str) -> Option<String> {
+        let command_len = packet.chars().next().map_or(0, char::len_utf8);
+        let (command, args) = packet.split_at(command_len);
+        let reply = match command {
+            "?" => self.stop.clone(),
+            "g" => self.read_registers(),
+            "G" => self.write_registers(args),
+            "p" => self.read_register(args),
+            "P" => self.write_register(args),
+            "m" => self.read_memory(args),
+            "M" => self.write_memory(args),
+            "c" | "s" => return self.resume(command, args),
+            "Z" => self.set_point(args, true),
+            "z" => self.set_point(args, false),
+            "H" | "T" => "OK".into(),
+            "D" => {
+                self.done = true;
+                "OK".into()
+            }
+            "k" => {
+                self.done = true;
+                return None;
+            }
+            "q" => query(args),
+            _ => String::new(),
+        };
+
+        Some(reply)
+    }
+
+    fn read_registers(&self) -> String {
+        (0..REGISTER_COUNT)
+            .map(|regnum| encode_hex(&self.register_value(regnum).to_le_bytes()))
+            .collect()
+    }
+
+    fn write_registers(&mut self, args: &str) -> String {
+        let Some(bytes) = decode_hex(args.as_bytes()) else {
+            return EINVAL.into();
+        };
+
+        if bytes.len() != REGISTER_COUNT * REGISTER_SIZE {
+            return EINVAL.into();
+        }
+
+        let values: Vec<_> = bytes
+            .chunks(REGISTER_SIZE)
+            .map(|chunk| i64::from_le_bytes(chunk.try_into().unwrap()))
+            .collect();
+        if values
+            .iter()
+            .enumerate()
+            .any(|(regnum, &value)| !self.set_register_value(regnum, value, true))
+        {
+            return EINVAL.into();
+        }
+
+        for (regnum, &value) in values.iter().enumerate() {
+            self.set_register_value(regnum, value, false);
+        }
+
+        "OK".into()
+    }
+
+    fn read_register(&self, args: &str) -> String {
+        match usize::from_str_radix(args, 16) {
+            Ok(regnum) if regnum < REGISTER_COUNT => {
+                encode_hex(&self.register_value(regnum).to_le_bytes())
+            }
+            _ => EINVAL.into(),
+        }
+    }
+
+    fn write_register(&mut self, args: &str) -> String {
+        let Some((regnum, value)) = args.split_once('=') else {
+            return EINVAL.into();
+        };
+
+        let regnum = usize::from_str_radix(regnum, 16).ok();
+        let value = decode_hex(value.as_bytes())
+            .and_then(|bytes| <[u8; REGISTER_SIZE]>::try_from(bytes).ok())
+            .map(i64::from_le_bytes);
+        match (regnum, value) {
+            (Some(regnum), Some(value)) if self.set_register_value(regnum, value, false) => {
+                "OK".into()
+            }
+            _ => EINVAL.into(),
+        }
+    }
+
+    fn register_value(&self, regnum: usize) -> i64 {
+        let vm = self.debugger.vm();
+        match register(regnum) {
+            Some(register) => vm.registers()[register].try_into_int().unwrap(),
+            None => i64::from(vm.pc()),
+        }
+    }
+
+    // Returns false if the value doesn't fit, leaving the register as it was
+    // when `check` is set. Writes to $zero are ignored.
+    fn set_register_value(&mut self, regnum: usize, value: i64, check: bool) -> bool {
+        let vm = self.debugger.vm_mut();
+        if regnum == PC_REGNUM {
+            let Ok(pc) = i32::try_from(value) else {
+                return false;
+            };
+
+            if !check {
+                vm.set_pc(pc);
+            }
+
+            return true;
+        }
+
+        let (Some(register), Ok(value)) = (register(regnum), T24::try_from_int(value)) else {
+            return false;
+        };
+
+        if !check && register != crate::registers::ZERO {
+            vm.registers_mut()[register] = value;
+        }
+
+        true
+    }
+
+    fn read_memory(&self, args: &str) -> String {
+        let Some((addr, len)) = memory_args(args) else {
+            return EINVAL.into();
+        };
+
+        let vm = self.debugger.vm();
+        if vm.memory_range(addr, len, 1).is_err() {
+            return EFAULT.into();
+        }
+
+        let trytes = vm.memory().read(addr, len).unwrap();
+        trytes
+            .iter()
+            .map(|&tryte| encode_hex(&tryte_to_i16(tryte).to_le_bytes()))
+            .collect()
+    }
+
+    fn write_memory(&mut self, args: &str) -> String {
+        let Some((args, data)) = args.split_once(':') else {
+            return EINVAL.into();
+        };
+
+        let Some((addr, len)) = memory_args(args) else {
+            return EINVAL.into();
+        };
+
+        let trytes = decode_hex(data.as_bytes())
+            .filter(|bytes| bytes.len() == len * TRYTE_SIZE)
+            .and_then(|bytes| {
+                bytes
+                    .chunks(TRYTE_SIZE)
+                    .map(|pair| i16_to_tryte(i16::from_le_bytes([pair[0], pair[1]])).ok())
+                    .collect::<Option<Vec<_>>>()
+            });
+        let Some(trytes) = trytes else {
+            return EINVAL.into();
+        };
+
+        let vm = self.debugger.vm_mut();
+        if vm.memory_range(addr, len, 1).is_err() {
+            return EFAULT.into();
+        }
+
+        vm.memory_mut().write(addr, &trytes).unwrap();
+        "OK".into()
+    }
+
+    // `s` replies once the instruction has run, while `c` only starts the
+    // program, and `serve` replies when it stops.
+    fn resume(&mut self, command: &str, args: &str) -> Option<String> {
+        if !args.is_empty() {
+            let Some(pc) = parse_addr(args) else {
+                return Some(EINVAL.into());
+            };
+
+            self.debugger.vm_mut().set_pc(pc);
+        }
+
+        if command == "c" {
+            self.running = true;
+            return None;
+        }
+
+        self.stop = stop_reply(&self.debugger.step());
+        Some(self.stop.clone())
+    }
+
+    // Z0 and Z1 are breakpoints, while Z2, Z3 and Z4 are write, read and
+    // access watchpoints. The kind of a watchpoint is its length in trytes.
+    fn set_point(&mut self, args: &str, insert: bool) -> String {
+        let mut fields = args.split(',');
+        let (Some(point), Some(addr), Some(kind)) = (fields.next(), fields.next(), fields.next())
+        else {
+            return EINVAL.into();
+        };
+
+        let len = usize::from_str_radix(kind, 16)
+            .ok()
+            .filter(|&len| i32::try_from(len).is_ok());
+        let (Some(addr), Some(len)) = (parse_addr(addr), len) else {
+            return EINVAL.into();
+        };
+
+        let kind = match point {
+            "0" | "1" => {
+                if insert {
+                    self.debugger.add_breakpoint(addr);
+                } else {
+                    self.debugger.remove_breakpoint(addr);
+                }
+
+                return "OK".into();
+            }
+            "2" => WatchKind::Write,
+            "3" => WatchKind::Read,
+            "4" => WatchKind::Access,
+            _ => return String::new(),
+        };
+
+        let watchpoint = Watchpoint::memory(kind, addr, len.max(1));
+        if insert {
+            self.debugger.add_watchpoint(watchpoint);
+        } else {
+            self.debugger.remove_watchpoint(&watchpoint);
+        }
+
+        "OK".into()
+    }
+}
+
+fn query(query: &str) -> String {
+    if query.starts_with("Supported") {
+        format!("PacketSize={PACKET_SIZE:x};qXfer:features:read+;QStartNoAckMode+;swbreak+")
+    } else if let Some(args) = query.strip_prefix("Xfer:features:read:target.xml:") {
+        read_chunk(&target_description(), args).unwrap_or_else(|| EINVAL.into())
+    } else {
+        match query {
+            "Attached" => "1".into(),
+            "C" => "QC1".into(),
+            "fThreadInfo" => "m1".into(),
+            "sThreadInfo" => "l".into(),
+            _ => String::new(),
+        }
+    }
+}
+
+// The registers in `g` packet order, with pc last.
+pub fn target_description() -> String {
+    use std::fmt::Write as _;
+
+    let mut xml = String::from(
+        "<?xml version=\"1.0\"?>\n\
+         <!DOCTYPE target SYSTEM \"gdb-target.dtd\">\n\
+         <target version=\"1.0\">\n  \
+         <feature name=\"org.btm.core\">\n",
+    );
+
+    let registers = Register::all().map(Register::name).chain(["pc"]);
+    for (regnum, name) in registers.enumerate() {
+        let kind = if regnum == PC_REGNUM {
+            "code_ptr"
+        } else {
+            "int64"
+        };
+        writeln!(
+            xml,
+            "    <reg name=\"{name}\" bitsize=\"64\" type=\"{kind}\" regnum=\"{regnum}\"/>"
+        )
+        .unwrap();
+    }
+
+    xml.push_str("  </feature>\n</target>\n");
+    xml
+}
+
+fn register(regnum: usize) -> Option<Register> {
+    Register::all().nth(regnum).filter(|_| regnum < PC_REGNUM)
+}
+
+fn stop_reply(event: &Event) -> String {
+    match event {
+        Event::Step | Event::Break { .. } => stop_signal(SIGTRAP),
+        Event::Breakpoint { .. } => format!("T{SIGTRAP:02x}swbreak:;"),
+        Event::Watch(hit) => match hit.target {
+            Target::Memory { addr, .. } => {
+                let name = match hit.kind {
+                    WatchKind::Read => "rwatch",
+                    _ => "watch",
+                };
+                let addr = i64::from(addr).cast_unsigned();
+                format!("T{SIGTRAP:02x}{name}:{addr:x};")
+            }
+            Target::Register(_) => stop_signal(SIGTRAP),
+        },
+        Event::Exit { code } => format!("W{:02x}", code.rem_euclid(256)),
+        Event::Trap(trap) => stop_signal(match trap.cause {
+            TrapCause::InvalidOpcode(_)
+            | TrapCause::InvalidInstruction
+            | TrapCause::InvalidShift(_) => SIGILL,
+            TrapCause::MisalignedAccess { .. } | TrapCause::OutOfBounds { .. } => SIGSEGV,
+            TrapCause::DivideByZero => SIGFPE,
+            TrapCause::InvalidSyscall(_) | TrapCause::Syscall(_) => SIGSYS,
+        }),
+    }
+}
+
+fn stop_signal(signal: u8) -> String {
+    format!("S{signal:02x}")
+}
+
+// `offset,length` of a qXfer read
+fn read_chunk(data: &str, args: &str) -> Option<String> {
+    let (offset, len) = args.split_once(',')?;
+    let offset = usize::from_str_radix(offset, 16).ok()?;
+    let len = usize::from_str_radix(len, 16).ok()?;
+
+    let chunk = data.get(offset.min(data.len())..)?;
+    if chunk.len() <= len {
+        Some(format!("l{chunk}"))
+    } else {
+        Some(format!("m{}", &chunk[..len]))
+    }
+}
+
+// `addr,length`
+fn memory_args(args: &str) -> Option<(i32, usize)> {
+    let (addr, len) = args.split_once(',')?;
+    let len = usize::from_str_radix(len, 16)
+        .ok()
+        .filter(|&len| len <= MAX_MEMORY_LEN)?;
+    Some((parse_addr(addr)?, len))
+}
+
+// Addresses are sent unsigned, so negative ones wrap around.
+fn parse_addr(text: &str) -> Option<i32> {
+    let addr = u64::from_str_radix(text, 16).ok()?.cast_signed();
+    i32::try_from(addr).ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::{BufReader, Read};
+    use std::net::{TcpListener, TcpStream};
+    use std::thread;
+
+    use super::*;
+    use crate::debugger::tests::{PROGRAM, debugger};
+
+    // Sends one packet at a time and waits for the reply, like gdb does.
+    struct Client {
+        reader: BufReader<TcpStream>,
+        writer: TcpStream,
+        ack: bool,
+    }
+
+    impl Client {
+        fn request(&mut self, data: &str) -> String {
+            self.send(data);
+            if data == "QStartNoAckMode" {
+                self.ack = false;
+            }
+
+            self.reply()
+        }
+
+        fn send(&mut self, data: &str) {
+            let checksum = data.bytes().fold(0u8, u8::wrapping_add);
+            write!(self.writer, "${data}#{checksum:02x}").unwrap();
+            if self.ack {
+                assert_eq!(b'+', self.byte());
+            }
+        }
+
+        fn reply(&mut self) -> String {
+            assert_eq!(b'$', self.byte());
+            let mut reply = Vec::new();
+            self.reader.read_until(b'#', &mut reply).unwrap();
+            reply.pop();
+
+            let mut checksum = [0; 2];
+            self.reader.read_exact(&mut checksum).unwrap();
+            assert_eq!(
+                Some(vec![
+                    reply.iter().fold(0u8, |sum, &byte| sum.wrapping_add(byte))
+                ]),
+                decode_hex(&checksum)
+            );
+
+            if self.ack {
+                self.writer.write_all(b"+").unwrap();
+            }
+
+            String::from_utf8(reply).unwrap()
+        }
+
+        fn byte(&mut self) -> u8 {
+            let mut byte = [0];
+            self.reader.read_exact(&mut byte).unwrap();
+            byte[0]
+        }
+    }
+
+    fn session<H: SyscallHandler + Send>(
+        debugger: &mut Debugger<H>,
+        script: impl FnOnce(&mut Client),
+    ) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+
+        thread::scope(|scope| {
+            scope.spawn(|| {
+                let (stream, _) = listener.accept().unwrap();
+                stream.set_nodelay(true).unwrap();
+                let reader = BufReader::new(stream.try_clone().unwrap());
+                Stub::new(debugger).serve(reader, stream).unwrap();
+            });
+
+            let stream = TcpStream::connect(addr).unwrap();
+            stream.set_nodelay(true).unwrap();
+            let mut client = Client {
+                reader: BufReader::new(stream.try_clone().unwrap()),
+                writer: stream,
+                ack: true,
+            };
+
+            script(&mut client);
+        });
+    }
+
+    #[test]
+    fn serve_scripted_client() {
+        let mut debugger = debugger(PROGRAM);
+        let square = debugger.debug_info().symbol("square").unwrap();
+        session(&mut debugger, |client| {
+            let supported = client.request("qSupported:swbreak+");
+            assert!(supported.contains("qXfer:features:read+"));
+            assert_eq!("OK", client.request("QStartNoAckMode"));
+
+            let xml = client.request("qXfer:features:read:target.xml:0,fff");
+            assert!(xml.starts_with("l<?xml"));
+            assert!(
+                xml.contains("<reg name=\"s14\" bitsize=\"64\" type=\"int64\" regnum=\"40\"/>")
+            );
+            assert_eq!(
+                "m<?xml",
+                client.request("qXfer:features:read:target.xml:0,5")
+            );
+
+            assert_eq!("S05", client.request("?"));
+            assert_eq!(REGISTER_COUNT * 16, client.request("g").len());
+            assert_eq!("0000000000000000", client.request("p29"));
+
+            assert_eq!("OK", client.request(&format!("Z0,{square:x},4")));
+            assert_eq!("T05swbreak:;", client.request("c"));
+            assert_eq!("2400000000000000", client.request("p29"));
+            assert_eq!("0300000000000000", client.request("p11"));
+            assert_eq!("OK", client.request("P11=feffffffffffffff"));
+            assert_eq!("feffffffffffffff", client.request("p11"));
+            assert_eq!("E16", client.request("P11=ffffffffffffff7f"));
+
+            assert_eq!("OK", client.request("M64,2:0500fbff"));
+            assert_eq!("0500fbff", client.request("m64,2"));
+            assert_eq!("0000", client.request("mffffffffffffff00,1"));
+            assert_eq!("E0e", client.request("m1000,1"));
+            assert_eq!("E16", client.request("M64,1:6d01"));
+            assert_eq!("E16", client.request("m0,80000000"));
+            assert_eq!("E16", client.request("m0,1001"));
+            assert_eq!("E0e", client.request("m7fffffff,1"));
+            assert_eq!("E16", client.request("Z2,0,ffffffffffffffff"));
+            assert_eq!("OK", client.request("Z2,7fffff00,7fffffff"));
+
+            assert_eq!("OK", client.request(&format!("z0,{square:x},4")));
+            assert_eq!("S05", client.request("s"));
+            assert_eq!("2800000000000000", client.request("p29"));
+            assert_eq!("S05", client.request("c"));
+            assert_eq!("W07", client.request("c"));
+            assert_eq!("W07", client.request("?"));
+            assert_eq!("", client.request("vMustReplyEmpty"));
+            assert_eq!("", client.request("é1"));
+            assert_eq!("OK", client.request("D"));
+        });
+
+        assert_eq!(Some(7), debugger.exited());
+    }
+
+    #[test]
+    fn interrupt_running_program() {
+        let mut debugger = debugger("spin: j spin\n");
+        session(&mut debugger, |client| {
+            client.send("c");
+            client.writer.write_all(&[0x03]).unwrap();
+            assert_eq!("S02", client.reply());
+            assert_eq!("0000000000000000", client.request("p29"));
+            assert_eq!("S02", client.request("?"));
+            assert_eq!("OK", client.request("D"));
+        });
+    }
+}
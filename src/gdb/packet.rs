@@ -0,0 +1,145 @@
+use std::io::{self, BufRead, ErrorKind, Write};
+
+const HEX_DIGITS: &[u8; 16] = b"0123456789abcdef";
+const INTERRUPT: u8 = 0x03;
+
+pub(super) enum Input {
+    Packet(String),
+    // a packet whose checksum didn't match
+    Corrupt,
+    // a `-`, asking for the last packet again
+    Resend,
+    Interrupt,
+}
+
+// Reads the next packet, `-` or interrupt, skipping anything else such as
+// `+`. Returns None once the client disconnects.
+pub(super) fn receive<R: BufRead>(input: &mut R) -> io::Result<Option<Input>> {
+    loop {
+        let Some(byte) = read_byte(input)? else {
+            return Ok(None);
+        };
+
+        match byte {
+            b'$' => {}
+            b'-' => return Ok(Some(Input::Resend)),
+            INTERRUPT => return Ok(Some(Input::Interrupt)),
+            _ => continue,
+        }
+
+        let mut data = Vec::new();
+        input.read_until(b'#', &mut data)?;
+        if data.pop() != Some(b'#') {
+            return Ok(None);
+        }
+
+        let mut checksum = [0; 2];
+        match input.read_exact(&mut checksum) {
+            Err(error) if error.kind() == ErrorKind::UnexpectedEof => return Ok(None),
+            result => result?,
+        }
+
+        if decode_hex(&checksum).is_none_or(|expected| expected != [sum(&data)]) {
+            return Ok(Some(Input::Corrupt));
+        }
+
+        let data = String::from_utf8(data)
+            .map_err(|error| io::Error::new(ErrorKind::InvalidData, error))?;
+        return Ok(Some(Input::Packet(data)));
+    }
+}
+
+fn read_byte<R: BufRead>(input: &mut R) -> io::Result<Option<u8>> {
+    let buffer = input.fill_buf()?;
+    let Some(&byte) = buffer.first() else {
+        return Ok(None);
+    };
+
+    input.consume(1);
+    Ok(Some(byte))
+}
+
+// Frames packets as `$data#checksum`. Until no-ack mode starts, every packet
+// received is acknowledged with `acknowledge`.
+pub(super) struct Connection<W> {
+    output: W,
+    ack: bool,
+    last: Vec<u8>,
+}
+
+impl<W: Write> Connection<W> {
+    pub fn new(output: W) -> Self {
+        Connection {
+            output,
+            ack: true,
+            last: Vec::new(),
+        }
+    }
+
+    pub fn disable_ack(&mut self) {
+        self.ack = false;
+    }
+
+    // Sends `+` for a valid packet and `-` for a corrupt one.
+    pub fn acknowledge(&mut self, valid: bool) -> io::Result<()> {
+        if self.ack {
+            self.output.write_all(if valid { b"+" } else { b"-" })?;
+            self.output.flush()?;
+        }
+
+        Ok(())
+    }
+
+    pub fn resend(&mut self) -> io::Result<()> {
+        self.output.write_all(&self.last)?;
+        self.output.flush()
+    }
+
+    pub fn send(&mut self, data: &str) -> io::Result<()> {
+        let mut packet = Vec::with_capacity(data.len() + 4);
+        packet.push(b'$');
+        for &byte in data.as_bytes() {
+            if matches!(byte, b'#' | b'$' | b'}' | b'*') {
+                packet.extend_from_slice(&[b'}', byte ^ 0x20]);
+            } else {
+                packet.push(byte);
+            }
+        }
+
+        let checksum = sum(&packet[1..]);
+        packet.push(b'#');
+        packet.extend_from_slice(encode_hex(&[checksum]).as_bytes());
+
+        self.output.write_all(&packet)?;
+        self.output.flush()?;
+        self.last = packet;
+        Ok(())
+    }
+}
+
+fn sum(data: &[u8]) -> u8 {
+    data.iter().fold(0, |sum, &byte| sum.wrapping_add(byte))
+}
+
+pub(super) fn encode_hex(bytes: &[u8]) -> String {
+    let mut text = String::with_capacity(2 * bytes.len());
+    for &byte in bytes {
+        text.push(char::from(HEX_DIGITS[usize::from(byte >> 4)]));
+        text.push(char::from(HEX_DIGITS[usize::from(byte & 0xf)]));
+    }
+
+    text
+}
+
+pub(super) fn decode_hex(text: &[u8]) -> Option<Vec<u8>> {
+    if !text.len().is_multiple_of(2) {
+        return None;
+    }
+
+    text.chunks(2)
+        .map(|pair| {
+            let digits = std::str::from_utf8(pair).ok()?;
+            u8::from_str_radix(digits, 16).ok()
+        })
+        .collect()
+}
@@ -0,0 +1,344 @@
+use std::fmt;
+
+use ternary::{T24, TInt, Tryte};
+
+use crate::debug::LineTable;
+
+pub const MAGIC: [u8; 4] = *b"BTM\0";
+pub const VERSION: u16 = 2;
+
+const TRYTE_MAX: i16 = 364;
+
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum Error {
+    InvalidMagic,
+    UnsupportedVersion(u16),
+    Truncated,
+    TrailingBytes,
+    ChecksumMismatch { expected: u32, found: u32 },
+    InvalidTryte(i16),
+    InvalidSymbol,
+    InvalidSymbolOffset(u32),
+    TooLarge(usize),
+    OverlappingSegments(i32),
+    InvalidSection(u8),
+    InvalidRelocation(u8),
+    InvalidFile(u32),
+    UnsortedLines,
+}
+
+pub type Result<T> = std::result::Result<T, Error>;
+
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct Image {
+    pub entry: i32,
+    pub segments: Vec<Segment>,
+    pub symbols: Vec<Symbol>,
+    pub lines: LineTable,
+}
+
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Segment {
+    pub addr: i32,
+    pub trytes: Vec<Tryte>,
+}
+
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Symbol {
+    pub name: String,
+    pub addr: i32,
+}
+
+impl Image {
+    pub fn new(entry: i32) -> Self {
+        Image {
+            entry,
+            segments: Vec::new(),
+            symbols: Vec::new(),
+            lines: LineTable::default(),
+        }
+    }
+
+    pub fn from_words(origin: i32, words: &[T24]) -> Self {
+        let trytes = words.iter().flat_map(|word| word.into_trytes()).collect();
+        let mut image = Image::new(origin);
+        image.segments.push(Segment {
+            addr: origin,
+            trytes,
+        });
+        image
+    }
+
+    pub fn to_bytes(&self) -> Result<Vec<u8>> {
+        let mut bytes = Vec::new();
+        bytes.extend_from_slice(&MAGIC);
+        bytes.extend_from_slice(&VERSION.to_le_bytes());
+        bytes.extend_from_slice(&self.entry.to_le_bytes());
+        bytes.extend_from_slice(&count(self.segments.len())?.to_le_bytes());
+        bytes.extend_from_slice(&count(self.symbols.len())?.to_le_bytes());
+
+        for segment in &self.segments {
+            let len = count(segment.trytes.len())?;
+            bytes.extend_from_slice(&segment.addr.to_le_bytes());
+            bytes.extend_from_slice(&len.to_le_bytes());
+            for &tryte in &segment.trytes {
+                bytes.extend_from_slice(&tryte_to_i16(tryte).to_le_bytes());
+            }
+        }
+
+        for symbol in &self.symbols {
+            let len = u16::try_from(symbol.name.len()).map_err(|_| Error::InvalidSymbol)?;
+            bytes.extend_from_slice(&len.to_le_bytes());
+            bytes.extend_from_slice(symbol.name.as_bytes());
+            bytes.extend_from_slice(&symbol.addr.to_le_bytes());
+        }
+
+        self.lines.write(&mut bytes)?;
+
+        let checksum = checksum(&bytes);
+        bytes.extend_from_slice(&checksum.to_le_bytes());
+        Ok(bytes)
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self> {
+        if bytes.len() < MAGIC.len() || bytes[..MAGIC.len()] != MAGIC {
+            return Err(Error::InvalidMagic);
+        }
+
+        let Some(body_len) = bytes.len().checked_sub(4) else {
+            return Err(Error::Truncated);
+        };
+
+        let (body, checksum_bytes) = bytes.split_at(body_len);
+        let mut reader = Reader {
+            bytes: body,
+            position: MAGIC.len(),
+        };
+
+        // version 1 images have no line table
+        let version = reader.u16()?;
+        if !(1..=VERSION).contains(&version) {
+            return Err(Error::UnsupportedVersion(version));
+        }
+
+        let expected = u32::from_le_bytes(checksum_bytes.try_into().unwrap());
+        let found = checksum(body);
+        if expected != found {
+            return Err(Error::ChecksumMismatch { expected, found });
+        }
+
+        let entry = reader.i32()?;
+        let segment_count = reader.u32()?;
+        let symbol_count = reader.u32()?;
+
+        let mut image = Image::new(entry);
+        for _ in 0..segment_count {
+            let addr = reader.i32()?;
+            let len = reader.u32()?;
+            let trytes = (0..len)
+                .map(|_| i16_to_tryte(reader.i16()?))
+                .collect::<Result<_>>()?;
+            image.segments.push(Segment { addr, trytes });
+        }
+
+        for _ in 0..symbol_count {
+            let len = reader.u16()?;
+            let name = String::from_utf8(reader.take(len.into())?.to_vec())
+                .map_err(|_| Error::InvalidSymbol)?;
+            let addr = reader.i32()?;
+            image.symbols.push(Symbol { name, addr });
+        }
+
+        if version >= 2 {
+            image.lines = LineTable::read(&mut reader)?;
+        }
+
+        if reader.position != body.len() {
+            return Err(Error::TrailingBytes);
+        }
+
+        Ok(image)
+    }
+
+    pub fn symbol(&self, name: &str) -> Option<&Symbol> {
+        self.symbols.iter().find(|symbol| symbol.name == name)
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::InvalidMagic => write!(f, "not a BTM image"),
+            Error::UnsupportedVersion(version) => {
+                write!(f, "unsupported image version {version}")
+            }
+            Error::Truncated => write!(f, "image is truncated"),
+            Error::TrailingBytes => write!(f, "image has trailing bytes"),
+            Error::ChecksumMismatch { expected, found } => {
+                write!(
+                    f,
+                    "checksum mismatch (expected {expected:#010x}, found {found:#010x})"
+                )
+            }
+            Error::InvalidTryte(value) => write!(f, "invalid tryte value {value}"),
+            Error::InvalidSymbol => write!(f, "invalid symbol name"),
+            Error::InvalidSymbolOffset(offset) => {
+                write!(f, "symbol offset {offset} is outside its section")
+            }
+            Error::TooLarge(len) => write!(f, "{len} entries is too many for an image"),
+            Error::OverlappingSegments(addr) => write!(f, "segments overlap at address {addr}"),
+            Error::InvalidSection(section) => write!(f, "invalid section {section}"),
+            Error::InvalidRelocation(kind) => write!(f, "invalid relocation kind {kind}"),
+            Error::InvalidFile(file) => write!(f, "invalid file index {file}"),
+            Error::UnsortedLines => write!(f, "line table is not sorted"),
+        }
+    }
+}
+
+impl std::error::Error for Error {}
+
+pub(crate) struct Reader<'a> {
+    pub bytes: &'a [u8],
+    pub position: usize,
+}
+
+impl Reader<'_> {
+    pub fn take(&mut self, len: usize) -> Result<&[u8]> {
+        let end = self.position.checked_add(len).ok_or(Error::Truncated)?;
+        let bytes = self.bytes.get(self.position..end).ok_or(Error::Truncated)?;
+        self.position = end;
+        Ok(bytes)
+    }
+
+    fn array<const N: usize>(&mut self) -> Result<[u8; N]> {
+        Ok(self.take(N)?.try_into().unwrap())
+    }
+
+    pub fn u16(&mut self) -> Result<u16> {
+        Ok(u16::from_le_bytes(self.array()?))
+    }
+
+    pub fn i16(&mut self) -> Result<i16> {
+        Ok(i16::from_le_bytes(self.array()?))
+    }
+
+    pub fn u32(&mut self) -> Result<u32> {
+        Ok(u32::from_le_bytes(self.array()?))
+    }
+
+    pub fn i32(&mut self) -> Result<i32> {
+        Ok(i32::from_le_bytes(self.array()?))
+    }
+
+    pub fn u8(&mut self) -> Result<u8> {
+        Ok(self.take(1)?[0])
+    }
+
+    pub fn i64(&mut self) -> Result<i64> {
+        Ok(i64::from_le_bytes(self.array()?))
+    }
+}
+
+pub(crate) fn count(len: usize) -> Result<u32> {
+    u32::try_from(len).map_err(|_| Error::TooLarge(len))
+}
+
+pub(crate) fn tryte_to_i16(tryte: Tryte) -> i16 {
+    TInt::<1>::try_from(&[tryte][..])
+        .and_then(TInt::try_into_int)
+        .unwrap()
+}
+
+pub(crate) fn i16_to_tryte(value: i16) -> Result<Tryte> {
+    if !(-TRYTE_MAX..=TRYTE_MAX).contains(&value) {
+        return Err(Error::InvalidTryte(value));
+    }
+
+    Ok(TInt::<1>::try_from_int(value).unwrap().into_trytes()[0])
+}
+
+pub(crate) fn checksum(bytes: &[u8]) -> u32 {
+    bytes.iter().fold(0x811c_9dc5, |hash, &byte| {
+        (hash ^ u32::from(byte)).wrapping_mul(0x0100_0193)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::debug::Location;
+
+    fn image() -> Image {
+        let words = [
+            T24::try_from_int(-1).unwrap(),
+            T24::try_from_int(123_456).unwrap(),
+        ];
+
+        let mut image = Image::from_words(-8, &words);
+        image.segments.push(Segment {
+            addr: 40,
+            trytes: TInt::<2>::try_from_int(-300)
+                .unwrap()
+                .into_trytes()
+                .to_vec(),
+        });
+        image.symbols.push(Symbol {
+            name: "start".into(),
+            addr: -8,
+        });
+        image.lines.push(
+            -8,
+            &Location {
+                file: Some("start.s".into()),
+                line: 2,
+                column: 5,
+            },
+        );
+        image
+    }
+
+    #[test]
+    fn image_round_trip() {
+        let image = image();
+        let bytes = image.to_bytes().unwrap();
+        assert_eq!(MAGIC, bytes[..4]);
+        assert_eq!(image, Image::from_bytes(&bytes).unwrap());
+        assert_eq!(Some(-8), image.symbol("start").map(|symbol| symbol.addr));
+    }
+
+    #[test]
+    fn image_errors() {
+        let bytes = image().to_bytes().unwrap();
+        let len = bytes.len();
+
+        assert_eq!(Err(Error::InvalidMagic), Image::from_bytes(b"BTX\0"));
+
+        let mut version = bytes.clone();
+        version[4] = 3;
+        assert_eq!(
+            Err(Error::UnsupportedVersion(3)),
+            Image::from_bytes(&version)
+        );
+
+        let mut corrupt = bytes.clone();
+        corrupt[20] ^= 1;
+        assert!(matches!(
+            Image::from_bytes(&corrupt),
+            Err(Error::ChecksumMismatch { .. })
+        ));
+
+        let mut truncated = bytes[..len - 10].to_vec();
+        truncated.extend_from_slice(&checksum(&truncated).to_le_bytes());
+        assert_eq!(Err(Error::Truncated), Image::from_bytes(&truncated));
+
+        let mut trailing = bytes[..len - 4].to_vec();
+        trailing.push(0);
+        trailing.extend_from_slice(&checksum(&trailing).to_le_bytes());
+        assert_eq!(Err(Error::TrailingBytes), Image::from_bytes(&trailing));
+
+        let mut tryte = bytes[..len - 4].to_vec();
+        tryte[26..28].copy_from_slice(&400_i16.to_le_bytes());
+        tryte.extend_from_slice(&checksum(&tryte).to_le_bytes());
+        assert_eq!(Err(Error::InvalidTryte(400)), Image::from_bytes(&tryte));
+    }
+}
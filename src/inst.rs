@@ -1,4 +1,6 @@
-use ternary::{T24, Tryte};
+use std::fmt;
+
+use ternary::T24;
 
 use crate::error::Result;
 use crate::opcodes::{self, Opcode};
@@ -87,17 +89,186 @@ impl Inst {
             _ => unreachable!(),
         }
     }
+
+    pub fn opcode(self) -> Opcode {
+        match self {
+            Inst::And(_) => opcodes::AND,
+            Inst::Or(_) => opcodes::OR,
+            Inst::Tmul(_) => opcodes::TMUL,
+            Inst::Tcmp(_) => opcodes::TCMP,
+            Inst::Cmp(_) => opcodes::CMP,
+            Inst::Shf(_) => opcodes::SHF,
+            Inst::Add(_) => opcodes::ADD,
+            Inst::Mul(_) => opcodes::MUL,
+            Inst::Div(_) => opcodes::DIV,
+            Inst::Andi(_) => opcodes::ANDI,
+            Inst::Ori(_) => opcodes::ORI,
+            Inst::Tmuli(_) => opcodes::TMULI,
+            Inst::Tcmpi(_) => opcodes::TCMPI,
+            Inst::Shfi(_) => opcodes::SHFI,
+            Inst::Addi(_) => opcodes::ADDI,
+            Inst::Lui(_) => opcodes::LUI,
+            Inst::Lt(_) => opcodes::LT,
+            Inst::Lh(_) => opcodes::LH,
+            Inst::Lw(_) => opcodes::LW,
+            Inst::St(_) => opcodes::ST,
+            Inst::Sh(_) => opcodes::SH,
+            Inst::Sw(_) => opcodes::SW,
+            Inst::BT(_) => opcodes::BT,
+            Inst::B0(_) => opcodes::B0,
+            Inst::B1(_) => opcodes::B1,
+            Inst::BT0(_) => opcodes::BT0,
+            Inst::BT1(_) => opcodes::BT1,
+            Inst::B01(_) => opcodes::B01,
+            Inst::Bal(_) => opcodes::BAL,
+            Inst::J(_) => opcodes::J,
+            Inst::Jal(_) => opcodes::JAL,
+            Inst::Jr(_) => opcodes::JR,
+            Inst::Jalr(_) => opcodes::JALR,
+            Inst::Syscall(_) => opcodes::SYSCALL,
+            Inst::Break(_) => opcodes::BREAK,
+        }
+    }
+
+    pub fn display_at(self, addr: i32) -> DisplayAt {
+        DisplayAt { inst: self, addr }
+    }
+
+    pub fn to_word(self) -> Result<T24> {
+        match self {
+            Inst::And(operands) => operands.to_word(opcodes::AND),
+            Inst::Or(operands) => operands.to_word(opcodes::OR),
+            Inst::Tmul(operands) => operands.to_word(opcodes::TMUL),
+            Inst::Tcmp(operands) => operands.to_word(opcodes::TCMP),
+            Inst::Cmp(operands) => operands.to_word(opcodes::CMP),
+            Inst::Shf(operands) => operands.to_word(opcodes::SHF),
+            Inst::Add(operands) => operands.to_word(opcodes::ADD),
+            Inst::Mul(operands) => operands.to_word(opcodes::MUL),
+            Inst::Div(operands) => operands.to_word(opcodes::DIV),
+            Inst::Andi(operands) => operands.to_word(opcodes::ANDI),
+            Inst::Ori(operands) => operands.to_word(opcodes::ORI),
+            Inst::Tmuli(operands) => operands.to_word(opcodes::TMULI),
+            Inst::Tcmpi(operands) => operands.to_word(opcodes::TCMPI),
+            Inst::Shfi(operands) => operands.to_word(opcodes::SHFI),
+            Inst::Addi(operands) => operands.to_word(opcodes::ADDI),
+            Inst::Lui(operands) => operands.to_word(opcodes::LUI),
+            Inst::Lt(operands) => operands.to_word(opcodes::LT),
+            Inst::Lh(operands) => operands.to_word(opcodes::LH),
+            Inst::Lw(operands) => operands.to_word(opcodes::LW),
+            Inst::St(operands) => operands.to_word(opcodes::ST),
+            Inst::Sh(operands) => operands.to_word(opcodes::SH),
+            Inst::Sw(operands) => operands.to_word(opcodes::SW),
+            Inst::BT(operands) => operands.to_word(opcodes::BT),
+            Inst::B0(operands) => operands.to_word(opcodes::B0),
+            Inst::B1(operands) => operands.to_word(opcodes::B1),
+            Inst::BT0(operands) => operands.to_word(opcodes::BT0),
+            Inst::BT1(operands) => operands.to_word(opcodes::BT1),
+            Inst::B01(operands) => operands.to_word(opcodes::B01),
+            Inst::Bal(operands) => operands.to_word(opcodes::BAL),
+            Inst::J(operands) => operands.to_word(opcodes::J),
+            Inst::Jal(operands) => operands.to_word(opcodes::JAL),
+            Inst::Jr(operands) => operands.to_word(opcodes::JR),
+            Inst::Jalr(operands) => operands.to_word(opcodes::JALR),
+            Inst::Syscall(operands) => operands.to_word(opcodes::SYSCALL),
+            Inst::Break(operands) => operands.to_word(opcodes::BREAK),
+        }
+    }
+}
+
+impl fmt::Display for Inst {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let opcode = self.opcode();
+        match self {
+            Inst::And(operands)
+            | Inst::Or(operands)
+            | Inst::Tmul(operands)
+            | Inst::Tcmp(operands)
+            | Inst::Cmp(operands)
+            | Inst::Shf(operands)
+            | Inst::Add(operands) => {
+                write!(f, "{opcode} {operands}")
+            }
+            Inst::Mul(operands) | Inst::Div(operands) => {
+                write!(f, "{opcode} {operands}")
+            }
+            Inst::Andi(operands)
+            | Inst::Ori(operands)
+            | Inst::Tmuli(operands)
+            | Inst::Tcmpi(operands)
+            | Inst::Shfi(operands)
+            | Inst::Addi(operands) => {
+                write!(f, "{opcode} {operands}")
+            }
+            Inst::Lui(operands) => {
+                write!(f, "{opcode} {operands}")
+            }
+            Inst::Lt(operands)
+            | Inst::Lh(operands)
+            | Inst::Lw(operands)
+            | Inst::St(operands)
+            | Inst::Sh(operands)
+            | Inst::Sw(operands) => {
+                write!(f, "{opcode} {operands}")
+            }
+            Inst::BT(operands)
+            | Inst::B0(operands)
+            | Inst::B1(operands)
+            | Inst::BT0(operands)
+            | Inst::BT1(operands)
+            | Inst::B01(operands) => {
+                write!(f, "{opcode} {operands}")
+            }
+            Inst::Bal(operands) => {
+                write!(f, "{opcode} {operands}")
+            }
+            Inst::J(operands) | Inst::Jal(operands) => {
+                write!(f, "{opcode} {operands}")
+            }
+            Inst::Jr(operands) | Inst::Jalr(operands) => {
+                write!(f, "{opcode} {operands}")
+            }
+            Inst::Syscall(_) | Inst::Break(_) => write!(f, "{opcode}"),
+        }
+    }
+}
+
+// Formats branch offsets as the absolute target they resolve to when the
+// instruction is at `addr`, which is the form the assembler expects.
+pub struct DisplayAt {
+    inst: Inst,
+    addr: i32,
+}
+
+impl fmt::Display for DisplayAt {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let opcode = self.inst.opcode();
+        let target = |offset: T24| {
+            let offset: i64 = offset.try_into_int().map_err(|_| fmt::Error)?;
+            Ok(i64::from(self.addr) + 4 + offset)
+        };
+
+        match self.inst {
+            Inst::BT(operands)
+            | Inst::B0(operands)
+            | Inst::B1(operands)
+            | Inst::BT0(operands)
+            | Inst::BT1(operands)
+            | Inst::B01(operands) => {
+                write!(f, "{opcode} {}, {}", operands.src, target(operands.offset)?)
+            }
+            Inst::Bal(operands) => write!(f, "{opcode} {}", target(operands.offset)?),
+            inst => write!(f, "{inst}"),
+        }
+    }
 }
 
 #[cfg(test)]
 mod tests {
-    use std::convert::TryInto;
-
     use super::*;
-    use crate::registers;
-    use ternary::test_constants::{T24_4096, T24_1073741824, TRYTE_6, TRYTE_NEG278};
-    use ternary::trit::{self, _1};
-    use ternary::{T12, tryte};
+    use crate::registers::{self, Register};
+    use ternary::T12;
+    use ternary::test_constants::{T24_4096, T24_1073741824};
+    use ternary::trit;
 
     pub const T12_4096: T12 = T24_4096.resize();
 
@@ -366,6 +537,311 @@ mod tests {
         assert!(inst(concat!("00000000000000000000", "110T")).is_err());
     }
 
+    #[test]
+    fn instruction_display() {
+        let t0 = registers::T0;
+        let t1 = registers::T1;
+        let t2 = registers::T2;
+        let immediate = T12::try_from_int(-42).unwrap();
+        let offset = T24::try_from_int(-8).unwrap();
+
+        assert_eq!(
+            "add $t0, $t1, $t2",
+            Inst::Add(operands::RRR {
+                dest: t0,
+                lhs: t1,
+                rhs: t2,
+            })
+            .to_string()
+        );
+        assert_eq!(
+            "mul $t0, $t1",
+            Inst::Mul(operands::RR { lhs: t0, rhs: t1 }).to_string()
+        );
+        assert_eq!(
+            "addi $sp, $sp, -42",
+            Inst::Addi(operands::RRI {
+                dest: registers::SP,
+                src: registers::SP,
+                immediate,
+            })
+            .to_string()
+        );
+        assert_eq!(
+            "lui $t0, -42",
+            Inst::Lui(operands::RI {
+                dest: t0,
+                immediate
+            })
+            .to_string()
+        );
+        assert_eq!(
+            "sw $fp, $ra, -42",
+            Inst::Sw(operands::RRO {
+                dest: registers::FP,
+                src: registers::RA,
+                offset: immediate,
+            })
+            .to_string()
+        );
+        assert_eq!(
+            "bT0 $t0, -8",
+            Inst::BT0(operands::RO { src: t0, offset }).to_string()
+        );
+        assert_eq!("bal -8", Inst::Bal(operands::O { offset }).to_string());
+        assert_eq!(
+            "jal 1073741824",
+            Inst::Jal(operands::A {
+                addr: T24_1073741824,
+            })
+            .to_string()
+        );
+        assert_eq!(
+            "jalr $ra",
+            Inst::Jalr(operands::R { src: registers::RA }).to_string()
+        );
+        assert_eq!("syscall", Inst::Syscall(operands::Empty).to_string());
+
+        assert_eq!(
+            "bT0 $t0, 100",
+            Inst::BT0(operands::RO { src: t0, offset })
+                .display_at(104)
+                .to_string()
+        );
+        assert_eq!(
+            "bal -4",
+            Inst::Bal(operands::O { offset }).display_at(0).to_string()
+        );
+        assert_eq!(
+            "jr $ra",
+            Inst::Jr(operands::R { src: registers::RA })
+                .display_at(0)
+                .to_string()
+        );
+    }
+
+    #[test]
+    fn instruction_from_non_canonical_word() {
+        assert!(inst(concat!("0000000T", "1T01", "1T00", "1T0T", "0000")).is_err());
+        assert!(inst(concat!("00000001", "0000", "1T00", "1T0T", "01T1")).is_err());
+        assert!(inst(concat!("0000000000001", "000", "1T0T", "1011")).is_err());
+        assert!(inst(concat!("0001T0T0TT01", "000T", "1T0T", "1TT0")).is_err());
+        assert!(inst(concat!("00000001T0T0TT01", "00T0", "1001")).is_err());
+        assert!(inst(concat!("1000000000000000000", "0", "11T0")).is_err());
+    }
+
+    #[test]
+    fn instruction_to_word_out_of_range() {
+        let max_offset = operands::max_value(operands::OFFSET_WIDTH);
+        let max_addr = operands::max_value(operands::ADDR_WIDTH);
+
+        let offset = T24::try_from_int(max_offset).unwrap();
+        let src = registers::T0;
+        assert!(Inst::BT(operands::RO { src, offset }).to_word().is_ok());
+        assert!(Inst::Bal(operands::O { offset }).to_word().is_ok());
+
+        let offset = T24::try_from_int(max_offset + 1).unwrap();
+        assert!(Inst::BT(operands::RO { src, offset }).to_word().is_err());
+        assert!(Inst::Bal(operands::O { offset }).to_word().is_err());
+
+        let addr = T24::try_from_int(-max_addr).unwrap();
+        assert!(Inst::J(operands::A { addr }).to_word().is_ok());
+        let addr = T24::try_from_int(-max_addr - 1).unwrap();
+        assert!(Inst::J(operands::A { addr }).to_word().is_err());
+    }
+
+    #[test]
+    fn instruction_round_trip() {
+        let mut rng = Rng(0x2545_F491_4F6C_DD1D);
+        let insts = all_insts(&mut rng);
+        assert!(insts.len() > 500_000);
+
+        for inst in insts {
+            let word = inst.to_word().unwrap();
+            assert_eq!(Ok(inst), Inst::from_word(word).map_err(|_| word));
+        }
+    }
+
+    #[test]
+    fn word_round_trip() {
+        let mut rng = Rng(0x9E37_79B9_7F4A_7C15);
+        let max_word = operands::max_value(24);
+        let mut accepted = 0;
+
+        let mut words = Vec::new();
+        for _ in 0..200_000 {
+            words.push(T24::try_from_int(rng.range(max_word)).unwrap());
+
+            let mut word = random_inst(&mut rng).to_word().unwrap();
+            let trit = [trit::_T, trit::_0, trit::_1][rng.index(3)];
+            word.set_trit(rng.index(24), trit);
+            words.push(word);
+        }
+
+        for word in words {
+            if let Ok(inst) = Inst::from_word(word) {
+                assert_eq!(word, inst.to_word().unwrap());
+                accepted += 1;
+            }
+        }
+
+        assert!(accepted > 100_000);
+    }
+
+    struct Rng(u64);
+
+    impl Rng {
+        fn next(&mut self) -> u64 {
+            self.0 ^= self.0 << 13;
+            self.0 ^= self.0 >> 7;
+            self.0 ^= self.0 << 17;
+            self.0
+        }
+
+        #[allow(clippy::cast_possible_truncation)]
+        fn index(&mut self, len: usize) -> usize {
+            (self.next() % len as u64) as usize
+        }
+
+        #[allow(clippy::cast_possible_wrap, clippy::cast_sign_loss)]
+        fn range(&mut self, max: i64) -> i64 {
+            (self.next() % (2 * max as u64 + 1)) as i64 - max
+        }
+
+        fn register(&mut self) -> Register {
+            let registers: Vec<_> = Register::all().collect();
+            registers[self.index(registers.len())]
+        }
+
+        fn values(&mut self, width: usize) -> Vec<i64> {
+            let max = operands::max_value(width);
+            let mut values = vec![0, 1, -1, max, -max];
+            values.extend((0..3).map(|_| self.range(max)));
+            values
+        }
+    }
+
+    fn all_insts(rng: &mut Rng) -> Vec<Inst> {
+        let rrr: [fn(operands::RRR) -> Inst; 7] = [
+            Inst::And,
+            Inst::Or,
+            Inst::Tmul,
+            Inst::Tcmp,
+            Inst::Cmp,
+            Inst::Shf,
+            Inst::Add,
+        ];
+        let rr: [fn(operands::RR) -> Inst; 2] = [Inst::Mul, Inst::Div];
+        let rri: [fn(operands::RRI) -> Inst; 6] = [
+            Inst::Andi,
+            Inst::Ori,
+            Inst::Tmuli,
+            Inst::Tcmpi,
+            Inst::Shfi,
+            Inst::Addi,
+        ];
+        let rro: [fn(operands::RRO) -> Inst; 6] =
+            [Inst::Lt, Inst::Lh, Inst::Lw, Inst::St, Inst::Sh, Inst::Sw];
+        let ro: [fn(operands::RO) -> Inst; 6] = [
+            Inst::BT,
+            Inst::B0,
+            Inst::B1,
+            Inst::BT0,
+            Inst::BT1,
+            Inst::B01,
+        ];
+        let a: [fn(operands::A) -> Inst; 2] = [Inst::J, Inst::Jal];
+        let r: [fn(operands::R) -> Inst; 2] = [Inst::Jr, Inst::Jalr];
+
+        let mut insts = vec![Inst::Syscall(operands::Empty), Inst::Break(operands::Empty)];
+
+        for dest in Register::all() {
+            for lhs in Register::all() {
+                for rhs in Register::all() {
+                    insts.extend(rrr.map(|f| f(operands::RRR { dest, lhs, rhs })));
+                }
+
+                insts.extend(rr.map(|f| {
+                    f(operands::RR {
+                        lhs: dest,
+                        rhs: lhs,
+                    })
+                }));
+
+                for value in rng.values(operands::IMMEDIATE_WIDTH) {
+                    let immediate = T12::try_from_int(value).unwrap();
+                    let src = lhs;
+                    insts.extend(rri.map(|f| {
+                        f(operands::RRI {
+                            dest,
+                            src,
+                            immediate,
+                        })
+                    }));
+                    insts.extend(rro.map(|f| {
+                        f(operands::RRO {
+                            dest,
+                            src,
+                            offset: immediate,
+                        })
+                    }));
+                }
+            }
+
+            for value in rng.values(operands::IMMEDIATE_WIDTH) {
+                let immediate = T12::try_from_int(value).unwrap();
+                insts.push(Inst::Lui(operands::RI { dest, immediate }));
+            }
+
+            for value in rng.values(operands::OFFSET_WIDTH) {
+                let offset = T24::try_from_int(value).unwrap();
+                insts.extend(ro.map(|f| f(operands::RO { src: dest, offset })));
+            }
+
+            insts.extend(r.map(|f| f(operands::R { src: dest })));
+        }
+
+        for value in rng.values(operands::OFFSET_WIDTH) {
+            let offset = T24::try_from_int(value).unwrap();
+            insts.push(Inst::Bal(operands::O { offset }));
+        }
+
+        for value in rng.values(operands::ADDR_WIDTH) {
+            let addr = T24::try_from_int(value).unwrap();
+            insts.extend(a.map(|f| f(operands::A { addr })));
+        }
+
+        insts
+    }
+
+    fn random_inst(rng: &mut Rng) -> Inst {
+        let immediate = T12::try_from_int(rng.range(operands::max_value(12))).unwrap();
+        let offset = T24::try_from_int(rng.range(operands::max_value(16))).unwrap();
+        let addr = T24::try_from_int(rng.range(operands::max_value(20))).unwrap();
+        let (dest, lhs, rhs) = (rng.register(), rng.register(), rng.register());
+
+        match rng.index(10) {
+            0 => Inst::Add(operands::RRR { dest, lhs, rhs }),
+            1 => Inst::Div(operands::RR { lhs, rhs }),
+            2 => Inst::Addi(operands::RRI {
+                dest,
+                src: lhs,
+                immediate,
+            }),
+            3 => Inst::Lui(operands::RI { dest, immediate }),
+            4 => Inst::Sw(operands::RRO {
+                dest,
+                src: lhs,
+                offset: immediate,
+            }),
+            5 => Inst::B1(operands::RO { src: lhs, offset }),
+            6 => Inst::Bal(operands::O { offset }),
+            7 => Inst::Jal(operands::A { addr }),
+            8 => Inst::Jalr(operands::R { src: lhs }),
+            _ => Inst::Syscall(operands::Empty),
+        }
+    }
+
     fn inst(s: &str) -> Result<Inst> {
         let word = T24::from_trit_str(s)?;
         Inst::from_word(word)
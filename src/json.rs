@@ -0,0 +1,564 @@
+use std::collections::BTreeMap;
+use std::fmt;
+use std::io::{self, BufRead, ErrorKind, Read, Write};
+use std::ops::Index;
+
+// Just enough JSON for the debug adapter and language server protocols.
+// Integers are kept apart from other numbers, since the protocols only use
+// them for ids, lines and columns.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub enum Value {
+    #[default]
+    Null,
+    Bool(bool),
+    Int(i64),
+    Float(f64),
+    String(String),
+    Array(Vec<Value>),
+    Object(BTreeMap<String, Value>),
+}
+
+static NULL: Value = Value::Null;
+
+// How deeply arrays and objects can nest, so that parsing can't overflow the
+// stack
+const MAX_DEPTH: usize = 256;
+
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Error {
+    pub offset: usize,
+    pub message: &'static str,
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} at offset {}", self.message, self.offset)
+    }
+}
+
+impl std::error::Error for Error {}
+
+impl Value {
+    pub fn parse(text: &str) -> Result<Self, Error> {
+        let mut parser = Parser {
+            bytes: text.as_bytes(),
+            offset: 0,
+            depth: 0,
+        };
+        let value = parser.value()?;
+        parser.skip_whitespace();
+        if parser.offset < text.len() {
+            return parser.error("trailing characters");
+        }
+
+        Ok(value)
+    }
+
+    pub fn as_str(&self) -> Option<&str> {
+        match self {
+            Value::String(string) => Some(string),
+            _ => None,
+        }
+    }
+
+    pub fn as_i64(&self) -> Option<i64> {
+        match self {
+            Value::Int(value) => Some(*value),
+            _ => None,
+        }
+    }
+
+    pub fn as_bool(&self) -> Option<bool> {
+        match self {
+            Value::Bool(value) => Some(*value),
+            _ => None,
+        }
+    }
+
+    pub fn as_array(&self) -> &[Value] {
+        match self {
+            Value::Array(values) => values,
+            _ => &[],
+        }
+    }
+
+    pub fn is_null(&self) -> bool {
+        *self == Value::Null
+    }
+
+    // Sets a field, turning anything that isn't an object into one.
+    pub fn insert(&mut self, key: &str, value: impl Into<Value>) {
+        if !matches!(self, Value::Object(_)) {
+            *self = Value::Object(BTreeMap::new());
+        }
+
+        if let Value::Object(fields) = self {
+            fields.insert(key.to_string(), value.into());
+        }
+    }
+}
+
+pub fn object<const N: usize>(fields: [(&str, Value); N]) -> Value {
+    Value::Object(
+        fields
+            .into_iter()
+            .map(|(key, value)| (key.to_string(), value))
+            .collect(),
+    )
+}
+
+// Missing fields and indexes are null, like in JavaScript.
+impl Index<&str> for Value {
+    type Output = Value;
+
+    fn index(&self, key: &str) -> &Value {
+        match self {
+            Value::Object(fields) => fields.get(key).unwrap_or(&NULL),
+            _ => &NULL,
+        }
+    }
+}
+
+impl Index<usize> for Value {
+    type Output = Value;
+
+    fn index(&self, index: usize) -> &Value {
+        self.as_array().get(index).unwrap_or(&NULL)
+    }
+}
+
+impl From<bool> for Value {
+    fn from(value: bool) -> Self {
+        Value::Bool(value)
+    }
+}
+
+impl From<i64> for Value {
+    fn from(value: i64) -> Self {
+        Value::Int(value)
+    }
+}
+
+impl From<i32> for Value {
+    fn from(value: i32) -> Self {
+        Value::Int(value.into())
+    }
+}
+
+impl From<usize> for Value {
+    fn from(value: usize) -> Self {
+        Value::Int(i64::try_from(value).unwrap_or(i64::MAX))
+    }
+}
+
+impl From<&str> for Value {
+    fn from(value: &str) -> Self {
+        Value::String(value.to_string())
+    }
+}
+
+impl From<String> for Value {
+    fn from(value: String) -> Self {
+        Value::String(value)
+    }
+}
+
+impl From<Vec<Value>> for Value {
+    fn from(values: Vec<Value>) -> Self {
+        Value::Array(values)
+    }
+}
+
+impl<T: Into<Value>> From<Option<T>> for Value {
+    fn from(value: Option<T>) -> Self {
+        value.map_or(Value::Null, Into::into)
+    }
+}
+
+impl fmt::Display for Value {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Value::Bool(value) => write!(f, "{value}"),
+            Value::Int(value) => write!(f, "{value}"),
+            Value::Float(value) if value.is_finite() => write!(f, "{value}"),
+            Value::Null | Value::Float(_) => write!(f, "null"),
+            Value::String(string) => write_string(f, string),
+            Value::Array(values) => {
+                write!(f, "[")?;
+                for (i, value) in values.iter().enumerate() {
+                    if i > 0 {
+                        write!(f, ",")?;
+                    }
+                    write!(f, "{value}")?;
+                }
+                write!(f, "]")
+            }
+            Value::Object(fields) => {
+                write!(f, "{{")?;
+                for (i, (key, value)) in fields.iter().enumerate() {
+                    if i > 0 {
+                        write!(f, ",")?;
+                    }
+                    write_string(f, key)?;
+                    write!(f, ":{value}")?;
+                }
+                write!(f, "}}")
+            }
+        }
+    }
+}
+
+fn write_string(f: &mut fmt::Formatter<'_>, string: &str) -> fmt::Result {
+    write!(f, "\"")?;
+    for c in string.chars() {
+        match c {
+            '"' => write!(f, "\\\"")?,
+            '\\' => write!(f, "\\\\")?,
+            '\n' => write!(f, "\\n")?,
+            '\r' => write!(f, "\\r")?,
+            '\t' => write!(f, "\\t")?,
+            c if c < ' ' => write!(f, "\\u{:04x}", u32::from(c))?,
+            c => write!(f, "{c}")?,
+        }
+    }
+    write!(f, "\"")
+}
+
+struct Parser<'a> {
+    bytes: &'a [u8],
+    offset: usize,
+    depth: usize,
+}
+
+impl Parser<'_> {
+    fn value(&mut self) -> Result<Value, Error> {
+        self.skip_whitespace();
+        match self.peek() {
+            Some(b'n') => self.keyword("null", Value::Null),
+            Some(b't') => self.keyword("true", Value::Bool(true)),
+            Some(b'f') => self.keyword("false", Value::Bool(false)),
+            Some(b'"') => self.string().map(Value::String),
+            Some(b'[') => self.nested(Self::array),
+            Some(b'{') => self.nested(Self::object),
+            Some(b'-' | b'0'..=b'9') => self.number(),
+            Some(_) => self.error("unexpected character"),
+            None => self.error("unexpected end of input"),
+        }
+    }
+
+    fn nested(&mut self, parse: fn(&mut Self) -> Result<Value, Error>) -> Result<Value, Error> {
+        if self.depth == MAX_DEPTH {
+            return self.error("nested too deeply");
+        }
+
+        self.depth += 1;
+        let value = parse(self);
+        self.depth -= 1;
+        value
+    }
+
+    fn keyword(&mut self, keyword: &str, value: Value) -> Result<Value, Error> {
+        if self.bytes[self.offset..].starts_with(keyword.as_bytes()) {
+            self.offset += keyword.len();
+            Ok(value)
+        } else {
+            self.error("invalid keyword")
+        }
+    }
+
+    fn number(&mut self) -> Result<Value, Error> {
+        let start = self.offset;
+        while let Some(b'-' | b'+' | b'.' | b'e' | b'E' | b'0'..=b'9') = self.peek() {
+            self.offset += 1;
+        }
+
+        let text = std::str::from_utf8(&self.bytes[start..self.offset]).unwrap();
+        match (text.parse(), text.parse()) {
+            (Ok(value), _) => Ok(Value::Int(value)),
+            (_, Ok(value)) => Ok(Value::Float(value)),
+            _ => {
+                self.offset = start;
+                self.error("invalid number")
+            }
+        }
+    }
+
+    fn string(&mut self) -> Result<String, Error> {
+        self.offset += 1;
+        let mut string = Vec::new();
+        loop {
+            let Some(byte) = self.peek() else {
+                return self.error("unterminated string");
+            };
+            self.offset += 1;
+
+            match byte {
+                b'"' => break,
+                b'\\' => {
+                    let Some(escape) = self.peek() else {
+                        return self.error("unterminated string");
+                    };
+                    self.offset += 1;
+
+                    let c = match escape {
+                        b'"' => '"',
+                        b'\\' => '\\',
+                        b'/' => '/',
+                        b'b' => '\u{8}',
+                        b'f' => '\u{c}',
+                        b'n' => '\n',
+                        b'r' => '\r',
+                        b't' => '\t',
+                        b'u' => self.unicode_escape()?,
+                        _ => return self.error("invalid escape"),
+                    };
+                    let mut buffer = [0; 4];
+                    string.extend_from_slice(c.encode_utf8(&mut buffer).as_bytes());
+                }
+                byte => string.push(byte),
+            }
+        }
+
+        String::from_utf8(string).or_else(|_| self.error("invalid UTF-8"))
+    }
+
+    // `\uXXXX`, which may be the first half of a surrogate pair
+    fn unicode_escape(&mut self) -> Result<char, Error> {
+        let high = self.hex4()?;
+        let code = if (0xd800..0xdc00).contains(&high) {
+            if !self.bytes[self.offset..].starts_with(b"\\u") {
+                return self.error("invalid surrogate pair");
+            }
+            self.offset += 2;
+
+            let low = self.hex4()?;
+            if !(0xdc00..0xe000).contains(&low) {
+                return self.error("invalid surrogate pair");
+            }
+            0x10000 + ((high - 0xd800) << 10) + (low - 0xdc00)
+        } else {
+            high
+        };
+
+        char::from_u32(code).map_or_else(|| self.error("invalid escape"), Ok)
+    }
+
+    fn hex4(&mut self) -> Result<u32, Error> {
+        let digits = self
+            .bytes
+            .get(self.offset..self.offset + 4)
+            .and_then(|digits| std::str::from_utf8(digits).ok())
+            .and_then(|digits| u32::from_str_radix(digits, 16).ok());
+        let Some(value) = digits else {
+            return self.error("invalid escape");
+        };
+
+        self.offset += 4;
+        Ok(value)
+    }
+
+    fn array(&mut self) -> Result<Value, Error> {
+        self.offset += 1;
+        let mut values = Vec::new();
+        self.skip_whitespace();
+        if self.peek() == Some(b']') {
+            self.offset += 1;
+            return Ok(Value::Array(values));
+        }
+
+        loop {
+            values.push(self.value()?);
+            self.skip_whitespace();
+            match self.peek() {
+                Some(b',') => self.offset += 1,
+                Some(b']') => {
+                    self.offset += 1;
+                    return Ok(Value::Array(values));
+                }
+                _ => return self.error("expected `,` or `]`"),
+            }
+        }
+    }
+
+    fn object(&mut self) -> Result<Value, Error> {
+        self.offset += 1;
+        let mut fields = BTreeMap::new();
+        self.skip_whitespace();
+        if self.peek() == Some(b'}') {
+            self.offset += 1;
+            return Ok(Value::Object(fields));
+        }
+
+        loop {
+            self.skip_whitespace();
+            if self.peek() != Some(b'"') {
+                return self.error("expected a key");
+            }
+            let key = self.string()?;
+
+            self.skip_whitespace();
+            if self.peek() != Some(b':') {
+                return self.error("expected `:`");
+            }
+            self.offset += 1;
+
+            fields.insert(key, self.value()?);
+            self.skip_whitespace();
+            match self.peek() {
+                Some(b',') => self.offset += 1,
+                Some(b'}') => {
+                    self.offset += 1;
+                    return Ok(Value::Object(fields));
+                }
+                _ => return self.error("expected `,` or `}`"),
+            }
+        }
+    }
+
+    fn skip_whitespace(&mut self) {
+        while let Some(b' ' | b'\t' | b'\n' | b'\r') = self.peek() {
+            self.offset += 1;
+        }
+    }
+
+    fn peek(&self) -> Option<u8> {
+        self.bytes.get(self.offset).copied()
+    }
+
+    fn error<T>(&self, message: &'static str) -> Result<T, Error> {
+        Err(Error {
+            offset: self.offset,
+            message,
+        })
+    }
+}
+
+// Reads a message framed with a `Content-Length` header, as used by both the
+// debug adapter and language server protocols. Returns None at the end of the
+// input. A body that isn't valid JSON is returned as an error rather than
+// failing the read, so that the caller can report it and carry on.
+pub fn read_message(input: &mut impl BufRead) -> io::Result<Option<Result<Value, Error>>> {
+    let mut length = None;
+    loop {
+        let mut line = String::new();
+        if input.read_line(&mut line)? == 0 {
+            return Ok(None);
+        }
+
+        let line = line.trim_end();
+        if line.is_empty() {
+            if length.is_some() {
+                break;
+            }
+            continue;
+        }
+
+        if let Some((name, value)) = line.split_once(':')
+            && name.eq_ignore_ascii_case("Content-Length")
+        {
+            length = value.trim().parse::<usize>().ok();
+        }
+    }
+
+    // read as it arrives, rather than trusting the length with an allocation
+    let length = length.unwrap();
+    let mut body = Vec::new();
+    input.take(length as u64).read_to_end(&mut body)?;
+    if body.len() < length {
+        return Err(ErrorKind::UnexpectedEof.into());
+    }
+
+    let body = match String::from_utf8(body) {
+        Ok(body) => body,
+        Err(error) => {
+            return Ok(Some(Err(Error {
+                offset: error.utf8_error().valid_up_to(),
+                message: "invalid UTF-8",
+            })));
+        }
+    };
+
+    Ok(Some(Value::parse(&body)))
+}
+
+pub fn write_message(output: &mut impl Write, message: &Value) -> io::Result<()> {
+    let body = message.to_string();
+    write!(output, "Content-Length: {}\r\n\r\n{body}", body.len())?;
+    output.flush()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_and_print() {
+        let text = r#" {"a": [1, -2.5, true, null], "b": "x\"é😀\n", "c": {}} "#;
+        let value = Value::parse(text).unwrap();
+        assert_eq!(Some(1), value["a"][0].as_i64());
+        assert_eq!(Value::Float(-2.5), value["a"][1]);
+        assert_eq!(Some("x\"é😀\n"), value["b"].as_str());
+        assert!(value["missing"]["deeper"].is_null());
+        assert_eq!(
+            r#"{"a":[1,-2.5,true,null],"b":"x\"é😀\n","c":{}}"#,
+            value.to_string()
+        );
+
+        assert_eq!(
+            Err(Error {
+                offset: 6,
+                message: "expected `,` or `]`"
+            }),
+            Value::parse("[1, 2 3]")
+        );
+        assert!(Value::parse("{\"a\" 1}").is_err());
+        assert!(Value::parse("1 2").is_err());
+
+        let nested = "[".repeat(MAX_DEPTH) + &"]".repeat(MAX_DEPTH);
+        assert!(Value::parse(&nested).is_ok());
+        assert_eq!(
+            Err(Error {
+                offset: MAX_DEPTH,
+                message: "nested too deeply"
+            }),
+            Value::parse(&"[".repeat(100_000))
+        );
+    }
+
+    #[test]
+    fn frame_messages() {
+        let mut output = Vec::new();
+        write_message(&mut output, &object([("seq", 1.into())])).unwrap();
+        write_message(&mut output, &Value::from("é")).unwrap();
+        assert_eq!(
+            "Content-Length: 9\r\n\r\n{\"seq\":1}Content-Length: 4\r\n\r\n\"é\"",
+            String::from_utf8(output.clone()).unwrap()
+        );
+
+        let mut input = output.as_slice();
+        assert_eq!(
+            Some(Ok(object([("seq", 1.into())]))),
+            read_message(&mut input).unwrap()
+        );
+        assert_eq!(
+            Some(Ok(Value::from("é"))),
+            read_message(&mut input).unwrap()
+        );
+        assert_eq!(None, read_message(&mut input).unwrap());
+
+        let mut input = &b"Content-Length: 3\r\n\r\n{]}Content-Length: 2\r\n\r\n\xff1"[..];
+        assert!(matches!(read_message(&mut input), Ok(Some(Err(_)))));
+        assert_eq!(
+            Some(Err(Error {
+                offset: 0,
+                message: "invalid UTF-8"
+            })),
+            read_message(&mut input).unwrap()
+        );
+        assert_eq!(None, read_message(&mut input).unwrap());
+
+        let mut input = &b"Content-Length: 99999999999\r\n\r\n{}"[..];
+        let error = read_message(&mut input).unwrap_err();
+        assert_eq!(ErrorKind::UnexpectedEof, error.kind());
+    }
+}
@@ -1,9 +1,41 @@
 #![deny(clippy::all, clippy::pedantic)]
-#![allow(unused)] // necessary until there are binaries
+#![allow(
+    clippy::missing_errors_doc,
+    clippy::missing_panics_doc,
+    clippy::must_use_candidate
+)]
 
+pub mod asm;
+pub mod dap;
+pub mod debug;
+pub mod debugger;
+pub mod disasm;
 mod error;
+pub mod gdb;
+pub mod image;
 mod inst;
-mod opcodes;
-mod operands;
-mod registers;
+pub mod json;
+pub mod link;
+pub mod lsp;
+pub mod map;
+mod memory;
+pub mod numerals;
+pub mod object;
+pub mod opcodes;
+pub mod operands;
+pub mod registers;
+pub mod syscall;
+pub mod trace;
+mod trap;
 mod vm;
+pub mod watch;
+
+pub use error::{Error, Result};
+pub use image::Image;
+pub use inst::{DisplayAt, Inst};
+pub use memory::Memory;
+pub use opcodes::Opcode;
+pub use registers::{Register, Registers};
+pub use syscall::SyscallHandler;
+pub use trap::{Trap, TrapCause};
+pub use vm::{StopReason, VM};
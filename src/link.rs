@@ -0,0 +1,515 @@
+use std::collections::HashMap;
+use std::fmt;
+
+use ternary::{T12, T24, Tryte};
+
+use crate::asm::ENTRY_SYMBOL;
+use crate::image::{Image, Segment, Symbol};
+use crate::inst::Inst;
+use crate::map::{Map, SectionEntry};
+use crate::object::{Object, RelocKind, Relocation, Section, Target};
+use crate::operands::{self, ADDR_WIDTH, OFFSET_WIDTH};
+
+const WORD_SIZE: i64 = 4;
+const WORD_WIDTH: usize = 24;
+
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum Error {
+    UndefinedSymbol(String),
+    DuplicateSymbol(String),
+    OutOfRange(String, i64, usize),
+    InvalidRelocation(Section, u32),
+    AddressOutOfRange(i64),
+    Overlap(Section, Section),
+    InvalidLine(i32),
+}
+
+pub type Result<T> = std::result::Result<T, Error>;
+
+// The address of `.text`, and optionally of `.data` and `.bss`. A section
+// without an address follows the previous one, aligned to a word.
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub struct Layout {
+    pub text: i32,
+    pub data: Option<i32>,
+    pub bss: Option<i32>,
+}
+
+// Each object's sections are placed one after another within the sections of
+// the image, aligned to a word. Global symbols are visible to every object,
+// while local symbols only resolve relocations in the object defining them.
+pub fn link(objects: &[Object], layout: &Layout) -> Result<Image> {
+    link_with_map(objects, layout).map(|(image, _)| image)
+}
+
+pub fn link_with_map(objects: &[Object], layout: &Layout) -> Result<(Image, Map)> {
+    let placement = Placement::new(objects, layout)?;
+    let addr = |i, section, offset| placement.addr(i, section, offset);
+
+    let mut globals = HashMap::new();
+    for (i, object) in objects.iter().enumerate() {
+        for symbol in object.symbols.iter().filter(|symbol| symbol.global) {
+            let value = addr(i, symbol.section, symbol.offset);
+            if globals.insert(symbol.name.as_str(), value).is_some() {
+                return Err(Error::DuplicateSymbol(symbol.name.clone()));
+            }
+        }
+    }
+
+    let mut sections: [Vec<Tryte>; 2] = Default::default();
+    for (i, object) in objects.iter().enumerate() {
+        for (trytes, section) in sections.iter_mut().zip([Section::Text, Section::Data]) {
+            let offset = usize::try_from(placement.offsets[i][section.index()]).unwrap();
+            trytes.resize(offset, Tryte::ZERO);
+        }
+
+        let start = sections.each_ref().map(Vec::len);
+        sections[0].extend_from_slice(&object.text);
+        sections[1].extend_from_slice(&object.data);
+
+        for relocation in &object.relocations {
+            let (name, target) = match &relocation.target {
+                Target::Section(section) => (section.name(), addr(i, *section, 0)),
+                Target::Symbol(name) => {
+                    let target = match object.symbol(name) {
+                        Some(symbol) => addr(i, symbol.section, symbol.offset),
+                        None => *globals
+                            .get(name.as_str())
+                            .ok_or_else(|| Error::UndefinedSymbol(name.clone()))?,
+                    };
+                    (name.as_str(), target)
+                }
+            };
+
+            let Some(trytes) = sections.get_mut(relocation.section.index()) else {
+                return Err(invalid(relocation));
+            };
+
+            let place = addr(i, relocation.section, relocation.offset);
+            let start =
+                start[relocation.section.index()] + usize::try_from(relocation.offset).unwrap();
+            let field = trytes
+                .get_mut(start..start + usize::try_from(relocation.kind.size()).unwrap())
+                .ok_or_else(|| invalid(relocation))?;
+            let value = target.checked_add(relocation.addend);
+            patch(field, relocation, name, value, place)?;
+        }
+    }
+
+    let mut image = Image::new(layout.text);
+    let bss = vec![Tryte::ZERO; usize::try_from(placement.sizes[Section::Bss.index()]).unwrap()];
+    for (section, trytes) in Section::ALL
+        .into_iter()
+        .zip(sections.into_iter().chain([bss]))
+    {
+        if !trytes.is_empty() {
+            image.segments.push(Segment {
+                addr: narrow(placement.bases[section.index()])?,
+                trytes,
+            });
+        }
+    }
+
+    let mut symbols = Vec::new();
+    for (i, object) in objects.iter().enumerate() {
+        for symbol in &object.symbols {
+            let value = addr(i, symbol.section, symbol.offset);
+            image.symbols.push(Symbol {
+                name: symbol.name.clone(),
+                addr: narrow(value)?,
+            });
+            symbols.push((symbol.name.clone(), symbol.section, value));
+        }
+
+        for (offset, location) in object.lines.iter() {
+            let offset = u32::try_from(offset)
+                .ok()
+                .filter(|&offset| offset < object.section_size(Section::Text))
+                .ok_or(Error::InvalidLine(offset))?;
+            let addr = addr(i, Section::Text, offset);
+            image.lines.push(narrow(addr)?, &location);
+        }
+    }
+
+    image
+        .symbols
+        .sort_by(|a, b| (a.addr, &a.name).cmp(&(b.addr, &b.name)));
+
+    if let Some(&entry) = globals.get(ENTRY_SYMBOL) {
+        image.entry = narrow(entry)?;
+    }
+
+    let sections = Section::ALL
+        .into_iter()
+        .map(|section| SectionEntry {
+            section,
+            addr: placement.bases[section.index()],
+            size: placement.sizes[section.index()],
+        })
+        .collect();
+    let map = Map::new(sections, &image.segments, symbols);
+
+    Ok((image, map))
+}
+
+// The offset of each object's sections within the sections of the image, and
+// the size and address of each section of the image.
+struct Placement {
+    offsets: Vec<[i64; 3]>,
+    sizes: [i64; 3],
+    bases: [i64; 3],
+}
+
+impl Placement {
+    fn new(objects: &[Object], layout: &Layout) -> Result<Self> {
+        let mut offsets = vec![[0; 3]; objects.len()];
+        let mut sizes = [0; 3];
+        for (object, offsets) in objects.iter().zip(&mut offsets) {
+            for section in Section::ALL {
+                let len = i64::from(object.section_size(section));
+                let size = &mut sizes[section.index()];
+                if len > 0 {
+                    *size = align_up(*size);
+                }
+
+                offsets[section.index()] = *size;
+                *size += len;
+            }
+        }
+
+        let mut bases = [0; 3];
+        let mut end = i64::from(layout.text);
+        for (section, addr) in
+            Section::ALL
+                .into_iter()
+                .zip([Some(layout.text), layout.data, layout.bss])
+        {
+            let base = addr.map_or_else(|| align_up(end), i64::from);
+            bases[section.index()] = base;
+            end = base + sizes[section.index()];
+            if i32::try_from(base).is_err() || i32::try_from(end).is_err() {
+                return Err(Error::AddressOutOfRange(end));
+            }
+        }
+
+        for (i, a) in Section::ALL.into_iter().enumerate() {
+            for b in Section::ALL.into_iter().skip(i + 1) {
+                let (a_start, b_start) = (bases[a.index()], bases[b.index()]);
+                let (a_end, b_end) = (a_start + sizes[a.index()], b_start + sizes[b.index()]);
+                if a_start < b_end && b_start < a_end {
+                    return Err(Error::Overlap(a, b));
+                }
+            }
+        }
+
+        Ok(Placement {
+            offsets,
+            sizes,
+            bases,
+        })
+    }
+
+    fn addr(&self, object: usize, section: Section, offset: u32) -> i64 {
+        self.bases[section.index()] + self.offsets[object][section.index()] + i64::from(offset)
+    }
+}
+
+fn patch(
+    field: &mut [Tryte],
+    relocation: &Relocation,
+    name: &str,
+    value: Option<i64>,
+    place: i64,
+) -> Result<()> {
+    // None if the value overflowed, in which case the addend is reported
+    let check = |value: Option<i64>, width: usize| match value {
+        Some(value) if operands::fits_width(value, width) => Ok(value),
+        _ => Err(Error::OutOfRange(
+            name.to_string(),
+            value.unwrap_or(relocation.addend),
+            width,
+        )),
+    };
+
+    match relocation.kind {
+        RelocKind::Word => {
+            let value = check(value, WORD_WIDTH)?;
+            field.copy_from_slice(&T24::try_from_int(value).unwrap().into_trytes());
+        }
+        RelocKind::Address => {
+            let addr = T24::try_from_int(check(value, ADDR_WIDTH)?).unwrap();
+            let inst = match decode(field, relocation)? {
+                Inst::J(_) => Inst::J(operands::A { addr }),
+                Inst::Jal(_) => Inst::Jal(operands::A { addr }),
+                _ => return Err(invalid(relocation)),
+            };
+            encode(field, inst, relocation)?;
+        }
+        RelocKind::Branch => {
+            let offset = check(
+                value.and_then(|value| value.checked_sub(place + WORD_SIZE)),
+                OFFSET_WIDTH,
+            )?;
+            let offset = T24::try_from_int(offset).unwrap();
+            let inst = match decode(field, relocation)? {
+                Inst::BT(ops) => Inst::BT(operands::RO { offset, ..ops }),
+                Inst::B0(ops) => Inst::B0(operands::RO { offset, ..ops }),
+                Inst::B1(ops) => Inst::B1(operands::RO { offset, ..ops }),
+                Inst::BT0(ops) => Inst::BT0(operands::RO { offset, ..ops }),
+                Inst::BT1(ops) => Inst::BT1(operands::RO { offset, ..ops }),
+                Inst::B01(ops) => Inst::B01(operands::RO { offset, ..ops }),
+                Inst::Bal(_) => Inst::Bal(operands::O { offset }),
+                _ => return Err(invalid(relocation)),
+            };
+            encode(field, inst, relocation)?;
+        }
+        RelocKind::LoadAddress => {
+            let (hi, lo) = operands::split_address(check(value, WORD_WIDTH)?);
+            let (upper, lower) = field.split_at_mut(4);
+            let (Inst::Lui(lui), Inst::Addi(addi)) =
+                (decode(upper, relocation)?, decode(lower, relocation)?)
+            else {
+                return Err(invalid(relocation));
+            };
+
+            let immediate = |value| T12::try_from_int(value).unwrap();
+            let lui = Inst::Lui(operands::RI {
+                immediate: immediate(hi),
+                ..lui
+            });
+            let addi = Inst::Addi(operands::RRI {
+                immediate: immediate(lo),
+                ..addi
+            });
+            encode(upper, lui, relocation)?;
+            encode(lower, addi, relocation)?;
+        }
+        RelocKind::Hi | RelocKind::Lo => {
+            let (hi, lo) = operands::split_address(check(value, WORD_WIDTH)?);
+            let part = if relocation.kind == RelocKind::Hi {
+                hi
+            } else {
+                lo
+            };
+            let inst = with_immediate(decode(field, relocation)?, T12::try_from_int(part).unwrap())
+                .ok_or_else(|| invalid(relocation))?;
+            encode(field, inst, relocation)?;
+        }
+    }
+
+    Ok(())
+}
+
+// Replaces the 12-trit immediate or offset of `inst`, if it has one.
+fn with_immediate(inst: Inst, immediate: T12) -> Option<Inst> {
+    let rri = |ops| operands::RRI { immediate, ..ops };
+    let rro = |ops| operands::RRO {
+        offset: immediate,
+        ..ops
+    };
+
+    let inst = match inst {
+        Inst::Lui(ops) => Inst::Lui(operands::RI { immediate, ..ops }),
+        Inst::Andi(ops) => Inst::Andi(rri(ops)),
+        Inst::Ori(ops) => Inst::Ori(rri(ops)),
+        Inst::Tmuli(ops) => Inst::Tmuli(rri(ops)),
+        Inst::Tcmpi(ops) => Inst::Tcmpi(rri(ops)),
+        Inst::Shfi(ops) => Inst::Shfi(rri(ops)),
+        Inst::Addi(ops) => Inst::Addi(rri(ops)),
+        Inst::Lt(ops) => Inst::Lt(rro(ops)),
+        Inst::Lh(ops) => Inst::Lh(rro(ops)),
+        Inst::Lw(ops) => Inst::Lw(rro(ops)),
+        Inst::St(ops) => Inst::St(rro(ops)),
+        Inst::Sh(ops) => Inst::Sh(rro(ops)),
+        Inst::Sw(ops) => Inst::Sw(rro(ops)),
+        _ => return None,
+    };
+
+    Some(inst)
+}
+
+fn decode(field: &[Tryte], relocation: &Relocation) -> Result<Inst> {
+    let word = T24::try_from(field).map_err(|_| invalid(relocation))?;
+    Inst::from_word(word).map_err(|_| invalid(relocation))
+}
+
+fn encode(field: &mut [Tryte], inst: Inst, relocation: &Relocation) -> Result<()> {
+    let word = inst.to_word().map_err(|_| invalid(relocation))?;
+    field.copy_from_slice(&word.into_trytes());
+    Ok(())
+}
+
+fn invalid(relocation: &Relocation) -> Error {
+    Error::InvalidRelocation(relocation.section, relocation.offset)
+}
+
+fn narrow(addr: i64) -> Result<i32> {
+    i32::try_from(addr).map_err(|_| Error::AddressOutOfRange(addr))
+}
+
+fn align_up(value: i64) -> i64 {
+    value + (WORD_SIZE - value.rem_euclid(WORD_SIZE)) % WORD_SIZE
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::UndefinedSymbol(name) => write!(f, "undefined symbol `{name}`"),
+            Error::DuplicateSymbol(name) => write!(f, "symbol `{name}` is defined more than once"),
+            Error::OutOfRange(name, value, width) => {
+                write!(
+                    f,
+                    "relocation against `{name}`: value {value} does not fit in {width} trits"
+                )
+            }
+            Error::InvalidRelocation(section, offset) => {
+                write!(f, "invalid relocation at {}+{offset}", section.name())
+            }
+            Error::AddressOutOfRange(addr) => write!(f, "address {addr} is out of range"),
+            Error::Overlap(a, b) => write!(f, "`{}` overlaps `{}`", a.name(), b.name()),
+            Error::InvalidLine(offset) => write!(f, "line table entry at invalid offset {offset}"),
+        }
+    }
+}
+
+impl std::error::Error for Error {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::asm::assemble_object;
+    use crate::syscall::Console;
+    use crate::{StopReason, VM};
+
+    const MAIN: &str = r#"
+        .global _start
+        _start:
+            jal helper
+            li $a0, 1
+            syscall
+            la $a1, msg
+            li $a0, 3
+            syscall
+            b0 $zero, done
+            break
+        done:
+            bal finish
+        .data
+        msg: .asciz "!"
+    "#;
+
+    const HELPER: &str = "
+        .globl helper, finish
+        helper:
+            la $t0, ptr
+            lw $t0, $t0, 0
+            lw $a1, $t0, 0
+            jr $ra
+        finish:
+            li $a0, 0
+            li $a1, 0
+            syscall
+        .data
+        ptr: .word value - 4
+        .bss
+        pad: .zero 2
+        .data
+            .word 7
+        value: .word 42
+    ";
+
+    fn objects(sources: &[&str]) -> Vec<Object> {
+        sources
+            .iter()
+            .map(|source| assemble_object(source).unwrap())
+            .collect()
+    }
+
+    fn run(image: &Image) -> String {
+        let console = Console::new(&b""[..], Vec::new());
+        let mut vm = VM::with_syscall_handler(729, console);
+        vm.load_image(image).unwrap();
+        assert_eq!(StopReason::Exit { code: 0 }, vm.run(image.entry).unwrap());
+        String::from_utf8(vm.syscall_handler().output().clone()).unwrap()
+    }
+
+    #[test]
+    fn link_objects() {
+        let objects = objects(&[HELPER, MAIN]);
+        let image = link(&objects, &Layout::default()).unwrap();
+        assert_eq!(image.symbol("_start").unwrap().addr, image.entry);
+        assert_eq!(Some(32), image.symbol("_start").map(|symbol| symbol.addr));
+        assert_eq!("7!", run(&image));
+
+        let line = |addr| image.lines.location(addr).map(|location| location.line);
+        assert_eq!(Some(4), line(0));
+        assert_eq!(Some(4), line(32));
+        assert_eq!(Some(7), line(44));
+        assert_eq!(Some(7), line(48));
+
+        let layout = Layout {
+            text: -200,
+            data: Some(100),
+            bss: None,
+        };
+        let image = link(&objects, &layout).unwrap();
+        let segments: Vec<_> = image
+            .segments
+            .iter()
+            .map(|segment| (segment.addr, segment.trytes.len()))
+            .collect();
+        assert_eq!(vec![(-200, 72), (100, 14), (116, 2)], segments);
+        assert_eq!(Some(108), image.symbol("value").map(|symbol| symbol.addr));
+        assert_eq!(Some(112), image.symbol("msg").map(|symbol| symbol.addr));
+        assert_eq!("7!", run(&image));
+    }
+
+    #[test]
+    fn link_errors() {
+        assert_eq!(
+            Err(Error::UndefinedSymbol("helper".into())),
+            link(&objects(&[MAIN]), &Layout::default())
+        );
+        assert_eq!(
+            Err(Error::DuplicateSymbol("helper".into())),
+            link(&objects(&[HELPER, HELPER]), &Layout::default())
+        );
+        assert_eq!(
+            Err(Error::OutOfRange("far".into(), 1_743_392_204, 20)),
+            link(
+                &objects(&["j far", ".global far\nnop\nfar: jr $ra"]),
+                &Layout {
+                    text: 1_743_392_196,
+                    ..Layout::default()
+                }
+            )
+        );
+        assert_eq!(
+            Err(Error::Overlap(Section::Text, Section::Data)),
+            link(
+                &objects(&["nop\nnop\n.data\n.word 1"]),
+                &Layout {
+                    text: 0,
+                    data: Some(4),
+                    bss: None,
+                }
+            )
+        );
+
+        let huge = ".word ext + 9223372036854775807";
+        assert_eq!(
+            Err(Error::OutOfRange("ext".into(), i64::MAX, 24)),
+            link(
+                &objects(&[huge, ".global ext\nnop\next: nop"]),
+                &Layout::default()
+            )
+        );
+
+        let mut objects = objects(&["start: nop"]);
+        objects[0].symbols[0].offset = u32::MAX;
+        assert_eq!(
+            Err(Error::AddressOutOfRange(4_294_967_295)),
+            link(&objects, &Layout::default())
+        );
+    }
+}
@@ -0,0 +1,453 @@
+mod document;
+
+use std::collections::HashMap;
+use std::io::{self, BufRead, Write};
+use std::path::PathBuf;
+
+use crate::json::{self, Value, object};
+
+use document::{Completion, CompletionKind, Document, Position, Range};
+
+// JSON-RPC error codes
+const PARSE_ERROR: i64 = -32700;
+const INVALID_PARAMS: i64 = -32602;
+const METHOD_NOT_FOUND: i64 = -32601;
+
+type RequestResult = Result<Value, (i64, String)>;
+
+// Serves the Language Server Protocol until the client exits or disconnects.
+// Documents are sent in full on every change, and assembled again each time
+// to publish their diagnostics.
+pub fn serve<R: BufRead, W: Write>(mut input: R, mut output: W) -> io::Result<()> {
+    let mut server = Server::default();
+    while let Some(message) = json::read_message(&mut input)? {
+        let message = match message {
+            Ok(message) => message,
+            Err(error) => {
+                json::write_message(&mut output, &parse_error(&error))?;
+                continue;
+            }
+        };
+
+        if message["method"].as_str() == Some("exit") {
+            break;
+        }
+
+        for reply in server.handle(&message) {
+            json::write_message(&mut output, &reply)?;
+        }
+    }
+
+    Ok(())
+}
+
+// The response to a message that isn't valid JSON, which has no id to answer.
+fn parse_error(error: &json::Error) -> Value {
+    object([
+        ("jsonrpc", "2.0".into()),
+        ("id", Value::Null),
+        (
+            "error",
+            object([
+                ("code", PARSE_ERROR.into()),
+                ("message", error.to_string().into()),
+            ]),
+        ),
+    ])
+}
+
+#[derive(Default)]
+struct Server {
+    // the open documents, by URI
+    documents: HashMap<String, Document>,
+}
+
+impl Server {
+    // Returns the response to a request, or the notifications that another
+    // notification caused.
+    fn handle(&mut self, message: &Value) -> Vec<Value> {
+        let method = message["method"].as_str().unwrap_or("");
+        let params = &message["params"];
+        let id = &message["id"];
+        if id.is_null() {
+            return self.notify(method, params);
+        }
+
+        let result = match method {
+            "initialize" => Ok(capabilities()),
+            "shutdown" => Ok(Value::Null),
+            "textDocument/hover" => self.hover(params),
+            "textDocument/definition" => self.definition(params),
+            "textDocument/references" => self.references(params),
+            "textDocument/completion" => self.completion(params),
+            _ => Err((METHOD_NOT_FOUND, format!("unsupported method `{method}`"))),
+        };
+
+        let mut response = object([("jsonrpc", "2.0".into()), ("id", id.clone())]);
+        match result {
+            Ok(result) => response.insert("result", result),
+            Err((code, message)) => response.insert(
+                "error",
+                object([("code", code.into()), ("message", message.into())]),
+            ),
+        }
+
+        vec![response]
+    }
+
+    fn notify(&mut self, method: &str, params: &Value) -> Vec<Value> {
+        let uri = params["textDocument"]["uri"].as_str().unwrap_or("");
+        let text = match method {
+            "textDocument/didOpen" => params["textDocument"]["text"].as_str(),
+            "textDocument/didChange" => params["contentChanges"]
+                .as_array()
+                .last()
+                .and_then(|change| change["text"].as_str()),
+            "textDocument/didClose" => {
+                self.documents.remove(uri);
+                return vec![publish_diagnostics(uri, Vec::new())];
+            }
+            _ => None,
+        };
+
+        let Some(text) = text else {
+            return Vec::new();
+        };
+
+        let document = Document::new(&path(uri), text);
+        let diagnostics = document
+            .diagnostics()
+            .iter()
+            .map(|diagnostic| {
+                object([
+                    ("range", range(&document, diagnostic.range)),
+                    ("severity", 1.into()),
+                    ("source", "btm".into()),
+                    ("message", diagnostic.message.clone().into()),
+                ])
+            })
+            .collect();
+        self.documents.insert(uri.to_string(), document);
+        vec![publish_diagnostics(uri, diagnostics)]
+    }
+
+    fn hover(&self, params: &Value) -> RequestResult {
+        let (_, document, position) = self.position(params)?;
+        let Some((hovered, text)) = document.hover(position) else {
+            return Ok(Value::Null);
+        };
+
+        Ok(object([
+            (
+                "contents",
+                object([("kind", "markdown".into()), ("value", text.into())]),
+            ),
+            ("range", range(document, hovered)),
+        ]))
+    }
+
+    fn definition(&self, params: &Value) -> RequestResult {
+        let (uri, document, position) = self.position(params)?;
+        Ok(document
+            .definition(position)
+            .map(|definition| location(uri, document, definition))
+            .into())
+    }
+
+    fn references(&self, params: &Value) -> RequestResult {
+        let (uri, document, position) = self.position(params)?;
+        let include_declaration = params["context"]["includeDeclaration"]
+            .as_bool()
+            .unwrap_or(true);
+        let locations: Vec<_> = document
+            .references(position, include_declaration)
+            .into_iter()
+            .map(|reference| location(uri, document, reference))
+            .collect();
+        Ok(locations.into())
+    }
+
+    fn completion(&self, params: &Value) -> RequestResult {
+        let (_, document, position) = self.position(params)?;
+        let items: Vec<_> = document
+            .completions(position)
+            .into_iter()
+            .map(|completion| completion_item(document, completion))
+            .collect();
+        Ok(items.into())
+    }
+
+    fn position<'a>(
+        &'a self,
+        params: &'a Value,
+    ) -> Result<(&'a str, &'a Document, Position), (i64, String)> {
+        let uri = params["textDocument"]["uri"]
+            .as_str()
+            .ok_or((INVALID_PARAMS, "missing document URI".to_string()))?;
+        let document = self
+            .documents
+            .get(uri)
+            .ok_or_else(|| (INVALID_PARAMS, format!("`{uri}` is not open")))?;
+
+        let line = params["position"]["line"].as_i64().unwrap_or(0);
+        let line = usize::try_from(line).unwrap_or(0);
+        let character = params["position"]["character"].as_i64().unwrap_or(0);
+        let character = chars(document.line(line), usize::try_from(character).unwrap_or(0));
+        Ok((uri, document, Position { line, character }))
+    }
+}
+
+fn capabilities() -> Value {
+    object([
+        (
+            "capabilities",
+            object([
+                ("textDocumentSync", 1.into()),
+                ("hoverProvider", true.into()),
+                ("definitionProvider", true.into()),
+                ("referencesProvider", true.into()),
+                (
+                    "completionProvider",
+                    object([("triggerCharacters", vec![Value::from("$")].into())]),
+                ),
+            ]),
+        ),
+        ("serverInfo", object([("name", "btm".into())])),
+    ])
+}
+
+fn publish_diagnostics(uri: &str, diagnostics: Vec<Value>) -> Value {
+    object([
+        ("jsonrpc", "2.0".into()),
+        ("method", "textDocument/publishDiagnostics".into()),
+        (
+            "params",
+            object([("uri", uri.into()), ("diagnostics", diagnostics.into())]),
+        ),
+    ])
+}
+
+fn completion_item(document: &Document, completion: Completion) -> Value {
+    let kind = match completion.kind {
+        CompletionKind::Macro => 3,
+        CompletionKind::Register => 6,
+        CompletionKind::Mnemonic => 14,
+        CompletionKind::Symbol => 21,
+    };
+
+    object([
+        ("label", completion.label.clone().into()),
+        ("kind", kind.into()),
+        ("detail", completion.detail.into()),
+        (
+            "textEdit",
+            object([
+                ("range", range(document, completion.range)),
+                ("newText", completion.label.into()),
+            ]),
+        ),
+    ])
+}
+
+fn location(uri: &str, document: &Document, target: Range) -> Value {
+    object([("uri", uri.into()), ("range", range(document, target))])
+}
+
+fn range(document: &Document, range: Range) -> Value {
+    let text = document.line(range.line);
+    let position = |character| {
+        object([
+            ("line", range.line.into()),
+            ("character", utf16_units(text, character).into()),
+        ])
+    };
+
+    object([
+        ("start", position(range.start)),
+        ("end", position(range.end)),
+    ])
+}
+
+// The UTF-16 length of the first `chars` characters of `text`, as positions
+// are counted in the protocol.
+fn utf16_units(text: &str, chars: usize) -> usize {
+    text.chars().take(chars).map(char::len_utf16).sum()
+}
+
+// The number of characters in the first `units` UTF-16 code units of `text`.
+fn chars(text: &str, units: usize) -> usize {
+    let mut total = 0;
+    text.chars()
+        .take_while(|c| {
+            total += c.len_utf16();
+            total <= units
+        })
+        .count()
+}
+
+// The path of a `file:` URI, which names the document in diagnostics and
+// locates its includes. Other URIs are used as they are.
+fn path(uri: &str) -> PathBuf {
+    let Some(path) = uri.strip_prefix("file://") else {
+        return PathBuf::from(uri);
+    };
+
+    let mut bytes = Vec::with_capacity(path.len());
+    let mut rest = path.as_bytes();
+    while let Some((&byte, tail)) = rest.split_first() {
+        let escaped = tail
+            .get(..2)
+            .and_then(|hex| std::str::from_utf8(hex).ok())
+            .and_then(|hex| u8::from_str_radix(hex, 16).ok());
+        match escaped {
+            Some(value) if byte == b'%' => {
+                bytes.push(value);
+                rest = &tail[2..];
+            }
+            _ => {
+                bytes.push(byte);
+                rest = tail;
+            }
+        }
+    }
+
+    PathBuf::from(String::from_utf8_lossy(&bytes).into_owned())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const URI: &str = "file:///tmp/btm%20lsp/main.s";
+
+    fn request(id: i64, method: &str, params: Value) -> Value {
+        object([
+            ("jsonrpc", "2.0".into()),
+            ("id", id.into()),
+            ("method", method.into()),
+            ("params", params),
+        ])
+    }
+
+    fn notification(method: &str, params: Value) -> Value {
+        object([
+            ("jsonrpc", "2.0".into()),
+            ("method", method.into()),
+            ("params", params),
+        ])
+    }
+
+    fn at(line: i64, character: i64) -> Value {
+        object([
+            ("textDocument", object([("uri", URI.into())])),
+            (
+                "position",
+                object([("line", line.into()), ("character", character.into())]),
+            ),
+        ])
+    }
+
+    #[test]
+    fn serve_session() {
+        let source = "main: li $t0, 1 # é\n    b main\n    adi $t0, $t0, 1\n";
+        let document = object([
+            ("uri", URI.into()),
+            ("languageId", "btm".into()),
+            ("version", 1.into()),
+            ("text", source.into()),
+        ]);
+        let change = object([
+            ("textDocument", object([("uri", URI.into())])),
+            (
+                "contentChanges",
+                vec![object([("text", "main: b mian\n".into())])].into(),
+            ),
+        ]);
+        let messages = [
+            request(1, "initialize", object([])),
+            notification("initialized", object([])),
+            notification("textDocument/didOpen", object([("textDocument", document)])),
+            request(2, "textDocument/hover", at(0, 6)),
+            request(3, "textDocument/definition", at(1, 7)),
+            request(4, "textDocument/completion", at(1, 6)),
+            request(5, "textDocument/frob", object([])),
+            notification("textDocument/didChange", change),
+            request(6, "shutdown", Value::Null),
+            notification("exit", Value::Null),
+            request(7, "shutdown", Value::Null),
+        ];
+
+        let mut input = Vec::new();
+        for message in &messages[..8] {
+            json::write_message(&mut input, message).unwrap();
+        }
+        input.extend_from_slice(b"Content-Length: 3\r\n\r\n{]}");
+        for message in &messages[8..] {
+            json::write_message(&mut input, message).unwrap();
+        }
+        let mut output = Vec::new();
+        serve(input.as_slice(), &mut output).unwrap();
+
+        let mut output = output.as_slice();
+        let mut replies = Vec::new();
+        while let Some(reply) = json::read_message(&mut output).unwrap() {
+            replies.push(reply.unwrap());
+        }
+        assert_eq!(9, replies.len());
+
+        let capabilities = &replies[0]["result"]["capabilities"];
+        assert_eq!(Some(1), capabilities["textDocumentSync"].as_i64());
+        assert_eq!(Some(true), capabilities["hoverProvider"].as_bool());
+
+        let diagnostics = &replies[1]["params"];
+        assert_eq!(Some(URI), diagnostics["uri"].as_str());
+        assert_eq!(1, diagnostics["diagnostics"].as_array().len());
+        let diagnostic = &diagnostics["diagnostics"][0];
+        assert_eq!(
+            Some("unknown mnemonic `adi`"),
+            diagnostic["message"].as_str()
+        );
+        assert_eq!(Some(2), diagnostic["range"]["start"]["line"].as_i64());
+        assert_eq!(Some(4), diagnostic["range"]["start"]["character"].as_i64());
+        assert_eq!(Some(7), diagnostic["range"]["end"]["character"].as_i64());
+
+        let hover = &replies[2]["result"];
+        let text = hover["contents"]["value"].as_str().unwrap();
+        assert!(text.starts_with("```\nli $dest, immediate (24)\n```\n"));
+        assert!(text.contains("addi $t0, $zero, 1"));
+
+        let definition = &replies[3]["result"];
+        assert_eq!(Some(URI), definition["uri"].as_str());
+        assert_eq!(Some(0), definition["range"]["start"]["line"].as_i64());
+        assert_eq!(Some(4), definition["range"]["end"]["character"].as_i64());
+
+        let completions = replies[4]["result"].as_array();
+        assert!(
+            completions
+                .iter()
+                .any(|item| item["label"].as_str() == Some("main"))
+        );
+
+        assert_eq!(Some(METHOD_NOT_FOUND), replies[5]["error"]["code"].as_i64());
+        assert_eq!(
+            Some("undefined symbol `mian`"),
+            replies[6]["params"]["diagnostics"][0]["message"].as_str()
+        );
+        assert_eq!(Some(PARSE_ERROR), replies[7]["error"]["code"].as_i64());
+        assert!(replies[7]["id"].is_null());
+        assert!(replies[8]["result"].is_null());
+        assert_eq!(Some(6), replies[8]["id"].as_i64());
+    }
+
+    #[test]
+    fn convert_positions() {
+        assert_eq!(4, utf16_units("a😀b c", 3));
+        assert_eq!(3, chars("a😀b c", 4));
+        assert_eq!(1, chars("a😀b c", 2));
+        assert_eq!(
+            PathBuf::from("/tmp/btm lsp/main.s"),
+            path("file:///tmp/btm%20lsp/main.s")
+        );
+        assert_eq!(PathBuf::from("untitled:1"), path("untitled:1"));
+    }
+}
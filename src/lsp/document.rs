@@ -0,0 +1,708 @@
+use std::collections::HashMap;
+use std::fmt::Write as _;
+use std::path::Path;
+
+use crate::asm::lexer::{self, Lexeme, Token};
+use crate::asm::{self, Contents, Error, Listing, PSEUDO_INSTRUCTIONS};
+use crate::numerals::{balanced_ternary, heptavintimal};
+use crate::opcodes::Opcode;
+use crate::operands::Shape;
+use crate::registers::Register;
+
+// Each diagnostic after the first costs another pass of the assembler.
+const MAX_DIAGNOSTICS: usize = 100;
+
+const WORD_SIZE: usize = 4;
+const WORD_WIDTH: usize = 24;
+
+// Lines and characters count from 0, and characters are chars rather than
+// the UTF-16 code units of the protocol.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub(super) struct Position {
+    pub line: usize,
+    pub character: usize,
+}
+
+// Part of a single line.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub(super) struct Range {
+    pub line: usize,
+    pub start: usize,
+    pub end: usize,
+}
+
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub(super) struct Diagnostic {
+    pub range: Range,
+    pub message: String,
+}
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub(super) enum CompletionKind {
+    Mnemonic,
+    Macro,
+    Register,
+    Symbol,
+}
+
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub(super) struct Completion {
+    pub label: String,
+    pub detail: String,
+    pub kind: CompletionKind,
+    // the text that the completion replaces
+    pub range: Range,
+}
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+enum Role {
+    Definition,
+    Reference,
+    // the first word of a statement, which names an instruction or a macro
+    Mnemonic,
+    Register,
+}
+
+// A word in the source that names something.
+#[derive(Clone, Debug)]
+struct Occurrence {
+    name: String,
+    range: Range,
+    role: Role,
+}
+
+// An open source file, along with what the assembler made of it.
+pub(super) struct Document {
+    file: String,
+    lines: Vec<String>,
+    diagnostics: Vec<Diagnostic>,
+    occurrences: Vec<Occurrence>,
+    // where each label, constant and macro is first defined
+    definitions: HashMap<String, Range>,
+    macros: Vec<String>,
+    listing: Option<Listing>,
+}
+
+impl Document {
+    // `path` names the document in the assembler's diagnostics, and includes
+    // are resolved relative to its directory.
+    pub fn new(path: &Path, text: &str) -> Self {
+        let lines: Vec<String> = text.split('\n').map(str::to_string).collect();
+        let mut document = Document {
+            file: path.display().to_string(),
+            lines,
+            diagnostics: Vec::new(),
+            occurrences: Vec::new(),
+            definitions: HashMap::new(),
+            macros: Vec::new(),
+            listing: None,
+        };
+
+        document.scan();
+        document.diagnose(path);
+        document
+            .diagnostics
+            .sort_by_key(|diagnostic| (diagnostic.range.line, diagnostic.range.start));
+        document
+    }
+
+    pub fn line(&self, index: usize) -> &str {
+        self.lines.get(index).map_or("", String::as_str)
+    }
+
+    pub fn diagnostics(&self) -> &[Diagnostic] {
+        &self.diagnostics
+    }
+
+    pub fn definition(&self, position: Position) -> Option<Range> {
+        let occurrence = self.occurrence_at(position)?;
+        self.definitions.get(&occurrence.name).copied()
+    }
+
+    pub fn references(&self, position: Position, include_declaration: bool) -> Vec<Range> {
+        let Some(occurrence) = self.occurrence_at(position) else {
+            return Vec::new();
+        };
+
+        let name = &occurrence.name;
+        if occurrence.role == Role::Mnemonic && !self.definitions.contains_key(name) {
+            return Vec::new();
+        }
+
+        self.occurrences
+            .iter()
+            .filter(|other| &other.name == name)
+            .filter(|other| match other.role {
+                Role::Definition => include_declaration,
+                Role::Reference | Role::Mnemonic => true,
+                Role::Register => false,
+            })
+            .map(|other| other.range)
+            .collect()
+    }
+
+    // Markdown describing the instruction, register or symbol at `position`.
+    pub fn hover(&self, position: Position) -> Option<(Range, String)> {
+        let occurrence = self.occurrence_at(position)?;
+        let name = occurrence.name.as_str();
+        let text = match occurrence.role {
+            Role::Mnemonic if !self.macros.iter().any(|m| m == name) => {
+                self.describe_instruction(name, occurrence.range.line)?
+            }
+            Role::Register => {
+                let register = Register::from_name(name.strip_prefix('$')?)?;
+                format!("`{register}`: register {}", register.into_index())
+            }
+            _ => self.describe_symbol(name)?,
+        };
+
+        Some((occurrence.range, text))
+    }
+
+    pub fn completions(&self, position: Position) -> Vec<Completion> {
+        let before: Vec<char> = self
+            .line(position.line)
+            .chars()
+            .take(position.character)
+            .collect();
+        if before.contains(&'#') {
+            return Vec::new();
+        }
+
+        let mut start = before
+            .iter()
+            .rposition(|&c| !is_word(c))
+            .map_or(0, |index| index + 1);
+        if start > 0 && before[start - 1] == '$' {
+            start -= 1;
+        }
+
+        let range = Range {
+            line: position.line,
+            start,
+            end: before.len(),
+        };
+        let completion = |label: String, detail: String, kind| Completion {
+            label,
+            detail,
+            kind,
+            range,
+        };
+
+        if before.get(start) == Some(&'$') {
+            return Register::all()
+                .map(|register| {
+                    let detail = format!("register {}", register.into_index());
+                    completion(register.to_string(), detail, CompletionKind::Register)
+                })
+                .collect();
+        }
+
+        let prefix: String = before[..start].iter().collect();
+        if !starts_statement(&prefix) {
+            let mut symbols: Vec<_> = self
+                .definitions
+                .keys()
+                .filter(|name| !self.macros.contains(name))
+                .collect();
+            symbols.sort();
+            return symbols
+                .into_iter()
+                .map(|name| {
+                    let detail = self.symbol_detail(name);
+                    completion(name.clone(), detail, CompletionKind::Symbol)
+                })
+                .collect();
+        }
+
+        let opcodes = Opcode::all().map(|opcode| {
+            let syntax = format!("{} {}", opcode.mnemonic(), Shape::of(opcode));
+            let detail = syntax.trim_end().to_string();
+            completion(opcode.mnemonic().into(), detail, CompletionKind::Mnemonic)
+        });
+        let pseudos = PSEUDO_INSTRUCTIONS.iter().map(|(mnemonic, operands)| {
+            let detail = format!("{mnemonic} {operands}").trim_end().to_string();
+            completion((*mnemonic).into(), detail, CompletionKind::Mnemonic)
+        });
+        let macros = self
+            .macros
+            .iter()
+            .map(|name| completion(name.clone(), "macro".into(), CompletionKind::Macro));
+        opcodes.chain(pseudos).chain(macros).collect()
+    }
+
+    fn occurrence_at(&self, position: Position) -> Option<&Occurrence> {
+        self.occurrences.iter().find(|occurrence| {
+            let range = occurrence.range;
+            range.line == position.line && (range.start..=range.end).contains(&position.character)
+        })
+    }
+
+    fn describe_instruction(&self, mnemonic: &str, line: usize) -> Option<String> {
+        let (syntax, note) = if let Some(opcode) = Opcode::from_mnemonic(mnemonic) {
+            let shape = Shape::of(opcode);
+            let syntax = format!("{} {shape}", opcode.mnemonic());
+            (syntax, format!("{} operands", shape.name()))
+        } else {
+            let (mnemonic, operands) = PSEUDO_INSTRUCTIONS
+                .iter()
+                .find(|(name, _)| name.eq_ignore_ascii_case(mnemonic))?;
+            (
+                format!("{mnemonic} {operands}"),
+                "pseudo-instruction".into(),
+            )
+        };
+
+        let mut text = format!("```\n{}\n```\n{note}\n", syntax.trim_end());
+        let words = self.words(line);
+        if !words.is_empty() {
+            text.push_str("\n```\n");
+            for row in words {
+                writeln!(text, "{row}").unwrap();
+            }
+            text.push_str("```\n");
+        }
+
+        Some(text)
+    }
+
+    // The address, trits and heptavintimal value of each word assembled from
+    // `line`, as in a listing.
+    fn words(&self, line: usize) -> Vec<String> {
+        let Some(listing) = &self.listing else {
+            return Vec::new();
+        };
+
+        let mut rows = Vec::new();
+        for listed in &listing.lines {
+            let Contents::Insts(insts) = &listed.contents else {
+                continue;
+            };
+            if listed.location.file.as_deref() != Some(&self.file)
+                || listed.location.line != line + 1
+            {
+                continue;
+            }
+
+            for (addr, inst) in (listed.addr..).step_by(WORD_SIZE).zip(insts) {
+                let Ok(word) = inst.to_word() else {
+                    continue;
+                };
+                let value: i64 = word.try_into_int().unwrap();
+                let text = match i32::try_from(addr) {
+                    Ok(addr) => inst.display_at(addr).to_string(),
+                    Err(_) => inst.to_string(),
+                };
+                rows.push(format!(
+                    "{addr}  {:0>WORD_WIDTH$}  0h{}  {text}",
+                    balanced_ternary(value),
+                    heptavintimal(value),
+                ));
+            }
+        }
+
+        rows
+    }
+
+    fn describe_symbol(&self, name: &str) -> Option<String> {
+        self.definitions.get(name)?;
+        Some(format!("`{name}`: {}", self.symbol_detail(name)))
+    }
+
+    fn symbol_detail(&self, name: &str) -> String {
+        if self.macros.iter().any(|m| m == name) {
+            return "macro".into();
+        }
+
+        let addr = self.listing.as_ref().and_then(|listing| {
+            listing.lines.iter().find_map(|line| match &line.contents {
+                Contents::Label(label) if label == name => Some(line.addr),
+                _ => None,
+            })
+        });
+        match addr {
+            Some(addr) => format!("label at address {addr}"),
+            None => "symbol".into(),
+        }
+    }
+
+    // Finds the labels, constants and macros that each line defines or
+    // refers to. Lines that don't tokenize are skipped, since the assembler
+    // reports them.
+    fn scan(&mut self) {
+        for (index, text) in self.lines.iter().enumerate() {
+            let Ok(lexemes) = lexer::tokenize(text, 0) else {
+                continue;
+            };
+
+            for statement in
+                lexemes.split(|lexeme| matches!(lexeme.token, Token::Newline | Token::Eof))
+            {
+                scan_statement(index, statement, &mut self.occurrences, &mut self.macros);
+            }
+        }
+
+        for occurrence in &self.occurrences {
+            if occurrence.role == Role::Definition {
+                self.definitions
+                    .entry(occurrence.name.clone())
+                    .or_insert(occurrence.range);
+            }
+        }
+    }
+
+    // The assembler stops at the first error, so after each one the line it
+    // was reported on is cleared, keeping any labels, and the document is
+    // assembled again. The listing comes from the first pass that succeeds.
+    fn diagnose(&mut self, path: &Path) {
+        let mut lines = self.lines.clone();
+        let mut cleared = vec![false; lines.len()];
+        while self.diagnostics.len() < MAX_DIAGNOSTICS {
+            let error = match asm::assemble_file_listed(path, &lines.join("\n"), 0) {
+                Ok(assembly) => {
+                    self.listing = Some(assembly.listing);
+                    return;
+                }
+                Err(error) => error,
+            };
+
+            let Some((line, column)) = self.locate(&error) else {
+                let line = self.include_line(&error);
+                let range = word_range(self.line(line), line, 1);
+                self.diagnostics.push(Diagnostic {
+                    range,
+                    message: error.to_string(),
+                });
+                return;
+            };
+
+            let index = line - 1;
+            self.diagnostics.push(Diagnostic {
+                range: word_range(self.line(index), index, column),
+                message: error.kind.to_string(),
+            });
+            if cleared[index] {
+                if lines[index].is_empty() {
+                    return;
+                }
+                lines[index].clear();
+            } else {
+                lines[index] = labels_only(&lines[index]);
+                cleared[index] = true;
+            }
+        }
+    }
+
+    // Where `error` is in this document: where it occurred, or else the
+    // innermost macro call in the document that led to it.
+    fn locate(&self, error: &Error) -> Option<(usize, usize)> {
+        let expansions = error
+            .expansions
+            .iter()
+            .map(|call| (call.file.as_deref(), call.line, call.column));
+        std::iter::once((error.file.as_deref(), error.line, error.column))
+            .chain(expansions)
+            .find(|&(file, line, _)| file == Some(&self.file) && line <= self.lines.len())
+            .map(|(_, line, column)| (line, column))
+    }
+
+    // The line that includes the file an error occurred in, or the first.
+    fn include_line(&self, error: &Error) -> usize {
+        let Some(file) = &error.file else {
+            return 0;
+        };
+
+        self.lines
+            .iter()
+            .position(|text| {
+                let Ok(lexemes) = lexer::tokenize(text, 0) else {
+                    return false;
+                };
+                matches!(
+                    lexemes.as_slice(),
+                    [
+                        Lexeme { token: Token::Ident(directive), .. },
+                        Lexeme { token: Token::String(name), .. },
+                        ..
+                    ] if directive == ".include" && Path::new(file).ends_with(name)
+                )
+            })
+            .unwrap_or(0)
+    }
+}
+
+fn scan_statement(
+    line: usize,
+    mut statement: &[Lexeme],
+    occurrences: &mut Vec<Occurrence>,
+    macros: &mut Vec<String>,
+) {
+    let mut push = |name: &str, lexeme: &Lexeme, role| {
+        let start = lexeme.span.column - 1;
+        let len = name.chars().count();
+        occurrences.push(Occurrence {
+            name: name.to_string(),
+            range: Range {
+                line,
+                start,
+                end: start + len,
+            },
+            role,
+        });
+    };
+
+    while let [
+        label @ Lexeme {
+            token: Token::Ident(name),
+            ..
+        },
+        Lexeme {
+            token: Token::Colon,
+            ..
+        },
+        rest @ ..,
+    ] = statement
+    {
+        push(name, label, Role::Definition);
+        statement = rest;
+    }
+
+    let [head, args @ ..] = statement else {
+        return;
+    };
+    let mut args = args;
+    let Token::Ident(name) = &head.token else {
+        return;
+    };
+
+    match name.as_str() {
+        ".equ" | ".set" | ".macro" => {
+            let [
+                symbol @ Lexeme {
+                    token: Token::Ident(symbol_name),
+                    ..
+                },
+                rest @ ..,
+            ] = args
+            else {
+                return;
+            };
+            push(symbol_name, symbol, Role::Definition);
+            if name == ".macro" {
+                // the rest are parameters
+                macros.push(symbol_name.clone());
+                return;
+            }
+            args = rest;
+        }
+        ".include" => return,
+        _ if name.starts_with('.') => {}
+        _ => push(name, head, Role::Mnemonic),
+    }
+
+    for (index, lexeme) in args.iter().enumerate() {
+        match &lexeme.token {
+            Token::Register(name) => push(&format!("${name}"), lexeme, Role::Register),
+            // skip the names of operators, as in `%hi(label)`
+            Token::Ident(_)
+                if index > 0
+                    && args[index - 1].token == Token::Percent
+                    && args.get(index + 1).map(|next| &next.token) == Some(&Token::LParen) => {}
+            Token::Ident(name) => push(name, lexeme, Role::Reference),
+            _ => {}
+        }
+    }
+}
+
+// Whether `prefix` is only labels, so that the next word is a mnemonic.
+fn starts_statement(prefix: &str) -> bool {
+    let Ok(lexemes) = lexer::tokenize(prefix, 0) else {
+        return false;
+    };
+
+    let statement = lexemes
+        .rsplit(|lexeme| lexeme.token == Token::Newline)
+        .next()
+        .unwrap_or_default();
+    let statement = &statement[..statement.len().saturating_sub(1)];
+    statement.chunks(2).all(|pair| {
+        matches!(
+            pair,
+            [
+                Lexeme {
+                    token: Token::Ident(_),
+                    ..
+                },
+                Lexeme {
+                    token: Token::Colon,
+                    ..
+                }
+            ]
+        )
+    })
+}
+
+fn labels_only(text: &str) -> String {
+    let Ok(lexemes) = lexer::tokenize(text, 0) else {
+        return String::new();
+    };
+
+    let mut labels = String::new();
+    for pair in lexemes.chunks(2) {
+        let [
+            Lexeme {
+                token: Token::Ident(name),
+                ..
+            },
+            Lexeme {
+                token: Token::Colon,
+                ..
+            },
+        ] = pair
+        else {
+            break;
+        };
+        write!(labels, "{name}: ").unwrap();
+    }
+
+    labels
+}
+
+// The word starting at the 1-based `column` of `text`, or the character there
+// if it doesn't start a word.
+fn word_range(text: &str, line: usize, column: usize) -> Range {
+    let start = column.saturating_sub(1);
+    let mut chars = text.chars().skip(start).peekable();
+    let sigil = usize::from(chars.next_if(|&c| c == '$' || c == '\\').is_some());
+    let len = sigil + chars.take_while(|&c| is_word(c)).count();
+    Range {
+        line,
+        start,
+        end: start + len.max(1),
+    }
+}
+
+fn is_word(c: char) -> bool {
+    c.is_ascii_alphanumeric() || c == '_' || c == '.'
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SOURCE: &str = "\
+.equ COUNT, 3
+.macro twice reg
+    add \\reg, \\reg, \\reg
+.endm
+
+main:
+    li $t0, COUNT
+    twice $t0
+loop: addi $t0, $t0, -1
+    bT0 $t0, loop
+    lui $t1, %hi(main)
+    jr $ra
+";
+
+    fn at(line: usize, character: usize) -> Position {
+        Position { line, character }
+    }
+
+    fn range(line: usize, start: usize, end: usize) -> Range {
+        Range { line, start, end }
+    }
+
+    #[test]
+    fn report_every_error() {
+        let source = "\
+start: frob $t0
+    addi $t9, $t0, 1
+    addi $t0, $t0, 300000
+    b nowhere
+    j start
+";
+        let document = Document::new(Path::new("a.s"), source);
+        let diagnostics: Vec<_> = document
+            .diagnostics()
+            .iter()
+            .map(|diagnostic| (diagnostic.range, diagnostic.message.as_str()))
+            .collect();
+        assert_eq!(
+            vec![
+                (range(0, 7, 11), "unknown mnemonic `frob`"),
+                (range(1, 9, 12), "unknown register `$t9`"),
+                (range(2, 19, 25), "value 300000 does not fit in 12 trits"),
+                (range(3, 6, 13), "undefined symbol `nowhere`"),
+            ],
+            diagnostics
+        );
+
+        let document = Document::new(Path::new("a.s"), SOURCE);
+        assert_eq!(Vec::<Diagnostic>::new(), document.diagnostics());
+    }
+
+    #[test]
+    fn find_symbols() {
+        let document = Document::new(Path::new("a.s"), SOURCE);
+        assert_eq!(Some(range(8, 0, 4)), document.definition(at(9, 14)));
+        assert_eq!(Some(range(0, 5, 10)), document.definition(at(6, 13)));
+        assert_eq!(Some(range(1, 7, 12)), document.definition(at(7, 6)));
+        assert_eq!(None, document.definition(at(6, 5)));
+
+        assert_eq!(
+            vec![range(5, 0, 4), range(10, 17, 21)],
+            document.references(at(5, 2), true)
+        );
+        assert_eq!(
+            vec![range(10, 17, 21)],
+            document.references(at(5, 2), false)
+        );
+        assert_eq!(Vec::<Range>::new(), document.references(at(10, 13), true));
+    }
+
+    #[test]
+    fn hover_instructions() {
+        let document = Document::new(Path::new("a.s"), SOURCE);
+        let (hovered, text) = document.hover(at(8, 7)).unwrap();
+        assert_eq!(range(8, 6, 10), hovered);
+        assert_eq!(
+            "```\naddi $dest, $src, immediate (12)\n```\nRRI operands\n\n```\n\
+             8  00000000000T1T0T1T0T1TTT  0h-L72D  addi $t0, $t0, -1\n```\n",
+            text
+        );
+
+        let (_, text) = document.hover(at(6, 4)).unwrap();
+        assert!(text.starts_with("```\nli $dest, immediate (24)\n```\npseudo-instruction\n"));
+        let (_, text) = document.hover(at(9, 13)).unwrap();
+        assert_eq!("`loop`: label at address 8", text);
+        let (_, text) = document.hover(at(11, 8)).unwrap();
+        assert_eq!("`$ra`: register 7", text);
+        assert_eq!(None, document.hover(at(4, 0)));
+    }
+
+    #[test]
+    fn complete_words() {
+        let document = Document::new(Path::new("a.s"), SOURCE);
+        let labels = |completions: Vec<Completion>| -> Vec<String> {
+            completions.into_iter().map(|c| c.label).collect()
+        };
+
+        let mnemonics = document.completions(at(8, 8));
+        assert_eq!(range(8, 6, 8), mnemonics[0].range);
+        let mnemonics = labels(mnemonics);
+        assert!(mnemonics.contains(&"addi".to_string()));
+        assert!(mnemonics.contains(&"la".to_string()));
+        assert!(mnemonics.contains(&"twice".to_string()));
+
+        let registers = document.completions(at(9, 9));
+        assert_eq!(range(9, 8, 9), registers[0].range);
+        assert_eq!("$zero", registers[0].label);
+        assert_eq!(41, registers.len());
+
+        assert_eq!(
+            vec!["COUNT", "loop", "main"],
+            labels(document.completions(at(9, 16)))
+        );
+    }
+}
@@ -0,0 +1,179 @@
+use std::fmt;
+
+use crate::image::Segment;
+use crate::object::Section;
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct SectionEntry {
+    pub section: Section,
+    pub addr: i64,
+    pub size: i64,
+}
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct SegmentEntry {
+    pub addr: i64,
+    pub size: i64,
+}
+
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct SymbolEntry {
+    pub name: String,
+    pub section: Section,
+    pub addr: i64,
+    pub size: i64,
+}
+
+// Where everything in an image or object ended up. In an object, addresses
+// are offsets into their section and there are no segments.
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct Map {
+    pub sections: Vec<SectionEntry>,
+    pub segments: Vec<SegmentEntry>,
+    pub symbols: Vec<SymbolEntry>,
+}
+
+impl Map {
+    // The size of a symbol is the distance to the next symbol in its section,
+    // or to the end of the section.
+    pub fn new(
+        sections: Vec<SectionEntry>,
+        segments: &[Segment],
+        symbols: impl IntoIterator<Item = (String, Section, i64)>,
+    ) -> Self {
+        let mut symbols: Vec<_> = symbols
+            .into_iter()
+            .map(|(name, section, addr)| SymbolEntry {
+                name,
+                section,
+                addr,
+                size: 0,
+            })
+            .collect();
+        symbols.sort_by(|a, b| {
+            (a.section.index(), a.addr, &a.name).cmp(&(b.section.index(), b.addr, &b.name))
+        });
+
+        for i in 0..symbols.len() {
+            let symbol = &symbols[i];
+            let end = symbols[i + 1..]
+                .iter()
+                .find(|next| next.section == symbol.section && next.addr > symbol.addr)
+                .map(|next| next.addr)
+                .or_else(|| {
+                    sections
+                        .iter()
+                        .find(|entry| entry.section == symbol.section)
+                        .map(|entry| entry.addr + entry.size)
+                })
+                .unwrap_or(symbol.addr);
+            let size = (end - symbol.addr).max(0);
+            symbols[i].size = size;
+        }
+
+        let segments = segments
+            .iter()
+            .map(|segment| SegmentEntry {
+                addr: i64::from(segment.addr),
+                size: i64::try_from(segment.trytes.len()).unwrap(),
+            })
+            .collect();
+
+        Map {
+            sections,
+            segments,
+            symbols,
+        }
+    }
+}
+
+impl fmt::Display for Map {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "sections:")?;
+        for entry in &self.sections {
+            let name = entry.section.name();
+            writeln!(f, "  {name:<6} {:>11} {:>11}", entry.addr, entry.size)?;
+        }
+
+        if !self.segments.is_empty() {
+            writeln!(f, "\nsegments:")?;
+            for entry in &self.segments {
+                writeln!(f, "  {:<6} {:>11} {:>11}", "", entry.addr, entry.size)?;
+            }
+        }
+
+        writeln!(f, "\nsymbols:")?;
+        for entry in &self.symbols {
+            let section = entry.section.name();
+            writeln!(
+                f,
+                "  {section:<6} {:>11} {:>11}  {}",
+                entry.addr, entry.size, entry.name
+            )?;
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+
+    use super::*;
+    use crate::asm::assemble_file_listed;
+
+    #[test]
+    fn map_sizes() {
+        let source = "
+            main: nop
+            loop: nop; nop
+            .data
+            .word 1
+            table: .word 2, 3
+            .bss
+            buf: .zero 5
+            end:
+        ";
+        let map = assemble_file_listed(Path::new("a.s"), source, 100)
+            .unwrap()
+            .map;
+
+        let sections: Vec<_> = map
+            .sections
+            .iter()
+            .map(|entry| (entry.section, entry.addr, entry.size))
+            .collect();
+        assert_eq!(
+            vec![
+                (Section::Text, 100, 12),
+                (Section::Data, 112, 12),
+                (Section::Bss, 124, 5),
+            ],
+            sections
+        );
+
+        let segments: Vec<_> = map
+            .segments
+            .iter()
+            .map(|entry| (entry.addr, entry.size))
+            .collect();
+        assert_eq!(vec![(100, 12), (112, 12), (124, 5)], segments);
+
+        let symbols: Vec<_> = map
+            .symbols
+            .iter()
+            .map(|entry| (entry.name.as_str(), entry.addr, entry.size))
+            .collect();
+        assert_eq!(
+            vec![
+                ("main", 100, 4),
+                ("loop", 104, 8),
+                ("table", 116, 8),
+                ("buf", 124, 5),
+                ("end", 129, 0),
+            ],
+            symbols
+        );
+    }
+}
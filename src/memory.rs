@@ -0,0 +1,126 @@
+use std::ops::Range;
+
+use ternary::{TInt, Tryte};
+
+use crate::error::{Error, Result};
+
+pub struct Memory(Vec<Tryte>);
+
+impl Memory {
+    pub fn new(size: u32) -> Self {
+        Memory(vec![Tryte::ZERO; size as usize])
+    }
+
+    pub fn size(&self) -> usize {
+        self.0.len()
+    }
+
+    // Computed in i64, so addresses and sizes near the ends of the i32 range
+    // are errors rather than overflows.
+    pub fn range(&self, addr: i32, size: usize, align: usize) -> Result<Range<usize>> {
+        let align_i64 = i64::try_from(align).unwrap_or(0);
+        if align_i64 == 0 || i64::from(addr) % align_i64 != 0 {
+            return Err(Error::InvalidAlignment(addr, align));
+        }
+
+        let addr_bounds = self.addr_bounds();
+        let start = i64::from(addr);
+        let end = i64::try_from(size)
+            .ok()
+            .and_then(|size| start.checked_add(size))
+            .ok_or(Error::InvalidAddress(addr))?;
+        if !addr_bounds.contains(&addr) || end > i64::from(addr_bounds.end) {
+            return Err(Error::InvalidAddress(addr));
+        }
+
+        let index_start = usize::try_from(start - i64::from(addr_bounds.start)).unwrap();
+        let index_end = index_start + size;
+        Ok(index_start..index_end)
+    }
+
+    pub fn addr_bounds(&self) -> Range<i32> {
+        let memory_size = self.0.len();
+        let offset = i32::try_from(memory_size / 2).unwrap();
+        (-offset)..offset
+    }
+
+    pub fn read(&self, addr: i32, len: usize) -> Result<&[Tryte]> {
+        let range = self.range(addr, len, 1)?;
+        Ok(&self.0[range])
+    }
+
+    pub fn write(&mut self, addr: i32, trytes: &[Tryte]) -> Result<()> {
+        let range = self.range(addr, trytes.len(), 1)?;
+        self.0[range].copy_from_slice(trytes);
+        Ok(())
+    }
+
+    pub fn load<const N: usize>(&self, addr: i32) -> Result<TInt<N>> {
+        let range = self.range(addr, N, N)?;
+        let trytes = &self.0[range];
+        Ok(TInt::<N>::try_from(trytes).unwrap())
+    }
+
+    pub fn store<const N: usize>(&mut self, addr: i32, value: TInt<N>) -> Result<()> {
+        let range = self.range(addr, N, N)?;
+        self.0[range].copy_from_slice(&value.into_trytes());
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use ternary::T24;
+
+    #[test]
+    fn load_store() {
+        let mut memory = Memory::new(16);
+        let word = T24::try_from_int(-123_456).unwrap();
+
+        memory.store(-8, word).unwrap();
+        assert_eq!(word, memory.load::<4>(-8).unwrap());
+        assert_eq!(T24::ZERO, memory.load::<4>(-4).unwrap());
+        assert_eq!(
+            word.into_trytes()[1],
+            memory.load::<1>(-7).unwrap().into_trytes()[0]
+        );
+
+        assert!(memory.load::<4>(8).is_err());
+        assert!(memory.load::<2>(-7).is_err());
+        assert!(memory.store(6, word).is_err());
+    }
+
+    #[test]
+    fn read_write() {
+        let mut memory = Memory::new(16);
+        let trytes = T24::try_from_int(987_654).unwrap().into_trytes();
+
+        memory.write(-3, &trytes[..3]).unwrap();
+        assert_eq!(&trytes[..3], memory.read(-3, 3).unwrap());
+        assert_eq!(&[Tryte::ZERO; 2], memory.read(-5, 2).unwrap());
+
+        assert!(memory.read(6, 3).is_err());
+        assert!(memory.write(-9, &trytes).is_err());
+    }
+
+    #[test]
+    fn range_limits() {
+        let memory = Memory::new(16);
+        assert_eq!(0..0, memory.range(-8, 0, 1).unwrap());
+        assert_eq!(12..16, memory.range(4, 4, 4).unwrap());
+
+        for addr in [i32::MAX, i32::MAX - 3, i32::MIN, i32::MIN + 1] {
+            assert!(memory.range(addr, 1, 1).is_err());
+            assert!(memory.range(addr, 0, 1).is_err());
+            assert!(memory.load::<4>(addr).is_err());
+            assert!(memory.read(addr, 4).is_err());
+        }
+
+        assert!(memory.range(0, usize::MAX, 1).is_err());
+        assert!(memory.range(0, 1 << 40, 1).is_err());
+        assert!(memory.range(0, 4, usize::MAX).is_err());
+        assert!(memory.range(0, 4, 0).is_err());
+    }
+}
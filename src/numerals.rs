@@ -0,0 +1,68 @@
+pub const HEPTAVINTIMAL_DIGITS: &str = "0123456789ABCDEFGHIJKLMNOPQ";
+
+pub fn balanced_ternary(value: i64) -> String {
+    if value == 0 {
+        return "0".to_string();
+    }
+
+    let mut trits = Vec::new();
+    let mut n = value;
+    while n != 0 {
+        let (trit, carry) = match n.rem_euclid(3) {
+            0 => ('0', 0),
+            1 => ('1', 0),
+            _ => ('T', 1),
+        };
+
+        trits.push(trit);
+        n = n.div_euclid(3) + carry;
+    }
+
+    trits.iter().rev().collect()
+}
+
+// Base 27 with the digits the assembler accepts after `0h`, and a sign.
+pub fn heptavintimal(value: i64) -> String {
+    let mut digits = Vec::new();
+    let mut n = value.unsigned_abs();
+    loop {
+        let digit = usize::try_from(n % 27).unwrap();
+        digits.push(HEPTAVINTIMAL_DIGITS.as_bytes()[digit] as char);
+        n /= 27;
+        if n == 0 {
+            break;
+        }
+    }
+
+    if value < 0 {
+        digits.push('-');
+    }
+
+    digits.iter().rev().collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn format_balanced_ternary() {
+        assert_eq!("0", balanced_ternary(0));
+        assert_eq!("1", balanced_ternary(1));
+        assert_eq!("T", balanced_ternary(-1));
+        assert_eq!("1T", balanced_ternary(2));
+        assert_eq!("T1", balanced_ternary(-2));
+        assert_eq!("10T", balanced_ternary(8));
+        assert_eq!("1T0T0TT01", balanced_ternary(4096));
+        assert_eq!("T1010110T", balanced_ternary(-4096));
+    }
+
+    #[test]
+    fn format_heptavintimal() {
+        assert_eq!("0", heptavintimal(0));
+        assert_eq!("Q", heptavintimal(26));
+        assert_eq!("1A", heptavintimal(37));
+        assert_eq!("-1A", heptavintimal(-37));
+        assert_eq!("QQQQ", heptavintimal(531_440));
+    }
+}
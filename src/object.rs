@@ -0,0 +1,379 @@
+use std::collections::HashSet;
+
+use ternary::Tryte;
+
+use crate::debug::LineTable;
+use crate::image::{self, Error, Reader, Result};
+
+pub const MAGIC: [u8; 4] = *b"BTO\0";
+pub const VERSION: u16 = 2;
+
+#[derive(Clone, Copy, Debug, Default, Eq, Hash, PartialEq)]
+pub enum Section {
+    #[default]
+    Text,
+    Data,
+    Bss,
+}
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum RelocKind {
+    Word,
+    Address,
+    Branch,
+    LoadAddress,
+    Hi,
+    Lo,
+}
+
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum Target {
+    Section(Section),
+    Symbol(String),
+}
+
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct Object {
+    pub text: Vec<Tryte>,
+    pub data: Vec<Tryte>,
+    pub bss_size: u32,
+    pub symbols: Vec<Symbol>,
+    pub relocations: Vec<Relocation>,
+    // addresses are offsets into `.text`
+    pub lines: LineTable,
+}
+
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Symbol {
+    pub name: String,
+    pub section: Section,
+    pub offset: u32,
+    pub global: bool,
+}
+
+// The field at `offset` in `section` is set to the address of `target` plus
+// `addend`, encoded according to `kind`.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Relocation {
+    pub section: Section,
+    pub offset: u32,
+    pub kind: RelocKind,
+    pub target: Target,
+    pub addend: i64,
+}
+
+impl Section {
+    pub const ALL: [Section; 3] = [Section::Text, Section::Data, Section::Bss];
+
+    pub fn index(self) -> usize {
+        self as usize
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Section::Text => ".text",
+            Section::Data => ".data",
+            Section::Bss => ".bss",
+        }
+    }
+
+    fn from_index(index: u8) -> Result<Self> {
+        Section::ALL
+            .get(usize::from(index))
+            .copied()
+            .ok_or(Error::InvalidSection(index))
+    }
+}
+
+impl RelocKind {
+    const ALL: [RelocKind; 6] = [
+        RelocKind::Word,
+        RelocKind::Address,
+        RelocKind::Branch,
+        RelocKind::LoadAddress,
+        RelocKind::Hi,
+        RelocKind::Lo,
+    ];
+
+    // the number of trytes the relocated field spans
+    pub fn size(self) -> u32 {
+        match self {
+            RelocKind::Word
+            | RelocKind::Address
+            | RelocKind::Branch
+            | RelocKind::Hi
+            | RelocKind::Lo => 4,
+            RelocKind::LoadAddress => 8,
+        }
+    }
+
+    fn from_index(index: u8) -> Result<Self> {
+        RelocKind::ALL
+            .get(usize::from(index))
+            .copied()
+            .ok_or(Error::InvalidRelocation(index))
+    }
+}
+
+impl Object {
+    pub fn section_size(&self, section: Section) -> u32 {
+        match section {
+            Section::Text => u32::try_from(self.text.len()).unwrap(),
+            Section::Data => u32::try_from(self.data.len()).unwrap(),
+            Section::Bss => self.bss_size,
+        }
+    }
+
+    pub fn symbol(&self, name: &str) -> Option<&Symbol> {
+        self.symbols.iter().find(|symbol| symbol.name == name)
+    }
+
+    // Symbols that are referenced by relocations but not defined here.
+    pub fn undefined(&self) -> Vec<&str> {
+        let mut seen = HashSet::new();
+        self.relocations
+            .iter()
+            .filter_map(|relocation| match &relocation.target {
+                Target::Symbol(name) if self.symbol(name).is_none() => Some(name.as_str()),
+                _ => None,
+            })
+            .filter(|name| seen.insert(*name))
+            .collect()
+    }
+
+    pub fn to_bytes(&self) -> Result<Vec<u8>> {
+        let mut bytes = Vec::new();
+        bytes.extend_from_slice(&MAGIC);
+        bytes.extend_from_slice(&VERSION.to_le_bytes());
+        write_trytes(&mut bytes, &self.text)?;
+        write_trytes(&mut bytes, &self.data)?;
+        bytes.extend_from_slice(&self.bss_size.to_le_bytes());
+        bytes.extend_from_slice(&image::count(self.symbols.len())?.to_le_bytes());
+        bytes.extend_from_slice(&image::count(self.relocations.len())?.to_le_bytes());
+
+        for symbol in &self.symbols {
+            write_name(&mut bytes, &symbol.name)?;
+            bytes.push(symbol.section as u8);
+            bytes.extend_from_slice(&symbol.offset.to_le_bytes());
+            bytes.push(u8::from(symbol.global));
+        }
+
+        for relocation in &self.relocations {
+            bytes.push(relocation.section as u8);
+            bytes.extend_from_slice(&relocation.offset.to_le_bytes());
+            bytes.push(relocation.kind as u8);
+            match &relocation.target {
+                Target::Section(section) => bytes.push(*section as u8),
+                Target::Symbol(name) => {
+                    bytes.push(u8::MAX);
+                    write_name(&mut bytes, name)?;
+                }
+            }
+            bytes.extend_from_slice(&relocation.addend.to_le_bytes());
+        }
+
+        self.lines.write(&mut bytes)?;
+
+        let checksum = image::checksum(&bytes);
+        bytes.extend_from_slice(&checksum.to_le_bytes());
+        Ok(bytes)
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self> {
+        if bytes.len() < MAGIC.len() || bytes[..MAGIC.len()] != MAGIC {
+            return Err(Error::InvalidMagic);
+        }
+
+        let Some(body_len) = bytes.len().checked_sub(4) else {
+            return Err(Error::Truncated);
+        };
+
+        let (body, checksum_bytes) = bytes.split_at(body_len);
+        let mut reader = Reader {
+            bytes: body,
+            position: MAGIC.len(),
+        };
+
+        // version 1 objects have no line table
+        let version = reader.u16()?;
+        if !(1..=VERSION).contains(&version) {
+            return Err(Error::UnsupportedVersion(version));
+        }
+
+        let expected = u32::from_le_bytes(checksum_bytes.try_into().unwrap());
+        let found = image::checksum(body);
+        if expected != found {
+            return Err(Error::ChecksumMismatch { expected, found });
+        }
+
+        let mut object = Object {
+            text: read_trytes(&mut reader)?,
+            data: read_trytes(&mut reader)?,
+            bss_size: reader.u32()?,
+            ..Object::default()
+        };
+
+        let symbol_count = reader.u32()?;
+        let relocation_count = reader.u32()?;
+
+        for _ in 0..symbol_count {
+            let name = read_name(&mut reader)?;
+            let section = Section::from_index(reader.u8()?)?;
+            let offset = reader.u32()?;
+            if offset > object.section_size(section) {
+                return Err(Error::InvalidSymbolOffset(offset));
+            }
+
+            let global = reader.u8()? != 0;
+            object.symbols.push(Symbol {
+                name,
+                section,
+                offset,
+                global,
+            });
+        }
+
+        for _ in 0..relocation_count {
+            let section = Section::from_index(reader.u8()?)?;
+            let offset = reader.u32()?;
+            let kind = RelocKind::from_index(reader.u8()?)?;
+            let target = match reader.u8()? {
+                u8::MAX => Target::Symbol(read_name(&mut reader)?),
+                index => Target::Section(Section::from_index(index)?),
+            };
+            let addend = reader.i64()?;
+            object.relocations.push(Relocation {
+                section,
+                offset,
+                kind,
+                target,
+                addend,
+            });
+        }
+
+        if version >= 2 {
+            object.lines = LineTable::read(&mut reader)?;
+        }
+
+        if reader.position != body.len() {
+            return Err(Error::TrailingBytes);
+        }
+
+        Ok(object)
+    }
+}
+
+fn write_trytes(bytes: &mut Vec<u8>, trytes: &[Tryte]) -> Result<()> {
+    bytes.extend_from_slice(&image::count(trytes.len())?.to_le_bytes());
+    for &tryte in trytes {
+        bytes.extend_from_slice(&image::tryte_to_i16(tryte).to_le_bytes());
+    }
+
+    Ok(())
+}
+
+fn read_trytes(reader: &mut Reader<'_>) -> Result<Vec<Tryte>> {
+    let len = reader.u32()?;
+    (0..len)
+        .map(|_| image::i16_to_tryte(reader.i16()?))
+        .collect()
+}
+
+fn write_name(bytes: &mut Vec<u8>, name: &str) -> Result<()> {
+    let len = u16::try_from(name.len()).map_err(|_| Error::InvalidSymbol)?;
+    bytes.extend_from_slice(&len.to_le_bytes());
+    bytes.extend_from_slice(name.as_bytes());
+    Ok(())
+}
+
+fn read_name(reader: &mut Reader<'_>) -> Result<String> {
+    let len = reader.u16()?;
+    String::from_utf8(reader.take(len.into())?.to_vec()).map_err(|_| Error::InvalidSymbol)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use ternary::TInt;
+
+    use crate::debug::SourceLine;
+
+    fn object() -> Object {
+        Object {
+            text: TInt::<4>::try_from_int(-42).unwrap().into_trytes().to_vec(),
+            data: vec![Tryte::ZERO; 3],
+            bss_size: 12,
+            symbols: vec![Symbol {
+                name: "main".into(),
+                section: Section::Text,
+                offset: 0,
+                global: true,
+            }],
+            relocations: vec![
+                Relocation {
+                    section: Section::Text,
+                    offset: 0,
+                    kind: RelocKind::Address,
+                    target: Target::Symbol("helper".into()),
+                    addend: -4,
+                },
+                Relocation {
+                    section: Section::Data,
+                    offset: 0,
+                    kind: RelocKind::Word,
+                    target: Target::Section(Section::Bss),
+                    addend: 8,
+                },
+            ],
+            lines: LineTable {
+                files: vec!["main.s".into()],
+                lines: vec![SourceLine {
+                    addr: 0,
+                    file: 0,
+                    line: 3,
+                    column: 5,
+                }],
+            },
+        }
+    }
+
+    #[test]
+    fn object_round_trip() {
+        let object = object();
+        let bytes = object.to_bytes().unwrap();
+        assert_eq!(MAGIC, bytes[..4]);
+        assert_eq!(object, Object::from_bytes(&bytes).unwrap());
+        assert_eq!(vec!["helper"], object.undefined());
+        assert_eq!(12, object.section_size(Section::Bss));
+    }
+
+    #[test]
+    fn object_errors() {
+        let bytes = object().to_bytes().unwrap();
+        assert_eq!(Err(Error::InvalidMagic), Object::from_bytes(b"BTM\0"));
+
+        let mut corrupt = bytes.clone();
+        corrupt[8] ^= 1;
+        assert!(matches!(
+            Object::from_bytes(&corrupt),
+            Err(Error::ChecksumMismatch { .. })
+        ));
+
+        let mut section = bytes[..bytes.len() - 4].to_vec();
+        let position = section.len() - 32 - 8 - 1 - 1 - 4 - 1;
+        section[position] = 7;
+        section.extend_from_slice(&image::checksum(&section).to_le_bytes());
+        assert_eq!(Err(Error::InvalidSection(7)), Object::from_bytes(&section));
+
+        let mut object = object();
+        object.symbols[0].offset = 5;
+        let bytes = object.to_bytes().unwrap();
+        assert_eq!(
+            Err(Error::InvalidSymbolOffset(5)),
+            Object::from_bytes(&bytes)
+        );
+        object.symbols[0].offset = 4;
+        assert!(Object::from_bytes(&object.to_bytes().unwrap()).is_ok());
+    }
+}
@@ -1,3 +1,4 @@
+use std::fmt;
 use std::ops::RangeInclusive;
 
 use ternary::tables::TRIT4_TO_I8;
@@ -45,6 +46,12 @@ pub const BREAK: Opcode = Opcode(34);
 
 pub const VALID_OPCODE_RANGE: RangeInclusive<i8> = AND.0..=BREAK.0;
 
+const MNEMONICS: [&str; 35] = [
+    "and", "or", "tmul", "tcmp", "cmp", "shf", "add", "mul", "div", "andi", "ori", "tmuli",
+    "tcmpi", "shfi", "addi", "lui", "lt", "lh", "lw", "st", "sh", "sw", "bT", "b0", "b1", "bT0",
+    "bT1", "b01", "bal", "j", "jal", "jr", "jalr", "syscall", "break",
+];
+
 impl Opcode {
     pub fn from_trit4(trit4: u8) -> Result<Self> {
         let index = TRIT4_TO_I8[trit4 as usize];
@@ -54,6 +61,33 @@ impl Opcode {
 
         Ok(Opcode(index))
     }
+
+    pub fn all() -> impl Iterator<Item = Self> {
+        VALID_OPCODE_RANGE.map(Opcode)
+    }
+
+    pub fn from_mnemonic(mnemonic: &str) -> Option<Self> {
+        let index = MNEMONICS
+            .iter()
+            .position(|m| m.eq_ignore_ascii_case(mnemonic))?;
+        let offset = i8::try_from(index).ok()?;
+        Some(Opcode(VALID_OPCODE_RANGE.start() + offset))
+    }
+
+    pub fn into_i8(self) -> i8 {
+        self.0
+    }
+
+    #[allow(clippy::cast_sign_loss)]
+    pub fn mnemonic(self) -> &'static str {
+        MNEMONICS[(self.0 - VALID_OPCODE_RANGE.start()) as usize]
+    }
+}
+
+impl fmt::Display for Opcode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.mnemonic())
+    }
 }
 
 #[cfg(test)]
@@ -101,4 +135,24 @@ mod tests {
         assert!(Opcode::from_trit4(0b00_00_00_11).is_err());
         assert!(Opcode::from_trit4(0b01_01_00_00).is_err());
     }
+
+    #[test]
+    fn opcode_from_mnemonic() {
+        assert_eq!(Some(AND), Opcode::from_mnemonic("and"));
+        assert_eq!(Some(BT), Opcode::from_mnemonic("bT"));
+        assert_eq!(Some(BT), Opcode::from_mnemonic("bt"));
+        assert_eq!(Some(BT1), Opcode::from_mnemonic("BT1"));
+        assert_eq!(Some(JALR), Opcode::from_mnemonic("jalr"));
+        assert_eq!(Some(BREAK), Opcode::from_mnemonic("break"));
+
+        assert_eq!(None, Opcode::from_mnemonic("nop"));
+        assert_eq!(None, Opcode::from_mnemonic("b"));
+    }
+
+    #[test]
+    fn opcode_display() {
+        assert_eq!("and", AND.to_string());
+        assert_eq!("bT0", BT0.to_string());
+        assert_eq!("break", BREAK.to_string());
+    }
 }
@@ -1,16 +1,165 @@
 #![allow(clippy::upper_case_acronyms)]
 
-use std::convert::{TryFrom, TryInto};
+use std::fmt;
+use std::ops::Range;
 
-use ternary::{T12, T24, Trit, Tryte, tryte};
+use ternary::trit::_0;
+use ternary::{T12, T24, TInt, Tryte};
 
 use crate::error::{Error, Result};
+use crate::opcodes::{self, Opcode};
 use crate::registers::Register;
 
 const TRIT4_BITMASK: u16 = 0b00_00_00_00_11_11_11_11;
 
+pub const IMMEDIATE_WIDTH: usize = 12;
+pub const OFFSET_WIDTH: usize = 16;
+pub const ADDR_WIDTH: usize = 20;
+
+// `lui` loads its immediate into the upper 12 trits of a word.
+pub const LUI_SCALE: i64 = 531_441;
+
+// The operands an instruction takes, named after the type that holds them.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Shape {
+    Empty,
+    R,
+    RR,
+    RRR,
+    RI,
+    RRI,
+    RRO,
+    RO,
+    O,
+    A,
+}
+
+impl Shape {
+    pub fn of(opcode: Opcode) -> Self {
+        match opcode {
+            opcodes::AND
+            | opcodes::OR
+            | opcodes::TMUL
+            | opcodes::TCMP
+            | opcodes::CMP
+            | opcodes::SHF
+            | opcodes::ADD => Shape::RRR,
+            opcodes::MUL | opcodes::DIV => Shape::RR,
+            opcodes::ANDI
+            | opcodes::ORI
+            | opcodes::TMULI
+            | opcodes::TCMPI
+            | opcodes::SHFI
+            | opcodes::ADDI => Shape::RRI,
+            opcodes::LUI => Shape::RI,
+            opcodes::LT | opcodes::LH | opcodes::LW | opcodes::ST | opcodes::SH | opcodes::SW => {
+                Shape::RRO
+            }
+            opcodes::BT
+            | opcodes::B0
+            | opcodes::B1
+            | opcodes::BT0
+            | opcodes::BT1
+            | opcodes::B01 => Shape::RO,
+            opcodes::BAL => Shape::O,
+            opcodes::J | opcodes::JAL => Shape::A,
+            opcodes::JR | opcodes::JALR => Shape::R,
+            _ => Shape::Empty,
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Shape::Empty => "Empty",
+            Shape::R => "R",
+            Shape::RR => "RR",
+            Shape::RRR => "RRR",
+            Shape::RI => "RI",
+            Shape::RRI => "RRI",
+            Shape::RRO => "RRO",
+            Shape::RO => "RO",
+            Shape::O => "O",
+            Shape::A => "A",
+        }
+    }
+}
+
+// The operands as they're written in assembly, with the width of each
+// immediate in trits.
+impl fmt::Display for Shape {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Shape::Empty => Ok(()),
+            Shape::R => write!(f, "$src"),
+            Shape::RR => write!(f, "$a, $b"),
+            Shape::RRR => write!(f, "$dest, $a, $b"),
+            Shape::RI => write!(f, "$dest, immediate ({IMMEDIATE_WIDTH})"),
+            Shape::RRI => write!(f, "$dest, $src, immediate ({IMMEDIATE_WIDTH})"),
+            Shape::RRO => write!(f, "$dest, $src, offset ({IMMEDIATE_WIDTH})"),
+            Shape::RO => write!(f, "$src, offset ({OFFSET_WIDTH})"),
+            Shape::O => write!(f, "offset ({OFFSET_WIDTH})"),
+            Shape::A => write!(f, "address ({ADDR_WIDTH})"),
+        }
+    }
+}
+
 pub trait Operand: Sized {
     fn from_word(word: T24) -> Result<Self>;
+    fn to_word(self, opcode: Opcode) -> Result<T24>;
+}
+
+#[allow(clippy::cast_possible_truncation)]
+pub fn max_value(width: usize) -> i64 {
+    (3_i64.pow(width as u32) - 1) / 2
+}
+
+pub fn fits_width(value: i64, width: usize) -> bool {
+    value.unsigned_abs() <= max_value(width).cast_unsigned()
+}
+
+// Splits a word-sized value into the immediates of a `lui` + `addi` pair.
+// Both halves are balanced, so each fits in 12 trits.
+pub fn split_address(value: i64) -> (i64, i64) {
+    let half = LUI_SCALE / 2;
+    let lo = (value + half).rem_euclid(LUI_SCALE) - half;
+    let hi = (value - lo) / LUI_SCALE;
+    (hi, lo)
+}
+
+fn expect_unused(word: T24, trits: Range<usize>) -> Result<()> {
+    if trits.into_iter().all(|i| word.trit(i) == _0) {
+        Ok(())
+    } else {
+        Err(ternary::Error::InvalidEncoding(word.into_trytes().into()).into())
+    }
+}
+
+fn register_field(register: Register) -> i64 {
+    i64::from(register.into_i8())
+}
+
+fn int_field<const N: usize>(value: TInt<N>, width: usize) -> Result<i64> {
+    let value = value.try_into_int()?;
+    if !fits_width(value, width) {
+        return Err(Error::ImmediateOutOfRange(value, width));
+    }
+
+    Ok(value)
+}
+
+fn fmt_int<const N: usize>(value: TInt<N>, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    let value: i64 = value.try_into_int().map_err(|_| fmt::Error)?;
+    write!(f, "{value}")
+}
+
+#[allow(clippy::cast_possible_truncation)]
+fn encode(opcode: Opcode, fields: &[(i64, usize)]) -> Result<T24> {
+    let mut value = i64::from(opcode.into_i8());
+    for &(field, position) in fields {
+        value += field * 3_i64.pow(position as u32);
+    }
+
+    Ok(T24::try_from_int(value)?)
 }
 
 #[derive(Clone, Copy, Debug, Eq, PartialEq)]
@@ -29,6 +178,16 @@ impl Operand for Empty {
             Err(ternary::Error::InvalidEncoding(trytes.into()).into())
         }
     }
+
+    fn to_word(self, opcode: Opcode) -> Result<T24> {
+        encode(opcode, &[])
+    }
+}
+
+impl fmt::Display for Empty {
+    fn fmt(&self, _: &mut fmt::Formatter<'_>) -> fmt::Result {
+        Ok(())
+    }
 }
 
 #[derive(Clone, Copy, Debug, Eq, PartialEq)]
@@ -38,11 +197,22 @@ pub struct R {
 
 impl Operand for R {
     fn from_word(word: T24) -> Result<Self> {
+        expect_unused(word, 8..24)?;
         let half = word.resize();
         let (_, trit4_src, _) = half.trit4_triple();
         let src = Register::from_trit4(trit4_src)?;
         Ok(Self { src })
     }
+
+    fn to_word(self, opcode: Opcode) -> Result<T24> {
+        encode(opcode, &[(register_field(self.src), 4)])
+    }
+}
+
+impl fmt::Display for R {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.src)
+    }
 }
 
 #[derive(Clone, Copy, Debug, Eq, PartialEq)]
@@ -53,6 +223,7 @@ pub struct RR {
 
 impl Operand for RR {
     fn from_word(word: T24) -> Result<Self> {
+        expect_unused(word, 12..24)?;
         let half = word.resize();
         let (_, trit4_lhs, trit4_rhs) = half.trit4_triple();
 
@@ -61,6 +232,18 @@ impl Operand for RR {
 
         Ok(Self { lhs, rhs })
     }
+
+    fn to_word(self, opcode: Opcode) -> Result<T24> {
+        let lhs = register_field(self.lhs);
+        let rhs = register_field(self.rhs);
+        encode(opcode, &[(lhs, 4), (rhs, 8)])
+    }
+}
+
+impl fmt::Display for RR {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}, {}", self.lhs, self.rhs)
+    }
 }
 
 #[derive(Clone, Copy, Debug, Eq, PartialEq)]
@@ -72,6 +255,7 @@ pub struct RRR {
 
 impl Operand for RRR {
     fn from_word(word: T24) -> Result<Self> {
+        expect_unused(word, 16..24)?;
         let half = word.resize();
         let (_, trit4_dest, trit4_lhs) = half.trit4_triple();
         let trit4_rhs = word.into_trytes()[2].low_trit4();
@@ -82,6 +266,19 @@ impl Operand for RRR {
 
         Ok(Self { dest, lhs, rhs })
     }
+
+    fn to_word(self, opcode: Opcode) -> Result<T24> {
+        let dest = register_field(self.dest);
+        let lhs = register_field(self.lhs);
+        let rhs = register_field(self.rhs);
+        encode(opcode, &[(dest, 4), (lhs, 8), (rhs, 12)])
+    }
+}
+
+impl fmt::Display for RRR {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}, {}, {}", self.dest, self.lhs, self.rhs)
+    }
 }
 
 #[derive(Clone, Copy, Debug, Eq, PartialEq)]
@@ -92,11 +289,25 @@ pub struct RI {
 
 impl Operand for RI {
     fn from_word(word: T24) -> Result<Self> {
+        expect_unused(word, 8..12)?;
         let (lo, immediate) = word.t12_pair();
         let (_, trit4_dest, _) = lo.trit4_triple();
         let dest = Register::from_trit4(trit4_dest)?;
         Ok(Self { dest, immediate })
     }
+
+    fn to_word(self, opcode: Opcode) -> Result<T24> {
+        let dest = register_field(self.dest);
+        let immediate = int_field(self.immediate, IMMEDIATE_WIDTH)?;
+        encode(opcode, &[(dest, 4), (immediate, 12)])
+    }
+}
+
+impl fmt::Display for RI {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}, ", self.dest)?;
+        fmt_int(self.immediate, f)
+    }
 }
 
 #[derive(Clone, Copy, Debug, Eq, PartialEq)]
@@ -120,6 +331,20 @@ impl Operand for RRI {
             immediate,
         })
     }
+
+    fn to_word(self, opcode: Opcode) -> Result<T24> {
+        let dest = register_field(self.dest);
+        let src = register_field(self.src);
+        let immediate = int_field(self.immediate, IMMEDIATE_WIDTH)?;
+        encode(opcode, &[(dest, 4), (src, 8), (immediate, 12)])
+    }
+}
+
+impl fmt::Display for RRI {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}, {}, ", self.dest, self.src)?;
+        fmt_int(self.immediate, f)
+    }
 }
 
 #[derive(Clone, Copy, Debug, Eq, PartialEq)]
@@ -139,6 +364,20 @@ impl Operand for RRO {
 
         Ok(Self { dest, src, offset })
     }
+
+    fn to_word(self, opcode: Opcode) -> Result<T24> {
+        let dest = register_field(self.dest);
+        let src = register_field(self.src);
+        let offset = int_field(self.offset, IMMEDIATE_WIDTH)?;
+        encode(opcode, &[(dest, 4), (src, 8), (offset, 12)])
+    }
+}
+
+impl fmt::Display for RRO {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}, {}, ", self.dest, self.src)?;
+        fmt_int(self.offset, f)
+    }
 }
 
 #[derive(Clone, Copy, Debug, Eq, PartialEq)]
@@ -157,6 +396,19 @@ impl Operand for RO {
 
         Ok(Self { src, offset })
     }
+
+    fn to_word(self, opcode: Opcode) -> Result<T24> {
+        let src = register_field(self.src);
+        let offset = int_field(self.offset, OFFSET_WIDTH)?;
+        encode(opcode, &[(src, 4), (offset, 8)])
+    }
+}
+
+impl fmt::Display for RO {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}, ", self.src)?;
+        fmt_int(self.offset, f)
+    }
 }
 
 #[derive(Clone, Copy, Debug, Eq, PartialEq)]
@@ -166,9 +418,21 @@ pub struct O {
 
 impl Operand for O {
     fn from_word(word: T24) -> Result<Self> {
+        expect_unused(word, 4..8)?;
         let offset = word >> 8;
         Ok(Self { offset })
     }
+
+    fn to_word(self, opcode: Opcode) -> Result<T24> {
+        let offset = int_field(self.offset, OFFSET_WIDTH)?;
+        encode(opcode, &[(offset, 8)])
+    }
+}
+
+impl fmt::Display for O {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt_int(self.offset, f)
+    }
 }
 
 #[derive(Clone, Copy, Debug, Eq, PartialEq)]
@@ -181,4 +445,15 @@ impl Operand for A {
         let addr = word >> 4;
         Ok(Self { addr })
     }
+
+    fn to_word(self, opcode: Opcode) -> Result<T24> {
+        let addr = int_field(self.addr, ADDR_WIDTH)?;
+        encode(opcode, &[(addr, 4)])
+    }
+}
+
+impl fmt::Display for A {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt_int(self.addr, f)
+    }
 }
@@ -1,6 +1,7 @@
+use std::fmt;
 use std::ops::{Index, IndexMut, RangeInclusive};
 
-use ternary::{T24, Tryte, tables::TRIT4_TO_I8, tryte};
+use ternary::{T24, tables::TRIT4_TO_I8};
 
 use crate::error::{Error, Result};
 
@@ -56,7 +57,13 @@ pub const S14: Register = Register(40);
 const VALID_REGISTER_RANGE: RangeInclusive<i8> = ZERO.0..=S14.0;
 #[allow(clippy::cast_sign_loss)]
 const REGISTER_COUNT: usize =
-    (*VALID_REGISTER_RANGE.end() - *VALID_REGISTER_RANGE.start()) as usize;
+    (*VALID_REGISTER_RANGE.end() - *VALID_REGISTER_RANGE.start() + 1) as usize;
+
+const NAMES: [&str; REGISTER_COUNT] = [
+    "zero", "lo", "hi", "gp", "sp", "tp", "fp", "ra", "a0", "a1", "a2", "a3", "a4", "a5", "a6",
+    "a7", "a8", "t0", "t1", "t2", "t3", "t4", "t5", "t6", "t7", "t8", "s0", "s1", "s2", "s3", "s4",
+    "s5", "s6", "s7", "s8", "s9", "s10", "s11", "s12", "s13", "s14",
+];
 
 impl Register {
     #[allow(clippy::cast_sign_loss)]
@@ -70,10 +77,34 @@ impl Register {
         Ok(Self(index))
     }
 
+    pub fn all() -> impl Iterator<Item = Self> {
+        VALID_REGISTER_RANGE.map(Self)
+    }
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        let index = NAMES.iter().position(|&n| n == name)?;
+        let offset = i8::try_from(index).ok()?;
+        Some(Self(VALID_REGISTER_RANGE.start() + offset))
+    }
+
     #[allow(clippy::cast_sign_loss)]
     pub fn into_index(self) -> usize {
         (self.0 - VALID_REGISTER_RANGE.start()) as usize
     }
+
+    pub fn into_i8(self) -> i8 {
+        self.0
+    }
+
+    pub fn name(self) -> &'static str {
+        NAMES[self.into_index()]
+    }
+}
+
+impl fmt::Display for Register {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "${}", self.name())
+    }
 }
 
 pub struct Registers([T24; REGISTER_COUNT]);
@@ -84,6 +115,12 @@ impl Registers {
     }
 }
 
+impl Default for Registers {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl Index<Register> for Registers {
     type Output = T24;
 
@@ -148,4 +185,29 @@ mod tests {
 
         assert!(Register::from_trit4(0b00_00_00_11).is_err());
     }
+
+    #[test]
+    fn register_from_name() {
+        assert_eq!(Some(ZERO), Register::from_name("zero"));
+        assert_eq!(Some(RA), Register::from_name("ra"));
+        assert_eq!(Some(A8), Register::from_name("a8"));
+        assert_eq!(Some(T0), Register::from_name("t0"));
+        assert_eq!(Some(S14), Register::from_name("s14"));
+
+        assert_eq!(None, Register::from_name("t9"));
+        assert_eq!(None, Register::from_name("$t0"));
+        assert_eq!(None, Register::from_name("T0"));
+    }
+
+    #[test]
+    fn register_display() {
+        assert_eq!("$zero", ZERO.to_string());
+        assert_eq!("$a8", A8.to_string());
+        assert_eq!("$s14", S14.to_string());
+
+        for register in Register::all() {
+            let name = register.to_string();
+            assert_eq!(Some(register), Register::from_name(&name[1..]));
+        }
+    }
 }
@@ -0,0 +1,308 @@
+use std::io::{self, BufRead, Write};
+
+use ternary::{T24, TInt};
+
+use crate::error::{Error, Result};
+use crate::memory::Memory;
+use crate::numerals;
+use crate::registers::{self, Registers};
+
+pub const EXIT: i64 = 0;
+pub const PRINT_INT: i64 = 1;
+pub const PRINT_TERNARY: i64 = 2;
+pub const PRINT_STRING: i64 = 3;
+pub const READ_INT: i64 = 4;
+pub const READ_LINE: i64 = 5;
+
+const REPLACEMENT_CHAR: char = '?';
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Action {
+    Continue,
+    Halt(i32),
+}
+
+pub trait SyscallHandler {
+    fn syscall(&mut self, registers: &mut Registers, memory: &mut Memory) -> Result<Action>;
+}
+
+impl<H: SyscallHandler + ?Sized> SyscallHandler for Box<H> {
+    fn syscall(&mut self, registers: &mut Registers, memory: &mut Memory) -> Result<Action> {
+        (**self).syscall(registers, memory)
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Stdio;
+
+impl SyscallHandler for Stdio {
+    fn syscall(&mut self, registers: &mut Registers, memory: &mut Memory) -> Result<Action> {
+        let mut input = io::stdin().lock();
+        let mut output = io::stdout().lock();
+        console(registers, memory, &mut input, &mut output)
+    }
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct Console<R, W> {
+    input: R,
+    output: W,
+}
+
+impl<R: BufRead, W: Write> Console<R, W> {
+    pub fn new(input: R, output: W) -> Self {
+        Console { input, output }
+    }
+
+    pub fn input(&self) -> &R {
+        &self.input
+    }
+
+    pub fn output(&self) -> &W {
+        &self.output
+    }
+
+    pub fn output_mut(&mut self) -> &mut W {
+        &mut self.output
+    }
+
+    pub fn into_inner(self) -> (R, W) {
+        (self.input, self.output)
+    }
+}
+
+impl<R: BufRead, W: Write> SyscallHandler for Console<R, W> {
+    fn syscall(&mut self, registers: &mut Registers, memory: &mut Memory) -> Result<Action> {
+        console(registers, memory, &mut self.input, &mut self.output)
+    }
+}
+
+pub fn console(
+    registers: &mut Registers,
+    memory: &mut Memory,
+    input: &mut dyn BufRead,
+    output: &mut dyn Write,
+) -> Result<Action> {
+    let number: i64 = registers[registers::A0].try_into_int()?;
+    match number {
+        EXIT => {
+            let code = registers[registers::A1].try_into_int()?;
+            return Ok(Action::Halt(code));
+        }
+        PRINT_INT => {
+            let value: i64 = registers[registers::A1].try_into_int()?;
+            write!(output, "{value}")?;
+            output.flush()?;
+        }
+        PRINT_TERNARY => {
+            let value: i64 = registers[registers::A1].try_into_int()?;
+            write!(output, "{}", numerals::balanced_ternary(value))?;
+            output.flush()?;
+        }
+        PRINT_STRING => {
+            let addr = registers[registers::A1].try_into_int()?;
+            write!(output, "{}", read_string(memory, addr)?)?;
+            output.flush()?;
+        }
+        READ_INT => {
+            let value = read_line(input)?
+                .and_then(|line| line.trim().parse::<i64>().ok())
+                .and_then(|value| T24::try_from_int(value).ok());
+            let status = if let Some(value) = value {
+                registers[registers::LO] = value;
+                0
+            } else {
+                registers[registers::LO] = T24::ZERO;
+                -1
+            };
+
+            registers[registers::HI] = T24::try_from_int(status)?;
+        }
+        READ_LINE => {
+            let addr = registers[registers::A1].try_into_int()?;
+            let capacity: i64 = registers[registers::A2].try_into_int()?;
+            let count = match read_line(input)? {
+                Some(line) => write_string(memory, addr, &line, capacity)?,
+                None => -1,
+            };
+
+            registers[registers::LO] = T24::try_from_int(count)?;
+        }
+        _ => return Err(Error::InvalidSyscall(number)),
+    }
+
+    Ok(Action::Continue)
+}
+
+fn read_line(input: &mut dyn BufRead) -> Result<Option<String>> {
+    let mut line = String::new();
+    if input.read_line(&mut line)? == 0 {
+        return Ok(None);
+    }
+
+    let len = line.trim_end_matches(['\r', '\n']).len();
+    line.truncate(len);
+    Ok(Some(line))
+}
+
+fn read_string(memory: &Memory, addr: i32) -> Result<String> {
+    let mut s = String::new();
+    for addr in addr.. {
+        let code: i32 = memory.load::<1>(addr)?.try_into_int()?;
+        if code == 0 {
+            break;
+        }
+
+        let c = u32::try_from(code).ok().and_then(char::from_u32);
+        s.push(c.unwrap_or(REPLACEMENT_CHAR));
+    }
+
+    Ok(s)
+}
+
+fn write_string(memory: &mut Memory, addr: i32, s: &str, capacity: i64) -> Result<i64> {
+    let Some(max_len) = capacity.checked_sub(1).filter(|&len| len >= 0) else {
+        return Ok(0);
+    };
+
+    let mut count = 0;
+    for c in s
+        .chars()
+        .take(usize::try_from(max_len).unwrap_or(usize::MAX))
+    {
+        let tryte = TInt::<1>::try_from_int(u32::from(c))
+            .or_else(|_| TInt::<1>::try_from_int(u32::from(REPLACEMENT_CHAR)))?;
+        memory.store(addr + count, tryte)?;
+        count += 1;
+    }
+
+    memory.store(addr + count, TInt::<1>::ZERO)?;
+    Ok(i64::from(count))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::io::Cursor;
+
+    use crate::registers::Register;
+
+    struct Machine {
+        registers: Registers,
+        memory: Memory,
+    }
+
+    impl Machine {
+        fn new() -> Self {
+            Machine {
+                registers: Registers::new(),
+                memory: Memory::new(64),
+            }
+        }
+
+        fn set(&mut self, register: Register, value: i64) {
+            self.registers[register] = T24::try_from_int(value).unwrap();
+        }
+
+        fn get(&self, register: Register) -> i64 {
+            self.registers[register].try_into_int().unwrap()
+        }
+
+        fn syscall(&mut self, number: i64, input: &str) -> (Action, String) {
+            let mut console = Console::new(Cursor::new(input.as_bytes()), Vec::new());
+            self.set(registers::A0, number);
+            let action = console
+                .syscall(&mut self.registers, &mut self.memory)
+                .unwrap();
+            (action, String::from_utf8(console.into_inner().1).unwrap())
+        }
+
+        fn try_syscall(&mut self, number: i64) -> Result<Action> {
+            let mut console = Console::new(Cursor::new(&b""[..]), Vec::new());
+            self.set(registers::A0, number);
+            console.syscall(&mut self.registers, &mut self.memory)
+        }
+    }
+
+    #[test]
+    fn syscall_exit() {
+        let mut machine = Machine::new();
+        machine.set(registers::A1, -3);
+        assert_eq!((Action::Halt(-3), String::new()), machine.syscall(EXIT, ""));
+    }
+
+    #[test]
+    fn syscall_print() {
+        let mut machine = Machine::new();
+        machine.set(registers::A1, -42);
+        assert_eq!("-42", machine.syscall(PRINT_INT, "").1);
+        assert_eq!("T1110", machine.syscall(PRINT_TERNARY, "").1);
+
+        for (addr, c) in (-8..).zip("hi ☃".chars()) {
+            let tryte = TInt::<1>::try_from_int(u32::from(c)).unwrap_or(TInt::<1>::ZERO);
+            machine.memory.store(addr, tryte).unwrap();
+        }
+
+        machine.set(registers::A1, -8);
+        assert_eq!("hi ", machine.syscall(PRINT_STRING, "").1);
+    }
+
+    #[test]
+    fn syscall_read_int() {
+        let mut machine = Machine::new();
+        machine.syscall(READ_INT, " -17 \n");
+        assert_eq!(
+            (-17, 0),
+            (machine.get(registers::LO), machine.get(registers::HI))
+        );
+
+        machine.syscall(READ_INT, "seven\n");
+        assert_eq!(
+            (0, -1),
+            (machine.get(registers::LO), machine.get(registers::HI))
+        );
+
+        machine.syscall(READ_INT, "141214768241\n");
+        assert_eq!(
+            (0, -1),
+            (machine.get(registers::LO), machine.get(registers::HI))
+        );
+
+        machine.syscall(READ_INT, "");
+        assert_eq!(
+            (0, -1),
+            (machine.get(registers::LO), machine.get(registers::HI))
+        );
+    }
+
+    #[test]
+    fn syscall_read_line() {
+        let mut machine = Machine::new();
+        machine.set(registers::A1, 0);
+        machine.set(registers::A2, 4);
+
+        machine.syscall(READ_LINE, "hello\r\nworld\n");
+        assert_eq!(3, machine.get(registers::LO));
+        assert_eq!("hel", read_string(&machine.memory, 0).unwrap());
+
+        machine.set(registers::A2, 16);
+        machine.syscall(READ_LINE, "\n");
+        assert_eq!(0, machine.get(registers::LO));
+        assert_eq!("", read_string(&machine.memory, 0).unwrap());
+
+        machine.syscall(READ_LINE, "");
+        assert_eq!(-1, machine.get(registers::LO));
+    }
+
+    #[test]
+    fn syscall_errors() {
+        let mut machine = Machine::new();
+        let result = machine.try_syscall(99);
+        assert!(matches!(result, Err(Error::InvalidSyscall(99))));
+
+        machine.set(registers::A1, 1_000);
+        let result = machine.try_syscall(PRINT_STRING);
+        assert!(matches!(result, Err(Error::InvalidAddress(1_000))));
+    }
+}
@@ -0,0 +1,418 @@
+use std::fmt;
+use std::io::{self, Read, Write};
+
+use ternary::T24;
+
+use crate::debug::DebugInfo;
+use crate::inst::Inst;
+use crate::numerals;
+use crate::registers::Register;
+
+pub const MAGIC: [u8; 4] = *b"BTT\0";
+pub const VERSION: u16 = 1;
+
+// Called by the VM after every instruction it retires. Instructions that trap
+// are not retired, so they never reach the tracer.
+pub trait Tracer {
+    // When false the VM records nothing, and the calls compile away.
+    const ENABLED: bool = true;
+
+    fn retire(&mut self, record: &Record<'_>);
+}
+
+// The tracer of a VM that isn't tracing.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct NoTracer;
+
+impl Tracer for NoTracer {
+    const ENABLED: bool = false;
+
+    fn retire(&mut self, _: &Record<'_>) {}
+}
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum AccessKind {
+    Read,
+    Write,
+}
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct RegisterWrite {
+    pub register: Register,
+    pub old: i64,
+    pub new: i64,
+}
+
+// For reads, `old` and `new` are both the value read.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct MemoryAccess {
+    pub kind: AccessKind,
+    pub addr: i32,
+    pub size: usize,
+    pub old: i64,
+    pub new: i64,
+}
+
+// An instruction that was retired, with every register it wrote and every
+// memory access it made, in order.
+#[derive(Clone, Copy, Debug)]
+pub struct Record<'a> {
+    pub pc: i32,
+    pub word: T24,
+    pub inst: Inst,
+    pub registers: &'a [RegisterWrite],
+    pub memory: &'a [MemoryAccess],
+}
+
+impl fmt::Display for Record<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let word: i64 = self.word.try_into_int().map_err(|_| fmt::Error)?;
+        let word = format!("0h{}", numerals::heptavintimal(word));
+        let inst = self.inst.display_at(self.pc).to_string();
+        if self.registers.is_empty() && self.memory.is_empty() {
+            return write!(f, "{:>11}  {word:<10} {inst}", self.pc);
+        }
+
+        write!(f, "{:>11}  {word:<10} {inst:<28}", self.pc)?;
+
+        for access in self.memory {
+            let size = access.size;
+            let unit = if size == 1 { "tryte" } else { "trytes" };
+            let (addr, old, new) = (access.addr, access.old, access.new);
+            match access.kind {
+                AccessKind::Read => write!(f, "  read {addr} ({size} {unit}): {new}")?,
+                AccessKind::Write => {
+                    write!(f, "  write {addr} ({size} {unit}): {old} -> {new}")?;
+                }
+            }
+        }
+
+        for write in self.registers {
+            write!(f, "  {}: {} -> {}", write.register, write.old, write.new)?;
+        }
+
+        Ok(())
+    }
+}
+
+// Writes a line per instruction, ending with the function and source line of
+// pc when given the program's debug info. Write errors don't stop the VM; the
+// first one is returned by `finish`.
+pub struct TextTracer<W> {
+    output: W,
+    debug_info: Option<DebugInfo>,
+    error: Option<io::Error>,
+}
+
+impl<W: Write> TextTracer<W> {
+    pub fn new(output: W) -> Self {
+        TextTracer {
+            output,
+            debug_info: None,
+            error: None,
+        }
+    }
+
+    #[must_use]
+    pub fn with_debug_info(self, debug_info: DebugInfo) -> Self {
+        TextTracer {
+            debug_info: Some(debug_info),
+            ..self
+        }
+    }
+
+    pub fn finish(&mut self) -> io::Result<()> {
+        finish(&mut self.output, &mut self.error)
+    }
+}
+
+impl<W: Write> Tracer for TextTracer<W> {
+    fn retire(&mut self, record: &Record<'_>) {
+        if self.error.is_some() {
+            return;
+        }
+
+        let annotation = self
+            .debug_info
+            .as_ref()
+            .map(|debug_info| debug_info.annotate(record.pc))
+            .filter(|annotation| !annotation.is_empty());
+        self.error = match annotation {
+            Some(annotation) => writeln!(self.output, "{record}  in {annotation}"),
+            None => writeln!(self.output, "{record}"),
+        }
+        .err();
+    }
+}
+
+// Writes the format described in docs/trace.md, which `replay` reads back.
+pub struct BinaryTracer<W> {
+    output: W,
+    buffer: Vec<u8>,
+    error: Option<io::Error>,
+}
+
+impl<W: Write> BinaryTracer<W> {
+    pub fn new(mut output: W) -> io::Result<Self> {
+        output.write_all(&MAGIC)?;
+        output.write_all(&VERSION.to_le_bytes())?;
+        Ok(BinaryTracer {
+            output,
+            buffer: Vec::new(),
+            error: None,
+        })
+    }
+
+    pub fn finish(&mut self) -> io::Result<()> {
+        finish(&mut self.output, &mut self.error)
+    }
+}
+
+impl<W: Write> Tracer for BinaryTracer<W> {
+    fn retire(&mut self, record: &Record<'_>) {
+        if self.error.is_some() {
+            return;
+        }
+
+        let buffer = &mut self.buffer;
+        buffer.clear();
+        write_varint(buffer, record.pc.into());
+        write_varint(buffer, record.word.try_into_int().unwrap());
+
+        buffer.push(u8::try_from(record.registers.len()).unwrap());
+        for write in record.registers {
+            buffer.push(write.register.into_i8().to_le_bytes()[0]);
+            write_varint(buffer, write.old);
+            write_varint(buffer, write.new);
+        }
+
+        buffer.push(u8::try_from(record.memory.len()).unwrap());
+        for access in record.memory {
+            buffer.push(match access.kind {
+                AccessKind::Read => 0,
+                AccessKind::Write => 1,
+            });
+            write_varint(buffer, access.addr.into());
+            buffer.push(u8::try_from(access.size).unwrap());
+            if access.kind == AccessKind::Write {
+                write_varint(buffer, access.old);
+            }
+            write_varint(buffer, access.new);
+        }
+
+        self.error = self.output.write_all(buffer).err();
+    }
+}
+
+fn finish<W: Write>(output: &mut W, error: &mut Option<io::Error>) -> io::Result<()> {
+    match error.take() {
+        Some(error) => Err(error),
+        None => output.flush(),
+    }
+}
+
+// Passes every record of a binary trace to `tracer`, returning how many there
+// were.
+pub fn replay<R: Read, T: Tracer>(mut input: R, tracer: &mut T) -> io::Result<usize> {
+    let mut header = [0; 6];
+    input.read_exact(&mut header)?;
+    if header[..4] != MAGIC {
+        return Err(invalid("not a btm trace"));
+    }
+
+    let version = u16::from_le_bytes([header[4], header[5]]);
+    if version != VERSION {
+        return Err(invalid("unsupported trace version"));
+    }
+
+    let mut registers = Vec::new();
+    let mut memory = Vec::new();
+    let mut count = 0;
+    while let Some(first) = read_byte(&mut input)? {
+        let pc = narrow(read_varint_from(&mut input, first)?)?;
+        let word = T24::try_from_int(read_varint(&mut input)?)
+            .map_err(|_| invalid("invalid instruction word"))?;
+        let inst = Inst::from_word(word).map_err(|_| invalid("invalid instruction word"))?;
+
+        registers.clear();
+        for _ in 0..read_u8(&mut input)? {
+            let index = i8::from_le_bytes([read_u8(&mut input)?]);
+            let register = Register::all()
+                .find(|register| register.into_i8() == index)
+                .ok_or_else(|| invalid("invalid register"))?;
+            let old = read_varint(&mut input)?;
+            let new = read_varint(&mut input)?;
+            registers.push(RegisterWrite { register, old, new });
+        }
+
+        memory.clear();
+        for _ in 0..read_u8(&mut input)? {
+            let kind = match read_u8(&mut input)? {
+                0 => AccessKind::Read,
+                1 => AccessKind::Write,
+                _ => return Err(invalid("invalid access kind")),
+            };
+            let addr = narrow(read_varint(&mut input)?)?;
+            let size = usize::from(read_u8(&mut input)?);
+            let old = match kind {
+                AccessKind::Read => None,
+                AccessKind::Write => Some(read_varint(&mut input)?),
+            };
+            let new = read_varint(&mut input)?;
+            memory.push(MemoryAccess {
+                kind,
+                addr,
+                size,
+                old: old.unwrap_or(new),
+                new,
+            });
+        }
+
+        tracer.retire(&Record {
+            pc,
+            word,
+            inst,
+            registers: &registers,
+            memory: &memory,
+        });
+        count += 1;
+    }
+
+    Ok(count)
+}
+
+// Zigzag LEB128, so small negative values are as short as small positive ones.
+fn write_varint(buffer: &mut Vec<u8>, value: i64) {
+    let mut n = (value << 1 ^ value >> 63).cast_unsigned();
+    while n >= 0x80 {
+        buffer.push(u8::try_from(n & 0x7f).unwrap() | 0x80);
+        n >>= 7;
+    }
+
+    buffer.push(u8::try_from(n).unwrap());
+}
+
+fn read_varint<R: Read>(input: &mut R) -> io::Result<i64> {
+    let first = read_u8(input)?;
+    read_varint_from(input, first)
+}
+
+fn read_varint_from<R: Read>(input: &mut R, first: u8) -> io::Result<i64> {
+    let mut n = u64::from(first & 0x7f);
+    let mut byte = first;
+    let mut shift = 7;
+    while byte & 0x80 != 0 {
+        if shift > 63 {
+            return Err(invalid("varint is too long"));
+        }
+
+        byte = read_u8(input)?;
+        n |= u64::from(byte & 0x7f) << shift;
+        shift += 7;
+    }
+
+    Ok((n >> 1).cast_signed() ^ -(n & 1).cast_signed())
+}
+
+fn read_byte<R: Read>(input: &mut R) -> io::Result<Option<u8>> {
+    let mut byte = [0];
+    loop {
+        match input.read(&mut byte) {
+            Ok(0) => return Ok(None),
+            Ok(_) => return Ok(Some(byte[0])),
+            Err(error) if error.kind() == io::ErrorKind::Interrupted => {}
+            Err(error) => return Err(error),
+        }
+    }
+}
+
+fn read_u8<R: Read>(input: &mut R) -> io::Result<u8> {
+    let mut byte = [0];
+    input.read_exact(&mut byte)?;
+    Ok(byte[0])
+}
+
+fn narrow(value: i64) -> io::Result<i32> {
+    i32::try_from(value).map_err(|_| invalid("address is out of range"))
+}
+
+fn invalid(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use ternary::T12;
+
+    use crate::operands;
+    use crate::registers;
+
+    fn record<'a>(registers: &'a [RegisterWrite], memory: &'a [MemoryAccess]) -> Record<'a> {
+        let inst = Inst::Sw(operands::RRO {
+            dest: registers::SP,
+            src: registers::T0,
+            offset: T12::try_from_int(-4).unwrap(),
+        });
+
+        Record {
+            pc: 8,
+            word: inst.to_word().unwrap(),
+            inst,
+            registers,
+            memory,
+        }
+    }
+
+    #[test]
+    fn varints() {
+        for value in [0, 1, -1, 63, -64, 64, 300, -300, i64::MAX, i64::MIN] {
+            let mut buffer = Vec::new();
+            write_varint(&mut buffer, value);
+            assert_eq!(value, read_varint(&mut buffer.as_slice()).unwrap());
+        }
+
+        let mut buffer = Vec::new();
+        write_varint(&mut buffer, -64);
+        assert_eq!(vec![0x7f], buffer);
+    }
+
+    #[test]
+    fn replay_binary() {
+        let writes = [RegisterWrite {
+            register: registers::T0,
+            old: 3,
+            new: -2,
+        }];
+        let accesses = [MemoryAccess {
+            kind: AccessKind::Write,
+            addr: -8,
+            size: 4,
+            old: 0,
+            new: 5,
+        }];
+
+        let mut binary = BinaryTracer::new(Vec::new()).unwrap();
+        let mut text = TextTracer::new(Vec::new());
+        for record in [record(&writes, &accesses), record(&[], &[])] {
+            binary.retire(&record);
+            text.retire(&record);
+        }
+        binary.finish().unwrap();
+
+        let mut replayed = TextTracer::new(Vec::new());
+        assert_eq!(2, replay(binary.output.as_slice(), &mut replayed).unwrap());
+        assert_eq!(text.output, replayed.output);
+
+        let lines = String::from_utf8(text.output).unwrap();
+        let first = lines.lines().next().unwrap();
+        assert!(first.starts_with("          8  0h"));
+        assert!(first.ends_with("-4               write -8 (4 trytes): 0 -> 5  $t0: 3 -> -2"));
+        assert!(first.contains(" sw $sp, $t0, -4 "));
+
+        let truncated = &binary.output[..binary.output.len() - 1];
+        assert!(replay(truncated, &mut NoTracer).is_err());
+        assert!(replay(&b"BTM\0\x01\x00"[..], &mut NoTracer).is_err());
+    }
+}
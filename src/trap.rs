@@ -0,0 +1,66 @@
+use std::fmt;
+
+use crate::error::Error;
+use crate::inst::Inst;
+
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum TrapCause {
+    InvalidOpcode(i8),
+    InvalidInstruction,
+    MisalignedAccess { addr: i32, align: usize },
+    OutOfBounds { addr: i64 },
+    DivideByZero,
+    InvalidShift(i64),
+    InvalidSyscall(i64),
+    Syscall(String),
+}
+
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Trap {
+    pub pc: i32,
+    pub inst: Option<Inst>,
+    pub cause: TrapCause,
+}
+
+impl From<Error> for TrapCause {
+    fn from(error: Error) -> Self {
+        match error {
+            Error::InvalidOpcode(opcode) => TrapCause::InvalidOpcode(opcode),
+            Error::InvalidAddress(addr) => TrapCause::OutOfBounds { addr: addr.into() },
+            Error::InvalidAlignment(addr, align) => TrapCause::MisalignedAccess { addr, align },
+            Error::InvalidSyscall(number) => TrapCause::InvalidSyscall(number),
+            Error::Trap(trap) => trap.cause,
+            error => TrapCause::Syscall(error.to_string()),
+        }
+    }
+}
+
+impl fmt::Display for TrapCause {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TrapCause::InvalidOpcode(opcode) => write!(f, "invalid opcode {opcode}"),
+            TrapCause::InvalidInstruction => write!(f, "invalid instruction"),
+            TrapCause::MisalignedAccess { addr, align } => {
+                write!(f, "address {addr} is not aligned to {align} trytes")
+            }
+            TrapCause::OutOfBounds { addr } => write!(f, "address {addr} is out of bounds"),
+            TrapCause::DivideByZero => write!(f, "division by zero"),
+            TrapCause::InvalidShift(amount) => write!(f, "invalid shift amount {amount}"),
+            TrapCause::InvalidSyscall(number) => write!(f, "invalid syscall {number}"),
+            TrapCause::Syscall(message) => write!(f, "syscall failed: {message}"),
+        }
+    }
+}
+
+impl fmt::Display for Trap {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} at pc {}", self.cause, self.pc)?;
+        if let Some(inst) = self.inst {
+            write!(f, " ({})", inst.display_at(self.pc))?;
+        }
+
+        Ok(())
+    }
+}
+
+impl std::error::Error for Trap {}
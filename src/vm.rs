@@ -1,97 +1,294 @@
-use std::ops::{BitAnd, BitOr, Mul, Range, RangeInclusive};
+use std::ops::{BitAnd, BitOr, Range};
+use std::result;
 
 use ternary::trit::{_0, _1, _T};
-use ternary::{T12, T24, T48, TInt, Trit, Tryte, tables::TRIT4_TO_I8, trit, tryte};
+use ternary::{T12, T24, T48, TInt, Trit};
 
+use crate::debug::DebugInfo;
 use crate::error::{Error, Result};
+use crate::image::{self, Image};
 use crate::inst::Inst;
+use crate::memory::Memory;
 use crate::operands;
 use crate::registers::{self, Register, Registers};
+use crate::syscall::{Action, Stdio, SyscallHandler};
+use crate::trace::{AccessKind, MemoryAccess, NoTracer, Record, RegisterWrite, Tracer};
+use crate::trap::{Trap, TrapCause};
+use crate::watch::{Hit, Target, WatchKind, Watchpoint};
 
-const TRIT3_POS_OFFSET: i8 = 13;
+const WORD_WIDTH: u64 = 24;
 
-pub struct VM {
-    running: bool,
+type Execution<T = ()> = result::Result<T, TrapCause>;
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum StopReason {
+    Exit { code: i32 },
+    Break { pc: i32 },
+    Watch(Hit),
+}
+
+pub struct VM<H = Stdio, T = NoTracer> {
+    stop: Option<StopReason>,
     pc: i32,
     registers: Registers,
-    memory: Vec<Tryte>,
+    memory: Memory,
+    syscall_handler: H,
+    debug_info: DebugInfo,
+    watchpoints: Vec<Watchpoint>,
+    tracer: T,
+    // the effects of the current instruction, collected only when tracing
+    register_writes: Vec<RegisterWrite>,
+    memory_accesses: Vec<MemoryAccess>,
 }
 
 impl VM {
     pub fn new(memory_size: u32) -> Self {
-        let memory = vec![Tryte::ZERO; memory_size as usize];
+        VM::with_syscall_handler(memory_size, Stdio)
+    }
+}
+
+impl<H: SyscallHandler> VM<H> {
+    pub fn with_syscall_handler(memory_size: u32, syscall_handler: H) -> Self {
+        let memory = Memory::new(memory_size);
 
         VM {
-            running: false,
+            stop: None,
             pc: 0,
             registers: Registers::new(),
             memory,
+            syscall_handler,
+            debug_info: DebugInfo::default(),
+            watchpoints: Vec::new(),
+            tracer: NoTracer,
+            register_writes: Vec::new(),
+            memory_accesses: Vec::new(),
+        }
+    }
+}
+
+impl<H: SyscallHandler, T: Tracer> VM<H, T> {
+    pub fn with_tracer<U: Tracer>(self, tracer: U) -> VM<H, U> {
+        VM {
+            stop: self.stop,
+            pc: self.pc,
+            registers: self.registers,
+            memory: self.memory,
+            syscall_handler: self.syscall_handler,
+            debug_info: self.debug_info,
+            watchpoints: self.watchpoints,
+            tracer,
+            register_writes: Vec::new(),
+            memory_accesses: Vec::new(),
         }
     }
 
-    pub fn run(&mut self, pc: i32) -> Result<()> {
+    pub fn pc(&self) -> i32 {
+        self.pc
+    }
+
+    pub fn set_pc(&mut self, pc: i32) {
         self.pc = pc;
-        self.running = true;
+    }
+
+    pub fn registers(&self) -> &Registers {
+        &self.registers
+    }
+
+    pub fn registers_mut(&mut self) -> &mut Registers {
+        &mut self.registers
+    }
+
+    pub fn memory(&self) -> &Memory {
+        &self.memory
+    }
+
+    pub fn memory_mut(&mut self) -> &mut Memory {
+        &mut self.memory
+    }
+
+    pub fn syscall_handler(&self) -> &H {
+        &self.syscall_handler
+    }
+
+    pub fn syscall_handler_mut(&mut self) -> &mut H {
+        &mut self.syscall_handler
+    }
+
+    pub fn tracer(&self) -> &T {
+        &self.tracer
+    }
+
+    pub fn tracer_mut(&mut self) -> &mut T {
+        &mut self.tracer
+    }
+
+    // The symbols and line table of the last image loaded.
+    pub fn debug_info(&self) -> &DebugInfo {
+        &self.debug_info
+    }
+
+    pub fn watchpoints(&self) -> &[Watchpoint] {
+        &self.watchpoints
+    }
+
+    // Returns false if the same watchpoint was already set.
+    pub fn add_watchpoint(&mut self, watchpoint: Watchpoint) -> bool {
+        if self.watchpoints.contains(&watchpoint) {
+            return false;
+        }
+
+        self.watchpoints.push(watchpoint);
+        true
+    }
+
+    pub fn remove_watchpoint(&mut self, watchpoint: &Watchpoint) -> bool {
+        let len = self.watchpoints.len();
+        self.watchpoints.retain(|w| w != watchpoint);
+        self.watchpoints.len() != len
+    }
 
-        while self.running {
-            self.step()?;
+    pub fn load_image(&mut self, image: &Image) -> Result<()> {
+        let mut ranges = image
+            .segments
+            .iter()
+            .map(|segment| {
+                let range = self.memory.range(segment.addr, segment.trytes.len(), 1)?;
+                Ok((range, segment.addr))
+            })
+            .collect::<Result<Vec<_>>>()?;
+
+        ranges.sort_unstable_by_key(|(range, _)| range.start);
+        for pair in ranges.windows(2) {
+            if pair[0].0.end > pair[1].0.start {
+                return Err(image::Error::OverlappingSegments(pair[1].1).into());
+            }
+        }
+
+        self.memory.range(image.entry, 4, 4)?;
+
+        for segment in &image.segments {
+            self.memory.write(segment.addr, &segment.trytes)?;
+        }
+
+        self.pc = image.entry;
+        self.debug_info = DebugInfo::new(image);
+        Ok(())
+    }
+
+    pub fn run(&mut self, pc: i32) -> result::Result<StopReason, Trap> {
+        self.pc = pc;
+        self.resume()
+    }
+
+    pub fn resume(&mut self) -> result::Result<StopReason, Trap> {
+        self.stop = None;
+
+        loop {
+            self.cycle()?;
+            if let Some(reason) = self.stop.take() {
+                return Ok(reason);
+            }
+        }
+    }
+
+    // Executes the instruction at pc, returning the reason the VM stopped if
+    // it did. After a trap, pc is left at the instruction that caused it.
+    pub fn step(&mut self) -> result::Result<Option<StopReason>, Trap> {
+        self.stop = None;
+        self.cycle()?;
+        Ok(self.stop.take())
+    }
+
+    fn cycle(&mut self) -> result::Result<(), Trap> {
+        let pc = self.pc;
+        let (word, inst) = self.fetch().map_err(|cause| Trap {
+            pc,
+            inst: None,
+            cause,
+        })?;
+
+        self.pc += 4;
+        self.execute(inst).map_err(|cause| {
+            self.pc = pc;
+            if T::ENABLED {
+                self.register_writes.clear();
+                self.memory_accesses.clear();
+            }
+
+            Trap {
+                pc,
+                inst: Some(inst),
+                cause,
+            }
+        })?;
+
+        if T::ENABLED {
+            self.tracer.retire(&Record {
+                pc,
+                word,
+                inst,
+                registers: &self.register_writes,
+                memory: &self.memory_accesses,
+            });
+            self.register_writes.clear();
+            self.memory_accesses.clear();
         }
 
         Ok(())
     }
 
-    fn step(&mut self) -> Result<()> {
-        let instruction = self.next_instruction()?;
-        match instruction {
+    fn fetch(&self) -> Execution<(T24, Inst)> {
+        let word = self.memory.load(self.pc)?;
+        let inst = Inst::from_word(word).map_err(|error| match error {
+            Error::InvalidOpcode(opcode) => TrapCause::InvalidOpcode(opcode),
+            _ => TrapCause::InvalidInstruction,
+        })?;
+
+        Ok((word, inst))
+    }
+
+    fn execute(&mut self, inst: Inst) -> Execution {
+        match inst {
             Inst::And(operands) => self.op_and(operands),
             Inst::Or(operands) => self.op_or(operands),
             Inst::Tmul(operands) => self.op_tmul(operands),
             Inst::Tcmp(operands) => self.op_tcmp(operands),
             Inst::Cmp(operands) => self.op_cmp(operands),
-            Inst::Shf(operands) => self.op_shf(operands),
+            Inst::Shf(operands) => self.op_shf(operands)?,
             Inst::Add(operands) => self.op_add(operands),
             Inst::Mul(operands) => self.op_mul(operands),
-            Inst::Div(operands) => self.op_div(operands),
+            Inst::Div(operands) => self.op_div(operands)?,
             Inst::Andi(operands) => self.op_andi(operands),
             Inst::Ori(operands) => self.op_ori(operands),
             Inst::Tmuli(operands) => self.op_tmuli(operands),
             Inst::Tcmpi(operands) => self.op_tcmpi(operands),
-            Inst::Shfi(operands) => self.op_shfi(operands),
+            Inst::Shfi(operands) => self.op_shfi(operands)?,
             Inst::Addi(operands) => self.op_addi(operands),
             Inst::Lui(operands) => self.op_lui(operands),
-            Inst::Lt(operands) => self.op_lt(operands),
-            Inst::Lh(operands) => self.op_lh(operands),
-            Inst::Lw(operands) => self.op_lw(operands),
-            Inst::St(operands) => self.op_st(operands),
-            Inst::Sh(operands) => self.op_sh(operands),
-            Inst::Sw(operands) => self.op_sw(operands),
-            Inst::BT(operands) => self.op_bt(operands),
-            Inst::B0(operands) => self.op_b0(operands),
-            Inst::B1(operands) => self.op_b1(operands),
-            Inst::BT0(operands) => self.op_bt0(operands),
-            Inst::BT1(operands) => self.op_bt1(operands),
-            Inst::B01(operands) => self.op_b01(operands),
-            Inst::Bal(operands) => self.op_bal(operands),
-            Inst::J(operands) => self.op_j(operands),
-            Inst::Jal(operands) => self.op_jal(operands),
-            Inst::Jr(operands) => self.op_jr(operands),
-            Inst::Jalr(operands) => self.op_jalr(operands),
-            Inst::Syscall(_) => self.op_syscall(),
+            Inst::Lt(operands) => self.op_lt(operands)?,
+            Inst::Lh(operands) => self.op_lh(operands)?,
+            Inst::Lw(operands) => self.op_lw(operands)?,
+            Inst::St(operands) => self.op_st(operands)?,
+            Inst::Sh(operands) => self.op_sh(operands)?,
+            Inst::Sw(operands) => self.op_sw(operands)?,
+            Inst::BT(operands) => self.op_bt(operands)?,
+            Inst::B0(operands) => self.op_b0(operands)?,
+            Inst::B1(operands) => self.op_b1(operands)?,
+            Inst::BT0(operands) => self.op_bt0(operands)?,
+            Inst::BT1(operands) => self.op_bt1(operands)?,
+            Inst::B01(operands) => self.op_b01(operands)?,
+            Inst::Bal(operands) => self.op_bal(operands)?,
+            Inst::J(operands) => self.op_j(operands)?,
+            Inst::Jal(operands) => self.op_jal(operands)?,
+            Inst::Jr(operands) => self.op_jr(operands)?,
+            Inst::Jalr(operands) => self.op_jalr(operands)?,
+            Inst::Syscall(_) => self.op_syscall()?,
             Inst::Break(_) => self.op_break(),
         }
 
         Ok(())
     }
 
-    fn next_instruction(&mut self) -> Result<Inst> {
-        let range = self.memory_range(self.pc, 4, 4)?;
-        self.pc += 4;
-
-        let trytes = &self.memory[range];
-        let word = T24::try_from(trytes).unwrap();
-        Inst::from_word(word)
-    }
-
     fn op_and(&mut self, operands: operands::RRR) {
         self.simple_rrr(operands, T24::bitand);
     }
@@ -111,27 +308,26 @@ impl VM {
     fn op_cmp(&mut self, operands: operands::RRR) {
         let lhs = self.registers[operands.lhs];
         let rhs = self.registers[operands.rhs];
-        let cmp_trit = lhs.cmp_trit(rhs);
+        let mut value = T24::ZERO;
+        value.set_trit(0, lhs.cmp_trit(rhs));
 
-        self.registers[operands.dest] = T24::ZERO;
-        self.registers[operands.dest].set_trit(0, cmp_trit);
-        self.registers[registers::ZERO] = T24::ZERO;
+        self.set_register(operands.dest, value);
     }
 
-    fn op_shf(&mut self, operands: operands::RRR) {
-        let offset = self.registers[operands.rhs].try_into_int().unwrap();
-        self.shift(operands.dest, operands.lhs, offset);
+    fn op_shf(&mut self, operands: operands::RRR) -> Execution {
+        let offset = int(self.registers[operands.rhs]);
+        self.shift(operands.dest, operands.lhs, offset)
     }
 
     fn op_add(&mut self, operands: operands::RRR) {
         let lhs = self.registers[operands.lhs];
         let rhs = self.registers[operands.rhs];
         let (sum, carry) = lhs.add_with_carry(rhs, _0);
+        let mut hi = T24::ZERO;
+        hi.set_trit(0, carry);
 
-        self.registers[operands.dest] = sum;
-        self.registers[registers::HI] = T24::ZERO;
-        self.registers[registers::HI].set_trit(0, carry);
-        self.registers[registers::ZERO] = T24::ZERO;
+        self.set_register(operands.dest, sum);
+        self.set_register(registers::HI, hi);
     }
 
     fn op_mul(&mut self, operands: operands::RR) {
@@ -143,17 +339,21 @@ impl VM {
         let lo = T24::try_from(&product_trytes[..4]).unwrap();
         let hi = T24::try_from(&product_trytes[4..]).unwrap();
 
-        self.registers[registers::LO] = lo;
-        self.registers[registers::HI] = hi;
+        self.set_register(registers::LO, lo);
+        self.set_register(registers::HI, hi);
     }
 
-    fn op_div(&mut self, operands: operands::RR) {
+    fn op_div(&mut self, operands: operands::RR) -> Execution {
         let lhs = self.registers[operands.lhs];
         let rhs = self.registers[operands.rhs];
-        let (quotient, remainder) = lhs.div_rem(rhs);
+        if rhs == T24::ZERO {
+            return Err(TrapCause::DivideByZero);
+        }
 
-        self.registers[registers::LO] = quotient;
-        self.registers[registers::HI] = remainder;
+        let (quotient, remainder) = lhs.div_rem(rhs);
+        self.set_register(registers::LO, quotient);
+        self.set_register(registers::HI, remainder);
+        Ok(())
     }
 
     fn op_andi(&mut self, operands: operands::RRI) {
@@ -172,119 +372,148 @@ impl VM {
         self.simple_rri(operands, |r, i| r.tcmp(i.resize()));
     }
 
-    fn op_shfi(&mut self, operands: operands::RRI) {
-        let offset = operands.immediate.try_into_int().unwrap();
-        self.shift(operands.dest, operands.src, offset);
+    fn op_shfi(&mut self, operands: operands::RRI) -> Execution {
+        let offset = int(operands.immediate.resize());
+        self.shift(operands.dest, operands.src, offset)
     }
 
     fn op_addi(&mut self, operands: operands::RRI) {
         let lhs = self.registers[operands.src];
         let rhs = operands.immediate.resize();
         let (sum, carry) = lhs.add_with_carry(rhs, _0);
+        let mut hi = T24::ZERO;
+        hi.set_trit(0, carry);
 
-        self.registers[operands.dest] = sum;
-        self.registers[registers::HI] = T24::ZERO;
-        self.registers[registers::HI].set_trit(0, carry);
-        self.registers[registers::ZERO] = T24::ZERO;
+        self.set_register(operands.dest, sum);
+        self.set_register(registers::HI, hi);
     }
 
     fn op_lui(&mut self, operands: operands::RI) {
-        self.registers[operands.dest] = operands.immediate.resize() << 12;
-        self.registers[registers::ZERO] = T24::ZERO;
+        self.set_register(operands.dest, operands.immediate.resize() << 12);
     }
 
-    fn op_lt(&mut self, operands: operands::RRO) {
-        self.load::<1>(operands);
+    fn op_lt(&mut self, operands: operands::RRO) -> Execution {
+        self.load::<1>(operands)
     }
 
-    fn op_lh(&mut self, operands: operands::RRO) {
-        self.load::<2>(operands);
+    fn op_lh(&mut self, operands: operands::RRO) -> Execution {
+        self.load::<2>(operands)
     }
 
-    fn op_lw(&mut self, operands: operands::RRO) {
-        self.load::<4>(operands);
+    fn op_lw(&mut self, operands: operands::RRO) -> Execution {
+        self.load::<4>(operands)
     }
 
-    fn op_st(&mut self, operands: operands::RRO) {
-        self.store::<1>(operands);
+    fn op_st(&mut self, operands: operands::RRO) -> Execution {
+        self.store::<1>(operands)
     }
 
-    fn op_sh(&mut self, operands: operands::RRO) {
-        self.store::<2>(operands);
+    fn op_sh(&mut self, operands: operands::RRO) -> Execution {
+        self.store::<2>(operands)
     }
 
-    fn op_sw(&mut self, operands: operands::RRO) {
-        self.store::<4>(operands);
+    fn op_sw(&mut self, operands: operands::RRO) -> Execution {
+        self.store::<4>(operands)
     }
 
-    fn op_bt(&mut self, operands: operands::RO) {
+    fn op_bt(&mut self, operands: operands::RO) -> Execution {
         let selector = self.branch_selector(operands);
-        let offset = operands.offset.try_into_int().unwrap();
-        self.branch(selector, offset, 0, 0);
+        let offset = int(operands.offset);
+        self.branch(selector, offset, 0, 0)
     }
 
-    fn op_b0(&mut self, operands: operands::RO) {
+    fn op_b0(&mut self, operands: operands::RO) -> Execution {
         let selector = self.branch_selector(operands);
-        let offset = operands.offset.try_into_int().unwrap();
-        self.branch(selector, 0, offset, 0);
+        let offset = int(operands.offset);
+        self.branch(selector, 0, offset, 0)
     }
 
-    fn op_b1(&mut self, operands: operands::RO) {
+    fn op_b1(&mut self, operands: operands::RO) -> Execution {
         let selector = self.branch_selector(operands);
-        let offset = operands.offset.try_into_int().unwrap();
-        self.branch(selector, 0, 0, offset);
+        let offset = int(operands.offset);
+        self.branch(selector, 0, 0, offset)
     }
 
-    fn op_bt0(&mut self, operands: operands::RO) {
+    fn op_bt0(&mut self, operands: operands::RO) -> Execution {
         let selector = self.branch_selector(operands);
-        let offset = operands.offset.try_into_int().unwrap();
-        self.branch(selector, offset, offset, 0);
+        let offset = int(operands.offset);
+        self.branch(selector, offset, offset, 0)
     }
 
-    fn op_bt1(&mut self, operands: operands::RO) {
+    fn op_bt1(&mut self, operands: operands::RO) -> Execution {
         let selector = self.branch_selector(operands);
-        let offset = operands.offset.try_into_int().unwrap();
-        self.branch(selector, offset, 0, offset);
+        let offset = int(operands.offset);
+        self.branch(selector, offset, 0, offset)
     }
 
-    fn op_b01(&mut self, operands: operands::RO) {
+    fn op_b01(&mut self, operands: operands::RO) -> Execution {
         let selector = self.branch_selector(operands);
-        let offset = operands.offset.try_into_int().unwrap();
-        self.branch(selector, 0, offset, offset);
+        let offset = int(operands.offset);
+        self.branch(selector, 0, offset, offset)
     }
 
-    fn op_bal(&mut self, operands: operands::O) {
-        let offset: i32 = operands.offset.try_into_int().unwrap();
-        self.save_pc();
-        self.pc += offset;
+    fn op_bal(&mut self, operands: operands::O) -> Execution {
+        let target = addr(i64::from(self.pc) + int(operands.offset))?;
+        self.save_pc()?;
+        self.pc = target;
+        Ok(())
     }
 
-    fn op_j(&mut self, operands: operands::A) {
-        self.pc = operands.addr.try_into_int().unwrap();
+    fn op_j(&mut self, operands: operands::A) -> Execution {
+        self.pc = addr(int(operands.addr))?;
+        Ok(())
     }
 
-    fn op_jal(&mut self, operands: operands::A) {
-        self.save_pc();
-        self.pc = operands.addr.try_into_int().unwrap();
+    fn op_jal(&mut self, operands: operands::A) -> Execution {
+        let target = addr(int(operands.addr))?;
+        self.save_pc()?;
+        self.pc = target;
+        Ok(())
     }
 
-    fn op_jr(&mut self, operands: operands::R) {
-        self.pc = self.registers[operands.src].try_into_int().unwrap();
+    fn op_jr(&mut self, operands: operands::R) -> Execution {
+        self.pc = addr(int(self.registers[operands.src]))?;
+        Ok(())
     }
 
-    fn op_jalr(&mut self, operands: operands::R) {
-        self.save_pc();
-        self.pc = self.registers[operands.src].try_into_int().unwrap();
+    fn op_jalr(&mut self, operands: operands::R) -> Execution {
+        let target = addr(int(self.registers[operands.src]))?;
+        self.save_pc()?;
+        self.pc = target;
+        Ok(())
     }
 
-    #[allow(clippy::unused_self)]
-    fn op_syscall(&mut self) {
-        unimplemented!()
+    fn op_syscall(&mut self) -> Execution {
+        // the handler writes registers directly, so watched or traced
+        // registers are compared with their values from before the call
+        let watched: Vec<_> = Register::all()
+            .filter(|&register| T::ENABLED || self.is_watched(register))
+            .map(|register| (register, self.registers[register]))
+            .collect();
+
+        let action = self
+            .syscall_handler
+            .syscall(&mut self.registers, &mut self.memory);
+        self.registers[registers::ZERO] = T24::ZERO;
+
+        for (register, old) in watched {
+            let new = self.registers[register];
+            if new != old {
+                self.registers[register] = old;
+                self.set_register(register, new);
+            }
+        }
+
+        if let Action::Halt(code) = action? {
+            self.stop = Some(StopReason::Exit { code });
+        }
+
+        Ok(())
     }
 
-    #[allow(clippy::unused_self)]
     fn op_break(&mut self) {
-        unimplemented!()
+        let pc = self.pc - 4;
+        self.stop = Some(StopReason::Break { pc });
     }
 
     fn simple_rrr<F>(&mut self, operands: operands::RRR, f: F)
@@ -295,8 +524,7 @@ impl VM {
         let rhs = self.registers[operands.rhs];
         let value = f(lhs, rhs);
 
-        self.registers[operands.dest] = value;
-        self.registers[registers::ZERO] = T24::ZERO;
+        self.set_register(operands.dest, value);
     }
 
     fn simple_rri<F>(&mut self, operands: operands::RRI, f: F)
@@ -307,23 +535,25 @@ impl VM {
         let rhs = operands.immediate;
         let value = f(lhs, rhs);
 
-        self.registers[operands.dest] = value;
-        self.registers[registers::ZERO] = T24::ZERO;
+        self.set_register(operands.dest, value);
     }
 
-    fn shift(&mut self, dest_reg: Register, src_reg: Register, offset: isize) {
+    fn shift(&mut self, dest_reg: Register, src_reg: Register, offset: i64) -> Execution {
+        if offset.unsigned_abs() > WORD_WIDTH {
+            return Err(TrapCause::InvalidShift(offset));
+        }
+
         let value = self.registers[src_reg];
-        self.registers[dest_reg] = value.shf(offset);
-        self.registers[registers::ZERO] = T24::ZERO;
+        self.set_register(dest_reg, value.shf(isize::try_from(offset).unwrap()));
+        Ok(())
     }
 
-    #[allow(clippy::cast_sign_loss)]
     fn branch_selector(&self, operands: operands::RO) -> Trit {
         let src = self.registers[operands.src];
         src.trit(0)
     }
 
-    fn branch(&mut self, selector: Trit, offset_t: i32, offset_0: i32, offset_1: i32) {
+    fn branch(&mut self, selector: Trit, offset_t: i64, offset_0: i64, offset_1: i64) -> Execution {
         let mut jump_table = [0; 4];
         jump_table[_T.into_index()] = offset_t;
         jump_table[_0.into_index()] = offset_0;
@@ -331,72 +561,435 @@ impl VM {
 
         let i = selector.into_index();
         let offset = jump_table[i];
-        self.pc += offset;
+        self.pc = addr(i64::from(self.pc) + offset)?;
+        Ok(())
     }
 
-    fn load<const N: usize>(&mut self, operands: operands::RRO) -> Result<()> {
-        let addr = self.memory_op_addr(operands);
-        let range = self.memory_range(addr, N, N)?;
-        let trytes = &self.memory[range];
-        let src = TInt::<N>::try_from(trytes).unwrap();
+    fn load<const N: usize>(&mut self, operands: operands::RRO) -> Execution {
+        let addr = self.memory_op_addr(operands.src, operands.offset)?;
+        let src: TInt<N> = self.memory.load(addr)?;
+        if T::ENABLED || !self.watchpoints.is_empty() {
+            let value = src.try_into_int().unwrap();
+            self.watch_memory(WatchKind::Read, addr, N, value, value);
+            self.trace_memory(AccessKind::Read, addr, N, value, value);
+        }
 
-        self.registers[operands.dest] = src.resize();
-        self.registers[registers::ZERO] = T24::ZERO;
+        self.set_register(operands.dest, src.resize());
         Ok(())
     }
 
-    fn store<const N: usize>(&mut self, operands: operands::RRO) -> Result<()> {
-        let addr = self.memory_op_addr(operands);
-        let range = self.memory_range(addr, N, N)?;
-        let trytes = &mut self.memory[range];
-        let dest: &mut [Tryte; N] = trytes.try_into().unwrap();
+    fn store<const N: usize>(&mut self, operands: operands::RRO) -> Execution {
+        let addr = self.memory_op_addr(operands.dest, operands.offset)?;
+        let src: TInt<N> = self.registers[operands.src].resize();
+        if T::ENABLED || !self.watchpoints.is_empty() {
+            let old: TInt<N> = self.memory.load(addr)?;
+            let old = old.try_into_int().unwrap();
+            let new = src.try_into_int().unwrap();
+            self.watch_memory(WatchKind::Write, addr, N, old, new);
+            self.trace_memory(AccessKind::Write, addr, N, old, new);
+        }
 
-        let src = self.registers[operands.src];
-        *dest = src.resize().into_trytes();
+        self.memory.store::<N>(addr, src)?;
         Ok(())
     }
 
-    fn memory_op_addr(&mut self, operands: operands::RRO) -> i32 {
-        let base_addr: i32 = self.registers[operands.dest].try_into_int().unwrap();
-        let offset: i32 = operands.offset.try_into_int().unwrap();
-        base_addr + offset
+    fn memory_op_addr(&self, base_reg: Register, offset: T12) -> Execution<i32> {
+        let base_addr = int(self.registers[base_reg]);
+        addr(base_addr + int(offset.resize()))
     }
 
     pub fn memory_range(&self, addr: i32, size: usize, align: usize) -> Result<Range<usize>> {
-        let size_i32 = i32::try_from(size).unwrap();
-        let align_i32 = i32::try_from(align).unwrap();
+        self.memory.range(addr, size, align)
+    }
+
+    fn save_pc(&mut self) -> Execution {
+        let pc = self.pc;
+        let value =
+            T24::try_from_int(pc).map_err(|_| TrapCause::OutOfBounds { addr: pc.into() })?;
+        self.set_register(registers::RA, value);
+        Ok(())
+    }
 
-        if addr % align_i32 != 0 {
-            return Err(Error::InvalidAlignment(addr, align));
+    // Every instruction writes registers through here, so that watchpoints
+    // and tracers see the write. Writes to $zero are discarded.
+    fn set_register(&mut self, register: Register, value: T24) {
+        if register == registers::ZERO {
+            return;
         }
 
-        let addr_bounds = self.addr_bounds();
-        let addr_end = addr + size_i32;
-        if !addr_bounds.contains(&addr) || !addr_bounds.contains(&(addr_end - 1)) {
-            return Err(Error::InvalidAddress(addr));
+        if T::ENABLED {
+            self.register_writes.push(RegisterWrite {
+                register,
+                old: int(self.registers[register]),
+                new: int(value),
+            });
         }
 
-        let index_start = usize::try_from(addr - addr_bounds.start).unwrap();
-        let index_end = index_start + size;
-        Ok(index_start..index_end)
+        if self.is_watched(register) {
+            self.hit(Hit {
+                pc: self.pc - 4,
+                kind: WatchKind::Write,
+                target: Target::Register(register),
+                old: int(self.registers[register]),
+                new: int(value),
+            });
+        }
+
+        self.registers[register] = value;
+    }
+
+    fn is_watched(&self, register: Register) -> bool {
+        self.watchpoints
+            .iter()
+            .any(|watchpoint| watchpoint.matches_register(register))
     }
 
-    fn addr_bounds(&self) -> Range<i32> {
-        let memory_size = self.memory.len();
-        let offset = i32::try_from(memory_size / 2).unwrap();
-        (-offset)..offset
+    fn watch_memory(&mut self, kind: WatchKind, addr: i32, size: usize, old: i64, new: i64) {
+        if self
+            .watchpoints
+            .iter()
+            .any(|watchpoint| watchpoint.matches_memory(kind, addr, size))
+        {
+            self.hit(Hit {
+                pc: self.pc - 4,
+                kind,
+                target: Target::Memory { addr, size },
+                old,
+                new,
+            });
+        }
+    }
+
+    fn trace_memory(&mut self, kind: AccessKind, addr: i32, size: usize, old: i64, new: i64) {
+        if T::ENABLED {
+            self.memory_accesses.push(MemoryAccess {
+                kind,
+                addr,
+                size,
+                old,
+                new,
+            });
+        }
     }
 
-    fn save_pc(&mut self) {
-        let value = T24::try_from_int(self.pc).unwrap();
-        self.registers[registers::RA] = value;
+    // The instruction still completes, and the first hit is the one reported.
+    fn hit(&mut self, hit: Hit) {
+        if self.stop.is_none() {
+            self.stop = Some(StopReason::Watch(hit));
+        }
     }
 }
 
+// every 24-trit value fits in an i64
+fn int(value: T24) -> i64 {
+    value.try_into_int().unwrap()
+}
+
+fn addr(value: i64) -> Execution<i32> {
+    i32::try_from(value).map_err(|_| TrapCause::OutOfBounds { addr: value })
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
 
+    use std::io;
+
+    use ternary::Tryte;
+
+    use crate::asm;
+    use crate::syscall::Console;
+    use crate::trace::TextTracer;
+
+    fn load(source: &str, origin: i32) -> VM {
+        let mut vm = VM::new(64);
+        vm.load_image(&asm::assemble(source, origin).unwrap())
+            .unwrap();
+        vm
+    }
+
+    fn run_trap(source: &str) -> Trap {
+        let mut vm = load(source, 0);
+        let trap = vm.run(0).unwrap_err();
+        assert_eq!(trap.pc, vm.pc());
+        trap
+    }
+
+    #[test]
+    fn run_until_exit() {
+        let source = "
+            addi $t0, $zero, 4
+        loop:
+            addi $a1, $a1, 10
+            addi $t0, $t0, -1
+            cmp $t1, $t0, $zero
+            b1 $t1, loop
+            addi $a0, $zero, 0
+            syscall
+            addi $a1, $zero, 1
+        ";
+
+        let mut vm = load(source, 0);
+        assert_eq!(StopReason::Exit { code: 40 }, vm.run(0).unwrap());
+        assert_eq!(28, vm.pc());
+    }
+
+    #[test]
+    fn resume_after_break() {
+        let source = "
+            addi $t0, $zero, 1
+            break
+            addi $t0, $t0, 1
+            break
+            addi $a1, $t0, 0
+            syscall
+        ";
+
+        let mut vm = load(source, -8);
+        assert_eq!(StopReason::Break { pc: -4 }, vm.run(-8).unwrap());
+        assert_eq!(0, vm.pc());
+        assert_eq!(T24::try_from_int(1).unwrap(), vm.registers()[registers::T0]);
+
+        vm.registers_mut()[registers::T0] = T24::try_from_int(5).unwrap();
+        assert_eq!(StopReason::Break { pc: 4 }, vm.resume().unwrap());
+        assert_eq!(StopReason::Exit { code: 6 }, vm.resume().unwrap());
+    }
+
+    #[test]
+    fn load_store_base_register() {
+        let source = "
+            addi $t0, $zero, 8
+            addi $t1, $zero, -5
+            sw $t0, $t1, 4
+            lw $t2, $t0, 4
+            addi $a1, $t2, 0
+            syscall
+        ";
+
+        let mut vm = load(source, -16);
+        assert_eq!(StopReason::Exit { code: -5 }, vm.run(-16).unwrap());
+        assert_eq!(
+            T24::try_from_int(-5).unwrap(),
+            vm.memory().load(12).unwrap()
+        );
+    }
+
+    #[test]
+    fn stop_at_watchpoints() {
+        let source = "
+            addi $t0, $zero, 8
+            addi $t1, $zero, -5
+            sw $t0, $t1, 4
+            lw $t2, $t0, 4
+            mul $t1, $t1
+            jal done
+        done:
+            addi $a1, $t2, 0
+            syscall
+        ";
+
+        let mut vm = load(source, -32);
+        let memory = |addr, size| Target::Memory { addr, size };
+        let hit = |pc, kind, target, old, new| {
+            Ok(StopReason::Watch(Hit {
+                pc,
+                kind,
+                target,
+                old,
+                new,
+            }))
+        };
+
+        assert!(vm.add_watchpoint(Watchpoint::memory(WatchKind::Write, 13, 1)));
+        assert!(vm.add_watchpoint(Watchpoint::memory(WatchKind::Read, 12, 4)));
+        assert!(vm.add_watchpoint(Watchpoint::register(registers::T2)));
+        assert!(!vm.add_watchpoint(Watchpoint::register(registers::T2)));
+        assert!(vm.add_watchpoint(Watchpoint::register(registers::LO)));
+        assert!(vm.add_watchpoint(Watchpoint::register(registers::RA)));
+
+        let write = WatchKind::Write;
+        assert_eq!(hit(-24, write, memory(12, 4), 0, -5), vm.run(-32));
+        assert_eq!(-20, vm.pc());
+        assert_eq!(
+            hit(-20, WatchKind::Read, memory(12, 4), -5, -5),
+            vm.resume()
+        );
+        assert_eq!(
+            T24::try_from_int(-5).unwrap(),
+            vm.registers()[registers::T2]
+        );
+
+        let lo = Target::Register(registers::LO);
+        assert_eq!(hit(-16, write, lo, 0, 25), vm.resume());
+        let ra = Target::Register(registers::RA);
+        assert_eq!(hit(-12, write, ra, 0, -8), vm.resume());
+
+        assert!(vm.remove_watchpoint(&Watchpoint::register(registers::T2)));
+        assert!(!vm.remove_watchpoint(&Watchpoint::register(registers::T2)));
+        assert_eq!(Ok(StopReason::Exit { code: -5 }), vm.resume());
+    }
+
+    #[test]
+    fn trace_retired_instructions() {
+        let source = "
+            addi $t0, $zero, 8
+            sw $t0, $t0, 4
+            lw $t1, $t0, 4
+            addi $a1, $t1, 0
+            syscall
+        ";
+
+        let mut output = Vec::new();
+        let mut vm = load(source, -32).with_tracer(TextTracer::new(&mut output));
+        assert_eq!(Ok(StopReason::Exit { code: 8 }), vm.run(-32));
+        vm.tracer_mut().finish().unwrap();
+        drop(vm);
+
+        let output = String::from_utf8(output).unwrap();
+        let lines: Vec<_> = output.lines().collect();
+        assert_eq!(5, lines.len());
+        assert!(lines[0].starts_with("        -32  "));
+        assert!(lines[0].ends_with("$t0: 0 -> 8  $hi: 0 -> 0"));
+        assert!(lines[1].ends_with("sw $t0, $t0, 4                write 12 (4 trytes): 0 -> 8"));
+        assert!(lines[2].ends_with("read 12 (4 trytes): 8  $t1: 0 -> 8"));
+        assert_eq!("        -16  0h16       syscall", lines[4]);
+
+        let mut output = Vec::new();
+        let vm = load("main:\nnop\nj main", 0);
+        let debug_info = vm.debug_info().clone();
+        let tracer = TextTracer::new(&mut output).with_debug_info(debug_info);
+        let mut vm = vm.with_tracer(tracer);
+        vm.step().unwrap();
+        vm.step().unwrap();
+        drop(vm);
+
+        let output = String::from_utf8(output).unwrap();
+        let lines: Vec<_> = output.lines().collect();
+        assert_eq!(
+            vec![
+                "          0  0h0        and $zero, $zero, $zero  in main (2:1)",
+                "          4  0h12       j 0  in main+4 (3:1)",
+            ],
+            lines
+        );
+
+        let mut vm = load("lw $t0, $zero, 2", 0).with_tracer(TextTracer::new(Vec::new()));
+        assert!(vm.step().is_err());
+        vm.tracer_mut().finish().unwrap();
+    }
+
+    #[test]
+    fn trap_causes() {
+        let trap = run_trap(".word 40");
+        assert_eq!((0, None), (trap.pc, trap.inst));
+        assert_eq!(TrapCause::InvalidOpcode(40), trap.cause);
+
+        let trap = run_trap("addi $t0, $zero, 1\nlw $t1, $t0, 2");
+        assert_eq!(4, trap.pc);
+        assert!(matches!(trap.inst, Some(Inst::Lw(_))));
+        assert_eq!(
+            TrapCause::MisalignedAccess { addr: 3, align: 4 },
+            trap.cause
+        );
+
+        let trap = run_trap("sw $zero, $t0, 100");
+        assert_eq!(TrapCause::OutOfBounds { addr: 100 }, trap.cause);
+
+        let trap = run_trap("lui $t0, 100000\njr $t0");
+        let addr = 53_144_100_000;
+        assert_eq!((4, TrapCause::OutOfBounds { addr }), (trap.pc, trap.cause));
+
+        let trap = run_trap("div $t0, $zero");
+        assert_eq!(TrapCause::DivideByZero, trap.cause);
+
+        let trap = run_trap("addi $t1, $zero, 25\nshf $t0, $t0, $t1");
+        assert_eq!((4, TrapCause::InvalidShift(25)), (trap.pc, trap.cause));
+
+        let trap = run_trap("shfi $t0, $t0, -30");
+        assert_eq!(TrapCause::InvalidShift(-30), trap.cause);
+
+        let trap = run_trap("addi $a0, $zero, 42\nsyscall");
+        assert_eq!(TrapCause::InvalidSyscall(42), trap.cause);
+    }
+
+    #[test]
+    fn run_random_memory() {
+        let mut vm = VM::with_syscall_handler(729, Console::new(io::empty(), io::sink()));
+        let mut state = 0x2545_f491_4f6c_dd1d_u64;
+        let mut next = |range: u64| {
+            state ^= state << 13;
+            state ^= state >> 7;
+            state ^= state << 17;
+            i32::try_from(state % range).unwrap()
+        };
+
+        for addr in vm.memory().addr_bounds() {
+            let tryte = TInt::<1>::try_from_int(next(729) - 364).unwrap();
+            vm.memory_mut().store(addr, tryte).unwrap();
+        }
+
+        for _ in 0..100_000 {
+            if vm.step().is_err() {
+                vm.set_pc(next(182) * 4 - 364);
+            }
+        }
+    }
+
+    #[test]
+    fn trap_display() {
+        let trap = run_trap("addi $t0, $zero, 1\nlw $t1, $t0, 2");
+        assert_eq!(
+            "address 3 is not aligned to 4 trytes at pc 4 (lw $t1, $t0, 2)",
+            trap.to_string()
+        );
+    }
+
+    #[test]
+    fn load_image_segments() {
+        let trytes = |values: &[i16]| -> Vec<Tryte> {
+            values
+                .iter()
+                .map(|&value| TInt::<1>::try_from_int(value).unwrap().into_trytes()[0])
+                .collect()
+        };
+
+        let mut image = Image::new(-4);
+        image.segments.push(image::Segment {
+            addr: 8,
+            trytes: trytes(&[1, 2, 3]),
+        });
+        image.segments.push(image::Segment {
+            addr: -32,
+            trytes: trytes(&[-1]),
+        });
+
+        let mut vm = VM::new(64);
+        vm.load_image(&image).unwrap();
+        assert_eq!(-4, vm.pc());
+        assert_eq!(&trytes(&[1, 2, 3])[..], vm.memory().read(8, 3).unwrap());
+        assert_eq!(&trytes(&[-1])[..], vm.memory().read(-32, 1).unwrap());
+
+        image.segments[1].addr = 10;
+        assert!(matches!(
+            vm.load_image(&image),
+            Err(Error::Image(image::Error::OverlappingSegments(10)))
+        ));
+
+        image.segments[1].addr = 32;
+        assert!(matches!(
+            vm.load_image(&image),
+            Err(Error::InvalidAddress(32))
+        ));
+
+        image.segments[1].addr = 0;
+        image.entry = 2;
+        assert!(matches!(
+            vm.load_image(&image),
+            Err(Error::InvalidAlignment(2, 4))
+        ));
+        assert_eq!(-4, vm.pc());
+    }
+
     #[test]
     fn memory_range_tryte() {
         let vm = VM::new(2);
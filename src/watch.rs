@@ -0,0 +1,123 @@
+use std::fmt;
+
+use crate::registers::Register;
+
+// The accesses a watchpoint stops on. `Access` matches both reads and writes.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum WatchKind {
+    Read,
+    Write,
+    Access,
+}
+
+impl WatchKind {
+    pub fn name(self) -> &'static str {
+        match self {
+            WatchKind::Read => "read",
+            WatchKind::Write => "write",
+            WatchKind::Access => "access",
+        }
+    }
+
+    fn matches(self, kind: WatchKind) -> bool {
+        self == kind || self == WatchKind::Access
+    }
+}
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Target {
+    Memory { addr: i32, size: usize },
+    Register(Register),
+}
+
+impl Target {
+    fn overlaps(self, addr: i32, size: usize) -> bool {
+        let Target::Memory {
+            addr: start,
+            size: len,
+        } = self
+        else {
+            return false;
+        };
+
+        let end = i64::from(start).saturating_add(i64::try_from(len).unwrap_or(i64::MAX));
+        let access_end = i64::from(addr).saturating_add(i64::try_from(size).unwrap_or(i64::MAX));
+        i64::from(start) < access_end && i64::from(addr) < end
+    }
+}
+
+impl fmt::Display for Target {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Target::Memory { addr, size: 1 } => write!(f, "{addr} (1 tryte)"),
+            Target::Memory { addr, size } => write!(f, "{addr} ({size} trytes)"),
+            Target::Register(register) => write!(f, "{register}"),
+        }
+    }
+}
+
+// Registers can only be watched for writes, since every instruction reads
+// them.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct Watchpoint {
+    pub kind: WatchKind,
+    pub target: Target,
+}
+
+impl Watchpoint {
+    pub fn memory(kind: WatchKind, addr: i32, size: usize) -> Self {
+        Watchpoint {
+            kind,
+            target: Target::Memory { addr, size },
+        }
+    }
+
+    pub fn register(register: Register) -> Self {
+        Watchpoint {
+            kind: WatchKind::Write,
+            target: Target::Register(register),
+        }
+    }
+
+    pub(crate) fn matches_memory(&self, kind: WatchKind, addr: i32, size: usize) -> bool {
+        self.kind.matches(kind) && self.target.overlaps(addr, size)
+    }
+
+    pub(crate) fn matches_register(&self, register: Register) -> bool {
+        self.target == Target::Register(register)
+    }
+}
+
+impl fmt::Display for Watchpoint {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} {}", self.kind.name(), self.target)
+    }
+}
+
+// An access that a watchpoint stopped on. `target` is what the instruction at
+// `pc` accessed, which for memory may only overlap the watched range. `kind`
+// is either `Read` or `Write`, and a read has the same old and new values.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct Hit {
+    pub pc: i32,
+    pub kind: WatchKind,
+    pub target: Target,
+    pub old: i64,
+    pub new: i64,
+}
+
+impl fmt::Display for Hit {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let Hit {
+            pc,
+            target,
+            old,
+            new,
+            ..
+        } = self;
+        match self.kind {
+            WatchKind::Read => write!(f, "read from {target} at pc {pc}: {new}"),
+            _ => write!(f, "write to {target} at pc {pc}: {old} -> {new}"),
+        }
+    }
+}
@@ -0,0 +1,183 @@
+use std::io::Cursor;
+
+use btm::link::{self, Layout};
+use btm::syscall::{self, Action, Console};
+use btm::{
+    Error, Image, Inst, Memory, Registers, StopReason, SyscallHandler, Trap, TrapCause, VM, asm,
+    disasm, operands, registers,
+};
+use ternary::T24;
+
+fn load(memory: &mut Memory, source: &str, origin: i32) {
+    let image = asm::assemble(source, origin).unwrap();
+    for segment in &image.segments {
+        memory.write(segment.addr, &segment.trytes).unwrap();
+    }
+}
+
+#[test]
+fn run_assembled_program() {
+    let source = "
+        addi $t0, $zero, 5
+        addi $t1, $t0, -7
+        add $t2, $t0, $t1
+        sw $sp, $t2, -4
+        j 300
+    ";
+
+    let mut vm = VM::new(81);
+    load(vm.memory_mut(), source, -40);
+    vm.registers_mut()[registers::SP] = T24::try_from_int(40).unwrap();
+
+    let trap = vm.run(-40).unwrap_err();
+    assert_eq!(
+        Trap {
+            pc: 300,
+            inst: None,
+            cause: TrapCause::OutOfBounds { addr: 300 },
+        },
+        trap
+    );
+    assert_eq!(300, vm.pc());
+
+    let registers = vm.registers();
+    assert_eq!(5, registers[registers::T0].try_into_int::<i32>().unwrap());
+    assert_eq!(-2, registers[registers::T1].try_into_int::<i32>().unwrap());
+    assert_eq!(3, registers[registers::T2].try_into_int::<i32>().unwrap());
+    assert_eq!(registers[registers::T2], vm.memory().load::<4>(36).unwrap());
+}
+
+#[test]
+fn decode_loaded_memory() {
+    let mut memory = Memory::new(16);
+    load(&mut memory, "jalr $s14\nbreak", 0);
+
+    assert_eq!(
+        Inst::Jalr(operands::R {
+            src: registers::S14,
+        }),
+        Inst::from_word(memory.load::<4>(0).unwrap()).unwrap()
+    );
+
+    let lines = disasm::disassemble(&memory, 0..8).unwrap();
+    assert_eq!("break", lines[1].to_string());
+}
+
+struct Counter {
+    console: Console<Cursor<Vec<u8>>, Vec<u8>>,
+    count: i64,
+}
+
+impl SyscallHandler for Counter {
+    fn syscall(&mut self, registers: &mut Registers, memory: &mut Memory) -> btm::Result<Action> {
+        let number: i64 = registers[registers::A0].try_into_int()?;
+        match number {
+            100 => {
+                self.count += 1;
+                registers[registers::LO] = T24::try_from_int(self.count)?;
+                Ok(Action::Continue)
+            }
+            101 => Err(Error::InvalidSyscall(number)),
+            _ => self.console.syscall(registers, memory),
+        }
+    }
+}
+
+#[test]
+fn run_with_syscall_handler() {
+    let source = "
+        addi $a0, $zero, 100
+        syscall
+        syscall
+        addi $a1, $lo, 0
+        addi $a0, $zero, 1
+        syscall
+        addi $a0, $zero, 4
+        syscall
+        addi $a1, $lo, 0
+        addi $a0, $zero, 2
+        syscall
+        addi $a0, $zero, 0
+        addi $a1, $zero, 7
+        syscall
+    ";
+
+    let console = Console::new(Cursor::new(b"5\n".to_vec()), Vec::new());
+    let counter = Counter { console, count: 0 };
+    let mut vm = VM::with_syscall_handler(64, counter);
+    load(vm.memory_mut(), source, -32);
+
+    assert_eq!(StopReason::Exit { code: 7 }, vm.run(-32).unwrap());
+    assert_eq!(2, vm.syscall_handler().count);
+    assert_eq!(b"21TT", &vm.syscall_handler().console.output()[..]);
+
+    let mut vm = VM::with_syscall_handler(16, Box::new(syscall::Stdio) as Box<dyn SyscallHandler>);
+    load(vm.memory_mut(), "addi $a0, $zero, 101\nsyscall", 0);
+    let trap = vm.run(0).unwrap_err();
+    assert_eq!((4, TrapCause::InvalidSyscall(101)), (trap.pc, trap.cause));
+}
+
+// Runs `image` until its `break` and returns the value left in `$t0`.
+fn run_to_break(image: &Image) -> i64 {
+    let mut vm = VM::new(729);
+    vm.load_image(image).unwrap();
+    assert!(matches!(
+        vm.run(image.entry).unwrap(),
+        StopReason::Break { .. }
+    ));
+    vm.registers()[registers::T0].try_into_int().unwrap()
+}
+
+#[test]
+fn hi_lo_pairs_match_vm() {
+    const MAX: i64 = 141_214_768_240;
+
+    let mut values = vec![
+        0,
+        1,
+        -1,
+        265_720,
+        265_721,
+        -265_721,
+        531_441,
+        -531_441,
+        MAX,
+        -MAX,
+        MAX - 265_720,
+        -MAX + 265_721,
+    ];
+
+    // xorshift, so that the values are spread over the whole word range but
+    // the test is reproducible
+    let mut state: u64 = 0x2545_f491_4f6c_dd1d;
+    while values.len() < 200 {
+        state ^= state << 13;
+        state ^= state >> 7;
+        state ^= state << 17;
+        let offset = i64::try_from(state % (2 * MAX as u64 + 1)).unwrap();
+        values.push(offset - MAX);
+    }
+
+    let target = asm::assemble_object(".global target\ntarget: break").unwrap();
+    for (i, &value) in values.iter().enumerate() {
+        let source = format!(
+            "_start:\n    lui $t0, %hi({value})\n    addi $t0, $t0, %lo({value})\n    break"
+        );
+        let image = asm::assemble(&source, -40).unwrap();
+        assert_eq!(value, run_to_break(&image), "assembled {value}");
+
+        let text = [-300, -8, 0, 100][i % 4];
+        let addend = value - (text + 12);
+        let source = format!(
+            ".global _start\n_start:\n    lui $t0, %hi(target + {addend})\n    \
+             addi $t0, $t0, %lo(target + {addend})\n    break"
+        );
+        let objects = [asm::assemble_object(&source).unwrap(), target.clone()];
+        let layout = Layout {
+            text: i32::try_from(text).unwrap(),
+            ..Layout::default()
+        };
+        let image = link::link(&objects, &layout).unwrap();
+        assert_eq!(value, run_to_break(&image), "linked {value} at {text}");
+    }
+}